@@ -0,0 +1,590 @@
+use crate::config::{MihomoLogLevel, MihomoMode};
+
+use std::collections::HashMap;
+use std::pin::pin;
+
+use anyhow::{bail, Context, Result};
+use futures_util::{Stream, StreamExt};
+use reqwest::{header::AUTHORIZATION, Client, Method, RequestBuilder, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+};
+
+/// Client for mihomo's RESTful external controller.
+///
+/// Referenced from https://wiki.metacubex.one/api
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    pub base_url: String,
+    secret: Option<String>,
+    client: Client,
+}
+
+/// A proxy (node or group) as reported by `GET /proxies`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiProxy {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Currently selected member, for groups.
+    pub now: Option<String>,
+    /// Members, for groups.
+    #[serde(default)]
+    pub all: Vec<String>,
+    /// Recent delay tests, newest last.
+    #[serde(default)]
+    pub history: Vec<DelayHistory>,
+}
+
+/// A proxy or rule provider as reported by `GET /providers/proxies` and `GET /providers/rules`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiProvider {
+    pub name: String,
+    /// `Proxy` or `Rule`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Where the provider is loaded from, e.g. `HTTP` or `File`.
+    #[serde(rename = "vehicleType")]
+    pub vehicle: String,
+    #[serde(default, rename = "updatedAt")]
+    pub updated_at: Option<String>,
+    /// Nodes, for proxy providers.
+    #[serde(default)]
+    pub proxies: Vec<ApiProxy>,
+    /// Number of rules, for rule providers.
+    #[serde(default, rename = "ruleCount")]
+    pub rule_count: Option<usize>,
+}
+
+/// Throughput over the last second, as streamed by `/traffic`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Traffic {
+    /// Upload in bytes per second.
+    pub up: u64,
+    /// Download in bytes per second.
+    pub down: u64,
+}
+
+/// Memory used by the core, as streamed by `/memory`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Memory {
+    /// Bytes in use.
+    pub inuse: u64,
+    /// Memory limit of the OS in bytes, 0 if unlimited.
+    #[serde(default)]
+    pub oslimit: u64,
+}
+
+/// Connection totals since the core started, as reported by `GET /connections`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionStats {
+    pub active: usize,
+    pub upload_total: u64,
+    pub download_total: u64,
+}
+
+/// A rule of the running config, as listed by `GET /rules`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiRule {
+    /// Rule type, e.g. `DomainSuffix`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub payload: String,
+    /// Proxy or group the rule sends matching connections to.
+    pub proxy: String,
+}
+
+/// A DNS answer record, as returned by `GET /dns/query`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DnsAnswer {
+    pub name: String,
+    /// Numeric record type, e.g. 1 for `A`.
+    #[serde(rename = "type")]
+    pub kind: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    pub data: String,
+}
+
+/// A log line, as streamed by `/logs`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiLog {
+    /// Level of the line, e.g. `info`.
+    #[serde(rename = "type")]
+    pub level: String,
+    pub payload: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DelayHistory {
+    pub time: String,
+    /// Delay in milliseconds, 0 if the test failed.
+    pub delay: u64,
+}
+
+/// An active connection as reported by `GET /connections`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiConnection {
+    pub id: String,
+    pub metadata: ConnectionMetadata,
+    #[serde(default)]
+    pub upload: u64,
+    #[serde(default)]
+    pub download: u64,
+    #[serde(default)]
+    pub start: String,
+    /// Proxies the connection goes through, innermost first.
+    #[serde(default)]
+    pub chains: Vec<String>,
+    #[serde(default)]
+    pub rule: String,
+    #[serde(default, rename = "rulePayload")]
+    pub rule_payload: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConnectionMetadata {
+    #[serde(default)]
+    pub network: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default, rename = "destinationIP")]
+    pub destination_ip: String,
+    #[serde(default, rename = "destinationPort")]
+    pub destination_port: String,
+    #[serde(default)]
+    pub process: String,
+}
+
+impl ApiConnection {
+    /// Destination host, falling back to its IP if the host is unknown.
+    pub fn host(&self) -> &str {
+        if self.metadata.host.is_empty() {
+            &self.metadata.destination_ip
+        } else {
+            &self.metadata.host
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Rules {
+    rules: Vec<ApiRule>,
+}
+
+#[derive(Deserialize)]
+struct DnsResponse {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(default, rename = "Answer")]
+    answer: Vec<DnsAnswer>,
+}
+
+#[derive(Deserialize)]
+struct Configs {
+    mode: String,
+}
+
+#[derive(Deserialize)]
+struct Connections {
+    #[serde(default)]
+    connections: Option<Vec<ApiConnection>>,
+    #[serde(default, rename = "uploadTotal")]
+    upload_total: u64,
+    #[serde(default, rename = "downloadTotal")]
+    download_total: u64,
+}
+
+#[derive(Deserialize)]
+struct Providers {
+    providers: HashMap<String, ApiProvider>,
+}
+
+#[derive(Deserialize)]
+struct Proxies {
+    proxies: HashMap<String, ApiProxy>,
+}
+
+#[derive(Deserialize)]
+struct Delay {
+    delay: u64,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: String,
+}
+
+/// Proxy types that select between other proxies.
+pub const GROUP_TYPES: [&str; 5] = ["Selector", "URLTest", "Fallback", "LoadBalance", "Relay"];
+
+/// Built-in proxy types that do not go through a remote server.
+const BUILTIN_TYPES: [&str; 6] = [
+    "Direct",
+    "Reject",
+    "RejectDrop",
+    "Pass",
+    "Compatible",
+    "Dns",
+];
+
+impl ApiProxy {
+    pub fn is_group(&self) -> bool {
+        GROUP_TYPES.contains(&self.kind.as_str())
+    }
+
+    /// Whether this is an actual proxy node, rather than a group or a built-in like `DIRECT`.
+    pub fn is_node(&self) -> bool {
+        !self.is_group() && !BUILTIN_TYPES.contains(&self.kind.as_str())
+    }
+
+    /// Delay of the latest test, `None` if never tested or if it failed.
+    pub fn last_delay(&self) -> Option<u64> {
+        self.history
+            .last()
+            .map(|history| history.delay)
+            .filter(|delay| *delay > 0)
+    }
+}
+
+impl ApiClient {
+    /// Client for the controller listening on `external_controller`, reached at `host` instead if
+    /// given (e.g. a remote machine) or at localhost if listening on all interfaces.
+    pub fn new(
+        external_controller: &str,
+        secret: Option<&str>,
+        host: Option<&str>,
+    ) -> Result<Self> {
+        let (listen_host, port) = external_controller
+            .rsplit_once(':')
+            .with_context(|| format!("`external_controller` `{external_controller}` invalid"))?;
+        let host = match (host, listen_host) {
+            (Some(host), _) => host,
+            (None, "" | "0.0.0.0" | "[::]") => "127.0.0.1",
+            (None, listen_host) => listen_host,
+        };
+        Ok(ApiClient {
+            base_url: format!("http://{host}:{port}"),
+            secret: secret.filter(|secret| !secret.is_empty()).map(String::from),
+            client: Client::new(),
+        })
+    }
+
+    /// Request to `path` on the controller, authorized with `secret` if set.
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.secret {
+            Some(secret) => builder.header(AUTHORIZATION, format!("Bearer {secret}")),
+            None => builder,
+        }
+    }
+
+    /// Path from `segments`, percent-encoded as proxy names may contain spaces and emojis.
+    pub fn path(segments: &[&str]) -> String {
+        let mut url = Url::parse("http://localhost").expect("valid url");
+        url.path_segments_mut()
+            .expect("url with path")
+            .extend(segments);
+        url.path().to_owned()
+    }
+
+    /// Send `request` and check that the controller accepted it.
+    pub async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response> {
+        let res = request.send().await.with_context(|| {
+            format!(
+                "failed to reach controller at {}, is mihomo running?",
+                self.base_url
+            )
+        })?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            bail!("controller responded with {status}: {}", body.trim());
+        }
+        Ok(res)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let res = self.send(self.request(Method::GET, path)).await?;
+        res.json()
+            .await
+            .with_context(|| format!("failed to parse controller response from {path}"))
+    }
+
+    /// Subscribe to the streaming endpoint `path` over a WebSocket, parsing each message as `T`.
+    pub async fn subscribe<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<impl Stream<Item = Result<T>>> {
+        let url = format!("ws{}{}", self.base_url.trim_start_matches("http"), path);
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(secret) = &self.secret {
+            request.headers_mut().insert(
+                "Authorization",
+                HeaderValue::from_str(&format!("Bearer {secret}"))?,
+            );
+        }
+        let (socket, _) = connect_async(request).await.with_context(|| {
+            format!(
+                "failed to reach controller at {}, is mihomo running?",
+                self.base_url
+            )
+        })?;
+
+        let path = path.to_owned();
+        Ok(socket.filter_map(move |message| {
+            let parsed = match message {
+                Ok(Message::Text(text)) => Some(
+                    serde_json::from_str(&text)
+                        .with_context(|| format!("failed to parse controller message from {path}")),
+                ),
+                Ok(_) => None,
+                Err(err) => Some(Err(err.into())),
+            };
+            async move { parsed }
+        }))
+    }
+
+    /// Live throughput, reported every second.
+    pub async fn traffic(&self) -> Result<impl Stream<Item = Result<Traffic>>> {
+        self.subscribe("/traffic").await
+    }
+
+    /// Memory currently used by the core.
+    ///
+    /// `/memory` reports 0 in its first message, so the first non-zero sample of the first few is
+    /// taken.
+    pub async fn memory(&self) -> Result<Memory> {
+        let mut samples = pin!(self.subscribe::<Memory>("/memory").await?.take(3));
+        let mut last = None;
+        while let Some(sample) = samples.next().await {
+            let sample = sample?;
+            if sample.inuse > 0 {
+                return Ok(sample);
+            }
+            last = Some(sample);
+        }
+        last.context("controller closed `/memory` without reporting memory usage")
+    }
+
+    /// Live log lines at `level` or above.
+    pub async fn logs(&self, level: &MihomoLogLevel) -> Result<impl Stream<Item = Result<ApiLog>>> {
+        let level = serde_json::to_value(level)?;
+        let level = level.as_str().unwrap_or("info");
+        self.subscribe(&format!("/logs?level={level}")).await
+    }
+
+    /// Version of the running core.
+    pub async fn version(&self) -> Result<String> {
+        Ok(self.get::<Version>("/version").await?.version)
+    }
+
+    /// All proxies and groups by name.
+    pub async fn proxies(&self) -> Result<HashMap<String, ApiProxy>> {
+        Ok(self.get::<Proxies>("/proxies").await?.proxies)
+    }
+
+    /// Proxy providers followed by rule providers, each sorted by name.
+    ///
+    /// The built-in `default` provider holding nodes defined inline in the config is skipped.
+    pub async fn providers(&self) -> Result<Vec<ApiProvider>> {
+        let mut all = Vec::new();
+        for path in ["/providers/proxies", "/providers/rules"] {
+            let mut providers: Vec<ApiProvider> = self
+                .get::<Providers>(path)
+                .await?
+                .providers
+                .into_values()
+                .filter(|provider| provider.vehicle != "Compatible")
+                .collect();
+            providers.sort_by(|a, b| a.name.cmp(&b.name));
+            all.extend(providers);
+        }
+        Ok(all)
+    }
+
+    /// Rules of the running config, in the order they are matched.
+    pub async fn rules(&self) -> Result<Vec<ApiRule>> {
+        Ok(self.get::<Rules>("/rules").await?.rules)
+    }
+
+    /// Re-fetch `provider` from its source.
+    pub async fn update_provider(&self, provider: &ApiProvider) -> Result<()> {
+        let kind = match provider.kind.as_str() {
+            "Rule" => "rules",
+            _ => "proxies",
+        };
+        let path = ApiClient::path(&["providers", kind, &provider.name]);
+        self.send(self.request(Method::PUT, &path)).await?;
+        Ok(())
+    }
+
+    /// Test delay of all nodes of proxy provider `name`.
+    pub async fn healthcheck_provider(&self, name: &str) -> Result<()> {
+        let path = ApiClient::path(&["providers", "proxies", name, "healthcheck"]);
+        self.send(self.request(Method::GET, &path)).await?;
+        Ok(())
+    }
+
+    /// Test delay of proxy `name` by requesting `url` through it, `None` if it timed out or failed.
+    pub async fn delay(&self, name: &str, url: &str, timeout_ms: u64) -> Result<Option<u64>> {
+        let request = self
+            .request(Method::GET, &ApiClient::path(&["proxies", name, "delay"]))
+            .query(&[("url", url), ("timeout", &timeout_ms.to_string())]);
+        let res = request.send().await.with_context(|| {
+            format!(
+                "failed to reach controller at {}, is mihomo running?",
+                self.base_url
+            )
+        })?;
+        if !res.status().is_success() {
+            return Ok(None);
+        }
+        Ok(Some(res.json::<Delay>().await?.delay).filter(|delay| *delay > 0))
+    }
+
+    /// Test delay of all members of proxy group `group` by requesting `url` through them, also
+    /// making URLTest and Fallback groups re-evaluate their selection. Members that timed out or
+    /// failed are left out.
+    pub async fn group_delay(
+        &self,
+        group: &str,
+        url: &str,
+        timeout_ms: u64,
+    ) -> Result<HashMap<String, u64>> {
+        let request = self
+            .request(Method::GET, &ApiClient::path(&["group", group, "delay"]))
+            .query(&[("url", url), ("timeout", &timeout_ms.to_string())]);
+        let res = self.send(request).await?;
+        let delays: HashMap<String, u64> = res
+            .json()
+            .await
+            .with_context(|| format!("failed to parse delays of group `{group}`"))?;
+        Ok(delays.into_iter().filter(|(_, delay)| *delay > 0).collect())
+    }
+
+    /// Switch proxy group `group` to `node`.
+    pub async fn select(&self, group: &str, node: &str) -> Result<()> {
+        let request = self
+            .request(Method::PUT, &ApiClient::path(&["proxies", group]))
+            .json(&serde_json::json!({ "name": node }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Proxy mode of the running core, e.g. `rule`.
+    pub async fn mode(&self) -> Result<String> {
+        Ok(self.get::<Configs>("/configs").await?.mode.to_lowercase())
+    }
+
+    /// Have the core download its latest release, replace its own binary and restart.
+    pub async fn upgrade(&self) -> Result<()> {
+        self.send(self.request(Method::POST, "/upgrade")).await?;
+        Ok(())
+    }
+
+    /// General settings of the running core, as kebab-case fields like in its config.
+    pub async fn configs(&self) -> Result<serde_json::Value> {
+        self.get("/configs").await
+    }
+
+    /// Switch the proxy mode of the running core, until it restarts.
+    pub async fn set_mode(&self, mode: &MihomoMode) -> Result<()> {
+        let request = self
+            .request(Method::PATCH, "/configs")
+            .json(&serde_json::json!({ "mode": mode }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Reload the config file mihomo was started with, without dropping connections.
+    ///
+    /// An empty `path` makes mihomo read its own config file, which is the same locally and on a
+    /// remote machine.
+    pub async fn reload_config(&self) -> Result<()> {
+        let request = self
+            .request(Method::PUT, "/configs?force=true")
+            .json(&serde_json::json!({ "path": "", "payload": "" }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Resolve `name` with mihomo's resolver, `record_type` being e.g. `A` or `AAAA`.
+    pub async fn dns_query(&self, name: &str, record_type: &str) -> Result<Vec<DnsAnswer>> {
+        let request = self
+            .request(Method::GET, "/dns/query")
+            .query(&[("name", name), ("type", record_type)]);
+        let res = self.send(request).await?;
+        let response: DnsResponse = res
+            .json()
+            .await
+            .with_context(|| "failed to parse controller response from /dns/query")?;
+        match response.status {
+            0 => Ok(response.answer),
+            2 => bail!("SERVFAIL resolving {name}"),
+            3 => bail!("{name} does not exist (NXDOMAIN)"),
+            status => bail!("failed to resolve {name}, DNS response code {status}"),
+        }
+    }
+
+    /// Active connections.
+    pub async fn connections(&self) -> Result<Vec<ApiConnection>> {
+        Ok(self
+            .get::<Connections>("/connections")
+            .await?
+            .connections
+            .unwrap_or_default())
+    }
+
+    /// Number of active connections and traffic totals.
+    pub async fn connection_stats(&self) -> Result<ConnectionStats> {
+        let connections = self.get::<Connections>("/connections").await?;
+        Ok(ConnectionStats {
+            active: connections.connections.map_or(0, |active| active.len()),
+            upload_total: connections.upload_total,
+            download_total: connections.download_total,
+        })
+    }
+
+    /// Close connection `id`.
+    pub async fn close_connection(&self, id: &str) -> Result<()> {
+        self.send(self.request(Method::DELETE, &format!("/connections/{id}")))
+            .await?;
+        Ok(())
+    }
+
+    /// Close all active connections.
+    pub async fn close_all_connections(&self) -> Result<()> {
+        self.send(self.request(Method::DELETE, "/connections"))
+            .await?;
+        Ok(())
+    }
+
+    /// Groups in config order (as listed by the built-in `GLOBAL` group), excluding `GLOBAL`.
+    pub async fn groups(&self) -> Result<Vec<ApiProxy>> {
+        let proxies = self.proxies().await?;
+        let order = proxies
+            .get("GLOBAL")
+            .map(|global| global.all.clone())
+            .unwrap_or_default();
+        let mut groups: Vec<ApiProxy> = proxies
+            .values()
+            .filter(|proxy| proxy.is_group() && proxy.name != "GLOBAL")
+            .cloned()
+            .collect();
+        groups.sort_by_key(|group| {
+            (
+                order
+                    .iter()
+                    .position(|name| *name == group.name)
+                    .unwrap_or(usize::MAX),
+                group.name.clone(),
+            )
+        });
+        Ok(groups)
+    }
+}
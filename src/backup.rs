@@ -0,0 +1,169 @@
+use crate::utils::create_parent_dir;
+
+use std::{
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// Archive files and directories into a `.tar.gz` backup at `backup_path`.
+///
+/// Each of `entries` is a `(name, path)` pair, stored in the archive under `name`. Entries whose
+/// path does not exist are skipped. Returns the names of archived entries.
+pub fn create_backup(backup_path: &str, entries: &[(&str, String)]) -> Result<Vec<String>> {
+    create_parent_dir(backup_path)?;
+    let file = File::create(backup_path)
+        .with_context(|| format!("failed to create backup `{}`", backup_path))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let mut archived = Vec::new();
+    for (name, path) in entries {
+        let path = Path::new(path);
+        if path.is_dir() {
+            archive.append_dir_all(name, path)?;
+        } else if path.is_file() {
+            archive.append_path_with_name(path, name)?;
+        } else {
+            continue;
+        }
+        archived.push(name.to_string());
+    }
+    archive.into_inner()?.finish()?;
+    Ok(archived)
+}
+
+/// Restore a `.tar.gz` backup created with [`create_backup`] to the paths of `entries`.
+///
+/// Archived entries are matched by name, unknown entries are rejected before anything is written.
+/// Returns the names of restored entries.
+pub fn restore_backup(backup_path: &str, entries: &[(&str, String)]) -> Result<Vec<String>> {
+    let open = || -> Result<tar::Archive<GzDecoder<File>>> {
+        let file = File::open(backup_path)
+            .with_context(|| format!("failed to open backup `{}`", backup_path))?;
+        Ok(tar::Archive::new(GzDecoder::new(file)))
+    };
+
+    // Resolve destinations of all archived files first, so that a foreign archive is not
+    // partially restored
+    let mut destinations = Vec::new();
+    for entry in open()?.entries()? {
+        let entry = entry?;
+        destinations.push(restore_destination(&entry.path()?, entries)?);
+    }
+
+    let mut restored: Vec<String> = Vec::new();
+    for (entry, (name, destination)) in open()?.entries()?.zip(destinations) {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            std::fs::create_dir_all(&destination)?;
+        } else {
+            create_parent_dir(&destination.to_string_lossy())?;
+            entry.unpack(&destination)?;
+        }
+        if !restored.contains(&name) {
+            restored.push(name);
+        }
+    }
+    Ok(restored)
+}
+
+fn restore_destination(archived: &Path, entries: &[(&str, String)]) -> Result<(String, PathBuf)> {
+    let mut components = archived.components();
+    let name = match components.next() {
+        Some(Component::Normal(name)) => name.to_string_lossy().to_string(),
+        _ => bail!("invalid entry `{}` in backup", archived.display()),
+    };
+    let rest = components.as_path();
+    if rest
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        bail!("invalid entry `{}` in backup", archived.display());
+    }
+
+    match entries.iter().find(|(entry_name, _)| *entry_name == name) {
+        Some((_, path)) if rest.as_os_str().is_empty() => Ok((name, PathBuf::from(path))),
+        Some((_, path)) => Ok((name, Path::new(path).join(rest))),
+        None => bail!("unknown entry `{}` in backup", archived.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn entries() -> Vec<(&'static str, String)> {
+        vec![
+            ("mihoro.toml", "/home/me/.config/mihoro.toml".to_owned()),
+            ("mihomo", "/home/me/.config/mihomo".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn rejects_entries_escaping_their_destination() {
+        assert!(restore_destination(Path::new("../mihoro.toml"), &entries()).is_err());
+        assert!(restore_destination(Path::new("mihomo/../../.bashrc"), &entries()).is_err());
+    }
+
+    #[test]
+    fn rejects_absolute_entries() {
+        assert!(restore_destination(Path::new("/etc/passwd"), &entries()).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_entries() {
+        let err = restore_destination(Path::new("ssh/id_ed25519"), &entries()).unwrap_err();
+        assert!(err.to_string().contains("unknown entry"));
+    }
+
+    #[test]
+    fn maps_entries_to_their_paths() {
+        let (name, destination) =
+            restore_destination(Path::new("mihomo/ui/index.html"), &entries()).unwrap();
+        assert_eq!(name, "mihomo");
+        assert_eq!(
+            destination,
+            PathBuf::from("/home/me/.config/mihomo/ui/index.html")
+        );
+    }
+
+    #[test]
+    fn restores_what_it_archived() {
+        let root = std::env::temp_dir().join(format!("mihoro-backup-{}", std::process::id()));
+        let config = root.join("mihoro.toml").to_string_lossy().into_owned();
+        let config_root = root.join("mihomo");
+        fs::create_dir_all(config_root.join("ui")).unwrap();
+        fs::write(&config, "remote_config_url = \"\"").unwrap();
+        fs::write(config_root.join("ui/index.html"), "<html>").unwrap();
+        let entries = vec![
+            ("mihoro.toml", config.clone()),
+            ("mihomo", config_root.to_string_lossy().into_owned()),
+            (
+                "missing",
+                root.join("missing").to_string_lossy().into_owned(),
+            ),
+        ];
+        let backup = root.join("backup.tar.gz").to_string_lossy().into_owned();
+
+        let archived = create_backup(&backup, &entries).unwrap();
+        assert_eq!(archived, ["mihoro.toml", "mihomo"]);
+        fs::remove_file(&config).unwrap();
+        fs::remove_dir_all(&config_root).unwrap();
+
+        let restored = restore_backup(&backup, &entries).unwrap();
+        assert_eq!(restored, ["mihoro.toml", "mihomo"]);
+        assert_eq!(
+            fs::read_to_string(&config).unwrap(),
+            "remote_config_url = \"\""
+        );
+        assert_eq!(
+            fs::read_to_string(config_root.join("ui/index.html")).unwrap(),
+            "<html>"
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
+use mihoro_core::config::{MihomoLogLevel, MihomoMode};
+use mihoro_core::cron::CronBackend;
+use mihoro_core::ui::Dashboard;
 
 #[derive(Parser)]
 #[command(author, about, version, arg_required_else_help(true))]
@@ -6,6 +9,12 @@ pub struct Args {
     /// Path to mihoro config file
     #[clap(short, long, default_value = "~/.config/mihoro.toml")]
     pub mihoro_config: String,
+    /// Name of the mihomo instance (profile) to manage, as defined under `instances`
+    #[clap(short, long, global = true, visible_alias = "profile")]
+    pub instance: Option<String>,
+    /// Manage mihomo on a remote machine over SSH, an alias under `hosts` or `user@server`
+    #[clap(long, global = true)]
+    pub host: Option<String>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -14,34 +23,229 @@ pub struct Args {
 pub enum Commands {
     /// Setup mihoro by downloading mihomo binary and remote config
     Setup,
-    /// Update mihomo remote config and restart mihomo.service
-    Update,
+    /// Update mihomo remote config, reload it or restart the core's service and update dashboard
+    Update {
+        /// Regenerate config from the last fetched remote config instead of downloading it
+        #[clap(long)]
+        offline: bool,
+        /// Print a unified diff of the config changes
+        #[clap(long)]
+        diff: bool,
+    },
     /// Update mihomo geodata
     UpdateGeodata,
-    /// Apply mihomo config overrides and restart mihomo.service
-    Apply,
-    /// Start mihomo.service with systemctl
+    /// Show what an update would change in the deployed config, without applying it
+    DiffRemote,
+    /// Show settings of running mihomo that differ from the deployed config, e.g. changed from a dashboard
+    DiffRuntime,
+    /// Apply mihomo config overrides and reload mihomo's config, or restart the core's service
+    Apply {
+        /// Restart the core's service instead of reloading through the controller, dropping connections
+        #[clap(long)]
+        restart: bool,
+    },
+    /// Watch mihoro config for changes and apply them automatically
+    #[cfg(target_os = "linux")]
+    Watch,
+    /// Serve an authenticated endpoint that triggers updates on `POST /update`
+    Serve,
+    /// Start the core's service
     Start,
-    /// Check mihomo.service status with systemctl
+    /// Check the core's service status
     Status,
-    /// Stop mihomo.service with systemctl
-    Stop,
-    /// Restart mihomo.service with systemctl
+    /// Stop the core's service
+    Stop {
+        /// Also revert gateway, LAN firewall and host DNS changes made by mihoro
+        #[clap(long)]
+        teardown: bool,
+    },
+    /// Restart the core's service
     Restart,
-    /// Check mihomo.service logs with journalctl
+    /// Run mihomo in the foreground without an init system, e.g. as a container's entrypoint
+    Run {
+        /// Fetch the remote config again every this many hours
+        #[clap(long, value_name = "HOURS", value_parser = clap::value_parser!(u64).range(1..))]
+        update_interval: Option<u64>,
+    },
+    /// Check the core's service logs, or from log file if configured
     #[clap(visible_alias("logs"))]
-    Log,
+    Log {
+        /// Stream logs from the controller API instead
+        #[clap(long)]
+        api: bool,
+        /// Lowest level of logs streamed from the controller API
+        #[clap(long, value_enum, requires = "api", default_value = "info")]
+        level: MihomoLogLevel,
+        /// Number of past lines to print
+        #[clap(short = 'n', long, default_value_t = 10, conflicts_with = "api")]
+        lines: usize,
+        /// Only print logs since this time, e.g. "1 hour ago" (journal only)
+        #[clap(long, conflicts_with = "api")]
+        since: Option<String>,
+        /// Only print lines matching this regex
+        #[clap(long, conflicts_with = "api")]
+        grep: Option<String>,
+        /// Print past lines and exit instead of following new ones
+        #[clap(long, conflicts_with = "api")]
+        no_follow: bool,
+    },
+    /// Rotate mihomo log file, or output a logrotate config for it
+    Logrotate {
+        /// Output logrotate config instead of rotating
+        #[clap(long)]
+        snippet: bool,
+    },
+    /// List proxy nodes and groups from mihomo config, without the running service
+    Nodes,
     /// Output proxy export commands
     Proxy {
         #[clap(subcommand)]
         proxy: Option<ProxyCommands>,
+        /// Also copy the output commands to the clipboard
+        #[clap(long, global = true)]
+        copy: bool,
+    },
+    /// Run a command with proxy environment variables set just for it
+    Exec {
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Verify installed binary, geodata and dashboard against checksums in mihoro.lock
+    Verify,
+    /// Compare versions of the installed binary, the running core and the latest release
+    Version,
+    /// Reinstall the core binary from `remote_mihomo_binary_url` and restart the core's service
+    Upgrade {
+        /// Have running mihomo upgrade itself to its latest release through the controller API
+        #[clap(long)]
+        via_api: bool,
+    },
+    /// Diagnose common setup problems
+    Doctor,
+    /// Probe service, controller and proxy silently, reporting health through the exit code
+    Healthcheck,
+    /// Manage firewall rules redirecting LAN traffic into mihomo
+    Gateway {
+        #[clap(subcommand)]
+        gateway: Option<GatewayCommands>,
+    },
+    /// Switch the selected node of a proxy group through the controller API
+    #[command(arg_required_else_help(true))]
+    Select {
+        /// Name of the proxy group
+        group: String,
+        /// Name of the node to select
+        #[clap(required_unless_present = "auto")]
+        node: Option<String>,
+        /// Test latency of all members and select the fastest one
+        #[clap(long, conflicts_with = "node")]
+        auto: bool,
+    },
+    /// Test latency of nodes through the controller API, sorted from fastest
+    Ping {
+        /// Proxy group to test all members of, or a single node, all nodes if omitted
+        target: Option<String>,
+        /// Run the delay test of this proxy group, making URLTest and Fallback groups re-evaluate
+        #[clap(long, conflicts_with = "target")]
+        group: Option<String>,
+    },
+    /// List active connections through the controller API
+    Connections {
+        #[clap(subcommand)]
+        connections: Option<ConnectionsCommands>,
+        /// Only show connections to hosts containing this pattern
+        #[clap(long)]
+        domain: Option<String>,
+        /// Only show connections from processes containing this pattern
+        #[clap(long)]
+        process: Option<String>,
+    },
+    /// Show live upload and download throughput through the controller API
+    Traffic,
+    /// Show a snapshot of the core's memory, CPU time and connections
+    Stats,
+    /// Switch proxy mode of running mihomo through the controller API, or show the current mode
+    Mode {
+        #[clap(value_enum)]
+        mode: Option<MihomoMode>,
+    },
+    /// Query mihomo's DNS resolver through the controller API
+    Dns {
+        #[clap(subcommand)]
+        dns: Option<DnsCommands>,
+    },
+    /// List proxy and rule providers through the controller API, or update them
+    Providers {
+        #[clap(subcommand)]
+        providers: Option<ProvidersCommands>,
+    },
+    /// List rules of running mihomo through the controller API, or test which one matches
+    Rules {
+        #[clap(subcommand)]
+        rules: Option<RulesCommands>,
+    },
+    /// Inspect and control running mihomo through its controller API
+    Ctl {
+        #[clap(subcommand)]
+        ctl: Option<CtlCommands>,
     },
+    /// Switch proxy groups according to schedule rules
+    Schedule {
+        #[clap(subcommand)]
+        schedule: Option<ScheduleCommands>,
+    },
+    /// Update mihomo config, geodata and binary periodically with cron or systemd timers
+    Cron {
+        #[clap(subcommand)]
+        cron: Option<CronCommands>,
+    },
+    /// Inspect the subscription at `remote_config_url`
+    Sub {
+        #[clap(subcommand)]
+        sub: Option<SubCommands>,
+    },
+    /// List config snapshots taken before updates, or roll back to one
+    Config {
+        #[clap(subcommand)]
+        config: Option<ConfigCommands>,
+    },
+    /// Open or close proxy ports in the host firewall for LAN clients
+    Lan {
+        #[clap(subcommand)]
+        lan: Option<LanCommands>,
+    },
+    /// Manage mihomo web dashboard
+    Ui {
+        #[clap(subcommand)]
+        ui: Option<UiCommands>,
+    },
+    /// Backup mihoro config, mihomo config, state, config history and dashboard to an archive
+    Backup {
+        /// Path of the `.tar.gz` archive to create
+        file: String,
+    },
+    /// Restore a backup archive and apply mihomo config overrides
+    Restore {
+        /// Path of the `.tar.gz` archive to restore
+        file: String,
+    },
+    /// Migrate an existing clashrup or Clash setup to mihoro
+    Migrate {
+        /// Overwrite existing mihoro config
+        #[clap(long)]
+        force: bool,
+    },
+    /// Prune config history and remove leftover downloads
+    Clean,
     /// Uninstall and remove mihoro and config
     Uninstall,
     /// Generate shell completions for mihoro
     Completions {
         #[clap(subcommand)]
         shell: Option<ClapShell>,
+        /// Install completions to the shell's conventional per-user location instead of printing
+        #[clap(long, global = true)]
+        install: bool,
     },
 }
 
@@ -54,6 +258,176 @@ pub enum ProxyCommands {
     ExportLan,
     /// Output and copy proxy unset shell commands
     Unset,
+    /// Check connectivity through proxy by requesting `test_url`
+    Test,
+    /// Output an ~/.ssh/config stanza sending SSH connections through proxy
+    Ssh {
+        /// Host pattern the stanza applies to
+        #[clap(default_value = "*")]
+        host: String,
+        /// Write the stanza to ~/.ssh/mihoro.conf for inclusion in ~/.ssh/config
+        #[clap(long)]
+        install: bool,
+    },
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum GatewayCommands {
+    /// Install firewall rules and kernel parameters redirecting LAN traffic into mihomo
+    Enable,
+    /// Remove firewall rules and kernel parameters installed by `gateway enable`
+    Disable,
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum CtlCommands {
+    /// Show each proxy group with its selected node and latency
+    Groups,
+    /// Test delay of all nodes and record results in quality history
+    Probe,
+    /// List proxy and rule providers with their health and last update
+    Providers,
+    /// Show recorded delay tests and quality score of a node
+    History {
+        /// Name of the node
+        node: String,
+    },
+    /// Close active connections, e.g. to re-establish them over a newly selected node
+    #[command(arg_required_else_help(true))]
+    Kill {
+        /// Close connections to hosts containing this pattern
+        #[clap(conflicts_with = "all")]
+        pattern: Option<String>,
+        /// Close all connections
+        #[clap(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum DnsCommands {
+    /// Resolve a domain with mihomo's resolver, marking fake-ip answers
+    Query {
+        domain: String,
+        /// Record type to query, e.g. A or AAAA
+        #[clap(long = "type", default_value = "A")]
+        record_type: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConnectionsCommands {
+    /// Close connections, e.g. stale or misrouted ones after switching nodes
+    #[command(group(ArgGroup::new("target").required(true).args(["id", "domain", "all"])))]
+    Close {
+        /// Close the connection with this ID
+        #[clap(long)]
+        id: Option<String>,
+        /// Close connections to hosts containing this pattern
+        #[clap(long)]
+        domain: Option<String>,
+        /// Close all connections
+        #[clap(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProvidersCommands {
+    /// Re-fetch providers from their source and health check proxy providers
+    Update {
+        /// Name of the provider to update, all providers if omitted
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RulesCommands {
+    /// Show which rule a destination matches and the proxy it goes through
+    #[command(arg_required_else_help(true))]
+    Match {
+        /// Domain or IP of the destination
+        destination: String,
+    },
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum ScheduleCommands {
+    /// Apply the schedule rule active now, once per time window
+    Apply,
+    /// Install a systemd timer applying schedule rules every 5 minutes
+    Install,
+    /// Remove the systemd timer installed by `schedule install`
+    Uninstall,
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum ConfigCommands {
+    /// List config snapshots kept under `backups/` next to the core config, newest first
+    History,
+    /// Restore a config snapshot and restart the core's service
+    Rollback {
+        /// Number of the snapshot as listed by `config history`, the newest if omitted
+        snapshot: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum SubCommands {
+    /// Show remaining traffic and expiry reported by the provider on the last update
+    Info,
+    /// Download the subscription and list its nodes, without applying it
+    Preview,
+    /// List recent config updates with their status, size and node count
+    History,
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum CronCommands {
+    /// Update config, geodata and binary periodically, each every `*_update_interval` hours
+    Enable {
+        /// Scheduler running updates, `systemd-timer` for systems without a cron daemon
+        #[clap(long, value_enum, default_value = "crontab")]
+        backend: CronBackend,
+        /// Hours between config updates, overriding `config_update_interval`
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        interval: Option<u32>,
+    },
+    /// Stop scheduled updates
+    Disable,
+    /// Show how updates are scheduled and when the timer runs next
+    Status,
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum LanCommands {
+    /// Allow proxy ports in firewalld or ufw
+    Open,
+    /// Remove firewall rules added by `lan open`
+    Close,
+}
+
+#[derive(Subcommand)]
+#[command(arg_required_else_help(true))]
+pub enum UiCommands {
+    /// Download and install a web dashboard to `external_ui`
+    Install {
+        /// Web dashboard to install
+        #[clap(value_enum)]
+        dashboard: Dashboard,
+    },
+    /// Update the installed web dashboard to its latest release
+    Update,
+    /// Open the web dashboard in browser
+    Open,
 }
 
 #[derive(Subcommand)]
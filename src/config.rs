@@ -1,20 +1,423 @@
-use crate::utils::create_parent_dir;
-
-use std::{collections::HashMap, fs, path::Path};
-
-use anyhow::{bail, Result};
+use crate::dirs::DirsConfig;
+use crate::filter::{filter_nodes, FilteredNodes};
+use crate::logfile::LogFileConfig;
+use crate::rename::{rename_nodes, NodeRename};
+use crate::resolver::HostDns;
+use crate::rules::{merge_rules, MergedRules};
+use crate::schedule::ScheduleRule;
+use crate::serve::ServeConfig;
+use crate::service::systemd::SystemdConfig;
+use crate::utils::{create_parent_dir, is_root, write_file_atomic};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, iter,
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::ValueEnum;
 use colored::Colorize;
+use regex::{Captures, Regex};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, USER_AGENT};
 use serde::{Deserialize, Serialize};
 
 /// `mihoro` configurations.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
+    /// Proxy core to manage, `mihomo` fields below refer to this core.
+    #[serde(default)]
+    pub core: Core,
     pub remote_mihomo_binary_url: String,
+    #[serde(default)]
     pub remote_config_url: String,
+
+    /// Mirrors tried in order if downloading from `remote_config_url` fails, or the list of URLs
+    /// on its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote_config_urls: Vec<String>,
+
+    /// Format the remote config is served in, converted to mihomo's if needed.
+    #[serde(default)]
+    pub remote_config_format: RemoteConfigFormat,
+
+    /// Authorization sent when fetching `remote_config_url`, instead of a token in the URL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_config_auth: Option<RemoteConfigAuth>,
+
+    /// Additional subscriptions whose nodes are merged into the remote config, each refreshed
+    /// every `update_interval` hours (e.g. `[[subscriptions]]`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<Subscription>,
+
+    /// Request settings of the config, binary and geodata downloads, e.g. `[downloads.config]`.
+    #[serde(default, skip_serializing_if = "Downloads::is_empty")]
+    pub downloads: Downloads,
+
     pub mihomo_binary_path: String,
     pub mihomo_config_root: String,
     pub user_systemd_root: String,
+
+    /// Name of the core's service, e.g. `mihomo-home` to run `mihomo-home.service` alongside a
+    /// distribution's own `mihomo.service`.
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Init system running the core as a service, detected from the running system if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_manager: Option<InitSystem>,
+
+    /// Sandbox the generated systemd unit, e.g. with `ProtectSystem=` and a capability bounding set.
+    #[serde(default)]
+    pub systemd_hardening: bool,
+
+    /// Filename of the generated core config under `mihomo_config_root`, defaulting to the core's
+    /// own (`config.yaml` for mihomo). If set, mihomo is run with `-f` pointing at it, so that it can
+    /// share its config root with other files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mihomo_config_name: Option<String>,
+
+    /// Directories for mihoro's own data, cache and state, defaulting to XDG base directories.
+    #[serde(flatten)]
+    pub dirs: DirsConfig,
+
     pub mihomo_config: MihomoConfig,
+
+    /// Named mihomo instances, each overriding any of the fields above (e.g. `[instances.work]`),
+    /// also accepted as `[profiles.work]`.
+    #[serde(default, alias = "profiles", skip_serializing_if = "HashMap::is_empty")]
+    pub instances: HashMap<String, toml::Table>,
+
+    /// Aliases of remote machines managed over SSH, mapped to SSH destinations (`user@server`).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub hosts: HashMap<String, String>,
+
+    /// Hours between config updates scheduled by `mihoro cron enable`.
+    #[serde(default = "default_config_update_interval")]
+    pub config_update_interval: u32,
+
+    /// Hours between geodata updates scheduled by `mihoro cron enable`, not scheduled if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geodata_update_interval: Option<u32>,
+
+    /// Hours between core binary upgrades scheduled by `mihoro cron enable`, not scheduled if
+    /// unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_update_interval: Option<u32>,
+
+    /// Number of previous core configs kept as snapshots on update.
+    #[serde(default = "default_history_keep")]
+    pub history_keep: usize,
+
+    /// Remove config snapshots older than this many days, regardless of `history_keep`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_max_age_days: Option<u64>,
+
+    /// URL requested through mihomo to check connectivity, e.g. by `proxy test` and `doctor`.
+    #[serde(default = "default_test_url")]
+    pub test_url: String,
+
+    /// Seconds to wait for mihomo's controller to respond after `update` and `apply` restart it, 0
+    /// to not wait.
+    #[serde(default = "default_health_check_timeout")]
+    pub health_check_timeout: u64,
+
+    /// Check once a day whether a newer mihoro release is available.
+    #[serde(default = "default_check_release")]
+    pub check_release: bool,
+
+    /// Show a desktop notification with `notify-send` after updates, summarizing what changed.
+    #[serde(default)]
+    pub notify: bool,
+
+    /// Time windows switching proxy groups to given nodes, applied by `mihoro schedule apply`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduleRule>,
+
+    /// Log mihomo's output to a file under its config root instead of the journal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<LogFileConfig>,
+
+    /// Opt-in endpoint for triggering updates with `mihoro serve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serve: Option<ServeConfig>,
+
+    /// Point the host's resolver to mihomo's DNS server while running with TUN and `dns-hijack`,
+    /// with `resolved` (a systemd-resolved drop-in) or `resolv-conf` (replacing /etc/resolv.conf).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_dns: Option<HostDns>,
+
+    /// Extra directives for the generated systemd unit, e.g. `Environment=` or `After=`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub systemd: Option<SystemdConfig>,
+}
+
+/// Proxy core managed by mihoro.
+///
+/// With `sing-box`, the remote config is expected in sing-box's JSON format and used as is, as
+/// `mihomo_config` overrides only apply to mihomo's config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Core {
+    #[default]
+    #[serde(rename = "mihomo")]
+    Mihomo,
+    #[serde(rename = "sing-box")]
+    SingBox,
+}
+
+/// A subscription listed under `[[subscriptions]]`, its nodes merged into the remote config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    /// Name of the subscription, also naming the proxy group selecting its nodes.
+    pub name: String,
+    /// URL of the subscription, in any of the formats `remote_config_url` accepts.
+    pub url: String,
+    /// Hours its cached copy is used before it is downloaded again, `config_update_interval` if
+    /// unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_interval: Option<u32>,
+}
+
+/// Format of the remote config, selected with `remote_config_format`.
+///
+/// With `sing-box`, the proxy outbounds of a sing-box JSON config are converted to mihomo proxies
+/// when `core` is mihomo. Share links and JSON variants of mihomo's format are detected either way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RemoteConfigFormat {
+    #[default]
+    #[serde(rename = "mihomo", alias = "clash")]
+    Mihomo,
+    #[serde(rename = "sing-box")]
+    SingBox,
+}
+
+/// Init system running the core as a service, selected with `service_manager`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InitSystem {
+    /// A unit under `user_systemd_root`, managed with `systemctl`.
+    #[default]
+    Systemd,
+    /// A service directory under `~/service` (or `/etc/sv` as root), managed with `sv`.
+    Runit,
+    /// An s6-rc service under `/etc/s6/sv` as root, or a service directory under `~/service`
+    /// supervised by the user's `s6-svscan`.
+    S6,
+    /// An LSB init script under `/etc/init.d`, managed with `service` and `update-rc.d`.
+    SysV,
+    /// A launch agent under `~/Library/LaunchAgents` on macOS, managed with `launchctl`.
+    Launchd,
+    /// An OpenRC service script under `/etc/init.d`, managed with `rc-service` and `rc-update`.
+    OpenRc,
+}
+
+impl Core {
+    /// Name of the core's binary, also used to find it inside release archives.
+    pub fn binary_name(&self) -> &'static str {
+        match self {
+            Core::Mihomo => "mihomo",
+            Core::SingBox => "sing-box",
+        }
+    }
+
+    /// GitHub repository the core is released from.
+    pub fn repo(&self) -> &'static str {
+        match self {
+            Core::Mihomo => "MetaCubeX/mihomo",
+            Core::SingBox => "SagerNet/sing-box",
+        }
+    }
+
+    /// Arguments making the core's binary print its version.
+    pub fn version_args(&self) -> &'static [&'static str] {
+        match self {
+            Core::Mihomo => &["-v"],
+            Core::SingBox => &["version"],
+        }
+    }
+
+    /// Arguments making the core's binary check the config at `config_path` without running it,
+    /// resolving files it refers to against `config_root`.
+    pub fn check_args<'a>(&self, config_root: &'a str, config_path: &'a str) -> Vec<&'a str> {
+        match self {
+            Core::Mihomo => vec!["-t", "-d", config_root, "-f", config_path],
+            Core::SingBox => vec!["check", "-D", config_root, "-c", config_path],
+        }
+    }
+
+    /// Filename of the core's config under the config root.
+    pub fn config_filename(&self) -> &'static str {
+        match self {
+            Core::Mihomo => "config.yaml",
+            Core::SingBox => "config.json",
+        }
+    }
+
+    /// Command line running the core with config from `config_root`, read from `config_name`
+    /// under it instead of the core's default config filename if given.
+    pub fn exec_start(
+        &self,
+        binary_path: &str,
+        config_root: &str,
+        config_name: Option<&str>,
+    ) -> String {
+        match (self, config_name) {
+            (Core::Mihomo, None) => format!("{binary_path} -d {config_root}"),
+            (Core::Mihomo, Some(name)) => {
+                format!("{binary_path} -d {config_root} -f {config_root}/{name}")
+            }
+            (Core::SingBox, name) => format!(
+                "{binary_path} run -D {config_root} -c {config_root}/{}",
+                name.unwrap_or(self.config_filename())
+            ),
+        }
+    }
+}
+
+impl InitSystem {
+    /// Name of the init system as set with `service_manager`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InitSystem::Systemd => "systemd",
+            InitSystem::Runit => "runit",
+            InitSystem::S6 => "s6",
+            InitSystem::SysV => "sysv",
+            InitSystem::Launchd => "launchd",
+            InitSystem::OpenRc => "openrc",
+        }
+    }
+}
+
+/// Request settings of each of mihoro's downloads.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Downloads {
+    /// The remote config at `remote_config_url`.
+    #[serde(default, skip_serializing_if = "DownloadConfig::is_empty")]
+    pub config: DownloadConfig,
+    /// The core binary at `remote_mihomo_binary_url`.
+    #[serde(default, skip_serializing_if = "DownloadConfig::is_empty")]
+    pub binary: DownloadConfig,
+    /// Geodata files at `geox_url`.
+    #[serde(default, skip_serializing_if = "DownloadConfig::is_empty")]
+    pub geodata: DownloadConfig,
+}
+
+impl Downloads {
+    fn is_empty(&self) -> bool {
+        *self == Downloads::default()
+    }
+}
+
+/// Request settings of a download, e.g. providers serving mihomo configs only to mihomo's own
+/// user agent:
+///
+/// ```toml
+/// [downloads.config]
+/// user_agent = "clash.meta"
+/// headers = { "X-Device" = "router" }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadConfig {
+    /// `User-Agent` header sent instead of none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Extra request headers.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// `Authorization` header, as in `remote_config_auth`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RemoteConfigAuth>,
+}
+
+impl DownloadConfig {
+    fn is_empty(&self) -> bool {
+        *self == DownloadConfig::default()
+    }
+
+    /// Request headers of the download.
+    pub fn request_headers(&self) -> Result<HeaderMap> {
+        let mut request_headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name `{name}` in `downloads`"))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value of header `{name}` in `downloads`"))?;
+            request_headers.insert(name, value);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            request_headers.insert(USER_AGENT, HeaderValue::from_str(user_agent)?);
+        }
+        if let Some(auth) = &self.auth {
+            request_headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth.header_value()?)?);
+        }
+        Ok(request_headers)
+    }
+}
+
+/// Authorization for the remote config request, e.g.
+/// `remote_config_auth = { type = "bearer", token_env = "SUB_TOKEN" }`.
+///
+/// Secrets are given inline or, preferably, read from the environment variable named by `*_env`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RemoteConfigAuth {
+    Bearer {
+        token: Option<String>,
+        token_env: Option<String>,
+    },
+    Basic {
+        username: String,
+        password: Option<String>,
+        password_env: Option<String>,
+    },
+}
+
+impl RemoteConfigAuth {
+    /// Value of the `Authorization` header.
+    pub fn header_value(&self) -> Result<String> {
+        match self {
+            RemoteConfigAuth::Bearer { token, token_env } => {
+                let token = resolve_secret("token", token, token_env)?;
+                Ok(format!("Bearer {token}"))
+            }
+            RemoteConfigAuth::Basic {
+                username,
+                password,
+                password_env,
+            } => {
+                let password = resolve_secret("password", password, password_env)?;
+                let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+                Ok(format!("Basic {credentials}"))
+            }
+        }
+    }
+}
+
+/// `template` with `{env:NAME}` placeholders replaced by the value of environment variable `NAME`,
+/// e.g. to keep a subscription token out of `mihoro.toml`.
+pub fn expand_env_placeholders(template: &str) -> Result<String> {
+    let placeholder = Regex::new(r"\{env:([A-Za-z_][A-Za-z0-9_]*)\}")?;
+    for captures in placeholder.captures_iter(template) {
+        let name = &captures[1];
+        if std::env::var_os(name).is_none() {
+            bail!("'{template}' refers to unset environment variable `{name}`");
+        }
+    }
+    let expanded = placeholder.replace_all(template, |captures: &Captures| {
+        std::env::var(&captures[1]).unwrap_or_default()
+    });
+    Ok(expanded.into_owned())
+}
+
+fn resolve_secret(field: &str, value: &Option<String>, env: &Option<String>) -> Result<String> {
+    match (value, env) {
+        (_, Some(env)) => std::env::var(env).with_context(|| {
+            format!("`{field}_env` of download authorization refers to unset variable `{env}`")
+        }),
+        (Some(value), None) => Ok(value.clone()),
+        (None, None) => bail!("download authorization requires `{field}` or `{field}_env`"),
+    }
 }
 
 /// `mihomo` configurations (partial).
@@ -25,21 +428,44 @@ pub struct MihomoConfig {
     pub port: u16,
     pub socks_port: u16,
     pub mixed_port: Option<u16>,
+    pub tproxy_port: Option<u16>,
+    pub redir_port: Option<u16>,
     pub allow_lan: Option<bool>,
     pub bind_address: Option<String>,
-    mode: MihomoMode,
-    log_level: MihomoLogLevel,
-    ipv6: Option<bool>,
-    external_controller: Option<String>,
-    external_ui: Option<String>,
-    secret: Option<String>,
+    pub mode: MihomoMode,
+    pub log_level: MihomoLogLevel,
+    pub ipv6: Option<bool>,
+    pub external_controller: Option<String>,
+    pub external_ui: Option<String>,
+    pub secret: Option<String>,
     pub geodata_mode: Option<bool>,
     pub geo_auto_update: Option<bool>,
     pub geo_update_interval: Option<u16>,
     pub geox_url: Option<GeoxUrl>,
+
+    /// Rules inserted before the remote config's `rules`, taking precedence over them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prepend_rules: Vec<String>,
+
+    /// Regexes of node names to keep from the remote config's `proxies`, all nodes if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_include: Vec<String>,
+
+    /// Regexes of node names to drop from the remote config's `proxies`, e.g. `过期|剩余流量`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_exclude: Vec<String>,
+
+    /// Rules renaming the remote config's nodes, applied in order after filtering.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_rename: Vec<NodeRename>,
+
+    /// Keys written under `experimental:` as is (e.g. `quic-go-disable-gso`), merged into the
+    /// remote config's own experimental settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub experimental: Option<toml::Table>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone)]
 pub enum MihomoMode {
     #[serde(alias = "global", rename(serialize = "global"))]
     Global,
@@ -49,7 +475,7 @@ pub enum MihomoMode {
     Direct,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone)]
 pub enum MihomoLogLevel {
     #[serde(alias = "silent", rename(serialize = "silent"))]
     Silent,
@@ -70,20 +496,80 @@ pub struct GeoxUrl {
     pub mmdb: String,
 }
 
+fn default_service_name() -> String {
+    String::from("mihomo")
+}
+
+fn default_config_update_interval() -> u32 {
+    24
+}
+
+fn default_history_keep() -> usize {
+    10
+}
+
+fn default_check_release() -> bool {
+    true
+}
+
+fn default_health_check_timeout() -> u64 {
+    10
+}
+
+fn default_test_url() -> String {
+    String::from("https://www.gstatic.com/generate_204")
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Config {
+    /// Default `mihoro` config, written to `mihoro.toml` on first run.
+    ///
+    /// When running as root, mihomo defaults to system-wide paths and runs as a system service, as
+    /// root rarely has the login session that user services depend on.
     pub fn new() -> Config {
+        let (binary_path, config_root, systemd_root) = if is_root() {
+            (
+                "/usr/local/bin/mihomo",
+                "/etc/mihomo",
+                "/etc/systemd/system",
+            )
+        } else {
+            (
+                "~/.local/bin/mihomo",
+                "~/.config/mihomo",
+                "~/.config/systemd/user",
+            )
+        };
         Config {
+            core: Core::Mihomo,
             remote_mihomo_binary_url: String::from(""),
             remote_config_url: String::from(""),
-            mihomo_binary_path: String::from("~/.local/bin/mihomo"),
-            mihomo_config_root: String::from("~/.config/mihomo"),
-            user_systemd_root: String::from("~/.config/systemd/user"),
+            remote_config_urls: Vec::new(),
+            remote_config_format: RemoteConfigFormat::Mihomo,
+            remote_config_auth: None,
+            subscriptions: Vec::new(),
+            downloads: Downloads::default(),
+            mihomo_binary_path: String::from(binary_path),
+            mihomo_config_root: String::from(config_root),
+            user_systemd_root: String::from(systemd_root),
+            service_name: default_service_name(),
+            service_manager: None,
+            systemd_hardening: false,
+            mihomo_config_name: None,
+            dirs: DirsConfig::default(),
 
             // https://wiki.metacubex.one/config/general
             mihomo_config: MihomoConfig {
                 port: 7891,
                 socks_port: 7892,
                 mixed_port: Some(7890),
+                tproxy_port: None,
+                redir_port: None,
                 allow_lan: Some(false),
                 bind_address: Some(String::from("*")),
                 mode: MihomoMode::Rule,
@@ -106,8 +592,48 @@ impl Config {
                         "https://cdn.jsdelivr.net/gh/MetaCubeX/meta-rules-dat@release/country.mmdb",
                     ),
                 }),
+                prepend_rules: Vec::new(),
+                node_include: Vec::new(),
+                node_exclude: Vec::new(),
+                node_rename: Vec::new(),
+                experimental: None,
             },
+            instances: HashMap::new(),
+            hosts: HashMap::new(),
+            config_update_interval: default_config_update_interval(),
+            geodata_update_interval: Some(7 * 24),
+            binary_update_interval: None,
+            history_keep: default_history_keep(),
+            history_max_age_days: None,
+            test_url: default_test_url(),
+            health_check_timeout: default_health_check_timeout(),
+            check_release: true,
+            notify: false,
+            schedule: Vec::new(),
+            log_file: None,
+            serve: None,
+            host_dns: None,
+            systemd: None,
+        }
+    }
+
+    /// URLs the remote config is downloaded from, in the order they are tried: `remote_config_url`
+    /// followed by `remote_config_urls`.
+    pub fn config_urls(&self) -> Vec<&str> {
+        let mut urls: Vec<&str> = Vec::new();
+        for url in iter::once(&self.remote_config_url).chain(&self.remote_config_urls) {
+            if !url.is_empty() && !urls.contains(&url.as_str()) {
+                urls.push(url);
+            }
         }
+        urls
+    }
+
+    /// Filename of the generated core config under `mihomo_config_root`.
+    pub fn config_filename(&self) -> &str {
+        self.mihomo_config_name
+            .as_deref()
+            .unwrap_or(self.core.config_filename())
     }
 
     /// Read raw config string from path and parse with crate toml.
@@ -117,6 +643,31 @@ impl Config {
         Ok(config)
     }
 
+    /// Derive the config of named instance `name` by merging its table over this config.
+    ///
+    /// Instances run side by side, so unless overridden, an instance gets its own config root at
+    /// `<mihomo_config_root>-<name>` and its own service named `<service_name>@<name>`, an
+    /// instance of the `<service_name>@` template under systemd.
+    pub fn instance(&self, name: &str) -> Result<Config> {
+        let Some(instance) = self.instances.get(name) else {
+            bail!("instance `{}` undefined in `instances`", name)
+        };
+
+        let mut base = toml::Table::try_from(self)?;
+        base.remove("instances");
+        base.insert(
+            String::from("mihomo_config_root"),
+            toml::Value::from(format!("{}-{}", self.mihomo_config_root, name)),
+        );
+        base.insert(
+            String::from("service_name"),
+            toml::Value::from(format!("{}@{}", self.service_name, name)),
+        );
+        merge_toml_table(&mut base, instance);
+        Ok(toml::Value::Table(base).try_into()?)
+    }
+
+    /// Serialize config as toml and write to path.
     pub fn write(&mut self, path: &Path) -> Result<()> {
         let serialized_config = toml::to_string(&self)?;
         fs::write(path, serialized_config)?;
@@ -124,6 +675,20 @@ impl Config {
     }
 }
 
+/// Recursively merge toml table `overrides` into `base`, with values in `overrides` taking precedence.
+fn merge_toml_table(base: &mut toml::Table, overrides: &toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(override_table)) => {
+                merge_toml_table(base_table, override_table)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
 /// Tries to parse mihoro config as toml from path.
 ///
 /// * If config file does not exist, creates default config file to path and returns error.
@@ -144,8 +709,10 @@ pub fn parse_config(path: &str) -> Result<Config> {
 
     // Parse config file
     let config = Config::setup_from(path)?;
+    if config.config_urls().is_empty() {
+        bail!("`remote_config_url` undefined");
+    }
     let required_urls = [
-        ("remote_config_url", &config.remote_config_url),
         ("mihomo_binary_path", &config.mihomo_binary_path),
         ("mihomo_config_root", &config.mihomo_config_root),
         ("user_systemd_root", &config.user_systemd_root),
@@ -157,10 +724,22 @@ pub fn parse_config(path: &str) -> Result<Config> {
             bail!("`{}` undefined", field)
         }
     }
+    for rule in &config.schedule {
+        rule.validate()?;
+    }
 
     Ok(config)
 }
 
+/// Outcome of [`apply_mihomo_override`].
+#[derive(Debug, Clone, Default)]
+pub struct AppliedOverride {
+    pub rules: MergedRules,
+    pub nodes: FilteredNodes,
+    /// Number of nodes renamed by `node_rename`.
+    pub renamed: usize,
+}
+
 /// `mihomoYamlConfig` is defined to support serde serialization and deserialization of arbitrary
 /// mihomo `config.yaml`, with support for fields defined in `mihomoConfig` for overrides and also
 /// extra fields that are not managed by `mihoro` by design (namely `proxies`, `proxy-groups`,
@@ -175,6 +754,12 @@ pub struct MihomoYamlConfig {
     #[serde(rename = "mixed-port", skip_serializing_if = "Option::is_none")]
     mixed_port: Option<u16>,
 
+    #[serde(rename = "tproxy-port", skip_serializing_if = "Option::is_none")]
+    tproxy_port: Option<u16>,
+
+    #[serde(rename = "redir-port", skip_serializing_if = "Option::is_none")]
+    redir_port: Option<u16>,
+
     #[serde(rename = "allow-lan", skip_serializing_if = "Option::is_none")]
     allow_lan: Option<bool>,
 
@@ -228,7 +813,16 @@ pub struct MihomoYamlConfig {
 /// * Fields defined in `mihoro.toml` will override the downloaded remote `config.yaml`.
 /// * Fields undefined will be removed from the downloaded `config.yaml`.
 /// * Fields not supported by `mihoro` will be kept as is.
-pub fn apply_mihomo_override(path: &str, override_config: &MihomoConfig) -> Result<()> {
+/// * `experimental` keys are merged into `experimental`, keeping the remote config's other keys.
+/// * `prepend_rules` are merged in front of `rules`, dropping duplicates.
+/// * Nodes filtered out by `node_include` and `node_exclude` are removed from `proxies` and the
+///   proxy groups, then `node_rename` renames the remaining ones everywhere they are referenced.
+///
+/// Returns the outcome of merging rules, filtering and renaming nodes for reporting.
+pub fn apply_mihomo_override(
+    path: &str,
+    override_config: &MihomoConfig,
+) -> Result<AppliedOverride> {
     let raw_mihomo_yaml = fs::read_to_string(path)?;
     let mut mihomo_yaml: MihomoYamlConfig = serde_yaml::from_str(&raw_mihomo_yaml)?;
 
@@ -236,6 +830,8 @@ pub fn apply_mihomo_override(path: &str, override_config: &MihomoConfig) -> Resu
     mihomo_yaml.port = Some(override_config.port);
     mihomo_yaml.socks_port = Some(override_config.socks_port);
     mihomo_yaml.mixed_port = override_config.mixed_port;
+    mihomo_yaml.tproxy_port = override_config.tproxy_port;
+    mihomo_yaml.redir_port = override_config.redir_port;
     mihomo_yaml.allow_lan = override_config.allow_lan;
     mihomo_yaml.bind_address = override_config.bind_address.clone();
     mihomo_yaml.mode = Some(override_config.mode.clone());
@@ -249,8 +845,65 @@ pub fn apply_mihomo_override(path: &str, override_config: &MihomoConfig) -> Resu
     mihomo_yaml.geo_update_interval = override_config.geo_update_interval;
     mihomo_yaml.geox_url = override_config.geox_url.clone();
 
+    // Merge experimental settings into the remote config's
+    if let Some(experimental) = &override_config.experimental {
+        let mut merged = match mihomo_yaml.extra.remove("experimental") {
+            Some(serde_yaml::Value::Mapping(mapping)) => mapping,
+            _ => serde_yaml::Mapping::new(),
+        };
+        for (key, value) in experimental {
+            merged.insert(key.as_str().into(), serde_yaml::to_value(value)?);
+        }
+        mihomo_yaml
+            .extra
+            .insert(String::from("experimental"), merged.into());
+    }
+
+    // Merge prepended rules, already present ones are dropped as duplicates when applied again
+    let rules: Vec<String> = match mihomo_yaml.extra.get("rules") {
+        Some(rules) => serde_yaml::from_value(rules.clone())
+            .with_context(|| "`rules` in mihomo config is not a list of rules")?,
+        None => Vec::new(),
+    };
+    let merged = merge_rules(&override_config.prepend_rules, &rules);
+    if !merged.rules.is_empty() {
+        mihomo_yaml
+            .extra
+            .insert(String::from("rules"), serde_yaml::to_value(&merged.rules)?);
+    }
+
+    // Filter and rename nodes, along with their references in proxy groups and rules
+    let mut groups = mihomo_yaml.extra.remove("proxy-groups");
+    let mut rules = mihomo_yaml.extra.remove("rules");
+    let (filtered, renamed) = match mihomo_yaml.extra.get_mut("proxies") {
+        Some(proxies) => (
+            filter_nodes(
+                proxies,
+                groups.as_mut(),
+                &override_config.node_include,
+                &override_config.node_exclude,
+            )?,
+            rename_nodes(
+                proxies,
+                groups.as_mut(),
+                rules.as_mut(),
+                &override_config.node_rename,
+            )?,
+        ),
+        None => (FilteredNodes::default(), 0),
+    };
+    for (key, value) in [("proxy-groups", groups), ("rules", rules)] {
+        if let Some(value) = value {
+            mihomo_yaml.extra.insert(String::from(key), value);
+        }
+    }
+
     // Write to file
     let serialized_mihomo_yaml = serde_yaml::to_string(&mihomo_yaml)?;
-    fs::write(path, serialized_mihomo_yaml)?;
-    Ok(())
+    write_file_atomic(path, serialized_mihomo_yaml)?;
+    Ok(AppliedOverride {
+        rules: merged,
+        nodes: filtered,
+        renamed,
+    })
 }
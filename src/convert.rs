@@ -0,0 +1,992 @@
+use crate::utils::write_file_atomic;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+use serde_yaml::{Mapping, Value};
+
+/// URI schemes of share links converted to mihomo proxies.
+const SHARE_LINK_SCHEMES: [&str; 4] = ["ss://", "vmess://", "trojan://", "vless://"];
+
+/// sing-box outbound types that are not proxies, left out when converting sing-box configs.
+const SING_BOX_NON_PROXY_TYPES: [&str; 5] = ["direct", "block", "dns", "selector", "urltest"];
+
+/// Selector group of the config generated from share links, routing all traffic.
+pub const SELECT_GROUP: &str = "Proxy";
+
+/// `url-test` group of the config generated from share links, picking the fastest proxy.
+pub const AUTO_GROUP: &str = "Auto";
+
+/// Outcome of converting a share-link subscription.
+#[derive(Debug, Clone, Default)]
+pub struct Conversion {
+    /// Number of proxies converted.
+    pub converted: usize,
+    /// Links that could not be converted, with the reason.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Convert the share-link subscription at `path` in place to a mihomo config, if it is one.
+///
+/// Subscriptions list one link per line, often base64 encoded as a whole. The generated config
+/// routes all traffic through a selector group defaulting to the fastest proxy, as measured by
+/// requesting `test_url`. Returns `None` if `path` holds anything but share links.
+pub fn try_convert_share_links_file_inplace(
+    path: &str,
+    test_url: &str,
+) -> Result<Option<Conversion>> {
+    let raw = fs::read_to_string(path).unwrap_or_default();
+    let links = match is_share_links(&raw) {
+        true => raw,
+        false => match decode_base64(&raw).filter(|decoded| is_share_links(decoded)) {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        },
+    };
+
+    let mut conversion = Conversion::default();
+    let mut names = HashSet::new();
+    let mut proxies = Vec::new();
+    for link in links.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match parse_share_link(link) {
+            Ok(mut proxy) => {
+                let name = unique_name(&proxy_name(&proxy), &mut names);
+                proxy.insert(Value::from("name"), Value::from(name));
+                proxies.push(Value::Mapping(proxy));
+            }
+            Err(err) => conversion.skipped.push((link.to_owned(), err.to_string())),
+        }
+    }
+    if proxies.is_empty() {
+        bail!("none of the share links in the subscription could be converted");
+    }
+    conversion.converted = proxies.len();
+    write_file_atomic(
+        path,
+        serde_yaml::to_string(&selector_config(proxies, test_url))?,
+    )?;
+    Ok(Some(conversion))
+}
+
+/// Convert the sing-box config at `path` in place to a mihomo config, mapping its shadowsocks,
+/// VMess, trojan, Hysteria2 and TUIC outbounds to proxies.
+///
+/// sing-box's routing does not translate to mihomo rules, so the generated config routes all
+/// traffic through a selector group defaulting to the fastest proxy as for share links.
+/// Outbounds that are not proxies (`direct`, `selector`, ...) are left out silently.
+///
+/// Reference: https://sing-box.sagernet.org/configuration/outbound/
+pub fn convert_sing_box_file_inplace(path: &str, test_url: &str) -> Result<Conversion> {
+    let raw = fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+    let config: serde_json::Value = serde_json::from_str(&raw)
+        .with_context(|| "remote config is not a sing-box JSON config")?;
+    let outbounds = config["outbounds"]
+        .as_array()
+        .with_context(|| "no `outbounds` in sing-box config")?;
+
+    let mut conversion = Conversion::default();
+    let mut names = HashSet::new();
+    let mut proxies = Vec::new();
+    for outbound in outbounds {
+        let tag = outbound["tag"].as_str().unwrap_or_default();
+        let outbound_type = outbound["type"].as_str().unwrap_or_default();
+        if SING_BOX_NON_PROXY_TYPES.contains(&outbound_type) {
+            continue;
+        }
+        match parse_sing_box_outbound(outbound) {
+            Ok(mut proxy) => {
+                let name = unique_name(&proxy_name(&proxy), &mut names);
+                proxy.insert(Value::from("name"), Value::from(name));
+                proxies.push(Value::Mapping(proxy));
+            }
+            Err(err) => conversion.skipped.push((tag.to_owned(), err.to_string())),
+        }
+    }
+    if proxies.is_empty() {
+        bail!("none of the outbounds in the sing-box config could be converted");
+    }
+    conversion.converted = proxies.len();
+    write_file_atomic(
+        path,
+        serde_yaml::to_string(&selector_config(proxies, test_url))?,
+    )?;
+    Ok(conversion)
+}
+
+/// Config routing all traffic through `proxies` with a selector group, defaulting to a
+/// `url-test` group picking the fastest of them by requesting `test_url`.
+fn selector_config(proxies: Vec<Value>, test_url: &str) -> Mapping {
+    let names: Vec<Value> = proxies
+        .iter()
+        .filter_map(|proxy| proxy.get("name").cloned())
+        .collect();
+    let mut select = vec![Value::from(AUTO_GROUP)];
+    select.extend(names.iter().cloned());
+    mapping([
+        ("proxies", Value::Sequence(proxies)),
+        (
+            "proxy-groups",
+            Value::Sequence(vec![
+                Value::Mapping(mapping([
+                    ("name", Value::from(SELECT_GROUP)),
+                    ("type", Value::from("select")),
+                    ("proxies", Value::Sequence(select)),
+                ])),
+                Value::Mapping(mapping([
+                    ("name", Value::from(AUTO_GROUP)),
+                    ("type", Value::from("url-test")),
+                    ("proxies", Value::Sequence(names)),
+                    ("url", Value::from(test_url)),
+                    ("interval", Value::from(300)),
+                ])),
+            ]),
+        ),
+        (
+            "rules",
+            Value::Sequence(vec![Value::from(format!("MATCH,{SELECT_GROUP}"))]),
+        ),
+    ])
+}
+
+/// Whether `content` starts with a share link, rather than being a config.
+fn is_share_links(content: &str) -> bool {
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| {
+            SHARE_LINK_SCHEMES
+                .iter()
+                .any(|scheme| line.starts_with(scheme))
+        })
+}
+
+/// Parse a single share link into a mihomo proxy, named after the link's fragment.
+///
+/// Reference: https://wiki.metacubex.one/config/proxies/
+pub fn parse_share_link(link: &str) -> Result<Mapping> {
+    let (scheme, rest) = link.split_once("://").with_context(|| "not a share link")?;
+    match scheme {
+        "ss" => parse_ss(rest),
+        "vmess" => parse_vmess(rest),
+        "trojan" => parse_trojan(rest),
+        "vless" => parse_vless(rest),
+        _ => bail!("unsupported scheme `{scheme}`"),
+    }
+}
+
+/// `ss://` links, as `ss://<base64 of method:password>@server:port/?plugin=...#name` (SIP002) or
+/// `ss://<base64 of method:password@server:port>#name`.
+///
+/// Reference: https://shadowsocks.org/doc/sip002.html
+fn parse_ss(rest: &str) -> Result<Mapping> {
+    let (rest, name) = split_fragment(rest);
+    let (main, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let main = main.trim_end_matches('/');
+    let main = match main.contains('@') {
+        true => main.to_owned(),
+        false => decode_base64(main).with_context(|| "invalid base64 in link")?,
+    };
+    let (userinfo, server) = main
+        .rsplit_once('@')
+        .with_context(|| "missing server in link")?;
+    // 2022 ciphers are not base64 encoded, but percent-encoded
+    let userinfo = percent_decode(userinfo);
+    let userinfo = match userinfo.contains(':') {
+        true => userinfo,
+        false => decode_base64(&userinfo).with_context(|| "invalid base64 in link")?,
+    };
+    let (cipher, password) = userinfo
+        .split_once(':')
+        .with_context(|| "missing cipher in link")?;
+    let (server, port) = split_host_port(server)?;
+
+    let mut proxy = mapping([
+        ("name", Value::from(name.unwrap_or_default())),
+        ("type", Value::from("ss")),
+        ("server", Value::from(server)),
+        ("port", Value::from(port)),
+        ("cipher", Value::from(cipher)),
+        ("password", Value::from(password)),
+        ("udp", Value::from(true)),
+    ]);
+
+    if let Some(plugin) = query_params(query).get("plugin") {
+        insert_ss_plugin(&mut proxy, plugin)?;
+    }
+    Ok(proxy)
+}
+
+/// Insert shadowsocks `plugin` with its options, given as `<name>;<opt>=<value>;...`.
+fn insert_ss_plugin(proxy: &mut Mapping, plugin: &str) -> Result<()> {
+    let mut fields = plugin.split(';').filter(|field| !field.is_empty());
+    let plugin_name = fields.next().unwrap_or_default();
+    let opts: HashMap<&str, &str> = fields
+        .map(|field| field.split_once('=').unwrap_or((field, "true")))
+        .collect();
+    match plugin_name {
+        "obfs-local" | "simple-obfs" => {
+            proxy.insert(Value::from("plugin"), Value::from("obfs"));
+            proxy.insert(
+                Value::from("plugin-opts"),
+                Value::Mapping(mapping([
+                    ("mode", Value::from(*opts.get("obfs").unwrap_or(&"http"))),
+                    ("host", Value::from(*opts.get("obfs-host").unwrap_or(&""))),
+                ])),
+            );
+        }
+        "v2ray-plugin" => {
+            let mut plugin_opts = mapping([
+                (
+                    "mode",
+                    Value::from(*opts.get("mode").unwrap_or(&"websocket")),
+                ),
+                ("host", Value::from(*opts.get("host").unwrap_or(&""))),
+                ("path", Value::from(*opts.get("path").unwrap_or(&"/"))),
+            ]);
+            if opts.contains_key("tls") {
+                plugin_opts.insert(Value::from("tls"), Value::from(true));
+            }
+            proxy.insert(Value::from("plugin"), Value::from("v2ray-plugin"));
+            proxy.insert(Value::from("plugin-opts"), Value::Mapping(plugin_opts));
+        }
+        plugin => bail!("unsupported plugin `{plugin}`"),
+    }
+    Ok(())
+}
+
+/// `vmess://` links, as base64 encoded JSON in the format popularized by v2rayN.
+///
+/// Reference: https://github.com/2dust/v2rayN/wiki/Description-of-VMess-share-link
+fn parse_vmess(rest: &str) -> Result<Mapping> {
+    let decoded = decode_base64(rest).with_context(|| "invalid base64 in link")?;
+    let json: serde_json::Value =
+        serde_json::from_str(&decoded).with_context(|| "invalid JSON in link")?;
+    // Fields are strings, though some clients write numbers
+    let field = |key: &str| match json.get(key) {
+        Some(serde_json::Value::String(value)) => value.clone(),
+        Some(serde_json::Value::Number(value)) => value.to_string(),
+        _ => String::new(),
+    };
+
+    let server = field("add");
+    let port: u16 = field("port")
+        .parse()
+        .with_context(|| "invalid port in link")?;
+    let cipher = match field("scy").as_str() {
+        "" => String::from("auto"),
+        cipher => cipher.to_owned(),
+    };
+    let mut proxy = mapping([
+        ("name", Value::from(field("ps"))),
+        ("type", Value::from("vmess")),
+        ("server", Value::from(server)),
+        ("port", Value::from(port)),
+        ("uuid", Value::from(field("id"))),
+        (
+            "alterId",
+            Value::from(field("aid").parse::<u32>().unwrap_or(0)),
+        ),
+        ("cipher", Value::from(cipher)),
+        ("udp", Value::from(true)),
+    ]);
+    if field("tls") == "tls" {
+        proxy.insert(Value::from("tls"), Value::from(true));
+        let sni = match field("sni").as_str() {
+            "" => field("host"),
+            sni => sni.to_owned(),
+        };
+        if !sni.is_empty() {
+            proxy.insert(Value::from("servername"), Value::from(sni));
+        }
+        insert_alpn(&mut proxy, &field("alpn"));
+        insert_nonempty(&mut proxy, "client-fingerprint", &field("fp"));
+    }
+    insert_transport(
+        &mut proxy,
+        &field("net"),
+        &field("host"),
+        &field("path"),
+        &field("path"),
+    )?;
+    Ok(proxy)
+}
+
+/// `trojan://password@server:port?sni=...&type=ws&path=...#name` links.
+fn parse_trojan(rest: &str) -> Result<Mapping> {
+    let link = UrlLink::parse(rest)?;
+    let mut proxy = mapping([
+        ("name", Value::from(link.name.clone().unwrap_or_default())),
+        ("type", Value::from("trojan")),
+        ("server", Value::from(link.server.clone())),
+        ("port", Value::from(link.port)),
+        ("password", Value::from(link.userinfo.clone())),
+        ("udp", Value::from(true)),
+    ]);
+    let sni = match link.param("sni") {
+        "" => link.param("peer"),
+        sni => sni,
+    };
+    insert_nonempty(&mut proxy, "sni", sni);
+    insert_tls_params(&mut proxy, &link);
+    insert_transport(
+        &mut proxy,
+        link.param("type"),
+        link.param("host"),
+        link.param("path"),
+        link.param("serviceName"),
+    )?;
+    Ok(proxy)
+}
+
+/// `vless://uuid@server:port?security=reality&pbk=...&type=grpc&serviceName=...#name` links.
+///
+/// Reference: https://github.com/XTLS/Xray-core/discussions/716
+fn parse_vless(rest: &str) -> Result<Mapping> {
+    let link = UrlLink::parse(rest)?;
+    let mut proxy = mapping([
+        ("name", Value::from(link.name.clone().unwrap_or_default())),
+        ("type", Value::from("vless")),
+        ("server", Value::from(link.server.clone())),
+        ("port", Value::from(link.port)),
+        ("uuid", Value::from(link.userinfo.clone())),
+        ("udp", Value::from(true)),
+    ]);
+    insert_nonempty(&mut proxy, "flow", link.param("flow"));
+    match link.param("security") {
+        "tls" | "xtls" => {
+            proxy.insert(Value::from("tls"), Value::from(true));
+        }
+        "reality" => {
+            proxy.insert(Value::from("tls"), Value::from(true));
+            let mut reality = mapping([("public-key", Value::from(link.param("pbk")))]);
+            if !link.param("sid").is_empty() {
+                reality.insert(Value::from("short-id"), Value::from(link.param("sid")));
+            }
+            proxy.insert(Value::from("reality-opts"), Value::Mapping(reality));
+        }
+        "" | "none" => (),
+        security => bail!("unsupported security `{security}`"),
+    }
+    if proxy.contains_key("tls") {
+        insert_nonempty(&mut proxy, "servername", link.param("sni"));
+        insert_tls_params(&mut proxy, &link);
+    }
+    insert_transport(
+        &mut proxy,
+        link.param("type"),
+        link.param("host"),
+        link.param("path"),
+        link.param("serviceName"),
+    )?;
+    Ok(proxy)
+}
+
+/// A sing-box outbound as a mihomo proxy, named after its tag.
+fn parse_sing_box_outbound(outbound: &serde_json::Value) -> Result<Mapping> {
+    let field = |key: &str| outbound[key].as_str().unwrap_or_default();
+    let server = field("server");
+    if server.is_empty() {
+        bail!("missing server in outbound");
+    }
+    let port = outbound["server_port"]
+        .as_u64()
+        .and_then(|port| u16::try_from(port).ok())
+        .with_context(|| "missing or invalid `server_port` in outbound")?;
+    let common = |proxy_type: &str| {
+        mapping([
+            ("name", Value::from(field("tag"))),
+            ("type", Value::from(proxy_type)),
+            ("server", Value::from(server)),
+            ("port", Value::from(port)),
+        ])
+    };
+
+    let tls = &outbound["tls"];
+    let mut proxy = match field("type") {
+        "shadowsocks" => {
+            let mut proxy = common("ss");
+            proxy.extend(mapping([
+                ("cipher", Value::from(field("method"))),
+                ("password", Value::from(field("password"))),
+                ("udp", Value::from(true)),
+            ]));
+            if !field("plugin").is_empty() {
+                let plugin = format!("{};{}", field("plugin"), field("plugin_opts"));
+                insert_ss_plugin(&mut proxy, &plugin)?;
+            }
+            return Ok(proxy);
+        }
+        "vmess" => {
+            let mut proxy = common("vmess");
+            let cipher = match field("security") {
+                "" => "auto",
+                cipher => cipher,
+            };
+            proxy.extend(mapping([
+                ("uuid", Value::from(field("uuid"))),
+                (
+                    "alterId",
+                    Value::from(outbound["alter_id"].as_u64().unwrap_or(0)),
+                ),
+                ("cipher", Value::from(cipher)),
+                ("udp", Value::from(true)),
+            ]));
+            if tls["enabled"].as_bool() == Some(true) {
+                proxy.insert(Value::from("tls"), Value::from(true));
+                insert_sing_box_tls(&mut proxy, tls, "servername");
+            }
+            proxy
+        }
+        "trojan" => {
+            let mut proxy = common("trojan");
+            proxy.extend(mapping([
+                ("password", Value::from(field("password"))),
+                ("udp", Value::from(true)),
+            ]));
+            insert_sing_box_tls(&mut proxy, tls, "sni");
+            proxy
+        }
+        "hysteria2" => {
+            let mut proxy = common("hysteria2");
+            proxy.insert(Value::from("password"), Value::from(field("password")));
+            for (key, speed) in [("up", "up_mbps"), ("down", "down_mbps")] {
+                if let Some(mbps) = outbound[speed].as_u64() {
+                    proxy.insert(Value::from(key), Value::from(mbps));
+                }
+            }
+            let obfs = &outbound["obfs"];
+            if let Some(obfs_type) = obfs["type"].as_str() {
+                proxy.insert(Value::from("obfs"), Value::from(obfs_type));
+                insert_nonempty(
+                    &mut proxy,
+                    "obfs-password",
+                    obfs["password"].as_str().unwrap_or_default(),
+                );
+            }
+            insert_sing_box_tls(&mut proxy, tls, "sni");
+            return Ok(proxy);
+        }
+        "tuic" => {
+            let mut proxy = common("tuic");
+            proxy.extend(mapping([
+                ("uuid", Value::from(field("uuid"))),
+                ("password", Value::from(field("password"))),
+            ]));
+            insert_nonempty(
+                &mut proxy,
+                "congestion-controller",
+                field("congestion_control"),
+            );
+            insert_nonempty(&mut proxy, "udp-relay-mode", field("udp_relay_mode"));
+            if outbound["zero_rtt_handshake"].as_bool() == Some(true) {
+                proxy.insert(Value::from("reduce-rtt"), Value::from(true));
+            }
+            insert_sing_box_tls(&mut proxy, tls, "sni");
+            return Ok(proxy);
+        }
+        outbound_type => bail!("unsupported outbound type `{outbound_type}`"),
+    };
+
+    // Only VMess and trojan run over V2Ray transports
+    let transport = &outbound["transport"];
+    let network = match transport["type"].as_str().unwrap_or_default() {
+        "http" => "h2",
+        network => network,
+    };
+    let host = match network {
+        "h2" => json_strings(&transport["host"]).join(","),
+        _ => json_strings(&transport["headers"]["Host"])
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+    };
+    insert_transport(
+        &mut proxy,
+        network,
+        &host,
+        transport["path"].as_str().unwrap_or_default(),
+        transport["service_name"].as_str().unwrap_or_default(),
+    )?;
+    Ok(proxy)
+}
+
+/// Insert settings of a sing-box outbound's `tls` object, with its server name as `sni_key`.
+fn insert_sing_box_tls(proxy: &mut Mapping, tls: &serde_json::Value, sni_key: &str) {
+    insert_nonempty(
+        proxy,
+        sni_key,
+        tls["server_name"].as_str().unwrap_or_default(),
+    );
+    if tls["insecure"].as_bool() == Some(true) {
+        proxy.insert(Value::from("skip-cert-verify"), Value::from(true));
+    }
+    insert_alpn(proxy, &json_strings(&tls["alpn"]).join(","));
+    let utls = &tls["utls"];
+    if utls["enabled"].as_bool() == Some(true) {
+        let fingerprint = utls["fingerprint"].as_str().unwrap_or("chrome");
+        proxy.insert(Value::from("client-fingerprint"), Value::from(fingerprint));
+    }
+}
+
+/// Strings of a sing-box field given as either a string or a list of them.
+fn json_strings(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(value) => vec![value.clone()],
+        serde_json::Value::Array(values) => values
+            .iter()
+            .filter_map(|value| value.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Share link of the form `userinfo@server:port?params#name`, after the scheme.
+struct UrlLink {
+    userinfo: String,
+    server: String,
+    port: u16,
+    params: HashMap<String, String>,
+    name: Option<String>,
+}
+
+impl UrlLink {
+    fn parse(rest: &str) -> Result<UrlLink> {
+        let (rest, name) = split_fragment(rest);
+        let (main, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (userinfo, server) = main
+            .trim_end_matches('/')
+            .rsplit_once('@')
+            .with_context(|| "missing server in link")?;
+        let (server, port) = split_host_port(server)?;
+        Ok(UrlLink {
+            userinfo: percent_decode(userinfo),
+            server,
+            port,
+            params: query_params(query),
+            name,
+        })
+    }
+
+    /// Query parameter `key`, empty if missing.
+    fn param(&self, key: &str) -> &str {
+        self.params.get(key).map_or("", String::as_str)
+    }
+}
+
+/// Insert TLS settings shared by trojan and VLESS links: ALPN, fingerprint and certificate checks.
+fn insert_tls_params(proxy: &mut Mapping, link: &UrlLink) {
+    insert_alpn(proxy, link.param("alpn"));
+    insert_nonempty(proxy, "client-fingerprint", link.param("fp"));
+    if matches!(link.param("allowInsecure"), "1" | "true") {
+        proxy.insert(Value::from("skip-cert-verify"), Value::from(true));
+    }
+}
+
+/// Insert the transport `network` with its options, plain TCP adding none.
+fn insert_transport(
+    proxy: &mut Mapping,
+    network: &str,
+    host: &str,
+    path: &str,
+    service_name: &str,
+) -> Result<()> {
+    let opts = match network {
+        "" | "tcp" => return Ok(()),
+        "ws" => {
+            let mut opts = mapping([(
+                "path",
+                Value::from(if path.is_empty() { "/" } else { path }),
+            )]);
+            if !host.is_empty() {
+                opts.insert(
+                    Value::from("headers"),
+                    Value::Mapping(mapping([("Host", Value::from(host))])),
+                );
+            }
+            opts
+        }
+        "grpc" => mapping([("grpc-service-name", Value::from(service_name))]),
+        "h2" => mapping([
+            (
+                "host",
+                Value::Sequence(host.split(',').map(Value::from).collect()),
+            ),
+            (
+                "path",
+                Value::from(if path.is_empty() { "/" } else { path }),
+            ),
+        ]),
+        network => bail!("unsupported transport `{network}`"),
+    };
+    proxy.insert(Value::from("network"), Value::from(network));
+    proxy.insert(Value::from(format!("{network}-opts")), Value::Mapping(opts));
+    Ok(())
+}
+
+/// Insert comma-separated `alpn` as a list, if given.
+fn insert_alpn(proxy: &mut Mapping, alpn: &str) {
+    if !alpn.is_empty() {
+        let alpn = alpn.split(',').map(|protocol| Value::from(protocol.trim()));
+        proxy.insert(Value::from("alpn"), Value::Sequence(alpn.collect()));
+    }
+}
+
+/// Insert `key` if `value` is not empty.
+fn insert_nonempty(proxy: &mut Mapping, key: &str, value: &str) {
+    if !value.is_empty() {
+        proxy.insert(Value::from(key), Value::from(value));
+    }
+}
+
+/// Mapping of `entries` in order.
+fn mapping<const N: usize>(entries: [(&str, Value); N]) -> Mapping {
+    entries
+        .into_iter()
+        .map(|(key, value)| (Value::from(key), value))
+        .collect()
+}
+
+/// Name of `proxy`, or its `server:port` if the link had none.
+fn proxy_name(proxy: &Mapping) -> String {
+    match proxy.get("name").and_then(Value::as_str) {
+        Some(name) if !name.trim().is_empty() => name.trim().to_owned(),
+        _ => format!(
+            "{}:{}",
+            proxy
+                .get("server")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+            proxy
+                .get("port")
+                .and_then(Value::as_u64)
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// `name`, suffixed with a number if already in `names` as mihomo requires unique proxy names.
+pub fn unique_name(name: &str, names: &mut HashSet<String>) -> String {
+    let mut unique = name.to_owned();
+    let mut n = 2;
+    while names.contains(&unique) {
+        unique = format!("{name} {n}");
+        n += 1;
+    }
+    names.insert(unique.clone());
+    unique
+}
+
+/// Split `link` into the part before `#` and the percent-decoded name after it.
+fn split_fragment(link: &str) -> (&str, Option<String>) {
+    match link.split_once('#') {
+        Some((rest, name)) => (rest, Some(percent_decode(name))),
+        None => (link, None),
+    }
+}
+
+/// Split `server:port` or `[ipv6]:port`.
+fn split_host_port(server: &str) -> Result<(String, u16)> {
+    let (host, port) = server
+        .rsplit_once(':')
+        .with_context(|| "missing port in link")?;
+    let port = port
+        .parse()
+        .with_context(|| format!("invalid port `{port}` in link"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        bail!("missing server in link");
+    }
+    Ok((host.to_owned(), port))
+}
+
+/// Percent-decoded query parameters of `query`, e.g. `sni=example.com&type=ws`.
+fn query_params(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+        .collect()
+}
+
+/// Decode `%XX` escapes in `text`, leaving invalid ones as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decode base64 `text` as UTF-8, in the standard or URL-safe alphabet and with or without
+/// padding, as providers use all of them.
+fn decode_base64(text: &str) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(&text).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(proxy: &Mapping, key: &str) -> Value {
+        proxy.get(key).cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_sip002_ss_link_with_plugin() {
+        let userinfo = URL_SAFE_NO_PAD.encode("aes-256-gcm:secret");
+        let link = format!(
+            "ss://{userinfo}@ss.example.com:8388/?plugin=obfs-local%3Bobfs%3Dtls%3Bobfs-host%3Dcdn.example.com#HK%2001"
+        );
+        let proxy = parse_share_link(&link).unwrap();
+        assert_eq!(get(&proxy, "name"), "HK 01");
+        assert_eq!(get(&proxy, "type"), "ss");
+        assert_eq!(get(&proxy, "server"), "ss.example.com");
+        assert_eq!(get(&proxy, "port"), 8388);
+        assert_eq!(get(&proxy, "cipher"), "aes-256-gcm");
+        assert_eq!(get(&proxy, "password"), "secret");
+        assert_eq!(get(&proxy, "plugin"), "obfs");
+        assert_eq!(get(&proxy, "plugin-opts")["mode"], "tls");
+        assert_eq!(get(&proxy, "plugin-opts")["host"], "cdn.example.com");
+    }
+
+    #[test]
+    fn parses_legacy_and_2022_ss_links() {
+        let legacy = STANDARD.encode("chacha20-ietf-poly1305:pass@1.2.3.4:443");
+        let proxy = parse_share_link(&format!("ss://{legacy}#Legacy")).unwrap();
+        assert_eq!(get(&proxy, "cipher"), "chacha20-ietf-poly1305");
+        assert_eq!(get(&proxy, "password"), "pass");
+        assert_eq!(get(&proxy, "server"), "1.2.3.4");
+        assert_eq!(get(&proxy, "port"), 443);
+
+        let proxy =
+            parse_share_link("ss://2022-blake3-aes-128-gcm:a%2Bb%3D@[::1]:8443#SS2022").unwrap();
+        assert_eq!(get(&proxy, "cipher"), "2022-blake3-aes-128-gcm");
+        assert_eq!(get(&proxy, "password"), "a+b=");
+        assert_eq!(get(&proxy, "server"), "::1");
+    }
+
+    #[test]
+    fn parses_vmess_link() {
+        let json = r#"{"v":"2","ps":"JP 02","add":"vm.example.com","port":"443","id":"uuid-1","aid":0,"net":"ws","host":"cdn.example.com","path":"/ray","tls":"tls","alpn":"h2,http/1.1"}"#;
+        let proxy = parse_share_link(&format!("vmess://{}", STANDARD.encode(json))).unwrap();
+        assert_eq!(get(&proxy, "name"), "JP 02");
+        assert_eq!(get(&proxy, "type"), "vmess");
+        assert_eq!(get(&proxy, "port"), 443);
+        assert_eq!(get(&proxy, "uuid"), "uuid-1");
+        assert_eq!(get(&proxy, "alterId"), 0);
+        assert_eq!(get(&proxy, "cipher"), "auto");
+        assert_eq!(get(&proxy, "tls"), true);
+        assert_eq!(get(&proxy, "servername"), "cdn.example.com");
+        assert_eq!(get(&proxy, "alpn")[1], "http/1.1");
+        assert_eq!(get(&proxy, "network"), "ws");
+        assert_eq!(get(&proxy, "ws-opts")["path"], "/ray");
+        assert_eq!(get(&proxy, "ws-opts")["headers"]["Host"], "cdn.example.com");
+    }
+
+    #[test]
+    fn parses_trojan_link() {
+        let proxy = parse_share_link(
+            "trojan://p%40ss@tr.example.com:443?sni=sni.example.com&type=grpc&serviceName=svc&allowInsecure=1#US",
+        )
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "trojan");
+        assert_eq!(get(&proxy, "password"), "p@ss");
+        assert_eq!(get(&proxy, "sni"), "sni.example.com");
+        assert_eq!(get(&proxy, "skip-cert-verify"), true);
+        assert_eq!(get(&proxy, "network"), "grpc");
+        assert_eq!(get(&proxy, "grpc-opts")["grpc-service-name"], "svc");
+    }
+
+    #[test]
+    fn parses_vless_reality_link() {
+        let proxy = parse_share_link(
+            "vless://uuid-2@vl.example.com:443?security=reality&pbk=key&sid=ab&sni=www.example.com&fp=chrome&flow=xtls-rprx-vision#SG",
+        )
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "vless");
+        assert_eq!(get(&proxy, "uuid"), "uuid-2");
+        assert_eq!(get(&proxy, "flow"), "xtls-rprx-vision");
+        assert_eq!(get(&proxy, "tls"), true);
+        assert_eq!(get(&proxy, "servername"), "www.example.com");
+        assert_eq!(get(&proxy, "client-fingerprint"), "chrome");
+        assert_eq!(get(&proxy, "reality-opts")["public-key"], "key");
+        assert_eq!(get(&proxy, "reality-opts")["short-id"], "ab");
+        assert!(proxy.get("network").is_none());
+    }
+
+    #[test]
+    fn rejects_unsupported_links() {
+        assert!(parse_share_link("hysteria://host:443").is_err());
+        assert!(parse_share_link("trojan://pass@host").is_err());
+        assert!(parse_share_link("vless://uuid@host:443?security=unknown").is_err());
+        assert!(parse_share_link("vless://uuid@host:443?type=quic").is_err());
+    }
+
+    #[test]
+    fn detects_base64_encoded_share_links() {
+        let links = "trojan://pass@host:443#A\nss://YWVzLTI1Ni1nY206cGFzcw@host:8388#B\n";
+        assert!(is_share_links(links));
+        let decoded = decode_base64(&STANDARD.encode(links)).unwrap();
+        assert!(is_share_links(&decoded));
+        assert!(!is_share_links("proxies:\n  - name: A\n"));
+    }
+
+    #[test]
+    fn names_proxies_uniquely() {
+        let mut names = HashSet::new();
+        assert_eq!(unique_name("A", &mut names), "A");
+        assert_eq!(unique_name("A", &mut names), "A 2");
+        assert_eq!(unique_name("A", &mut names), "A 3");
+        let unnamed = mapping([("server", Value::from("host")), ("port", Value::from(443))]);
+        assert_eq!(proxy_name(&unnamed), "host:443");
+    }
+
+    #[test]
+    fn converts_sing_box_shadowsocks() {
+        let proxy = parse_sing_box_outbound(&serde_json::json!({
+            "type": "shadowsocks", "tag": "HK", "server": "ss.example.com", "server_port": 8388,
+            "method": "aes-128-gcm", "password": "secret",
+            "plugin": "v2ray-plugin", "plugin_opts": "mode=websocket;host=cdn.example.com;tls",
+        }))
+        .unwrap();
+        assert_eq!(get(&proxy, "name"), "HK");
+        assert_eq!(get(&proxy, "type"), "ss");
+        assert_eq!(get(&proxy, "port"), 8388);
+        assert_eq!(get(&proxy, "cipher"), "aes-128-gcm");
+        assert_eq!(get(&proxy, "plugin"), "v2ray-plugin");
+        assert_eq!(get(&proxy, "plugin-opts")["host"], "cdn.example.com");
+        assert_eq!(get(&proxy, "plugin-opts")["tls"], true);
+    }
+
+    #[test]
+    fn converts_sing_box_vmess_with_transport() {
+        let proxy = parse_sing_box_outbound(&serde_json::json!({
+            "type": "vmess", "tag": "JP", "server": "vm.example.com", "server_port": 443,
+            "uuid": "uuid-1", "alter_id": 0,
+            "tls": {"enabled": true, "server_name": "vm.example.com", "alpn": ["h2"],
+                    "utls": {"enabled": true, "fingerprint": "firefox"}},
+            "transport": {"type": "http", "host": ["a.example.com", "b.example.com"], "path": "/h2"},
+        }))
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "vmess");
+        assert_eq!(get(&proxy, "cipher"), "auto");
+        assert_eq!(get(&proxy, "tls"), true);
+        assert_eq!(get(&proxy, "servername"), "vm.example.com");
+        assert_eq!(get(&proxy, "alpn")[0], "h2");
+        assert_eq!(get(&proxy, "client-fingerprint"), "firefox");
+        assert_eq!(get(&proxy, "network"), "h2");
+        assert_eq!(get(&proxy, "h2-opts")["host"][1], "b.example.com");
+        assert_eq!(get(&proxy, "h2-opts")["path"], "/h2");
+    }
+
+    #[test]
+    fn converts_sing_box_trojan_with_transport() {
+        let proxy = parse_sing_box_outbound(&serde_json::json!({
+            "type": "trojan", "tag": "US", "server": "tr.example.com", "server_port": 443,
+            "password": "secret",
+            "tls": {"enabled": true, "server_name": "sni.example.com", "insecure": true},
+            "transport": {"type": "ws", "path": "/ws", "headers": {"Host": "cdn.example.com"}},
+        }))
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "trojan");
+        assert_eq!(get(&proxy, "sni"), "sni.example.com");
+        assert_eq!(get(&proxy, "skip-cert-verify"), true);
+        assert_eq!(get(&proxy, "network"), "ws");
+        assert_eq!(get(&proxy, "ws-opts")["headers"]["Host"], "cdn.example.com");
+    }
+
+    #[test]
+    fn converts_sing_box_hysteria2() {
+        let proxy = parse_sing_box_outbound(&serde_json::json!({
+            "type": "hysteria2", "tag": "SG", "server": "hy.example.com", "server_port": 8443,
+            "password": "secret", "up_mbps": 50, "down_mbps": 200,
+            "obfs": {"type": "salamander", "password": "obfs"},
+            "tls": {"enabled": true, "server_name": "hy.example.com"},
+        }))
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "hysteria2");
+        assert_eq!(get(&proxy, "up"), 50);
+        assert_eq!(get(&proxy, "down"), 200);
+        assert_eq!(get(&proxy, "obfs"), "salamander");
+        assert_eq!(get(&proxy, "obfs-password"), "obfs");
+        assert_eq!(get(&proxy, "sni"), "hy.example.com");
+    }
+
+    #[test]
+    fn converts_sing_box_tuic() {
+        let proxy = parse_sing_box_outbound(&serde_json::json!({
+            "type": "tuic", "tag": "TW", "server": "tuic.example.com", "server_port": 443,
+            "uuid": "uuid-3", "password": "secret", "congestion_control": "bbr",
+            "udp_relay_mode": "native", "zero_rtt_handshake": true,
+            "tls": {"enabled": true, "alpn": "h3"},
+        }))
+        .unwrap();
+        assert_eq!(get(&proxy, "type"), "tuic");
+        assert_eq!(get(&proxy, "uuid"), "uuid-3");
+        assert_eq!(get(&proxy, "congestion-controller"), "bbr");
+        assert_eq!(get(&proxy, "udp-relay-mode"), "native");
+        assert_eq!(get(&proxy, "reduce-rtt"), true);
+        assert_eq!(get(&proxy, "alpn")[0], "h3");
+    }
+
+    #[test]
+    fn rejects_invalid_sing_box_outbounds() {
+        let missing_port = serde_json::json!({"type": "trojan", "server": "host"});
+        assert!(parse_sing_box_outbound(&missing_port).is_err());
+        let wireguard =
+            serde_json::json!({"type": "wireguard", "server": "host", "server_port": 1});
+        assert!(parse_sing_box_outbound(&wireguard).is_err());
+    }
+
+    #[test]
+    fn converts_sing_box_config_skipping_non_proxies() {
+        let path =
+            std::env::temp_dir().join(format!("mihoro-sing-box-{}.json", std::process::id()));
+        let path = path.to_string_lossy();
+        let config = serde_json::json!({"outbounds": [
+            {"type": "selector", "tag": "select", "outbounds": ["A"]},
+            {"type": "direct", "tag": "direct"},
+            {"type": "trojan", "tag": "A", "server": "a.example.com", "server_port": 443, "password": "p"},
+            {"type": "trojan", "tag": "A", "server": "b.example.com", "server_port": 443, "password": "p"},
+            {"type": "wireguard", "tag": "wg", "server": "c.example.com", "server_port": 51820},
+        ]});
+        fs::write(path.as_ref(), config.to_string()).unwrap();
+        let conversion = convert_sing_box_file_inplace(&path, "https://example.com").unwrap();
+        let converted: Value =
+            serde_yaml::from_str(&fs::read_to_string(path.as_ref()).unwrap()).unwrap();
+        fs::remove_file(path.as_ref()).ok();
+
+        assert_eq!(conversion.converted, 2);
+        assert_eq!(conversion.skipped.len(), 1);
+        assert_eq!(conversion.skipped[0].0, "wg");
+        assert_eq!(converted["proxies"][1]["name"], "A 2");
+        assert_eq!(converted["proxy-groups"][0]["name"], SELECT_GROUP);
+        assert_eq!(converted["proxy-groups"][1]["url"], "https://example.com");
+    }
+}
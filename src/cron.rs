@@ -0,0 +1,197 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+
+/// Job scheduled by `mihoro cron`, each on its own interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateTask {
+    /// `mihoro update`, every `config_update_interval` hours.
+    Config,
+    /// `mihoro update-geodata`, every `geodata_update_interval` hours.
+    Geodata,
+    /// `mihoro upgrade`, every `binary_update_interval` hours.
+    Binary,
+}
+
+impl UpdateTask {
+    pub const ALL: [UpdateTask; 3] = [UpdateTask::Config, UpdateTask::Geodata, UpdateTask::Binary];
+
+    /// mihoro subcommand the task runs, also naming its systemd units and crontab entry.
+    pub fn command(&self) -> &'static str {
+        match self {
+            UpdateTask::Config => "update",
+            UpdateTask::Geodata => "update-geodata",
+            UpdateTask::Binary => "upgrade",
+        }
+    }
+
+    /// What the task updates.
+    pub fn target(&self) -> &'static str {
+        match self {
+            UpdateTask::Config => "config",
+            UpdateTask::Geodata => "geodata",
+            UpdateTask::Binary => "binary",
+        }
+    }
+}
+
+/// Scheduler running `mihoro update` periodically for `mihoro cron`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CronBackend {
+    /// An entry in the user's crontab, run by the cron daemon.
+    Crontab,
+    /// A systemd service and timer, for systems without a cron daemon.
+    SystemdTimer,
+}
+
+/// When a task running every `interval` hours is due, as `(crontab schedule, OnCalendar)`.
+///
+/// Neither cron nor systemd calendars count hours across days, so intervals of a day or less run
+/// at multiples of `interval` past midnight, and longer ones at midnight on every `interval / 24`th
+/// day of the month.
+pub fn interval_schedule(interval: u32) -> Result<(String, String)> {
+    match interval {
+        1..=24 => Ok((
+            format!("0 */{interval} * * *"),
+            format!("*-*-* 00/{interval}:00:00"),
+        )),
+        25.. if interval.is_multiple_of(24) && interval / 24 <= 31 => {
+            let days = interval / 24;
+            Ok((
+                format!("0 0 */{days} * *"),
+                format!("*-*-01/{days} 00:00:00"),
+            ))
+        }
+        _ => bail!(
+            "invalid update interval of {interval} hours, use 1 to 24 hours or whole days up to \
+             31 days (744 hours)"
+        ),
+    }
+}
+
+/// Systemd service and timer units running `exec_start` of `task` on calendar `on_calendar`, as
+/// `(service, timer)`.
+///
+/// Updates missed while the machine was off run as soon as it is back, and are spread over a few
+/// minutes so that machines sharing a subscription do not fetch it all at once.
+pub fn update_units(task: UpdateTask, exec_start: &str, on_calendar: &str) -> (String, String) {
+    let target = task.target();
+    let service = format!(
+        "[Unit]
+Description=Update mihomo {target} with mihoro
+
+[Service]
+Type=oneshot
+ExecStart={exec_start}
+"
+    );
+    let timer = format!(
+        "[Unit]
+Description=Update mihomo {target} with mihoro periodically
+
+[Timer]
+OnCalendar={on_calendar}
+RandomizedDelaySec=10min
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+"
+    );
+    (service, timer)
+}
+
+/// Crontab entry running `command` on `schedule`, tagged with `marker` to find it again.
+pub fn crontab_entry(command: &str, schedule: &str, marker: &str) -> String {
+    format!("{schedule} {command} # {marker}")
+}
+
+/// Entry of `crontab` tagged with `marker`.
+pub fn find_entry<'a>(crontab: &'a str, marker: &str) -> Option<&'a str> {
+    let tag = format!("# {marker}");
+    crontab.lines().find(|line| line.trim_end().ends_with(&tag))
+}
+
+/// `crontab` without the entry tagged with `marker`.
+pub fn remove_entry(crontab: &str, marker: &str) -> String {
+    let tag = format!("# {marker}");
+    crontab
+        .lines()
+        .filter(|line| !line.trim_end().ends_with(&tag))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// The current user's crontab, empty if they have none yet.
+pub fn read_crontab() -> Result<String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| "failed to execute crontab, use `--backend systemd-timer` without cron")?;
+    // `crontab -l` fails if the user has no crontab
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Ok(String::new()),
+    }
+}
+
+/// Replace the current user's crontab with `crontab`.
+pub fn write_crontab(crontab: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| "failed to execute crontab")?;
+    child
+        .stdin
+        .take()
+        .context("failed to open crontab's stdin")?
+        .write_all(crontab.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("`crontab -` exited with {status}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedules_hourly_intervals_past_midnight() {
+        let (cron, calendar) = interval_schedule(1).unwrap();
+        assert_eq!(cron, "0 */1 * * *");
+        assert_eq!(calendar, "*-*-* 00/1:00:00");
+        let (cron, calendar) = interval_schedule(6).unwrap();
+        assert_eq!(cron, "0 */6 * * *");
+        assert_eq!(calendar, "*-*-* 00/6:00:00");
+    }
+
+    #[test]
+    fn schedules_a_day_at_midnight() {
+        let (cron, calendar) = interval_schedule(24).unwrap();
+        assert_eq!(cron, "0 */24 * * *");
+        assert_eq!(calendar, "*-*-* 00/24:00:00");
+    }
+
+    #[test]
+    fn schedules_whole_days_on_days_of_the_month() {
+        let (cron, calendar) = interval_schedule(48).unwrap();
+        assert_eq!(cron, "0 0 */2 * *");
+        assert_eq!(calendar, "*-*-01/2 00:00:00");
+        let (cron, calendar) = interval_schedule(744).unwrap();
+        assert_eq!(cron, "0 0 */31 * *");
+        assert_eq!(calendar, "*-*-01/31 00:00:00");
+    }
+
+    #[test]
+    fn rejects_intervals_not_fitting_a_calendar() {
+        for interval in [0, 25, 36, 47, 768] {
+            assert!(interval_schedule(interval).is_err(), "{interval} hours");
+        }
+    }
+}
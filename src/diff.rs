@@ -0,0 +1,419 @@
+use crate::config::Core;
+
+use std::fs;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde_json::{Map, Value};
+
+/// A change between two core configs, keyed by the dotted path of the changed field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A value changed from `old` to `new`, `None` if absent.
+    Value {
+        key: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// Items of a list were added, removed or modified, identified by their name (or the item
+    /// itself for lists of strings like rules).
+    Items {
+        key: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+        modified: Vec<String>,
+    },
+}
+
+/// Item changes beyond this are summarized by counts instead of listing each item.
+const MAX_LISTED_ITEMS: usize = 5;
+
+/// Read a core config at `path` as a generic value, regardless of its format.
+pub fn read_core_config(path: &str, core: Core) -> Result<Value> {
+    let raw = fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+    match core {
+        Core::Mihomo => serde_yaml::from_str(&raw),
+        Core::SingBox => serde_json::from_str(&raw).map_err(serde::de::Error::custom),
+    }
+    .with_context(|| format!("failed to parse {path}"))
+}
+
+/// Changes from `old` to `new` config.
+pub fn diff_configs(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_value("", old, new, &mut changes);
+    changes
+}
+
+/// Fields of `local` and `runtime` found in both, recursively, to compare a config with the
+/// running settings reported by the controller, which omits most sections (e.g. proxies) and
+/// reports defaults for settings the config leaves out.
+///
+/// Strings differing only in case (e.g. `gvisor` and `gVisor`) are taken as equal.
+pub fn common_fields(local: &Value, runtime: &Value) -> (Value, Value) {
+    match (local, runtime) {
+        (Value::Object(local), Value::Object(runtime)) => {
+            let (mut local_common, mut runtime_common) = (Map::new(), Map::new());
+            for (field, local_value) in local {
+                if let Some(runtime_value) = runtime.get(field) {
+                    let (local_value, runtime_value) = common_fields(local_value, runtime_value);
+                    local_common.insert(field.clone(), local_value);
+                    runtime_common.insert(field.clone(), runtime_value);
+                }
+            }
+            (Value::Object(local_common), Value::Object(runtime_common))
+        }
+        (Value::String(local), Value::String(runtime)) if local.eq_ignore_ascii_case(runtime) => {
+            (Value::String(local.clone()), Value::String(local.clone()))
+        }
+        (local, runtime) => (local.clone(), runtime.clone()),
+    }
+}
+
+fn diff_value(key: &str, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (field, old_value) in old {
+                let field_key = join_key(key, field);
+                match new.get(field) {
+                    Some(new_value) => diff_value(&field_key, old_value, new_value, changes),
+                    None => changes.push(Change::Value {
+                        key: field_key,
+                        old: Some(display_value(old_value)),
+                        new: None,
+                    }),
+                }
+            }
+            for (field, new_value) in new {
+                if !old.contains_key(field) {
+                    changes.push(Change::Value {
+                        key: join_key(key, field),
+                        old: None,
+                        new: Some(display_value(new_value)),
+                    });
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) if is_named_list(old) && is_named_list(new) => {
+            let find = |items: &[Value], name: &str| {
+                items
+                    .iter()
+                    .find(|item| item_name(item).as_deref() == Some(name))
+                    .cloned()
+            };
+            let (mut added, mut removed, mut modified) = (Vec::new(), Vec::new(), Vec::new());
+            for name in old.iter().filter_map(item_name) {
+                match find(new, &name) {
+                    Some(new_item) if Some(&new_item) != find(old, &name).as_ref() => {
+                        modified.push(name)
+                    }
+                    Some(_) => (),
+                    None => removed.push(name),
+                }
+            }
+            for name in new.iter().filter_map(item_name) {
+                if find(old, &name).is_none() {
+                    added.push(name);
+                }
+            }
+            changes.push(Change::Items {
+                key: key.to_owned(),
+                added,
+                removed,
+                modified,
+            });
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            // Reordering items alone is still a change, e.g. for rules
+            changes.push(Change::Items {
+                key: key.to_owned(),
+                added: new
+                    .iter()
+                    .filter(|item| !old.contains(item))
+                    .map(display_value)
+                    .collect(),
+                removed: old
+                    .iter()
+                    .filter(|item| !new.contains(item))
+                    .map(display_value)
+                    .collect(),
+                modified: Vec::new(),
+            });
+        }
+        _ => changes.push(Change::Value {
+            key: key.to_owned(),
+            old: (!old.is_null()).then(|| display_value(old)),
+            new: (!new.is_null()).then(|| display_value(new)),
+        }),
+    }
+}
+
+fn join_key(key: &str, field: &str) -> String {
+    if key.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{key}.{field}")
+    }
+}
+
+/// Name of a list item, `name` for mihomo proxies and groups and `tag` for sing-box outbounds.
+fn item_name(item: &Value) -> Option<String> {
+    item.get("name")
+        .or_else(|| item.get("tag"))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+fn is_named_list(items: &[Value]) -> bool {
+    !items.is_empty() && items.iter().all(|item| item_name(item).is_some())
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => format!("[{} items]", items.len()),
+        Value::Object(_) => String::from("{...}"),
+        _ => value.to_string(),
+    }
+}
+
+/// Print changes as a concise colored diff.
+pub fn print_changes(changes: &[Change]) {
+    if changes.is_empty() {
+        println!("  {}", "no changes".dimmed());
+        return;
+    }
+    for change in changes {
+        match change {
+            Change::Value { key, old, new } => match (old, new) {
+                (None, Some(new)) => println!("  {} {}: {}", "+".green(), key.bold(), new),
+                (Some(old), None) => println!("  {} {}: {}", "-".red(), key.bold(), old),
+                (old, new) => println!(
+                    "  {} {}: {} -> {}",
+                    "~".yellow(),
+                    key.bold(),
+                    old.as_deref().unwrap_or_default().red(),
+                    new.as_deref().unwrap_or_default().green()
+                ),
+            },
+            Change::Items {
+                key,
+                added,
+                removed,
+                modified,
+            } => {
+                if added.len() + removed.len() + modified.len() > MAX_LISTED_ITEMS {
+                    println!(
+                        "  {} {}: {} added, {} removed, {} modified",
+                        "~".yellow(),
+                        key.bold(),
+                        added.len().to_string().green(),
+                        removed.len().to_string().red(),
+                        modified.len().to_string().yellow()
+                    );
+                } else if added.is_empty() && removed.is_empty() && modified.is_empty() {
+                    println!("  {} {}: reordered", "~".yellow(), key.bold());
+                } else {
+                    for item in added {
+                        println!("  {} {}: {}", "+".green(), key.bold(), item);
+                    }
+                    for item in removed {
+                        println!("  {} {}: {}", "-".red(), key.bold(), item);
+                    }
+                    for item in modified {
+                        println!("  {} {}: {}", "~".yellow(), key.bold(), item);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lines of context around changes in a unified diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Changed lines beyond which a diff gives up on matching lines and replaces them as a whole.
+const MAX_DIFF_EDITS: usize = 4000;
+
+/// A line of an edit script between two texts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit<'a> {
+    Keep(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Core config at `path` as text with its fields sorted, so that configs written with fields in
+/// a different order compare line by line.
+pub fn canonical_config(path: &str, core: Core) -> Result<String> {
+    let config = read_core_config(path, core)?;
+    Ok(match core {
+        Core::Mihomo => serde_yaml::to_string(&config)?,
+        Core::SingBox => serde_json::to_string_pretty(&config)? + "\n",
+    })
+}
+
+/// Print a colored unified diff from `old` to `new` text, labelled `old_label` and `new_label`.
+pub fn print_unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines);
+    if edits.iter().all(|edit| matches!(edit, Edit::Keep(_))) {
+        println!("  {}", "no changes".dimmed());
+        return;
+    }
+
+    println!("{}", format!("--- {old_label}").red().bold());
+    println!("{}", format!("+++ {new_label}").green().bold());
+    let changed: Vec<usize> = (0..edits.len())
+        .filter(|&i| !matches!(edits[i], Edit::Keep(_)))
+        .collect();
+    let mut i = 0;
+    while i < changed.len() {
+        // Extend the hunk while the next change is within reach of its trailing context
+        let start = changed[i].saturating_sub(DIFF_CONTEXT);
+        let mut end = changed[i];
+        while i + 1 < changed.len() && changed[i + 1] <= end + 2 * DIFF_CONTEXT + 1 {
+            i += 1;
+            end = changed[i];
+        }
+        let end = (end + DIFF_CONTEXT + 1).min(edits.len());
+        i += 1;
+
+        // Hunks start at the line after those before them, or at that line if they are empty
+        let (old_before, new_before) = line_counts(&edits[..start]);
+        let (old_len, new_len) = line_counts(&edits[start..end]);
+        let range = |before: usize, len: usize| format!("{},{len}", before + (len > 0) as usize);
+        println!(
+            "{}",
+            format!(
+                "@@ -{} +{} @@",
+                range(old_before, old_len),
+                range(new_before, new_len)
+            )
+            .cyan()
+        );
+        for edit in &edits[start..end] {
+            match edit {
+                Edit::Keep(line) => println!(" {line}"),
+                Edit::Delete(line) => println!("{}", format!("-{line}").red()),
+                Edit::Insert(line) => println!("{}", format!("+{line}").green()),
+            }
+        }
+    }
+}
+
+/// Numbers of old and new lines covered by `edits`.
+fn line_counts(edits: &[Edit]) -> (usize, usize) {
+    edits.iter().fold((0, 0), |(old, new), edit| match edit {
+        Edit::Keep(_) => (old + 1, new + 1),
+        Edit::Delete(_) => (old + 1, new),
+        Edit::Insert(_) => (old, new + 1),
+    })
+}
+
+/// Shortest edit script turning `old` into `new` lines, by Myers' algorithm on what remains
+/// after trimming their common prefix and suffix.
+///
+/// Reference: http://www.xmailserver.org/diff2.pdf
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|line| Edit::Keep(line)).collect();
+    match myers(old_middle, new_middle) {
+        Some(middle) => edits.extend(middle),
+        None => {
+            edits.extend(old_middle.iter().map(|line| Edit::Delete(line)));
+            edits.extend(new_middle.iter().map(|line| Edit::Insert(line)));
+        }
+    }
+    edits.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Edit::Keep(line)),
+    );
+    edits
+}
+
+/// Myers' greedy diff, `None` if more than [`MAX_DIFF_EDITS`] lines changed.
+fn myers<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Edit<'a>>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m).min(MAX_DIFF_EDITS as isize);
+    // Furthest reaching `x` on each diagonal `k = x - y`, offset to index from 0
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let at = |k: isize| (k + offset) as usize;
+    // Diagonals -d..=d of `v` before each round `d`, to backtrack the path taken
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    let mut found = false;
+    'rounds: for d in 0..=max {
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                true => v[at(k + 1)],
+                false => v[at(k - 1)] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'rounds;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        // `v` holds diagonals -d..=d of the previous round
+        let previous = |k: isize| v[(k + d) as usize];
+        let previous_k = match k == -d || (k != d && previous(k - 1) < previous(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let previous_x = if d == 0 { 0 } else { previous(previous_k) };
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Keep(old[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            match x == previous_x {
+                true => edits.push(Edit::Insert(new[y as usize - 1])),
+                false => edits.push(Edit::Delete(old[x as usize - 1])),
+            }
+            x = previous_x;
+            y = previous_y;
+        }
+    }
+    edits.reverse();
+    Some(edits)
+}
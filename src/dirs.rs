@@ -0,0 +1,72 @@
+use std::env;
+
+use serde::{Deserialize, Serialize};
+use shellexpand::tilde;
+
+/// Directory overrides from `mihoro.toml`, all optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DirsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_dir: Option<String>,
+}
+
+/// Directories mihoro keeps its own files in, following the XDG base directory specification.
+///
+/// * `data` - persistent data such as history and logs.
+/// * `cache` - downloads and staging files that can be safely removed.
+/// * `state` - state mihoro persists between runs.
+#[derive(Debug, Clone)]
+pub struct MihoroDirs {
+    pub data: String,
+    pub cache: String,
+    pub state: String,
+}
+
+impl MihoroDirs {
+    /// Resolve directories, in order of precedence, from `MIHORO_{DATA,CACHE,STATE}_DIR`, the
+    /// corresponding keys in `mihoro.toml`, or `$XDG_{DATA,CACHE,STATE}_HOME/mihoro`.
+    pub fn resolve(config: &DirsConfig) -> MihoroDirs {
+        MihoroDirs {
+            data: resolve_dir(
+                "MIHORO_DATA_DIR",
+                &config.data_dir,
+                "XDG_DATA_HOME",
+                "~/.local/share",
+            ),
+            cache: resolve_dir(
+                "MIHORO_CACHE_DIR",
+                &config.cache_dir,
+                "XDG_CACHE_HOME",
+                "~/.cache",
+            ),
+            state: resolve_dir(
+                "MIHORO_STATE_DIR",
+                &config.state_dir,
+                "XDG_STATE_HOME",
+                "~/.local/state",
+            ),
+        }
+    }
+}
+
+fn resolve_dir(
+    env_key: &str,
+    configured: &Option<String>,
+    xdg_key: &str,
+    xdg_default: &str,
+) -> String {
+    let non_empty_env = |key: &str| env::var(key).ok().filter(|value| !value.is_empty());
+    let dir = match (non_empty_env(env_key), configured) {
+        (Some(dir), _) => dir,
+        (None, Some(dir)) => dir.clone(),
+        (None, None) => {
+            let xdg_home = non_empty_env(xdg_key).unwrap_or_else(|| xdg_default.to_owned());
+            format!("{xdg_home}/mihoro")
+        }
+    };
+    tilde(&dir).to_string()
+}
@@ -0,0 +1,125 @@
+use crate::config::InitSystem;
+use crate::service::detect_init_system;
+use crate::sysctl::{read_sysctl, GATEWAY_SYSCTLS};
+
+use std::path::Path;
+
+use colored::Colorize;
+
+/// Result of a single `mihoro doctor` check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn pass(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_owned(),
+            passed: true,
+            detail,
+        }
+    }
+
+    pub fn fail(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_owned(),
+            passed: false,
+            detail,
+        }
+    }
+}
+
+/// Check that file `path` exists.
+pub fn check_file(name: &str, path: &str) -> Check {
+    if Path::new(path).exists() {
+        Check::pass(name, format!("found at {path}"))
+    } else {
+        Check::fail(name, format!("missing at {path}"))
+    }
+}
+
+/// Check that init system `init`, set with `service_manager` if `explicit`, is the one running.
+pub fn check_init_system(init: InitSystem, explicit: bool) -> Check {
+    let source = if explicit { "configured" } else { "detected" };
+    match detect_init_system() {
+        Some(detected) if detected != init => Check::fail(
+            "init system",
+            format!(
+                "{} configured, but {} is running",
+                init.name(),
+                detected.name()
+            ),
+        ),
+        Some(_) => Check::pass("init system", format!("{} ({source})", init.name())),
+        None if explicit => Check::pass("init system", format!("{} ({source})", init.name())),
+        None => Check::fail(
+            "init system",
+            format!(
+                "none detected, falling back to {}, set `service_manager`",
+                init.name()
+            ),
+        ),
+    }
+}
+
+/// Check kernel parameters required by gateway mode.
+pub fn check_gateway_sysctls() -> Vec<Check> {
+    GATEWAY_SYSCTLS
+        .iter()
+        .filter_map(|(key, expected)| {
+            read_sysctl(key).map(|value| {
+                if value == *expected {
+                    Check::pass(key, format!("= {value}"))
+                } else {
+                    Check::fail(key, format!("= {value}, expected {expected}"))
+                }
+            })
+        })
+        .collect()
+}
+
+/// Print checks as an aligned list.
+pub fn print_checks(checks: &[Check]) {
+    let width = checks
+        .iter()
+        .map(|check| check.name.len())
+        .max()
+        .unwrap_or(0);
+    for check in checks {
+        let mark = if check.passed {
+            "ok".green().bold()
+        } else {
+            "!!".red().bold()
+        };
+        println!(
+            "{} {:width$} {}",
+            mark,
+            check.name.bold(),
+            check.detail.dimmed()
+        );
+    }
+}
+
+/// Outcome of `mihoro healthcheck`, the first failing probe in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Healthy,
+    ServiceInactive,
+    ControllerUnreachable,
+    ProxyUnreachable,
+}
+
+impl Health {
+    /// Exit code reporting the outcome, distinct per failing probe.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Health::Healthy => 0,
+            Health::ServiceInactive => 1,
+            Health::ControllerUnreachable => 2,
+            Health::ProxyUnreachable => 3,
+        }
+    }
+}
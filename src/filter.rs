@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde_yaml::Value;
+
+/// Target given to proxy groups whose members were all filtered out.
+const FALLBACK_MEMBER: &str = "DIRECT";
+
+/// Outcome of filtering nodes with [`filter_nodes`].
+#[derive(Debug, Clone, Default)]
+pub struct FilteredNodes {
+    /// Names of the nodes dropped.
+    pub dropped: Vec<String>,
+    /// Proxy groups left without members, which now point at `DIRECT` for mihomo to load them.
+    pub emptied_groups: Vec<String>,
+}
+
+/// Compile the regexes listed under `field` in `mihoro.toml`.
+pub fn compile_patterns(field: &str, patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("invalid regex `{pattern}` in `{field}`"))
+        })
+        .collect()
+}
+
+/// Drop nodes from mihomo's `proxies` whose names match none of `include` (if any) or any of
+/// `exclude`, removing them from the members of `groups` too.
+///
+/// Fails if no node is left, as the config would be of no use.
+pub fn filter_nodes(
+    proxies: &mut Value,
+    groups: Option<&mut Value>,
+    include: &[String],
+    exclude: &[String],
+) -> Result<FilteredNodes> {
+    let mut filtered = FilteredNodes::default();
+    let Some(proxies) = proxies.as_sequence_mut() else {
+        return Ok(filtered);
+    };
+    if include.is_empty() && exclude.is_empty() {
+        return Ok(filtered);
+    }
+    let include = compile_patterns("node_include", include)?;
+    let exclude = compile_patterns("node_exclude", exclude)?;
+
+    let total = proxies.len();
+    proxies.retain(|proxy| {
+        let name = proxy["name"].as_str().unwrap_or_default();
+        let kept = (include.is_empty() || include.iter().any(|regex| regex.is_match(name)))
+            && !exclude.iter().any(|regex| regex.is_match(name));
+        if !kept {
+            filtered.dropped.push(name.to_owned());
+        }
+        kept
+    });
+    if total > 0 && proxies.is_empty() {
+        bail!("`node_include` and `node_exclude` filter out all {total} nodes");
+    }
+
+    for group in groups
+        .and_then(Value::as_sequence_mut)
+        .into_iter()
+        .flatten()
+    {
+        // Groups pulling members from providers or all proxies are not left empty
+        let has_other_sources = ["use", "include-all", "include-all-proxies"]
+            .iter()
+            .any(|key| {
+                group
+                    .get(key)
+                    .is_some_and(|value| value != &Value::Bool(false))
+            });
+        let name = group["name"].as_str().unwrap_or_default().to_owned();
+        let Some(members) = group.get_mut("proxies").and_then(Value::as_sequence_mut) else {
+            continue;
+        };
+        let before = members.len();
+        members.retain(|member| {
+            !member
+                .as_str()
+                .is_some_and(|member| filtered.dropped.iter().any(|name| name == member))
+        });
+        if before > 0 && members.is_empty() && !has_other_sources {
+            members.push(Value::from(FALLBACK_MEMBER));
+            filtered.emptied_groups.push(name);
+        }
+    }
+    Ok(filtered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_included_nodes_not_excluded() {
+        let mut proxies = yaml("[{name: HK 01}, {name: HK 02 Expire}, {name: JP 01}]");
+        let filtered = filter_nodes(
+            &mut proxies,
+            None,
+            &patterns(&["HK"]),
+            &patterns(&["Expire"]),
+        )
+        .unwrap();
+        assert_eq!(proxies, yaml("[{name: HK 01}]"));
+        assert_eq!(filtered.dropped, ["HK 02 Expire", "JP 01"]);
+    }
+
+    #[test]
+    fn points_emptied_groups_at_direct() {
+        let mut proxies = yaml("[{name: HK 01}, {name: JP 01}]");
+        let mut groups =
+            yaml("[{name: Japan, proxies: [JP 01]}, {name: All, proxies: [HK 01, JP 01]}]");
+        let filtered =
+            filter_nodes(&mut proxies, Some(&mut groups), &[], &patterns(&["JP"])).unwrap();
+        assert_eq!(
+            groups,
+            yaml("[{name: Japan, proxies: [DIRECT]}, {name: All, proxies: [HK 01]}]")
+        );
+        assert_eq!(filtered.emptied_groups, ["Japan"]);
+    }
+
+    #[test]
+    fn leaves_groups_with_other_sources_empty() {
+        let mut proxies = yaml("[{name: HK 01}, {name: JP 01}]");
+        let mut groups = yaml(
+            "[{name: Providers, use: [airport], proxies: [JP 01]},
+              {name: Everything, include-all: true, proxies: [JP 01]},
+              {name: Disabled, include-all: false, proxies: [JP 01]}]",
+        );
+        let filtered =
+            filter_nodes(&mut proxies, Some(&mut groups), &[], &patterns(&["JP"])).unwrap();
+        assert_eq!(groups[0]["proxies"], yaml("[]"));
+        assert_eq!(groups[1]["proxies"], yaml("[]"));
+        assert_eq!(groups[2]["proxies"], yaml("[DIRECT]"));
+        assert_eq!(filtered.emptied_groups, ["Disabled"]);
+    }
+
+    #[test]
+    fn fails_if_no_node_is_left() {
+        let mut proxies = yaml("[{name: HK 01}]");
+        assert!(filter_nodes(&mut proxies, None, &patterns(&["JP"]), &[]).is_err());
+    }
+}
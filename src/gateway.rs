@@ -0,0 +1,165 @@
+use crate::utils::{command_exists, RootCommand};
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Firewall used to redirect LAN traffic into mihomo.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallBackend {
+    Nftables,
+    Iptables,
+}
+
+/// How LAN traffic is redirected into mihomo.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GatewayMode {
+    /// TCP and UDP through mihomo's `tproxy-port`, with policy routing by firewall mark.
+    Tproxy,
+    /// TCP only through mihomo's `redir-port`.
+    Redir,
+}
+
+/// Gateway rules installed with `mihoro gateway enable`, recorded so that they can be removed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GatewayState {
+    pub backend: FirewallBackend,
+    pub mode: GatewayMode,
+    pub port: u16,
+
+    /// Kernel parameters before the sysctl drop-in was applied, restored on disable.
+    #[serde(default)]
+    pub previous_sysctls: BTreeMap<String, String>,
+}
+
+/// Destinations never redirected into mihomo: local, private and multicast ranges.
+const BYPASS_IPV4: [&str; 8] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+];
+
+/// Firewall mark and routing table used to deliver tproxy'd packets locally.
+const ROUTE_MARK: &str = "0x162";
+const ROUTE_TABLE: &str = "162";
+
+/// Detect the firewall backend, preferring nftables over iptables.
+pub fn detect_backend() -> Result<FirewallBackend> {
+    if command_exists("nft") {
+        Ok(FirewallBackend::Nftables)
+    } else if command_exists("iptables") {
+        Ok(FirewallBackend::Iptables)
+    } else {
+        bail!("neither `nft` nor `iptables` found, install nftables or iptables first")
+    }
+}
+
+/// Commands installing rules that redirect forwarded LAN traffic into mihomo.
+pub fn enable_commands(gateway: &GatewayState) -> Vec<RootCommand> {
+    let port = gateway.port;
+    let mut commands = match gateway.backend {
+        FirewallBackend::Nftables => {
+            let redirect = match gateway.mode {
+                GatewayMode::Tproxy => format!(
+                    "type filter hook prerouting priority mangle; policy accept;
+        ip daddr @bypass return
+        meta l4proto {{ tcp, udp }} meta mark set {ROUTE_MARK} tproxy ip to 127.0.0.1:{port} accept"
+                ),
+                GatewayMode::Redir => format!(
+                    "type nat hook prerouting priority dstnat; policy accept;
+        ip daddr @bypass return
+        meta l4proto tcp redirect to :{port}"
+                ),
+            };
+            let ruleset = format!(
+                "table inet mihoro {{
+    set bypass {{
+        type ipv4_addr; flags interval;
+        elements = {{ {} }}
+    }}
+    chain prerouting {{
+        {redirect}
+    }}
+}}
+",
+                BYPASS_IPV4.join(", ")
+            );
+            vec![RootCommand::new("nft -f -").stdin(ruleset)]
+        }
+        FirewallBackend::Iptables => {
+            let table = match gateway.mode {
+                GatewayMode::Tproxy => "mangle",
+                GatewayMode::Redir => "nat",
+            };
+            let mut commands = vec![RootCommand::new(&format!("iptables -t {table} -N MIHORO"))];
+            for cidr in BYPASS_IPV4 {
+                commands.push(RootCommand::new(&format!(
+                    "iptables -t {table} -A MIHORO -d {cidr} -j RETURN"
+                )));
+            }
+            match gateway.mode {
+                GatewayMode::Tproxy => {
+                    for protocol in ["tcp", "udp"] {
+                        commands.push(RootCommand::new(&format!(
+                            "iptables -t mangle -A MIHORO -p {protocol} -j TPROXY \
+                             --on-ip 127.0.0.1 --on-port {port} --tproxy-mark {ROUTE_MARK}"
+                        )));
+                    }
+                }
+                GatewayMode::Redir => commands.push(RootCommand::new(&format!(
+                    "iptables -t nat -A MIHORO -p tcp -j REDIRECT --to-ports {port}"
+                ))),
+            }
+            commands.push(RootCommand::new(&format!(
+                "iptables -t {table} -A PREROUTING -j MIHORO"
+            )));
+            commands
+        }
+    };
+
+    // Deliver marked packets to the local tproxy listener
+    if gateway.mode == GatewayMode::Tproxy {
+        commands.push(RootCommand::new(&format!(
+            "ip rule add fwmark {ROUTE_MARK} table {ROUTE_TABLE}"
+        )));
+        commands.push(RootCommand::new(&format!(
+            "ip route add local 0.0.0.0/0 dev lo table {ROUTE_TABLE}"
+        )));
+    }
+    commands
+}
+
+/// Commands removing rules installed by [`enable_commands`].
+pub fn disable_commands(gateway: &GatewayState) -> Vec<RootCommand> {
+    let mut commands = match gateway.backend {
+        FirewallBackend::Nftables => vec![RootCommand::new("nft delete table inet mihoro")],
+        FirewallBackend::Iptables => {
+            let table = match gateway.mode {
+                GatewayMode::Tproxy => "mangle",
+                GatewayMode::Redir => "nat",
+            };
+            vec![
+                RootCommand::new(&format!("iptables -t {table} -D PREROUTING -j MIHORO")),
+                RootCommand::new(&format!("iptables -t {table} -F MIHORO")),
+                RootCommand::new(&format!("iptables -t {table} -X MIHORO")),
+            ]
+        }
+    };
+    if gateway.mode == GatewayMode::Tproxy {
+        commands.push(RootCommand::new(&format!(
+            "ip rule del fwmark {ROUTE_MARK} table {ROUTE_TABLE}"
+        )));
+        commands.push(RootCommand::new(&format!(
+            "ip route del local 0.0.0.0/0 dev lo table {ROUTE_TABLE}"
+        )));
+    }
+    commands
+}
@@ -0,0 +1,74 @@
+use crate::utils::create_parent_dir;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+
+/// Copy the core config at `config_path` into `history_root` as a timestamped snapshot.
+///
+/// Returns the snapshot's path, or `None` if there is no config to snapshot yet.
+pub fn snapshot_config(config_path: &str, history_root: &str) -> Result<Option<PathBuf>> {
+    let config_path = Path::new(config_path);
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    let extension = config_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("yaml");
+    let snapshot_path = Path::new(history_root).join(format!(
+        "config-{}.{extension}",
+        Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    create_parent_dir(&snapshot_path.to_string_lossy())?;
+    fs::copy(config_path, &snapshot_path)
+        .with_context(|| format!("failed to snapshot config to {}", snapshot_path.display()))?;
+    Ok(Some(snapshot_path))
+}
+
+/// Config snapshots in `history_root`, newest first.
+pub fn list_snapshots(history_root: &str) -> Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(history_root) else {
+        return Ok(Vec::new());
+    };
+    let mut snapshots = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("config-"))
+        })
+        .collect::<Vec<_>>();
+    // Timestamps in filenames sort chronologically
+    snapshots.sort();
+    snapshots.reverse();
+    Ok(snapshots)
+}
+
+/// Remove snapshots beyond the newest `keep` or older than `max_age_days`, returning how many
+/// were removed.
+pub fn prune_snapshots(
+    history_root: &str,
+    keep: usize,
+    max_age_days: Option<u64>,
+) -> Result<usize> {
+    let max_age = max_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let mut removed = 0;
+    for (index, snapshot) in list_snapshots(history_root)?.iter().enumerate() {
+        let expired = max_age.is_some_and(|max_age| {
+            fs::metadata(snapshot)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > max_age)
+        });
+        if index >= keep || expired {
+            fs::remove_file(snapshot)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
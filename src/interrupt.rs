@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use colored::Colorize;
+
+/// Files or directories currently being written, removed if mihoro is interrupted before they are
+/// complete.
+static PARTIAL_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Whether Ctrl-C is left to the running command, set by [`handle_elsewhere`].
+static HANDLED_ELSEWHERE: AtomicBool = AtomicBool::new(false);
+
+/// Register `path` as partially written until [`untrack`] is called.
+pub fn track(path: &str) {
+    if let Ok(mut files) = PARTIAL_FILES.lock() {
+        files.push(path.to_owned());
+    }
+}
+
+/// Unregister `path`, once it is complete or removed.
+pub fn untrack(path: &str) {
+    if let Ok(mut files) = PARTIAL_FILES.lock() {
+        files.retain(|file| file != path);
+    }
+}
+
+/// Remove all partially written files, returning the removed paths.
+pub fn remove_partial_files() -> Vec<String> {
+    let Ok(mut files) = PARTIAL_FILES.lock() else {
+        return Vec::new();
+    };
+    files
+        .drain(..)
+        .filter(|file| {
+            if Path::new(file).is_dir() {
+                fs::remove_dir_all(file).is_ok()
+            } else {
+                fs::remove_file(file).is_ok()
+            }
+        })
+        .collect()
+}
+
+/// Leave Ctrl-C to the running command instead of exiting, e.g. so that `mihoro run` stops the
+/// core it supervises first.
+pub fn handle_elsewhere() {
+    HANDLED_ELSEWHERE.store(true, Ordering::SeqCst);
+}
+
+/// Exit cleanly on Ctrl-C, removing partially written files first, unless [`handle_elsewhere`]
+/// was called.
+///
+/// Files are only ever written in full to their final path by renaming complete partial files
+/// into place, so that an interrupted run never leaves a half-written config or binary behind.
+pub async fn handle_ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() || HANDLED_ELSEWHERE.load(Ordering::SeqCst) {
+        return;
+    }
+    let removed = remove_partial_files();
+    eprintln!();
+    if !removed.is_empty() {
+        eprintln!(
+            "{} Interrupted, removed partial files: {}",
+            "mihoro:".yellow(),
+            removed.join(", ")
+        );
+    }
+    std::process::exit(130);
+}
@@ -0,0 +1,82 @@
+use crate::utils::{command_exists, RootCommand};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Host firewall that proxy ports are opened in for LAN clients.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostFirewall {
+    Firewalld,
+    Ufw,
+}
+
+/// Ports opened with `mihoro lan open`, recorded so that exactly these are closed again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LanState {
+    pub firewall: HostFirewall,
+    pub ports: Vec<u16>,
+}
+
+/// Detect the host firewall, preferring firewalld over ufw.
+pub fn detect_firewall() -> Result<HostFirewall> {
+    if command_exists("firewall-cmd") {
+        Ok(HostFirewall::Firewalld)
+    } else if command_exists("ufw") {
+        Ok(HostFirewall::Ufw)
+    } else {
+        bail!("neither `firewall-cmd` nor `ufw` found, open proxy ports in your firewall manually")
+    }
+}
+
+/// Commands allowing TCP and UDP traffic to `lan.ports`.
+pub fn open_commands(lan: &LanState) -> Vec<RootCommand> {
+    match lan.firewall {
+        HostFirewall::Firewalld => {
+            let mut commands: Vec<RootCommand> = lan
+                .ports
+                .iter()
+                .flat_map(|port| {
+                    ["tcp", "udp"].map(|protocol| {
+                        RootCommand::new(&format!(
+                            "firewall-cmd --permanent --add-port={port}/{protocol}"
+                        ))
+                    })
+                })
+                .collect();
+            commands.push(RootCommand::new("firewall-cmd --reload"));
+            commands
+        }
+        HostFirewall::Ufw => lan
+            .ports
+            .iter()
+            .map(|port| RootCommand::new(&format!("ufw allow {port} comment mihoro")))
+            .collect(),
+    }
+}
+
+/// Commands removing the rules added by [`open_commands`].
+pub fn close_commands(lan: &LanState) -> Vec<RootCommand> {
+    match lan.firewall {
+        HostFirewall::Firewalld => {
+            let mut commands: Vec<RootCommand> = lan
+                .ports
+                .iter()
+                .flat_map(|port| {
+                    ["tcp", "udp"].map(|protocol| {
+                        RootCommand::new(&format!(
+                            "firewall-cmd --permanent --remove-port={port}/{protocol}"
+                        ))
+                    })
+                })
+                .collect();
+            commands.push(RootCommand::new("firewall-cmd --reload"));
+            commands
+        }
+        HostFirewall::Ufw => lan
+            .ports
+            .iter()
+            .map(|port| RootCommand::new(&format!("ufw delete allow {port}")))
+            .collect(),
+    }
+}
@@ -0,0 +1,99 @@
+//! `mihoro_core` - the library behind the `mihoro` CLI.
+//!
+//! Exposes the building blocks `mihoro` uses to manage a per-user mihomo instance, so that other
+//! tools (GUIs, status bars, provisioning scripts) can embed the same functionality:
+//!
+//! * [`api`] - a client for mihomo's external controller API.
+//! * [`backup`] - archiving and restoring mihoro and mihomo configs.
+//! * [`config`] - parse `mihoro.toml` and apply overrides to mihomo's `config.yaml`.
+//! * [`convert`] - converting share-link and sing-box subscriptions to mihomo configs.
+//! * [`cron`] - crontab entries and systemd timers running `mihoro update` periodically.
+//! * [`dirs`] - XDG base directories for mihoro's own data, cache and state.
+//! * [`diff`] - comparing core configs, e.g. before and after applying overrides.
+//! * [`doctor`] - checks diagnosing common setup problems.
+//! * [`filter`] - filtering the remote config's nodes by name.
+//! * [`gateway`] - firewall rules turning the machine into a transparent proxy gateway.
+//! * [`history`] - snapshots of previous core configs with retention.
+//! * [`interrupt`] - cleaning up partially written files on Ctrl-C.
+//! * [`lan`] - opening proxy ports in the host firewall for LAN clients.
+//! * [`lock`] - checksums of installed artifacts recorded in `mihoro.lock`.
+//! * [`logfile`] - logging mihomo to a file with rotation.
+//! * [`merge`] - merging the nodes of additional subscriptions into the remote config.
+//! * [`migrate`] - migrating existing clashrup or Clash setups.
+//! * [`mihoro`] - high level operations (setup, update, apply, uninstall) on top of a [`Config`].
+//! * [`nodes`] - proxy nodes and groups read from the core config.
+//! * [`notify`] - desktop notifications summarizing config updates.
+//! * [`proxy`] - shell commands for exporting and unsetting proxy environment variables.
+//! * [`quality`] - delay test history scoring nodes by stability.
+//! * [`release`] - checking for newer mihoro releases.
+//! * [`resolver`] - pointing the host's resolver to mihomo's DNS server.
+//! * [`rules`] - merging rule sources, dropping duplicates and finding shadowed rules.
+//! * [`remote`] - staging and syncing files to remote machines managed over SSH.
+//! * [`rename`] - renaming the remote config's nodes by regex replacements and templates.
+//! * [`schedule`] - time windows switching proxy groups to given nodes.
+//! * [`serve`] - an authenticated endpoint for triggering updates remotely.
+//! * [`service`] - running mihomo under systemd, OpenRC, runit, s6, SysV init or launchd, and
+//!   detecting which one is running.
+//! * [`sysctl`] - kernel parameters required by gateway mode.
+//! * [`state`] - state persisted by `mihoro` between runs.
+//! * [`subscription`] - traffic usage and expiry reported by subscription providers.
+//! * [`summary`] - the table summarizing each step of a setup or update run.
+//! * [`supervise`] - running the core in the foreground with `mihoro run`, e.g. in containers.
+//! * [`ui`] - installing web dashboards served through `external_ui`.
+//! * [`updates`] - results of recent config updates, listed by `mihoro sub history`.
+//! * [`watch`] - watching config files for changes (Linux only).
+//! * [`utils`] - downloading, extracting and decoding helpers.
+//!
+//! ```no_run
+//! use mihoro_core::Mihoro;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let mihoro = Mihoro::new("~/.config/mihoro.toml", None, None)?;
+//! mihoro.update(reqwest::Client::new()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod api;
+pub mod backup;
+pub mod config;
+pub mod convert;
+pub mod cron;
+pub mod diff;
+pub mod dirs;
+pub mod doctor;
+pub mod filter;
+pub mod gateway;
+pub mod history;
+pub mod interrupt;
+pub mod lan;
+pub mod lock;
+pub mod logfile;
+pub mod merge;
+pub mod migrate;
+pub mod mihoro;
+pub mod nodes;
+pub mod notify;
+pub mod proxy;
+pub mod quality;
+pub mod release;
+pub mod remote;
+pub mod rename;
+pub mod resolver;
+pub mod rules;
+pub mod schedule;
+pub mod serve;
+pub mod service;
+pub mod state;
+pub mod subscription;
+pub mod summary;
+pub mod supervise;
+pub mod sysctl;
+pub mod ui;
+pub mod updates;
+pub mod utils;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+pub use config::Config;
+pub use mihoro::Mihoro;
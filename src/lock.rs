@@ -0,0 +1,129 @@
+use crate::utils::{create_parent_dir, write_file_atomic};
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Checksums of installed artifacts (binary, geodata, dashboard), recorded in `mihoro.lock` as they
+/// are installed and checked by `mihoro verify`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub artifacts: BTreeMap<String, LockedArtifact>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedArtifact {
+    pub path: String,
+    pub sha256: String,
+    /// URL the artifact was downloaded from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Outcome of re-hashing a locked artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Intact,
+    Modified { sha256: String },
+    Missing,
+}
+
+impl Lockfile {
+    /// Read lockfile from `path`, empty if it does not exist yet.
+    pub fn load(path: &str) -> Result<Lockfile> {
+        if !Path::new(path).exists() {
+            return Ok(Lockfile::default());
+        }
+        let raw = fs::read_to_string(path)?;
+        toml::from_str(&raw).with_context(|| format!("failed to parse {path}"))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        create_parent_dir(path)?;
+        write_file_atomic(path, toml::to_string(self)?)
+    }
+
+    /// Hash the artifact at `path` and record it as `name`, replacing any previous record.
+    pub fn record(
+        &mut self,
+        name: &str,
+        path: &str,
+        source: Option<&str>,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let artifact = LockedArtifact {
+            path: path.to_owned(),
+            sha256: sha256_path(Path::new(path))?,
+            source: source.map(String::from),
+            version: version.map(String::from),
+        };
+        self.artifacts.insert(name.to_owned(), artifact);
+        Ok(())
+    }
+
+    /// Re-hash all recorded artifacts, in name order.
+    pub fn verify(&self) -> Vec<(&String, &LockedArtifact, Verification)> {
+        self.artifacts
+            .iter()
+            .map(|(name, artifact)| {
+                let path = Path::new(&artifact.path);
+                let verification = if !path.exists() {
+                    Verification::Missing
+                } else {
+                    match sha256_path(path) {
+                        Ok(sha256) if sha256 == artifact.sha256 => Verification::Intact,
+                        Ok(sha256) => Verification::Modified { sha256 },
+                        Err(_) => Verification::Missing,
+                    }
+                };
+                (name, artifact, verification)
+            })
+            .collect()
+    }
+}
+
+/// SHA-256 of the file at `path`, or of a directory's relative file paths and contents in path
+/// order.
+pub fn sha256_path(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        let mut files = Vec::new();
+        collect_files(path, &mut files)?;
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(path).unwrap_or(&file);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            io::copy(&mut File::open(&file)?, &mut hasher)?;
+        }
+    } else {
+        io::copy(&mut File::open(path)?, &mut hasher)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// First 16 hex digits of `hash`, for display.
+pub fn short_hash(hash: &str) -> &str {
+    hash.get(..16).unwrap_or(hash)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
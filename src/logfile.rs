@@ -0,0 +1,99 @@
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Config for logging mihomo's output to a file instead of the journal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogFileConfig {
+    /// Path of the log file, relative to mihomo's config root unless absolute.
+    #[serde(default = "default_log_path")]
+    pub path: String,
+    /// Rotate when the log file grows beyond this size, in megabytes.
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    /// Number of rotated log files to keep.
+    #[serde(default = "default_keep")]
+    pub keep: u32,
+    /// Remove rotated log files older than this many days.
+    pub max_age_days: Option<u64>,
+}
+
+fn default_log_path() -> String {
+    String::from("mihomo.log")
+}
+
+fn default_max_size_mb() -> u64 {
+    10
+}
+
+fn default_keep() -> u32 {
+    5
+}
+
+/// Rotate log file at `path` if it exceeds the configured size, returns whether it was rotated.
+///
+/// Rotated files are numbered `<path>.1` (newest) to `<path>.<keep>` (oldest). The log file is
+/// copied and truncated in place, as mihomo keeps writing to the same open file descriptor.
+pub fn rotate_log(path: &str, log_file: &LogFileConfig) -> Result<bool> {
+    let rotated = match fs::metadata(path) {
+        Ok(metadata) if metadata.len() >= log_file.max_size_mb * 1024 * 1024 => {
+            if log_file.keep > 0 {
+                for index in (1..log_file.keep).rev() {
+                    let from = format!("{path}.{index}");
+                    if Path::new(&from).exists() {
+                        fs::rename(&from, format!("{path}.{}", index + 1))?;
+                    }
+                }
+                fs::copy(path, format!("{path}.1"))?;
+            }
+            OpenOptions::new().write(true).open(path)?.set_len(0)?;
+            true
+        }
+        _ => false,
+    };
+
+    // Remove rotated files beyond `keep` or older than `max_age_days`
+    let max_age = log_file
+        .max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    for index in 1..=log_file.keep + 1 {
+        let rotated_path = format!("{path}.{index}");
+        let Ok(metadata) = fs::metadata(&rotated_path) else {
+            continue;
+        };
+        let expired = max_age.is_some_and(|max_age| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > max_age)
+        });
+        if index > log_file.keep || expired {
+            fs::remove_file(&rotated_path)?;
+        }
+    }
+    Ok(rotated)
+}
+
+/// logrotate config rotating log file at `path` like [`rotate_log`] does.
+pub fn logrotate_snippet(path: &str, log_file: &LogFileConfig) -> String {
+    let max_age = log_file
+        .max_age_days
+        .map(|days| format!("    maxage {days}\n"))
+        .unwrap_or_default();
+    format!(
+        "{path} {{
+    size {}M
+    rotate {}
+{max_age}    copytruncate
+    missingok
+    notifempty
+}}",
+        log_file.max_size_mb, log_file.keep
+    )
+}
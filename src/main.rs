@@ -1,23 +1,36 @@
 mod cmd;
-mod config;
-mod mihoro;
-mod proxy;
-mod systemctl;
-mod utils;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use clap_complete::{
     generate,
     shells::{Bash, Fish, Zsh},
+    Generator,
 };
 use colored::Colorize;
 use reqwest::Client;
-use std::{io, process::Command};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+};
 
-use cmd::{Args, ClapShell, Commands};
-use mihoro::Mihoro;
-use systemctl::Systemctl;
+use cmd::{
+    Args, ClapShell, Commands, ConfigCommands, ConnectionsCommands, CronCommands, CtlCommands,
+    DnsCommands, GatewayCommands, LanCommands, ProvidersCommands, ProxyCommands, RulesCommands,
+    ScheduleCommands, SubCommands, UiCommands,
+};
+#[cfg(target_os = "linux")]
+use mihoro_core::watch::FileWatcher;
+use mihoro_core::{
+    interrupt,
+    migrate::{detect_legacy_setup, migrate_config},
+    nodes::print_nodes,
+    service::systemd::{enable_linger, Systemctl},
+    utils::{copy_to_clipboard, create_parent_dir},
+    Mihoro,
+};
+use shellexpand::tilde;
 
 #[tokio::main]
 async fn main() {
@@ -29,68 +42,397 @@ async fn main() {
 
 async fn cli() -> Result<()> {
     let args = Args::parse();
+    tokio::spawn(interrupt::handle_ctrl_c());
     let client = Client::new();
-    let mihoro = Mihoro::new(&args.mihoro_config)?;
 
+    // Migration creates mihoro config, so it cannot be parsed beforehand
+    if let Some(Commands::Migrate { force }) = &args.command {
+        return migrate(&args.mihoro_config, *force);
+    }
+
+    let mihoro = Mihoro::new(
+        &args.mihoro_config,
+        args.instance.as_deref(),
+        args.host.as_deref(),
+    )?;
+
+    let release_client = client.clone();
     match &args.command {
-        Some(Commands::Setup) => mihoro.setup(client).await?,
-        Some(Commands::Update) => mihoro.update(client).await?,
-        Some(Commands::UpdateGeodata) => mihoro.update_geodata(client).await?,
-        Some(Commands::Apply) => mihoro.apply().await?,
+        Some(Commands::Setup) => {
+            mihoro.setup(client).await?;
+            if mihoro.linger_missing() {
+                offer_linger(&mihoro.prefix, &mihoro.service_name)?;
+            }
+        }
+        Some(Commands::Update { offline, diff }) => {
+            mihoro.update_config(client, *offline, *diff).await?
+        }
+        Some(Commands::UpdateGeodata) => {
+            mihoro.update_geodata(client).await?;
+        }
+        Some(Commands::DiffRemote) => mihoro.diff_remote(client).await?,
+        Some(Commands::DiffRuntime) => mihoro.diff_runtime().await?,
+        Some(Commands::Apply { restart }) => mihoro.apply(*restart).await?,
+        Some(Commands::Nodes) => print_nodes(&mihoro.nodes()?),
+        Some(Commands::Exec { command }) => mihoro.exec(command)?,
+        Some(Commands::Verify) => mihoro.verify()?,
+        Some(Commands::Version) => mihoro.version(&client).await?,
+        Some(Commands::Upgrade { via_api }) => mihoro.upgrade(client, *via_api).await?,
+        Some(Commands::Doctor) => mihoro.doctor().await?,
+        Some(Commands::Healthcheck) => std::process::exit(mihoro.healthcheck().await.exit_code()),
+        Some(Commands::Gateway { gateway }) => match gateway {
+            Some(GatewayCommands::Enable) => mihoro.gateway_enable()?,
+            Some(GatewayCommands::Disable) => mihoro.gateway_disable()?,
+            None => (),
+        },
+        Some(Commands::Select { group, node, .. }) => mihoro.select(group, node.as_deref()).await?,
+        Some(Commands::Ping {
+            group: Some(group), ..
+        }) => mihoro.ping_group(group).await?,
+        Some(Commands::Ping { target, .. }) => mihoro.ping(target.as_deref()).await?,
+        Some(Commands::Connections {
+            connections,
+            domain,
+            process,
+        }) => match connections {
+            Some(ConnectionsCommands::Close { id: Some(id), .. }) => {
+                mihoro.connection_close(id).await?
+            }
+            Some(ConnectionsCommands::Close { domain, .. }) => {
+                mihoro.ctl_kill(domain.as_deref()).await?
+            }
+            None => {
+                mihoro
+                    .connections(domain.as_deref(), process.as_deref())
+                    .await?
+            }
+        },
+        Some(Commands::Traffic) => mihoro.traffic().await?,
+        Some(Commands::Stats) => mihoro.stats().await?,
+        Some(Commands::Mode { mode }) => mihoro.mode(mode.as_ref()).await?,
+        Some(Commands::Dns {
+            dns:
+                Some(DnsCommands::Query {
+                    domain,
+                    record_type,
+                }),
+        }) => mihoro.dns_query(domain, record_type).await?,
+        Some(Commands::Dns { dns: None }) => (),
+        Some(Commands::Providers { providers }) => match providers {
+            Some(ProvidersCommands::Update { name }) => {
+                mihoro.providers_update(name.as_deref()).await?
+            }
+            None => mihoro.ctl_providers().await?,
+        },
+        Some(Commands::Rules { rules }) => match rules {
+            Some(RulesCommands::Match { destination }) => mihoro.rules_match(destination).await?,
+            None => mihoro.rules().await?,
+        },
+        Some(Commands::Ctl { ctl }) => match ctl {
+            Some(CtlCommands::Groups) => mihoro.ctl_groups().await?,
+            Some(CtlCommands::Probe) => mihoro.ctl_probe().await?,
+            Some(CtlCommands::Providers) => mihoro.ctl_providers().await?,
+            Some(CtlCommands::History { node }) => mihoro.ctl_history(node)?,
+            Some(CtlCommands::Kill { pattern, .. }) => mihoro.ctl_kill(pattern.as_deref()).await?,
+            None => (),
+        },
+        Some(Commands::Schedule { schedule }) => match schedule {
+            Some(ScheduleCommands::Apply) => mihoro.schedule_apply().await?,
+            Some(ScheduleCommands::Install) => mihoro.schedule_install()?,
+            Some(ScheduleCommands::Uninstall) => mihoro.schedule_uninstall()?,
+            None => (),
+        },
+        Some(Commands::Cron { cron }) => match cron {
+            Some(CronCommands::Enable { backend, interval }) => {
+                mihoro.cron_enable(*backend, *interval)?
+            }
+            Some(CronCommands::Disable) => mihoro.cron_disable()?,
+            Some(CronCommands::Status) => mihoro.cron_status()?,
+            None => (),
+        },
+        Some(Commands::Sub { sub }) => match sub {
+            Some(SubCommands::Info) => mihoro.sub_info()?,
+            Some(SubCommands::Preview) => print_nodes(&mihoro.sub_preview(client).await?),
+            Some(SubCommands::History) => mihoro.sub_history()?,
+            None => (),
+        },
+        Some(Commands::Config { config }) => match config {
+            Some(ConfigCommands::History) => mihoro.config_history()?,
+            Some(ConfigCommands::Rollback { snapshot }) => {
+                mihoro.config_rollback(*snapshot).await?
+            }
+            None => (),
+        },
+        Some(Commands::Lan { lan }) => match lan {
+            Some(LanCommands::Open) => mihoro.lan_open()?,
+            Some(LanCommands::Close) => mihoro.lan_close()?,
+            None => (),
+        },
+        Some(Commands::Ui { ui }) => match ui {
+            Some(UiCommands::Install { dashboard }) => {
+                mihoro.ui_install(client, *dashboard).await?
+            }
+            Some(UiCommands::Update) => mihoro.ui_update(&client).await?,
+            Some(UiCommands::Open) => mihoro.ui_open()?,
+            None => (),
+        },
+        #[cfg(target_os = "linux")]
+        Some(Commands::Watch) => {
+            let mut watcher = FileWatcher::new(&[&mihoro.config_path])?;
+            println!(
+                "{} Watching {} for changes, press Ctrl-C to stop",
+                mihoro.prefix.cyan(),
+                mihoro.config_path.underline()
+            );
+            loop {
+                watcher.wait()?;
+                println!("{} Config changed, applying...", mihoro.prefix.cyan());
+
+                // Parse changed `mihoro.toml` again, and keep watching if it is invalid
+                let applied = match Mihoro::new(
+                    &args.mihoro_config,
+                    args.instance.as_deref(),
+                    args.host.as_deref(),
+                ) {
+                    Ok(mihoro) => mihoro.apply(false).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = applied {
+                    eprintln!("{} {}", "error:".bright_red().bold(), err);
+                }
+            }
+        }
+        Some(Commands::Serve) => mihoro.serve(client).await?,
+        Some(Commands::Run { update_interval }) => mihoro.run(client, *update_interval).await?,
+        Some(Commands::Backup { file }) => mihoro.backup(file)?,
+        Some(Commands::Restore { file }) => {
+            mihoro.restore(file)?;
+
+            // Parse restored `mihoro.toml` again before applying
+            Mihoro::new(
+                &args.mihoro_config,
+                args.instance.as_deref(),
+                args.host.as_deref(),
+            )?
+            .apply(false)
+            .await?
+        }
+        Some(Commands::Migrate { .. }) => (),
+        Some(Commands::Clean) => mihoro.clean()?,
         Some(Commands::Uninstall) => mihoro.uninstall()?,
-        Some(Commands::Proxy { proxy }) => mihoro.proxy_commands(proxy)?,
+        Some(Commands::Proxy { proxy, copy }) => match proxy {
+            Some(ProxyCommands::Export) => print_snippet(&mihoro.proxy_export(), *copy)?,
+            Some(ProxyCommands::ExportLan) => print_snippet(&mihoro.proxy_export_lan()?, *copy)?,
+            Some(ProxyCommands::Unset) => print_snippet(&mihoro.proxy_unset(), *copy)?,
+            Some(ProxyCommands::Ssh { host, install }) => {
+                if *install {
+                    mihoro.proxy_ssh_install(host)?;
+                } else {
+                    print_snippet(&mihoro.proxy_ssh(host), *copy)?;
+                }
+            }
+            Some(ProxyCommands::Test) => {
+                let elapsed = mihoro.proxy_test().await?;
+                println!(
+                    "{} Reached {} through proxy in {}ms",
+                    mihoro.prefix.green(),
+                    mihoro.config.test_url.underline(),
+                    elapsed.as_millis()
+                );
+            }
+            _ => (),
+        },
 
-        Some(Commands::Start) => Systemctl::new()
-            .start("mihomo.service")
-            .execute()
+        Some(Commands::Start) => mihoro
+            .start()
             .map(|_| {
-                println!("{} Started mihomo.service", mihoro.prefix.green());
-            })?,
+                println!("{} Started {}", mihoro.prefix.green(), mihoro.service_name);
+            })
+            .and_then(|_| mihoro.host_dns_configure())?,
 
         Some(Commands::Status) => {
-            Systemctl::new().status("mihomo.service").execute()?;
+            mihoro.service().status()?;
         }
 
-        Some(Commands::Stop) => Systemctl::new().stop("mihomo.service").execute().map(|_| {
-            println!("{} Stopped mihomo.service", mihoro.prefix.green());
-        })?,
+        Some(Commands::Stop { teardown }) => mihoro
+            .service()
+            .stop()
+            .map(|_| {
+                println!("{} Stopped {}", mihoro.prefix.green(), mihoro.service_name);
+            })
+            .and_then(|_| match teardown {
+                true => mihoro.teardown(),
+                false => mihoro.host_dns_revert(),
+            })?,
 
-        Some(Commands::Restart) => {
-            Systemctl::new()
-                .restart("mihomo.service")
-                .execute()
-                .map(|_| {
-                    println!("{} Restarted mihomo.service", mihoro.prefix.green());
-                })?
-        }
+        Some(Commands::Restart) => mihoro.restart().map(|_| {
+            println!(
+                "{} Restarted {}",
+                mihoro.prefix.green(),
+                mihoro.service_name
+            );
+        })?,
 
-        Some(Commands::Log) => {
-            Command::new("journalctl")
-                .arg("--user")
-                .arg("-xeu")
-                .arg("mihomo.service")
-                .arg("-n")
-                .arg("10")
-                .arg("-f")
+        Some(Commands::Log {
+            api: true, level, ..
+        }) => mihoro.log_api(level).await?,
+        Some(Commands::Log {
+            api: false,
+            lines,
+            since,
+            grep,
+            no_follow,
+            ..
+        }) => {
+            mihoro
+                .log_command(*lines, since.as_deref(), grep.as_deref(), !no_follow)?
                 .spawn()
                 .expect("failed to execute process")
                 .wait()?;
         }
 
-        Some(Commands::Completions { shell }) => match shell {
-            Some(ClapShell::Bash) => {
-                generate(Bash, &mut Args::command(), "mihoro", &mut io::stdout())
-            }
-            Some(ClapShell::Zsh) => {
-                generate(Zsh, &mut Args::command(), "mihoro", &mut io::stdout())
-            }
-            Some(ClapShell::Fish) => {
-                generate(Fish, &mut Args::command(), "mihoro", &mut io::stdout())
+        Some(Commands::Logrotate { snippet }) => {
+            if *snippet {
+                println!("{}", mihoro.logrotate_snippet()?);
+            } else {
+                mihoro.rotate_logs()?;
             }
+        }
+
+        Some(Commands::Completions { shell, install }) => match shell {
+            Some(ClapShell::Bash) => completions(Bash, *install)?,
+            Some(ClapShell::Zsh) => completions(Zsh, *install)?,
+            Some(ClapShell::Fish) => completions(Fish, *install)?,
             _ => (),
         },
 
         None => (),
     }
+
+    mihoro.release_hint(&release_client).await;
+    Ok(())
+}
+
+/// Offer to enable lingering, without which user services stop on logout (e.g. of an SSH session).
+fn offer_linger(prefix: &str, service_name: &str) -> Result<()> {
+    println!(
+        "{} Lingering is disabled, {} will stop once you log out",
+        "warning:".yellow(),
+        service_name
+    );
+    print!(
+        "{} Enable it with `loginctl enable-linger`? [y/N] ",
+        prefix.cyan()
+    );
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        enable_linger()?;
+        println!("{} Enabled lingering", prefix.green());
+    }
+    Ok(())
+}
+
+/// Print shell commands, and copy them to the clipboard if `copy` is set.
+fn print_snippet(snippet: &str, copy: bool) -> Result<()> {
+    println!("{snippet}");
+    if copy {
+        // Report on stderr, so that stdout stays usable with `eval`
+        let method = copy_to_clipboard(snippet)?;
+        eprintln!("{} Copied to clipboard with {}", "mihoro:".green(), method);
+    }
+    Ok(())
+}
+
+/// Print completions for `shell`, or install them to the shell's per-user completions directory.
+fn completions<G: Generator>(shell: G, install: bool) -> Result<()> {
+    if !install {
+        generate(shell, &mut Args::command(), "mihoro", &mut io::stdout());
+        return Ok(());
+    }
+
+    let xdg_dir = |var: &str, default: &str| {
+        env::var(var)
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| tilde(default).to_string())
+    };
+    let path = match shell.file_name("mihoro").as_str() {
+        "_mihoro" => format!("{}/.zfunc/_mihoro", xdg_dir("ZDOTDIR", "~")),
+        "mihoro.fish" => format!(
+            "{}/fish/completions/mihoro.fish",
+            xdg_dir("XDG_CONFIG_HOME", "~/.config")
+        ),
+        _ => format!(
+            "{}/bash-completion/completions/mihoro",
+            xdg_dir("XDG_DATA_HOME", "~/.local/share")
+        ),
+    };
+    create_parent_dir(&path)?;
+    let mut file = fs::File::create(&path)?;
+    generate(shell, &mut Args::command(), "mihoro", &mut file);
+    println!(
+        "{} Installed completions to {}",
+        "mihoro:".green(),
+        path.underline().yellow()
+    );
+    if path.ends_with("_mihoro") {
+        println!(
+            "{} Make sure `{}` is added before `compinit` in your .zshrc",
+            "mihoro:".yellow(),
+            "fpath=(~/.zfunc $fpath)".bold()
+        );
+    }
+    Ok(())
+}
+
+/// Migrate clashrup or Clash settings into mihoro config and offer to disable the old service.
+fn migrate(mihoro_config: &str, force: bool) -> Result<()> {
+    let prefix = "mihoro:";
+    let config_path = tilde(mihoro_config).to_string();
+    if Path::new(&config_path).exists() && !force {
+        bail!(
+            "`{}` already exists, pass `--force` to overwrite",
+            config_path.underline()
+        );
+    }
+
+    let legacy = detect_legacy_setup()
+        .with_context(|| "neither `~/.config/clashrup.toml` nor `~/.config/clash` found")?;
+    let mut config = migrate_config(&legacy)?;
+    create_parent_dir(&config_path)?;
+    config.write(Path::new(&config_path))?;
+    println!(
+        "{} Migrated {} to {}",
+        prefix.green(),
+        legacy.config_path().underline(),
+        config_path.underline().yellow()
+    );
+
+    if config.remote_config_url.is_empty() {
+        println!(
+            "{} Subscription url unknown, fill in `{}`",
+            prefix.yellow(),
+            "remote_config_url".bold()
+        );
+    }
+    println!(
+        "{} Fill in `{}` and run `{}` to finish setup",
+        prefix.yellow(),
+        "remote_mihomo_binary_url".bold(),
+        "mihoro setup".bold()
+    );
+
+    let service = legacy.service_name();
+    print!("{} Stop and disable {}? [y/N] ", prefix.cyan(), service);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Systemctl::new().stop(service).execute()?;
+        Systemctl::new().disable(service).execute()?;
+        println!("{} Stopped and disabled {}", prefix.green(), service);
+    }
     Ok(())
 }
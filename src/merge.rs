@@ -0,0 +1,132 @@
+use crate::convert::unique_name;
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde_yaml::{Mapping, Value};
+
+/// Merge the nodes of subscription `name`'s mihomo config `subscription` into `config`. Returns
+/// the number of nodes merged.
+///
+/// The nodes are appended to `proxies`, renamed if their names are taken, and selected with a new
+/// `select` group named `name`, which joins the members of the config's first `select` group so
+/// that it can be picked where the config's own nodes are.
+pub fn merge_subscription(config: &mut Value, name: &str, subscription: &Value) -> Result<usize> {
+    let Some(config) = config.as_mapping_mut() else {
+        bail!("the remote config is not a mihomo config to merge subscriptions into");
+    };
+    let proxies = subscription
+        .get("proxies")
+        .and_then(Value::as_sequence)
+        .cloned()
+        .unwrap_or_default();
+    if proxies.is_empty() {
+        bail!("subscription `{name}` has no proxies");
+    }
+
+    // Node and group names share a namespace in mihomo
+    let mut names: HashSet<String> = ["proxies", "proxy-groups"]
+        .iter()
+        .filter_map(|key| config.get(*key).and_then(Value::as_sequence))
+        .flatten()
+        .filter_map(|entry| entry["name"].as_str().map(String::from))
+        .collect();
+    let mut members = Vec::new();
+    let mut merged = Vec::new();
+    for mut proxy in proxies {
+        let Some(proxy_name) = proxy.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        let proxy_name = unique_name(proxy_name, &mut names);
+        proxy["name"] = Value::from(proxy_name.clone());
+        members.push(Value::from(proxy_name));
+        merged.push(proxy);
+    }
+    let count = merged.len();
+    sequence_mut(config, "proxies").extend(merged);
+
+    let group_name = unique_name(name, &mut names);
+    let groups = sequence_mut(config, "proxy-groups");
+    if let Some(select) = groups
+        .iter_mut()
+        .find(|group| group["type"].as_str() == Some("select"))
+    {
+        if let Some(select_members) = select.get_mut("proxies").and_then(Value::as_sequence_mut) {
+            select_members.push(Value::from(group_name.as_str()));
+        }
+    }
+    let group: Mapping = [
+        ("name", Value::from(group_name)),
+        ("type", Value::from("select")),
+        ("proxies", Value::Sequence(members)),
+    ]
+    .into_iter()
+    .map(|(key, value)| (Value::from(key), value))
+    .collect();
+    groups.push(Value::Mapping(group));
+    Ok(count)
+}
+
+/// Sequence under `key` in `config`, created if missing or not a sequence.
+fn sequence_mut<'a>(config: &'a mut Mapping, key: &str) -> &'a mut Vec<Value> {
+    let value = config
+        .entry(Value::from(key))
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    if !value.is_sequence() {
+        *value = Value::Sequence(Vec::new());
+    }
+    value.as_sequence_mut().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(raw: &str) -> Value {
+        serde_yaml::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn merges_nodes_into_a_group_of_their_own() {
+        let mut config = yaml(
+            "proxies:\n- {name: A, type: ss}\nproxy-groups:\n\
+             - {name: Auto, type: url-test, proxies: [A]}\n\
+             - {name: Proxy, type: select, proxies: [Auto, A]}\n",
+        );
+        let subscription = yaml("proxies:\n- {name: A, type: trojan}\n- {name: B, type: vmess}\n");
+        let merged = merge_subscription(&mut config, "work", &subscription).unwrap();
+
+        assert_eq!(merged, 2);
+        let names: Vec<&str> = config["proxies"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|proxy| proxy["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["A", "A 2", "B"]);
+        assert_eq!(config["proxy-groups"][0]["proxies"], yaml("[A]"));
+        assert_eq!(
+            config["proxy-groups"][1]["proxies"],
+            yaml("[Auto, A, work]")
+        );
+        assert_eq!(config["proxy-groups"][2]["name"], "work");
+        assert_eq!(config["proxy-groups"][2]["proxies"], yaml("[A 2, B]"));
+    }
+
+    #[test]
+    fn renames_groups_clashing_with_node_names() {
+        let mut config = yaml("proxies:\n- {name: work, type: ss}\n");
+        let subscription = yaml("proxies:\n- {name: B, type: ss}\n");
+        merge_subscription(&mut config, "work", &subscription).unwrap();
+        assert_eq!(config["proxy-groups"][0]["name"], "work 2");
+    }
+
+    #[test]
+    fn rejects_subscriptions_without_proxies() {
+        let mut config = yaml("proxies: []\n");
+        assert!(merge_subscription(&mut config, "work", &yaml("rules: []\n")).is_err());
+        assert!(
+            merge_subscription(&mut yaml("[]"), "work", &yaml("proxies: [{name: A}]")).is_err()
+        );
+    }
+}
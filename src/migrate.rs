@@ -0,0 +1,143 @@
+use crate::config::{Config, MihomoLogLevel, MihomoMode};
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use shellexpand::tilde;
+
+/// An existing Clash setup that can be migrated to mihoro.
+#[derive(Debug, Clone)]
+pub enum LegacySetup {
+    /// `clashrup`, mihoro's predecessor, configured by `~/.config/clashrup.toml`.
+    Clashrup { config_path: String },
+    /// A plain Clash installation with its config under `~/.config/clash`.
+    Clash { config_path: String },
+}
+
+impl LegacySetup {
+    /// Name of the user systemd service running the legacy setup.
+    pub fn service_name(&self) -> &'static str {
+        "clash.service"
+    }
+
+    pub fn config_path(&self) -> &str {
+        match self {
+            LegacySetup::Clashrup { config_path } | LegacySetup::Clash { config_path } => {
+                config_path
+            }
+        }
+    }
+}
+
+/// `clashrup.toml`, only fields that carry over to mihoro.
+#[derive(Deserialize)]
+struct ClashrupConfig {
+    remote_config_url: String,
+    user_systemd_root: Option<String>,
+    clash_config: ClashrupClashConfig,
+}
+
+#[derive(Deserialize)]
+struct ClashrupClashConfig {
+    port: u16,
+    socks_port: u16,
+    allow_lan: Option<bool>,
+    bind_address: Option<String>,
+    mode: Option<MihomoMode>,
+    log_level: Option<MihomoLogLevel>,
+    ipv6: Option<bool>,
+    external_controller: Option<String>,
+    external_ui: Option<String>,
+}
+
+/// Clash's `config.yaml`, only fields that carry over to mihoro.
+#[derive(Deserialize)]
+struct ClashYamlConfig {
+    port: Option<u16>,
+    #[serde(rename = "socks-port")]
+    socks_port: Option<u16>,
+    #[serde(rename = "mixed-port")]
+    mixed_port: Option<u16>,
+    #[serde(rename = "allow-lan")]
+    allow_lan: Option<bool>,
+    #[serde(rename = "bind-address")]
+    bind_address: Option<String>,
+    mode: Option<MihomoMode>,
+    #[serde(rename = "log-level")]
+    log_level: Option<MihomoLogLevel>,
+    ipv6: Option<bool>,
+    #[serde(rename = "external-controller")]
+    external_controller: Option<String>,
+    secret: Option<String>,
+}
+
+/// Detect an existing clashrup or Clash setup, preferring clashrup as it knows the subscription.
+pub fn detect_legacy_setup() -> Option<LegacySetup> {
+    let clashrup_path = tilde("~/.config/clashrup.toml").to_string();
+    if Path::new(&clashrup_path).exists() {
+        return Some(LegacySetup::Clashrup {
+            config_path: clashrup_path,
+        });
+    }
+    let clash_path = tilde("~/.config/clash/config.yaml").to_string();
+    if Path::new(&clash_path).exists() {
+        return Some(LegacySetup::Clash {
+            config_path: clash_path,
+        });
+    }
+    None
+}
+
+/// Convert a legacy setup's settings into mihoro's config, on top of mihoro's defaults.
+///
+/// The Clash binary is never reused, as mihoro manages the mihomo core instead.
+pub fn migrate_config(legacy: &LegacySetup) -> Result<Config> {
+    let mut config = Config::new();
+    let raw_config = fs::read_to_string(legacy.config_path())
+        .with_context(|| format!("failed to read `{}`", legacy.config_path()))?;
+
+    match legacy {
+        LegacySetup::Clashrup { .. } => {
+            let clashrup: ClashrupConfig = toml::from_str(&raw_config)?;
+            config.remote_config_url = clashrup.remote_config_url;
+            if let Some(user_systemd_root) = clashrup.user_systemd_root {
+                config.user_systemd_root = user_systemd_root;
+            }
+
+            let (from, to) = (clashrup.clash_config, &mut config.mihomo_config);
+            to.port = from.port;
+            to.socks_port = from.socks_port;
+            // clashrup had no mixed port, keep exported ports the same as before
+            to.mixed_port = None;
+            to.allow_lan = from.allow_lan;
+            to.bind_address = from.bind_address;
+            to.mode = from.mode.unwrap_or(MihomoMode::Rule);
+            to.log_level = from.log_level.unwrap_or(MihomoLogLevel::Info);
+            to.ipv6 = from.ipv6;
+            to.external_controller = from.external_controller;
+            to.external_ui = from.external_ui;
+        }
+        LegacySetup::Clash { .. } => {
+            let clash: ClashYamlConfig = serde_yaml::from_str(&raw_config)?;
+            let to = &mut config.mihomo_config;
+            if clash.port.is_none() && clash.mixed_port.is_none() {
+                bail!("neither `port` nor `mixed-port` defined in Clash config");
+            }
+            to.port = clash.port.or(clash.mixed_port).unwrap_or(to.port);
+            to.socks_port = clash
+                .socks_port
+                .or(clash.mixed_port)
+                .unwrap_or(to.socks_port);
+            to.mixed_port = clash.mixed_port;
+            to.allow_lan = clash.allow_lan.or(to.allow_lan);
+            to.bind_address = clash.bind_address.or(to.bind_address.take());
+            to.mode = clash.mode.unwrap_or(MihomoMode::Rule);
+            to.log_level = clash.log_level.unwrap_or(MihomoLogLevel::Info);
+            to.ipv6 = clash.ipv6.or(to.ipv6);
+            to.external_controller = clash.external_controller.or(to.external_controller.take());
+            to.secret = clash.secret;
+        }
+    }
+    Ok(config)
+}
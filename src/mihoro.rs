@@ -1,305 +1,4020 @@
-use crate::cmd::ProxyCommands;
-use crate::config::{apply_mihomo_override, parse_config, Config};
-use crate::proxy::{proxy_export_cmd, proxy_unset_cmd};
-use crate::systemctl::Systemctl;
+use crate::api::{ApiClient, ApiConnection, ApiProvider, ApiRule};
+use crate::backup::{create_backup, restore_backup};
+use crate::config::{
+    apply_mihomo_override, expand_env_placeholders, parse_config, Config, Core, InitSystem,
+    MihomoLogLevel, MihomoMode, RemoteConfigFormat, Subscription,
+};
+use crate::convert::{
+    convert_sing_box_file_inplace, try_convert_share_links_file_inplace, Conversion, SELECT_GROUP,
+};
+use crate::cron::{
+    crontab_entry, find_entry, interval_schedule, read_crontab, remove_entry, update_units,
+    write_crontab, CronBackend, UpdateTask,
+};
+use crate::diff::{
+    canonical_config, common_fields, diff_configs, print_changes, print_unified_diff,
+    read_core_config,
+};
+use crate::dirs::MihoroDirs;
+use crate::doctor::{
+    check_file, check_gateway_sysctls, check_init_system, print_checks, Check, Health,
+};
+use crate::gateway::{
+    detect_backend, disable_commands, enable_commands, GatewayMode, GatewayState,
+};
+use crate::history::{list_snapshots, prune_snapshots, snapshot_config};
+use crate::interrupt;
+use crate::lan::{close_commands, detect_firewall, open_commands, LanState};
+use crate::lock::{short_hash, Lockfile, Verification};
+use crate::logfile::{logrotate_snippet, rotate_log};
+use crate::merge::merge_subscription;
+use crate::nodes::Nodes;
+use crate::notify::{send_notification, ConfigSummary};
+use crate::proxy::{proxy_env, proxy_export_cmd, proxy_unset_cmd, ssh_config_stanza, test_proxy};
+use crate::quality::QualityHistory;
+use crate::release::newer_release;
+use crate::remote::Remote;
+use crate::resolver::{self, tun_dns_server, HostDnsState};
+use crate::rules::{cidr_contains, parse_cidr, rule_matches};
+use crate::schedule::{active_rule, schedule_units, FASTEST};
+use crate::serve::serve;
+use crate::service::systemd::{linger_enabled, user_bus_available, Systemctl, SYSTEM_UNIT_ROOTS};
+use crate::service::{
+    detect_init_system, service_location, service_manager, ServiceManager, ServiceSpec,
+    ServiceTarget,
+};
+use crate::state::{DashboardState, RemoteConfigState, State};
+use crate::subscription::SubscriptionInfo;
+use crate::summary::RunSummary;
+use crate::supervise::supervise;
+use crate::sysctl::{self, current_gateway_sysctls, SYSCTL_DROPIN_PATH};
+use crate::ui::{dashboard_url, install_dashboard, Dashboard};
+use crate::updates::{UpdateHistory, UpdateRun};
 use crate::utils::{
-    create_parent_dir, delete_file, download_file, extract_gzip, try_decode_base64_file_inplace,
+    asset_os, command_exists, create_parent_dir, delete_file, download_file_with_headers,
+    error_page_details, extract_gzip, extract_tar_gz_binary, github_latest_release, is_root,
+    release_os, shell_quote, try_convert_json_file_inplace, try_decode_base64_file_inplace,
+    try_decompress_file_inplace, write_file_atomic, Downloaded, HttpStatusError,
 };
 
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::os::unix::prelude::PermissionsExt;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::os::unix::prelude::{CommandExt, PermissionsExt};
+use std::path::Path;
+use std::pin::pin;
+use std::process::Command;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local, TimeZone, Utc};
 use colored::Colorize;
+use futures_util::{stream, StreamExt};
 use local_ip_address::local_ip;
-use reqwest::Client;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED,
+    },
+    Client, StatusCode,
+};
 use shellexpand::tilde;
 
+/// Shadowed rules are listed up to this many, as subscriptions often carry plenty of them.
+const MAX_SHADOWED_WARNINGS: usize = 10;
+
+/// Minutes a subscription is refreshed before its `update_interval` is up.
+const SUBSCRIPTION_DUE_SLACK_MINUTES: i64 = 10;
+
+/// Seconds of download throughput shown by `mihoro traffic`.
+const TRAFFIC_SPARKLINE_WIDTH: usize = 30;
+
+/// A `mihoro` instance, holding the parsed `mihoro.toml` and the mihomo paths derived from it.
 #[derive(Debug)]
 pub struct Mihoro {
-    // global mihoro config
+    // global mihoro config, or the config of the selected instance
     pub prefix: String,
+    pub config_path: String,
     pub config: Config,
+    pub service_name: String,
+    pub init_system: InitSystem,
+    pub instance: Option<String>,
 
     // mihomo global variables derived from mihoro config
     pub mihomo_target_binary_path: String,
     pub mihomo_target_config_root: String,
     pub mihomo_target_config_path: String,
     pub mihomo_target_service_path: String,
+    /// Snapshots of the core config, under `backups/` in its config root.
+    pub mihomo_target_backups_root: String,
+
+    // mihoro's own data, cache and state directories, and state persisted between runs
+    pub dirs: MihoroDirs,
+    pub mihoro_state_path: String,
+    pub mihoro_cache_root: String,
+
+    // remote machine managed over SSH, target paths above are then local staging paths
+    pub remote: Option<Remote>,
+}
+
+/// Remote config downloaded by [`Mihoro::download_remote_config`].
+struct FetchedConfig {
+    /// `remote_config_url` or the mirror in `remote_config_urls` the config was downloaded from.
+    url: String,
+    status: StatusCode,
+    headers: HeaderMap,
 }
 
 impl Mihoro {
-    pub fn new(config_path: &String) -> Result<Mihoro> {
-        let config = parse_config(tilde(&config_path).as_ref())?;
-        return Ok(Mihoro {
+    /// Parse `mihoro.toml` at `config_path` (with `~` expanded) and derive mihomo's target paths.
+    ///
+    /// If `instance` is given, the named instance's config is used instead and its service is
+    /// named `<service_name>@<instance>`. If `host` is given (an alias under `hosts` or an SSH
+    /// destination), mihomo is managed on that machine over SSH. A default config is created if
+    /// none is found at `config_path`, in which case an error is returned to ask the user to fill
+    /// it in first.
+    pub fn new(config_path: &str, instance: Option<&str>, host: Option<&str>) -> Result<Mihoro> {
+        let config_path = tilde(config_path).to_string();
+        let mut config = parse_config(&config_path)?;
+        if let Some(name) = instance {
+            config = config.instance(name)?;
+        }
+        let name = config.service_name.clone();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+            || name.ends_with(".service")
+            || name.matches('@').count() > 1
+            || name.starts_with('@')
+            || name.ends_with('@')
+        {
+            bail!(
+                "invalid `service_name` `{name}`, expected letters, digits, `-`, `_`, `.` or a \
+                 single `@` between them without a `.service` suffix"
+            );
+        }
+        let dirs = MihoroDirs::resolve(&config.dirs);
+        let remote =
+            host.map(|host| Remote::new(config.hosts.get(host).map_or(host, |d| d), &dirs.cache));
+        let target_path = |path: &str| match &remote {
+            Some(remote) => remote.stage_path(path),
+            None => tilde(path).to_string(),
+        };
+        // An explicit `service_manager` wins, remote hosts cannot be probed and default to systemd
+        let init_system = match (config.service_manager, &remote) {
+            (Some(init), _) => init,
+            (None, Some(_)) => InitSystem::Systemd,
+            (None, None) => detect_init_system().unwrap_or_default(),
+        };
+        let (service_name, service_path) = service_location(
+            init_system,
+            &name,
+            &config.user_systemd_root,
+            is_root() && remote.is_none(),
+        );
+        let mihomo_target_service_path = target_path(&service_path);
+
+        // State and cached configs are kept per managed instance, and per host if managed over SSH
+        let state_scope = match &remote {
+            Some(remote) => format!(
+                "hosts/{}/{}",
+                remote.destination,
+                instance.unwrap_or("default")
+            ),
+            None => instance.unwrap_or("default").to_owned(),
+        };
+        let mihoro_state_path = format!("{}/{}/state.toml", dirs.state, state_scope);
+        let mihomo_target_backups_root =
+            target_path(&format!("{}/backups", config.mihomo_config_root));
+        let mihoro_cache_root = format!("{}/{}", dirs.cache, state_scope);
+        migrate_legacy_state(
+            &target_path(&format!("{}/mihoro-state.toml", config.mihomo_config_root)),
+            &mihoro_state_path,
+        )?;
+        migrate_legacy_history(
+            &format!("{}/{}/history", dirs.data, state_scope),
+            &mihomo_target_backups_root,
+        )?;
+
+        Ok(Mihoro {
             prefix: String::from("mihoro:"),
+            config_path,
             config: config.clone(),
-            mihomo_target_binary_path: tilde(&config.mihomo_binary_path).to_string(),
-            mihomo_target_config_root: tilde(&config.mihomo_config_root).to_string(),
-            mihomo_target_config_path: tilde(&format!("{}/config.yaml", config.mihomo_config_root))
-                .to_string(),
-            mihomo_target_service_path: tilde(&format!(
-                "{}/mihomo.service",
-                config.user_systemd_root
-            ))
-            .to_string(),
-        });
+            mihomo_target_binary_path: target_path(&config.mihomo_binary_path),
+            mihomo_target_config_root: target_path(&config.mihomo_config_root),
+            mihomo_target_config_path: target_path(&format!(
+                "{}/{}",
+                config.mihomo_config_root,
+                config.config_filename()
+            )),
+            mihomo_target_service_path,
+            mihomo_target_backups_root,
+            service_name,
+            init_system,
+            instance: instance.map(String::from),
+            dirs,
+            mihoro_state_path,
+            mihoro_cache_root,
+            remote,
+        })
     }
 
-    pub async fn setup(&self, client: Client) -> Result<()> {
-        println!(
-            "{} Setting up mihomo's binary, config, and systemd service...",
-            &self.prefix.cyan()
-        );
-
-        // Attempt to download and setup mihomo binary if needed
-        if fs::metadata(&self.mihomo_target_binary_path).is_ok() {
-            // If mihomo binary already exists at `mihomo_target_binary_path`, then skip setup
-            println!(
-                "{} Assuming mihomo binary already installed at {}, skipping setup",
-                self.prefix.yellow(),
-                self.mihomo_target_binary_path.underline().green()
+    /// Print a hint to stderr if a newer mihoro release is available, unless `check_release` is off.
+    pub async fn release_hint(&self, client: &Client) {
+        if !self.config.check_release {
+            return;
+        }
+        let cache_path = format!("{}/latest-release.toml", self.dirs.cache);
+        if let Ok(Some(latest)) = newer_release(client, &cache_path).await {
+            eprintln!(
+                "{} mihoro {} is available (installed v{}), set `check_release = false` to silence",
+                "hint:".cyan(),
+                latest.bold(),
+                env!("CARGO_PKG_VERSION")
             );
-        } else {
-            // Download mihomo binary and set permission to executable
-            download_file(
-                &client,
-                &self.config.remote_mihomo_binary_url,
-                "mihomo-downloaded-binary.tar.gz",
-            )
-            .await?;
-            extract_gzip(
-                "mihomo-downloaded-binary.tar.gz",
-                &self.mihomo_target_binary_path,
-                &self.prefix,
-            )?;
+        }
+    }
+
+    /// Compare versions of the installed binary, the running core and the latest release, hinting
+    /// at a restart or an upgrade if they differ.
+    pub async fn version(&self, client: &Client) -> Result<()> {
+        let core = self.config.core;
+        let installed = Command::new(&self.mihomo_target_binary_path)
+            .args(core.version_args())
+            .output()
+            .with_context(|| format!("failed to run {}", self.mihomo_target_binary_path))
+            .and_then(|output| {
+                parse_version(&String::from_utf8_lossy(&output.stdout))
+                    .with_context(|| "no version in its output")
+            });
+        let running = match self.api() {
+            Ok(api) => api.version().await.and_then(|version| {
+                parse_version(&version).with_context(|| format!("unknown version `{version}`"))
+            }),
+            Err(err) => Err(err),
+        };
+        let latest = github_latest_release(client, core.repo()).await;
 
-            let executable = fs::Permissions::from_mode(0o755);
-            fs::set_permissions(&self.mihomo_target_binary_path, executable)?;
+        let show = |version: &Result<String>| match version {
+            Ok(version) => version.bold().to_string(),
+            Err(err) => format!("{} {}", "unknown".yellow(), format!("({err})").dimmed()),
+        };
+        println!("installed  {}", show(&installed));
+        println!("running    {}", show(&running));
+        println!("latest     {}", show(&latest));
+
+        if let (Ok(installed), Ok(running)) = (&installed, &running) {
+            if installed != running {
+                println!(
+                    "{} Running {} differs from installed {}, restart {} to run it",
+                    "hint:".cyan(),
+                    running,
+                    installed,
+                    self.service_name
+                );
+            }
+        }
+        if let (Ok(installed), Ok(latest)) = (&installed, &latest) {
+            if installed != latest {
+                println!(
+                    "{} {} {} is available, point `{}` at it and run `mihoro upgrade`",
+                    "hint:".cyan(),
+                    core.binary_name(),
+                    latest,
+                    "remote_mihomo_binary_url".bold()
+                );
+            }
         }
+        Ok(())
+    }
 
-        // Download remote mihomo config and apply override
-        download_file(
-            &client,
-            &self.config.remote_config_url,
-            &self.mihomo_target_config_path,
-        )
-        .await?;
+    /// Path of state file `name`, next to `state.toml`.
+    pub fn state_file(&self, name: &str) -> String {
+        Path::new(&self.mihoro_state_path)
+            .with_file_name(name)
+            .to_string_lossy()
+            .into_owned()
+    }
 
-        // Try to decode base64 file in place if file is base64 encoding, otherwise do nothing
-        try_decode_base64_file_inplace(&self.mihomo_target_config_path)?;
+    /// `systemctl --user` builder, running on the remote machine if managed over SSH.
+    pub fn systemctl(&self) -> Systemctl {
+        match &self.remote {
+            Some(remote) => Systemctl::remote(&remote.destination),
+            None if self.system_scope() => Systemctl::system(),
+            None => Systemctl::new(),
+        }
+    }
+
+    /// mihomo's service, managed with the init system set by `service_manager` or detected.
+    pub fn service(&self) -> Box<dyn ServiceManager> {
+        service_manager(
+            self.init_system,
+            &self.config,
+            ServiceTarget {
+                name: self.service_name.clone(),
+                path: self.mihomo_target_service_path.clone(),
+                remote: self.remote.clone(),
+                prefix: self.prefix.clone(),
+            },
+            self.system_scope(),
+        )
+    }
 
-        apply_mihomo_override(&self.mihomo_target_config_path, &self.config.mihomo_config)?;
+    /// Whether mihomo runs as a system service rather than a user service, i.e. when running as
+    /// root with `user_systemd_root` pointing at a system unit directory.
+    pub fn system_scope(&self) -> bool {
+        let units_root = tilde(&self.config.user_systemd_root).to_string();
+        self.remote.is_none()
+            && is_root()
+            && SYSTEM_UNIT_ROOTS
+                .iter()
+                .any(|root| Path::new(&units_root).starts_with(root))
+    }
 
-        // Download geodata
-        self.update_geodata(client).await?;
+    /// Command for running command line `args` locally, or on the remote machine if managed over
+    /// SSH, with each argument quoted for the remote shell.
+    pub fn command<S: AsRef<str>>(&self, args: &[S]) -> Command {
+        match &self.remote {
+            Some(remote) => {
+                let mut cmd = Command::new("ssh");
+                cmd.arg("-t")
+                    .arg(&remote.destination)
+                    .args(args.iter().map(|arg| shell_quote(arg.as_ref())));
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(args[0].as_ref());
+                cmd.args(args[1..].iter().map(AsRef::as_ref));
+                cmd
+            }
+        }
+    }
 
-        // Create mihomo.service systemd file
-        create_mihomo_service(
-            &self.mihomo_target_binary_path,
-            &self.mihomo_target_config_root,
-            &self.mihomo_target_service_path,
-            &self.prefix,
+    /// Remove config snapshots beyond `history_keep` or older than `history_max_age_days`.
+    pub fn prune_history(&self) -> Result<usize> {
+        let removed = prune_snapshots(
+            &self.mihomo_target_backups_root,
+            self.config.history_keep,
+            self.config.history_max_age_days,
         )?;
+        if removed > 0 {
+            println!(
+                "{} Pruned {} config snapshots from {}",
+                self.prefix.green(),
+                removed,
+                self.mihomo_target_backups_root.underline().yellow()
+            );
+        }
+        Ok(removed)
+    }
 
-        Systemctl::new().enable("mihomo.service").execute()?;
-        Systemctl::new().start("mihomo.service").execute()?;
+    /// List config snapshots under `backups/` in the core's config root, newest first, numbered
+    /// for `config rollback`.
+    pub fn config_history(&self) -> Result<()> {
+        let snapshots = list_snapshots(&self.mihomo_target_backups_root)?;
+        if snapshots.is_empty() {
+            println!(
+                "{} No config snapshots in {} yet, one is taken before each update",
+                self.prefix.yellow(),
+                self.mihomo_target_backups_root.underline().yellow()
+            );
+            return Ok(());
+        }
+        println!(
+            "{} Config snapshots in {}",
+            self.prefix.cyan(),
+            self.mihomo_target_backups_root.underline().yellow()
+        );
+        let now = Utc::now();
+        for (index, snapshot) in snapshots.iter().enumerate() {
+            let age = fs::metadata(snapshot)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| format_age(now - DateTime::<Utc>::from(modified)))
+                .unwrap_or_default();
+            let summary = match ConfigSummary::read(&snapshot.to_string_lossy(), self.config.core) {
+                Ok(summary) => format!(
+                    "{} nodes, {} rules",
+                    summary.nodes.len(),
+                    summary.rules.len()
+                ),
+                Err(_) => "unreadable".to_owned(),
+            };
+            println!(
+                "{:>3}  {}  {:<10} {}",
+                (index + 1).to_string().bold(),
+                snapshot
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .underline(),
+                age,
+                summary.dimmed()
+            );
+        }
         Ok(())
     }
 
-    pub async fn update(&self, client: Client) -> Result<()> {
-        // Download remote mihomo config and apply override
-        download_file(
-            &client,
-            &self.config.remote_config_url,
-            &self.mihomo_target_config_path,
-        )
-        .await?;
-
-        // Try to decode base64 file in place if file is base64 encoding, otherwise do nothing
-        try_decode_base64_file_inplace(&self.mihomo_target_config_path)?;
+    /// Restore the `number`th newest config snapshot (the newest if `None`) as the target config and
+    /// restart `mihomo.service`.
+    ///
+    /// The config replaced is snapshotted first, so that a rollback can be undone in turn. The
+    /// cached remote config is dropped, as it is the config being rolled back from, and the next
+    /// `update` downloads the remote config in full.
+    pub async fn config_rollback(&self, number: Option<usize>) -> Result<()> {
+        let snapshots = list_snapshots(&self.mihomo_target_backups_root)?;
+        let number = number.unwrap_or(1);
+        if number == 0 {
+            bail!("snapshots are numbered from 1, see `mihoro config history`");
+        }
+        let Some(snapshot) = snapshots.get(number - 1) else {
+            match snapshots.len() {
+                0 => bail!("no config snapshots to roll back to"),
+                len => bail!("no snapshot #{number}, there are {len}, see `mihoro config history`"),
+            }
+        };
+        // Fail before touching anything if the snapshot is of no use
+        ConfigSummary::read(&snapshot.to_string_lossy(), self.config.core)?;
 
-        apply_mihomo_override(&self.mihomo_target_config_path, &self.config.mihomo_config)?;
+        snapshot_config(
+            &self.mihomo_target_config_path,
+            &self.mihomo_target_backups_root,
+        )?;
+        create_parent_dir(&self.mihomo_target_config_path)?;
+        write_file_atomic(&self.mihomo_target_config_path, fs::read(snapshot)?)?;
+        fs::remove_file(self.cached_remote_config_path()).ok();
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.remote_config.take().is_some() {
+            state.save(&self.mihoro_state_path)?;
+        }
+        self.push(&self.mihomo_target_config_path)?;
         println!(
-            "{} Updated and applied config overrides",
-            self.prefix.yellow()
+            "{} Rolled back {} to {}",
+            self.prefix.green(),
+            self.mihomo_target_config_path.underline().yellow(),
+            snapshot.display().to_string().underline()
         );
 
-        // Restart mihomo systemd service
-        println!("{} Restart mihomo.service", self.prefix.green());
-        Systemctl::new().restart("mihomo.service").execute()?;
+        self.restart().map(|_| {
+            println!(
+                "{} Restarted {}",
+                self.prefix.green().bold(),
+                self.service_name
+            );
+        })?;
+        self.wait_until_healthy().await?;
+        self.prune_history()?;
         Ok(())
     }
 
-    pub async fn update_geodata(&self, client: Client) -> Result<()> {
-        if let Some(geox_url) = self.config.mihomo_config.geox_url.clone() {
-            // Download geodata files based on `geodata_mode`
-            let geodata_mode = self.config.mihomo_config.geodata_mode.unwrap_or(false);
-            if geodata_mode {
-                download_file(
-                    &client,
-                    &geox_url.geoip,
-                    format!("{}/geoip.dat", &self.mihomo_target_config_root).as_str(),
-                )
-                .await?;
-                download_file(
-                    &client,
-                    &geox_url.geosite,
-                    format!("{}/geosite.dat", &self.mihomo_target_config_root).as_str(),
-                )
-                .await?;
-            } else {
-                download_file(
-                    &client,
-                    &geox_url.mmdb,
-                    format!("{}/country.mmdb", &self.mihomo_target_config_root).as_str(),
-                )
-                .await?;
-            }
+    /// Prune config history and remove leftover downloads from the cache.
+    pub fn clean(&self) -> Result<()> {
+        if self.prune_history()? == 0 {
+            println!("{} No config snapshots to prune", self.prefix.yellow());
+        }
+        delete_file(
+            &format!("{}/mihomo-downloaded-binary.tar.gz", self.dirs.cache),
+            &self.prefix,
+        )
+    }
 
-            println!("{} Downloaded and updated geodata", self.prefix.green());
+    /// Path of mihomo's log file if logging to a file, resolved against the config root.
+    pub fn log_file_path(&self) -> Option<String> {
+        let log_file = self.config.log_file.as_ref()?;
+        if Path::new(&log_file.path).is_absolute() {
+            Some(match &self.remote {
+                Some(remote) => remote.stage_path(&log_file.path),
+                None => log_file.path.clone(),
+            })
         } else {
+            Some(format!(
+                "{}/{}",
+                self.mihomo_target_config_root, log_file.path
+            ))
+        }
+    }
+
+    /// Follow mihomo's logs at `level` or above through the controller, without journalctl or a
+    /// log file.
+    pub async fn log_api(&self, level: &MihomoLogLevel) -> Result<()> {
+        let api = self.api()?;
+        let mut logs = pin!(api.logs(level).await?);
+        while let Some(log) = logs.next().await {
+            let log = log?;
+            let level = match log.level.as_str() {
+                "error" => log.level.red(),
+                "warning" => log.level.yellow(),
+                "info" => log.level.green(),
+                _ => log.level.dimmed(),
+            };
             println!(
-                "{} `geox_url` undefined, refer to {}",
-                self.prefix.yellow(),
-                "'https://wiki.metacubex.one/config/general/#geo_3'"
-                    .bold()
-                    .underline()
+                "{} {:7} {}",
+                Local::now().format("%H:%M:%S").to_string().dimmed(),
+                level,
+                log.payload
             );
         }
         Ok(())
     }
 
-    pub async fn apply(&self) -> Result<()> {
-        // Apply mihomo config override
-        apply_mihomo_override(&self.mihomo_target_config_path, &self.config.mihomo_config).map(
-            |_| {
-                println!(
-                    "{} Applied mihomo config overrides",
-                    self.prefix.green().bold()
-                );
+    /// File mihomo's output goes to, on the remote machine if managed over SSH: its log file if
+    /// configured, or where its service logs to otherwise, `None` for the journal.
+    fn log_output_path(&self) -> Result<Option<String>> {
+        let path = match self.log_file_path() {
+            Some(path) => path,
+            None => match self.service().output_path()? {
+                Some(path) => path,
+                None => return Ok(None),
             },
-        )?;
+        };
+        match &self.remote {
+            Some(remote) => remote.remote_path(&path).map(Some),
+            None => Ok(Some(path)),
+        }
+    }
 
-        // Restart mihomo systemd service
-        Systemctl::new()
-            .restart("mihomo.service")
-            .execute()
-            .map(|_| {
-                println!("{} Restarted mihomo.service", self.prefix.green().bold());
-            })?;
+    /// Command printing the last `lines` lines of mihomo's logs and following them if `follow`,
+    /// from its log file if configured or from the journal.
+    ///
+    /// Only lines matching regex `grep` are printed if given, counted before filtering for log
+    /// files. `since` (e.g. `1 hour ago`) is passed to `journalctl --since`, and cannot be used with
+    /// log files.
+    pub fn log_command(
+        &self,
+        lines: usize,
+        since: Option<&str>,
+        grep: Option<&str>,
+        follow: bool,
+    ) -> Result<Command> {
+        match self.log_output_path()? {
+            Some(log_path) => {
+                if since.is_some() {
+                    bail!("`--since` needs the journal, mihomo logs to {log_path}");
+                }
+                let mut script = format!("tail -n {lines}");
+                if follow {
+                    script.push_str(" -f");
+                }
+                script.push_str(&format!(" {}", shell_quote(&log_path)));
+                if let Some(pattern) = grep {
+                    script.push_str(&format!(
+                        " | grep --line-buffered -E {}",
+                        shell_quote(pattern)
+                    ));
+                }
+                // The remote shell already runs the command line passed to ssh
+                let cmd = match &self.remote {
+                    Some(remote) => {
+                        let mut cmd = Command::new("ssh");
+                        cmd.arg("-t").arg(&remote.destination).arg(script);
+                        cmd
+                    }
+                    None => {
+                        let mut cmd = Command::new("sh");
+                        cmd.arg("-c").arg(script);
+                        cmd
+                    }
+                };
+                Ok(cmd)
+            }
+            None => {
+                let mut args = vec!["journalctl"];
+                if !self.system_scope() {
+                    args.push("--user");
+                }
+                let lines = lines.to_string();
+                args.extend(["-xeu", &self.service_name, "-n", &lines]);
+                if let Some(since) = since {
+                    args.extend(["--since", since]);
+                }
+                if let Some(pattern) = grep {
+                    args.extend(["--grep", pattern]);
+                }
+                args.push(if follow { "-f" } else { "--no-pager" });
+                Ok(self.command(&args))
+            }
+        }
+    }
+
+    /// Rotate mihomo's log file according to `log_file`.
+    pub fn rotate_logs(&self) -> Result<()> {
+        let (Some(log_file), Some(log_path)) = (&self.config.log_file, self.log_file_path()) else {
+            bail!("`log_file` undefined, mihomo logs to the journal")
+        };
+        if self.remote.is_some() {
+            bail!("log rotation only runs on the local machine, use `logrotate --snippet` instead");
+        }
+        if rotate_log(&log_path, log_file)? {
+            println!(
+                "{} Rotated {}",
+                self.prefix.green(),
+                log_path.underline().yellow()
+            );
+        }
         Ok(())
     }
 
-    pub fn uninstall(&self) -> Result<()> {
-        Systemctl::new().stop("mihomo.service").execute()?;
-        Systemctl::new().disable("mihomo.service").execute()?;
+    /// logrotate config for mihomo's log file, as an alternative to [`Mihoro::rotate_logs`].
+    pub fn logrotate_snippet(&self) -> Result<String> {
+        let (Some(log_file), Some(log_path)) = (&self.config.log_file, self.log_file_path()) else {
+            bail!("`log_file` undefined, mihomo logs to the journal")
+        };
+        let log_path = match &self.remote {
+            Some(remote) => remote.remote_path(&log_path)?,
+            None => log_path,
+        };
+        Ok(logrotate_snippet(&log_path, log_file))
+    }
 
-        delete_file(&self.mihomo_target_service_path, &self.prefix)?;
-        delete_file(&self.mihomo_target_config_path, &self.prefix)?;
+    /// Upload a target file or directory to the remote machine if managed over SSH.
+    fn push(&self, path: &str) -> Result<()> {
+        match &self.remote {
+            Some(remote) => remote.push(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Download a target file from the remote machine if managed over SSH.
+    fn pull(&self, path: &str) -> Result<()> {
+        match &self.remote {
+            Some(remote) => remote.pull(path),
+            None => Ok(()),
+        }
+    }
 
-        Systemctl::new().daemon_reload().execute()?;
-        Systemctl::new().reset_failed().execute()?;
+    /// Download mihomo binary, remote config and geodata, then create and start `mihomo.service`.
+    pub async fn setup(&self, client: Client) -> Result<()> {
         println!(
-            "{} Disabled and reloaded systemd services",
-            self.prefix.green()
+            "{} Setting up mihomo's binary, config, and service...",
+            &self.prefix.cyan()
         );
-        println!(
-            "{} You may need to remove mihomo binary and config directory manually",
-            self.prefix.yellow()
+        if self.system_scope() {
+            println!(
+                "{} Running as root, installing mihomo as a system service",
+                self.prefix.cyan()
+            );
+        } else if self.init_system == InitSystem::Systemd && self.remote.is_none() && is_root() {
+            println!(
+                "{} Running as root with `{}` outside {}, `systemctl --user` needs a login session \
+                 of root, point it there to run mihomo as a system service instead",
+                "warning:".yellow(),
+                "user_systemd_root".bold(),
+                SYSTEM_UNIT_ROOTS[0]
+            );
+        } else if self.init_system == InitSystem::Systemd
+            && self.remote.is_none()
+            && !user_bus_available()
+        {
+            println!(
+                "{} No D-Bus session for `systemctl --user`, log in directly instead of with su or \
+                 sudo if enabling {} fails",
+                "warning:".yellow(),
+                self.service_name
+            );
+        }
+        let service = self.service();
+        service.check()?;
+
+        let mut summary = RunSummary::new();
+
+        // Attempt to download and setup mihomo binary if needed
+        if fs::metadata(&self.mihomo_target_binary_path).is_ok() {
+            // If mihomo binary already exists at `mihomo_target_binary_path`, then skip setup
+            println!(
+                "{} Assuming mihomo binary already installed at {}, skipping setup",
+                self.prefix.yellow(),
+                self.mihomo_target_binary_path.underline().green()
+            );
+            summary.add("binary", "already installed, unchanged");
+        } else {
+            self.install_binary(&client).await?;
+            summary.add(
+                "binary",
+                format!("installed to {}", self.mihomo_target_binary_path),
+            );
+        }
+
+        // Download remote mihomo config and apply override, keeping it for `apply` and offline
+        // updates to start over from
+        let fetched_path = self.fetched_remote_config_path();
+        let next_path = self.next_config_path();
+        let fetched = self
+            .download_remote_config(&client, &fetched_path, false)
+            .await?;
+        let headers = fetched
+            .as_ref()
+            .map(|fetched| fetched.headers.clone())
+            .unwrap_or_default();
+        let subscription = self.record_subscription(&headers)?;
+
+        self.normalize_downloaded_config(&fetched_path)?;
+        fs::copy(&fetched_path, &next_path)?;
+        self.refresh_subscriptions(&client).await?;
+        self.merge_subscriptions(&next_path, false)?;
+        self.deploy_config(&next_path)?;
+        if let Some(fetched) = &fetched {
+            self.cache_remote_config(&fetched_path, fetched)?;
+        }
+        self.push(&self.mihomo_target_config_path)?;
+        let config_summary =
+            ConfigSummary::read(&self.mihomo_target_config_path, self.config.core)?;
+        summary.add("config", config_summary.changes_since(None));
+        if let Some(info) = &subscription {
+            summary.add("subscription", format_subscription(info, Utc::now()));
+        }
+
+        // Download geodata
+        let geodata_updated = self.update_geodata(client).await?;
+        summary.add(
+            "geodata",
+            match geodata_updated {
+                true => "updated",
+                false => "skipped",
+            },
         );
 
-        let remove_cmd = format!(
-            "rm -R {} {}",
-            self.mihomo_target_binary_path, self.mihomo_target_config_root
+        self.install_service(service.as_ref(), config_summary.tun)?;
+        summary.add(
+            "service",
+            format!("{} enabled and started", self.service_name),
         );
-        println!("{} `{}`", "->".dimmed(), remove_cmd.underline().bold());
+        self.host_dns_configure()?;
+        summary.print(&self.prefix);
         Ok(())
     }
 
-    pub fn proxy_commands(&self, proxy: &Option<ProxyCommands>) -> Result<()> {
-        // `mixed_port` takes precedence over `port` and `socks_port` for proxy export
-        let port = self
-            .config
-            .mihomo_config
-            .mixed_port
-            .as_ref()
-            .unwrap_or(&self.config.mihomo_config.port);
-        let socks_port = self
-            .config
-            .mihomo_config
-            .mixed_port
-            .as_ref()
-            .unwrap_or(&self.config.mihomo_config.socks_port);
+    /// Create mihomo's service with `service`, running the core with TUN if `tun`, then enable and
+    /// start it.
+    fn install_service(&self, service: &dyn ServiceManager, tun: bool) -> Result<()> {
+        // Refer to paths on the remote machine if managed over SSH
+        let unit_path = |path: &str| service.definition_path(path);
+        let config_root = unit_path(&self.mihomo_target_config_root)?;
+        let spec = ServiceSpec {
+            exec_start: self.config.core.exec_start(
+                &unit_path(&self.mihomo_target_binary_path)?,
+                &config_root,
+                self.config.mihomo_config_name.as_deref(),
+            ),
+            log_path: self
+                .log_file_path()
+                .map(|path| unit_path(&path))
+                .transpose()?,
+            config_root,
+            tun,
+        };
+        service.install(&spec)?;
+        self.with_log_excerpt(service.enable())
+    }
 
-        match proxy {
-            Some(ProxyCommands::Export) => {
-                println!("{}", proxy_export_cmd("127.0.0.1", port, socks_port))
-            }
-            Some(ProxyCommands::ExportLan) => {
-                if !self.config.mihomo_config.allow_lan.unwrap_or(false) {
+    /// Download remote config, apply overrides and reload it into mihomo, or restart its service.
+    pub async fn update(&self, client: Client) -> Result<()> {
+        self.update_config(client, false, false).await
+    }
+
+    /// Regenerate config from the last fetched remote config in the cache, e.g. to apply override
+    /// edits while the network or the provider is down, and reload or restart mihomo.
+    pub async fn update_offline(&self, client: Client) -> Result<()> {
+        self.update_config(client, true, false).await
+    }
+
+    /// Update the config as in [`Mihoro::update`], from the cached remote config if `offline`, and
+    /// print a unified diff of the deployed config's changes if `diff`.
+    ///
+    /// The result is recorded for `mihoro sub history`.
+    pub async fn update_config(&self, client: Client, offline: bool, diff: bool) -> Result<()> {
+        let mut run = UpdateRun::start();
+        let result = self
+            .try_update_config(client, offline, diff, &mut run)
+            .await;
+        self.record_update(run, &result);
+        result
+    }
+
+    /// [`Mihoro::update_config`], filling in `run` as the update goes.
+    async fn try_update_config(
+        &self,
+        client: Client,
+        offline: bool,
+        diff: bool,
+        run: &mut UpdateRun,
+    ) -> Result<()> {
+        // Summarize current config to tell what changed, if it exists and parses
+        let previous = ConfigSummary::read(&self.mihomo_target_config_path, self.config.core).ok();
+        let previous_config = match diff {
+            true => canonical_config(&self.mihomo_target_config_path, self.config.core).ok(),
+            false => None,
+        };
+
+        // Generate the new config aside, the deployed one is only replaced once it validates
+        let fetched_path = self.fetched_remote_config_path();
+        let next_path = self.next_config_path();
+        let mut fetched = None;
+        let mut subscription = None;
+        if offline {
+            self.restore_cached_remote_config(&next_path)?;
+        } else {
+            self.refresh_subscriptions(&client).await?;
+
+            // Download remote mihomo config if it changed, keeping it for offline updates
+            run.url = self.config.config_urls().last().map(|url| url.to_string());
+            let downloaded = match self
+                .download_remote_config(&client, &fetched_path, true)
+                .await
+            {
+                Ok(downloaded) => downloaded,
+                Err(err) if Path::new(&self.cached_remote_config_path()).exists() => {
+                    return Err(reword_error(err, |err| {
+                        format!(
+                            "{err}, run `mihoro update --offline` to use the last fetched config"
+                        )
+                    }));
+                }
+                Err(err) => return Err(err),
+            };
+            self.record_fetch(run, downloaded.as_ref(), &fetched_path)?;
+            match downloaded {
+                Some(downloaded) => {
+                    subscription = self.record_subscription(&downloaded.headers)?;
+                    self.normalize_downloaded_config(&fetched_path)?;
+                    fs::copy(&fetched_path, &next_path)?;
+                    fetched = Some(downloaded);
+                }
+                None if self.cached_config_is_deployed()? => {
+                    // Nothing to update, leave the running service alone
+                    run.nodes = previous.as_ref().map(|summary| summary.nodes.len());
                     println!(
-                        "{} `{}` is false, proxy is not available for LAN",
-                        "warning:".yellow(),
-                        "allow_lan".bold()
+                        "{} Remote config not modified since the last update, skipping restart",
+                        self.prefix.green()
                     );
+                    return Ok(());
                 }
+                None => {
+                    println!(
+                        "{} Remote config not modified since the last update",
+                        self.prefix.yellow()
+                    );
+                    self.restore_cached_remote_config(&next_path)?;
+                }
+            }
+        }
+
+        self.merge_subscriptions(&next_path, false)?;
+        self.deploy_config(&next_path)?;
+        if let Some(fetched) = &fetched {
+            self.cache_remote_config(&fetched_path, fetched)?;
+        }
+        self.push(&self.mihomo_target_config_path)?;
+        println!(
+            "{} Updated and applied config overrides",
+            self.prefix.yellow()
+        );
+
+        let config_summary =
+            ConfigSummary::read(&self.mihomo_target_config_path, self.config.core)?;
+        run.nodes = Some(config_summary.nodes.len());
+        let changes = config_summary.changes_since(previous.as_ref());
+        if diff {
+            let config = canonical_config(&self.mihomo_target_config_path, self.config.core)?;
+            println!(
+                "{} Changes to {}:",
+                self.prefix.cyan(),
+                self.mihomo_target_config_path.underline()
+            );
+            print_unified_diff(
+                "previous config",
+                "updated config",
+                previous_config.as_deref().unwrap_or_default(),
+                &config,
+            );
+        }
+        // Only the steps an update takes are summarized, it leaves the binary and geodata alone
+        let mut summary = RunSummary::new();
+        summary.add(
+            "config",
+            match offline {
+                true => format!("{changes}, from cached remote config"),
+                false => changes.clone(),
+            },
+        );
+        if let Some(info) = &subscription {
+            summary.add("subscription", format_subscription(info, Utc::now()));
+        }
+        if self.config.notify {
+            if let Err(err) = send_notification("mihoro: config updated", &changes) {
+                println!("{} {}", "warning:".yellow(), err);
+            }
+        }
+
+        // Reload the config into running mihomo, or restart its service, recreating it if it was
+        // never set up or got removed
+        let service = self.service();
+        if service.is_active() && self.reload().await {
+            summary.add("service", "reloaded");
+        } else if service.is_installed() {
+            println!("{} Restart {}", self.prefix.green(), self.service_name);
+            self.restart()?;
+            summary.add("service", "restarted");
+        } else {
+            println!(
+                "{} {} not found, recreating it",
+                "warning:".yellow(),
+                self.service_name
+            );
+            self.install_service(service.as_ref(), config_summary.tun)?;
+            summary.add(
+                "service",
+                format!("{} recreated and started", self.service_name),
+            );
+        }
+        self.wait_until_healthy().await?;
+
+        self.prune_history()?;
 
+        // Updates usually run periodically, a good time to rotate file logs
+        if self.config.log_file.is_some() && self.remote.is_none() {
+            self.rotate_logs()?;
+        }
+
+        // Refresh installed dashboard, failing to do so should not fail the config update
+        if !offline {
+            if let Err(err) = self.ui_update(&client).await {
                 println!(
-                    "{}",
-                    proxy_export_cmd(&local_ip()?.to_string(), port, socks_port)
+                    "{} failed to update dashboard: {}",
+                    "warning:".yellow(),
+                    err
                 );
             }
-            Some(ProxyCommands::Unset) => {
-                println!("{}", proxy_unset_cmd())
-            }
-            _ => (),
         }
+        summary.print(&self.prefix);
         Ok(())
     }
-}
 
-/// Create a systemd service file for running mihomo as a service.
-///
-/// By default, user systemd services are created under `~/.config/systemd/user/mihomo.service` and
-/// invoked with `systemctl --user start mihomo.service`. Directory is created if not present.
-///
-/// Reference: https://wiki.metacubex.one/startup/service/
-fn create_mihomo_service(
-    mihomo_binary_path: &str,
-    mihomo_config_root: &str,
-    mihomo_service_path: &str,
-    prefix: &str,
-) -> Result<()> {
-    let service = format!(
-        "[Unit]
-Description=mihomo Daemon, Another Clash Kernel.
-After=network.target NetworkManager.service systemd-networkd.service iwd.service
-
-[Service]
-Type=simple
-LimitNPROC=500
-LimitNOFILE=1000000
-Restart=always
-ExecStartPre=/usr/bin/sleep 1s
-ExecStart={} -d {}
-ExecReload=/bin/kill -HUP $MAINPID
-
-[Install]
-WantedBy=default.target",
-        mihomo_binary_path, mihomo_config_root
-    );
-
-    // Create mihomo service directory if not exists
-    create_parent_dir(mihomo_service_path)?;
-
-    // Write mihomo.service contents to file
-    fs::write(mihomo_service_path, service)?;
-
-    println!(
-        "{} Created mihomo.service at {}",
-        prefix.green(),
-        mihomo_service_path.underline().yellow()
-    );
-    Ok(())
+    /// Run the core in the foreground instead of as a service, e.g. as a container's entrypoint,
+    /// updating its config every `update_interval` hours if given.
+    ///
+    /// The binary is installed if missing and the config fetched as in `setup`, then the core is
+    /// supervised until mihoro receives `SIGTERM` or `SIGINT`.
+    pub async fn run(&self, client: Client, update_interval: Option<u64>) -> Result<()> {
+        if self.remote.is_some() {
+            bail!(
+                "`run` runs the core in the foreground here, run mihoro on the remote host instead"
+            );
+        }
+        if fs::metadata(&self.mihomo_target_binary_path).is_err() {
+            self.install_binary(&client).await?;
+        }
+        self.refresh_config(&client).await?;
+        self.update_geodata(client.clone()).await?;
+        supervise(self, client, update_interval).await
+    }
+
+    /// Download the remote config and apply overrides without touching the service, falling back
+    /// to the last fetched config if the download fails.
+    ///
+    /// Returns whether the config changed, which it does not if the remote config was not modified
+    /// since the last download. The result is recorded for `mihoro sub history`.
+    pub async fn refresh_config(&self, client: &Client) -> Result<bool> {
+        let mut run = UpdateRun::start();
+        let result = self.try_refresh_config(client, &mut run).await;
+        self.record_update(run, &result);
+        result
+    }
+
+    /// [`Mihoro::refresh_config`], filling in `run` as the update goes.
+    async fn try_refresh_config(&self, client: &Client, run: &mut UpdateRun) -> Result<bool> {
+        let fetched_path = self.fetched_remote_config_path();
+        let next_path = self.next_config_path();
+        let mut fetched = None;
+        self.refresh_subscriptions(client).await?;
+        run.url = self.config.config_urls().last().map(|url| url.to_string());
+        let downloaded = self
+            .download_remote_config(client, &fetched_path, true)
+            .await;
+        if let Ok(downloaded) = &downloaded {
+            self.record_fetch(run, downloaded.as_ref(), &fetched_path)?;
+        }
+        match downloaded {
+            Ok(Some(downloaded)) => {
+                self.record_subscription(&downloaded.headers)?;
+                self.normalize_downloaded_config(&fetched_path)?;
+                fs::copy(&fetched_path, &next_path)?;
+                fetched = Some(downloaded);
+            }
+            Ok(None) if self.cached_config_is_deployed()? => {
+                println!(
+                    "{} Remote config not modified, keeping the current config",
+                    self.prefix.cyan()
+                );
+                run.nodes = ConfigSummary::read(&self.mihomo_target_config_path, self.config.core)
+                    .ok()
+                    .map(|summary| summary.nodes.len());
+                return Ok(false);
+            }
+            Ok(None) => self.restore_cached_remote_config(&next_path)?,
+            Err(err) if Path::new(&self.cached_remote_config_path()).exists() => {
+                println!("{} {}", "warning:".yellow(), err);
+                // Falling back to the cached config still counts as a failed update
+                run.status = http_status(&err);
+                run.error = Some(err.to_string());
+                self.restore_cached_remote_config(&next_path)?;
+            }
+            Err(err) => return Err(err),
+        }
+        self.merge_subscriptions(&next_path, false)?;
+        self.deploy_config(&next_path)?;
+        if let Some(fetched) = &fetched {
+            self.cache_remote_config(&fetched_path, fetched)?;
+        }
+        run.nodes = ConfigSummary::read(&self.mihomo_target_config_path, self.config.core)
+            .ok()
+            .map(|summary| summary.nodes.len());
+        println!(
+            "{} Updated and applied config overrides",
+            self.prefix.yellow()
+        );
+        Ok(true)
+    }
+
+    /// Serve the `mihoro serve` endpoint for triggering updates, as configured under `serve`.
+    pub async fn serve(&self, client: Client) -> Result<()> {
+        let serve_config =
+            self.config.serve.as_ref().with_context(|| {
+                "`serve` undefined, configure `listen` and `token` to enable it"
+            })?;
+        serve(self, client, serve_config).await
+    }
+
+    /// Show how the deployed config differs from what `mihoro update` would produce now.
+    ///
+    /// The remote config is fetched to the cache and overridden there, leaving the deployed
+    /// config, its history and the service untouched.
+    pub async fn diff_remote(&self, client: Client) -> Result<()> {
+        self.pull(&self.mihomo_target_config_path)?;
+        let deployed = read_core_config(&self.mihomo_target_config_path, self.config.core)
+            .with_context(|| "no deployed config to compare, run `mihoro setup` first")?;
+
+        let fetched_path = format!("{}/remote-config.yaml", self.mihoro_cache_root);
+        create_parent_dir(&fetched_path)?;
+        let fetched = async {
+            self.download_remote_config(&client, &fetched_path, false)
+                .await?;
+            self.normalize_downloaded_config(&fetched_path)?;
+            self.merge_subscriptions(&fetched_path, true)?;
+            self.apply_override(&fetched_path)?;
+            read_core_config(&fetched_path, self.config.core)
+        }
+        .await;
+        fs::remove_file(&fetched_path).ok();
+
+        let fetched = fetched?;
+        println!(
+            "{} Changes an update would make to {}:",
+            self.prefix.cyan(),
+            self.mihomo_target_config_path.underline()
+        );
+        print_changes(&diff_configs(&deployed, &fetched));
+        Ok(())
+    }
+
+    /// Nodes and groups the subscription serves now, fetched to the cache without touching the
+    /// deployed config, e.g. to check what a change on the provider's side brings before updating.
+    pub async fn sub_preview(&self, client: Client) -> Result<Nodes> {
+        let preview_path = format!("{}/preview-config", self.mihoro_cache_root);
+        create_parent_dir(&preview_path)?;
+        let nodes = async {
+            self.download_remote_config(&client, &preview_path, false)
+                .await?;
+            self.normalize_downloaded_config(&preview_path)?;
+            Nodes::read(&preview_path, self.config.core)
+        }
+        .await;
+        fs::remove_file(&preview_path).ok();
+
+        let nodes = nodes?;
+        println!(
+            "{} Nodes served by the subscription, not applied until `mihoro update`:",
+            self.prefix.cyan()
+        );
+        Ok(nodes)
+    }
+
+    /// Download the core binary from `remote_mihomo_binary_url` and install it as executable,
+    /// replacing the installed binary only once fully extracted.
+    async fn install_binary(&self, client: &Client) -> Result<()> {
+        // The remote machine's OS is unknown here, leave mismatches there to fail on start
+        let url = &self.config.remote_mihomo_binary_url;
+        if let Some(os) = asset_os(url).filter(|os| self.remote.is_none() && *os != release_os()) {
+            bail!(
+                "`remote_mihomo_binary_url` points at a {os} build, use the {} build of the \
+                 release on this machine",
+                release_os()
+            );
+        }
+        let downloaded_binary_path = format!("{}/mihomo-downloaded-binary.tar.gz", self.dirs.cache);
+        download_file_with_headers(
+            client,
+            &self.config.remote_mihomo_binary_url,
+            &downloaded_binary_path,
+            self.config.downloads.binary.request_headers()?,
+        )
+        .await?;
+        interrupt::track(&downloaded_binary_path);
+        match self.config.core {
+            Core::Mihomo => extract_gzip(
+                &downloaded_binary_path,
+                &self.mihomo_target_binary_path,
+                &self.prefix,
+            )?,
+            Core::SingBox => extract_tar_gz_binary(
+                &downloaded_binary_path,
+                self.config.core.binary_name(),
+                &self.mihomo_target_binary_path,
+                &self.prefix,
+            )?,
+        }
+        interrupt::untrack(&downloaded_binary_path);
+
+        let executable = fs::Permissions::from_mode(0o755);
+        fs::set_permissions(&self.mihomo_target_binary_path, executable)?;
+        self.lock_artifact(
+            "binary",
+            &self.mihomo_target_binary_path,
+            Some(&self.config.remote_mihomo_binary_url),
+            None,
+        )?;
+        self.push(&self.mihomo_target_binary_path)
+    }
+
+    /// Upgrade the core and restart it, by reinstalling the binary from `remote_mihomo_binary_url`
+    /// or, with `via_api`, by asking running mihomo to upgrade itself through the controller.
+    ///
+    /// mihomo then downloads its latest release to its own binary path and restarts, which works
+    /// even where that path is not writable by mihoro.
+    pub async fn upgrade(&self, client: Client, via_api: bool) -> Result<()> {
+        if !via_api {
+            self.install_binary(&client).await?;
+            self.restart().map(|_| {
+                println!(
+                    "{} Restarted {}",
+                    self.prefix.green().bold(),
+                    self.service_name
+                );
+            })?;
+            return self.wait_until_healthy().await;
+        }
+
+        if self.config.core != Core::Mihomo {
+            bail!(
+                "only mihomo upgrades itself through its controller, run `mihoro upgrade` without \
+                 `--via-api` instead"
+            );
+        }
+        let api = self.api()?;
+        println!(
+            "{} Asking {} to upgrade itself, this may take a while...",
+            self.prefix.cyan(),
+            self.service_name
+        );
+        api.upgrade().await?;
+        println!(
+            "{} Upgraded {}, restarting it",
+            self.prefix.green().bold(),
+            self.service_name
+        );
+
+        // mihomo restarts itself right after responding, wait for the old process to exit
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        self.wait_until_healthy().await?;
+
+        // Record the replaced binary, so that `mihoro verify` does not report it as modified
+        if self.remote.is_none() {
+            self.lock_artifact("binary", &self.mihomo_target_binary_path, None, None)?;
+        }
+        Ok(())
+    }
+
+    /// Show settings of running mihomo that differ from the deployed config, e.g. as changed from a
+    /// dashboard, which the next `mihoro update` or `mihoro apply` reverts.
+    pub async fn diff_runtime(&self) -> Result<()> {
+        self.pull(&self.mihomo_target_config_path)?;
+        let deployed = read_core_config(&self.mihomo_target_config_path, self.config.core)
+            .with_context(|| "no deployed config to compare, run `mihoro setup` first")?;
+        let runtime = self.api()?.configs().await?;
+
+        let (deployed, runtime) = common_fields(&deployed, &runtime);
+        let changes = diff_configs(&deployed, &runtime);
+        println!(
+            "{} Settings of running {} differing from {}:",
+            self.prefix.cyan(),
+            self.service_name,
+            self.mihomo_target_config_path.underline()
+        );
+        print_changes(&changes);
+        if !changes.is_empty() {
+            println!(
+                "{} Settings changed at runtime, e.g. from a dashboard, are lost on the next \
+                 `mihoro update` or `mihoro apply`, set them under `{}` in mihoro.toml to keep them",
+                "warning:".yellow(),
+                "mihomo_config".bold()
+            );
+        }
+        Ok(())
+    }
+
+    /// Download remote config to `path` with the headers under `[downloads.config]`, authorized by
+    /// `remote_config_auth` unless set there.
+    ///
+    /// `remote_config_url` and the mirrors in `remote_config_urls` are tried in order until one
+    /// succeeds. If `conditional`, the `ETag` and `Last-Modified` of the cached remote config are
+    /// sent along to the URL it came from, and `None` is returned without touching `path` if the
+    /// provider reports it not modified.
+    async fn download_remote_config(
+        &self,
+        client: &Client,
+        path: &str,
+        conditional: bool,
+    ) -> Result<Option<FetchedConfig>> {
+        let mut headers = self.config.downloads.config.request_headers()?;
+        if let Some(auth) = &self.config.remote_config_auth {
+            if !headers.contains_key(AUTHORIZATION) {
+                headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth.header_value()?)?);
+            }
+        }
+        let cached = match conditional {
+            true => self.cached_remote_config_state()?,
+            false => None,
+        };
+
+        let urls = self.config.config_urls();
+        let mut last_err = None;
+        for (i, url) in urls.iter().enumerate() {
+            let mut headers = headers.clone();
+            if let Some(remote) = cached.as_ref().filter(|remote| remote.url == *url) {
+                if let Some(etag) = &remote.etag {
+                    headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+                }
+                if let Some(last_modified) = &remote.last_modified {
+                    headers.insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+                }
+            }
+            // URLs are shown as written, keeping secrets from `{env:NAME}` out of messages and logs
+            let downloaded = async {
+                let expanded = expand_env_placeholders(url)?;
+                download_file_with_headers(client, &expanded, path, headers)
+                    .await
+                    .map_err(|err| match expanded == *url {
+                        true => err,
+                        false => {
+                            reword_error(err, |err| format!("{err:#}").replace(&expanded, url))
+                        }
+                    })
+            }
+            .await
+            .and_then(|downloaded| {
+                self.check_error_page(url, path, downloaded.as_ref())?;
+                Ok(downloaded)
+            });
+            match downloaded {
+                Ok(downloaded) => {
+                    if i > 0 {
+                        println!(
+                            "{} Downloaded remote config from mirror {}",
+                            self.prefix.yellow(),
+                            url.underline()
+                        );
+                    }
+                    return Ok(downloaded.map(|downloaded| FetchedConfig {
+                        url: url.to_string(),
+                        status: downloaded.status,
+                        headers: downloaded.headers,
+                    }));
+                }
+                Err(err) if i + 1 < urls.len() => {
+                    println!("{} {}, trying the next URL", "warning:".yellow(), err);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        match last_err {
+            Some(err) if urls.len() > 1 => Err(reword_error(err, |err| {
+                format!("{err}, all {} remote config URLs failed", urls.len())
+            })),
+            Some(err) => Err(err),
+            None => bail!("`remote_config_url` undefined"),
+        }
+    }
+
+    /// Fill in `run` with the outcome of downloading the remote config to `path`, which is `None`
+    /// if the cached remote config was not modified.
+    fn record_fetch(
+        &self,
+        run: &mut UpdateRun,
+        downloaded: Option<&FetchedConfig>,
+        path: &str,
+    ) -> Result<()> {
+        match downloaded {
+            Some(fetched) => {
+                run.url = Some(fetched.url.clone());
+                run.status = Some(fetched.status.as_u16());
+                run.bytes = Some(fs::metadata(path)?.len());
+            }
+            None => {
+                run.url = self.cached_remote_config_state()?.map(|remote| remote.url);
+                run.status = Some(StatusCode::NOT_MODIFIED.as_u16());
+            }
+        }
+        Ok(())
+    }
+
+    /// Append `run` with its `result` to the update history, warning if it cannot be saved.
+    fn record_update<T>(&self, mut run: UpdateRun, result: &Result<T>) {
+        if let Err(err) = result {
+            run.status = http_status(err).or(run.status);
+            run.error = Some(err.to_string());
+        }
+        let path = self.state_file("updates.json");
+        let saved = UpdateHistory::load(&path).and_then(|mut history| {
+            history.runs.push(run);
+            history.save(&path)
+        });
+        if let Err(err) = saved {
+            println!(
+                "{} failed to record update history: {}",
+                "warning:".yellow(),
+                err
+            );
+        }
+    }
+
+    /// List recent config updates recorded by `update` and `run`, newest first.
+    pub fn sub_history(&self) -> Result<()> {
+        let path = self.state_file("updates.json");
+        let history = UpdateHistory::load(&path)?;
+        if history.runs.is_empty() {
+            println!(
+                "{} No config updates recorded in {} yet",
+                self.prefix.yellow(),
+                path.underline().yellow()
+            );
+            return Ok(());
+        }
+        for run in history.runs.iter().rev() {
+            let time = Local
+                .timestamp_opt(run.time, 0)
+                .single()
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let result = match run.succeeded() {
+                true => format!("{:<6}", "ok").green(),
+                false => format!("{:<6}", "failed").red(),
+            };
+            let status = run
+                .status
+                .map(|status| status.to_string())
+                .unwrap_or_else(|| "-".to_owned());
+            let bytes = run
+                .bytes
+                .map(format_bytes)
+                .unwrap_or_else(|| "-".to_owned());
+            let nodes = run
+                .nodes
+                .map(|nodes| format!("{nodes} nodes"))
+                .unwrap_or_else(|| "-".to_owned());
+            println!(
+                "{}  {}  {:<5}  {:>8}  {:>9}  {}",
+                time,
+                result,
+                status,
+                bytes,
+                nodes,
+                run.url
+                    .as_deref()
+                    .unwrap_or("from cached remote config")
+                    .dimmed()
+            );
+            if let Some(error) = &run.error {
+                println!("    {}", error.dimmed());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if the remote config `downloaded` from `url` to `path` is an HTML page, which providers
+    /// serve with `200 OK` e.g. once a subscription expires.
+    ///
+    /// Only checked for configs that do not parse, so that configs mentioning HTML tags pass.
+    fn check_error_page(
+        &self,
+        url: &str,
+        path: &str,
+        downloaded: Option<&Downloaded>,
+    ) -> Result<()> {
+        let Some(downloaded) = downloaded else {
+            return Ok(());
+        };
+        if read_core_config(path, self.config.core).is_ok_and(|config| config.is_object()) {
+            return Ok(());
+        }
+        let body = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        match error_page_details(downloaded.status, &downloaded.headers, &body) {
+            Some(details) => {
+                fs::remove_file(path).ok();
+                bail!(
+                    "your provider returned an error page instead of a config from '{url}' \
+                     {details}"
+                )
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Validators of the cached remote config, if it exists.
+    fn cached_remote_config_state(&self) -> Result<Option<RemoteConfigState>> {
+        if !Path::new(&self.cached_remote_config_path()).exists() {
+            return Ok(None);
+        }
+        Ok(State::load(&self.mihoro_state_path)?.remote_config)
+    }
+
+    /// Keep the subscription usage reported in response `headers` of a config download, dropping
+    /// any previously kept if the provider stopped reporting it.
+    fn record_subscription(&self, headers: &HeaderMap) -> Result<Option<SubscriptionInfo>> {
+        let info = SubscriptionInfo::from_headers(headers, Utc::now());
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.subscription != info {
+            state.subscription = info.clone();
+            state.save(&self.mihoro_state_path)?;
+        }
+        Ok(info)
+    }
+
+    /// Print traffic usage and expiry of the subscription, as of the last config download.
+    pub fn sub_info(&self) -> Result<()> {
+        let Some(info) = State::load(&self.mihoro_state_path)?.subscription else {
+            bail!(
+                "no usage reported by the subscription provider, run `mihoro update` if it \
+                 sends a `subscription-userinfo` header"
+            );
+        };
+        let now = Utc::now();
+        let fetched_at = DateTime::from_timestamp(info.fetched_at, 0).unwrap_or(now);
+        println!(
+            "{} Subscription usage as of {}",
+            self.prefix.cyan(),
+            format_age(now - fetched_at)
+        );
+        println!(
+            "  {:9} {} (upload {}, download {})",
+            "used".bold(),
+            format_bytes(info.used()),
+            format_bytes(info.upload),
+            format_bytes(info.download)
+        );
+        match info.remaining() {
+            Some(remaining) => {
+                println!("  {:9} {}", "total".bold(), format_bytes(info.total));
+                println!("  {:9} {}", "left".bold(), format_bytes(remaining));
+            }
+            None => println!("  {:9} unlimited", "total".bold()),
+        }
+        match (info.expires_at(), info.days_left(now)) {
+            (Some(expires_at), Some(days)) => println!(
+                "  {:9} {} ({})",
+                "expires".bold(),
+                expires_at.with_timezone(&Local).format("%Y-%m-%d"),
+                match days {
+                    ..=-1 => String::from("expired"),
+                    days => format!("{days} days left"),
+                }
+            ),
+            _ => println!("  {:9} never", "expires".bold()),
+        }
+        if info.running_low(now) {
+            println!(
+                "{} Subscription running low, renew it with your provider",
+                "warning:".yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Keep the normalized remote config at `path` for `mihoro update --offline`.
+    fn cache_remote_config(&self, path: &str, fetched: &FetchedConfig) -> Result<()> {
+        let cached_path = self.cached_remote_config_path();
+        fs::rename(path, &cached_path)
+            .with_context(|| format!("failed to cache remote config to {cached_path}"))?;
+
+        // Keep the validators the response came with, for conditional downloads next time
+        let header = |name| {
+            fetched
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let remote = (etag.is_some() || last_modified.is_some()).then(|| RemoteConfigState {
+            url: fetched.url.clone(),
+            etag,
+            last_modified,
+        });
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.remote_config != remote {
+            state.remote_config = remote;
+            state.save(&self.mihoro_state_path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether applying overrides to the cached remote config yields the target config, so that
+    /// updating from it would change nothing.
+    ///
+    /// Configs are compared parsed, as the order of their fields is not stable across overrides.
+    fn cached_config_is_deployed(&self) -> Result<bool> {
+        let Ok(deployed) = read_core_config(&self.mihomo_target_config_path, self.config.core)
+        else {
+            return Ok(false);
+        };
+        let staged_path = format!("{}/staged-config", self.mihoro_cache_root);
+        fs::copy(self.cached_remote_config_path(), &staged_path)?;
+        // Overrides were reported when the target config was generated, apply them quietly
+        let staged = match self.config.core {
+            Core::Mihomo => self
+                .merge_subscriptions(&staged_path, true)
+                .and_then(|_| apply_mihomo_override(&staged_path, &self.config.mihomo_config))
+                .map(|_| ()),
+            Core::SingBox => Ok(()),
+        }
+        .and_then(|_| read_core_config(&staged_path, self.config.core));
+        fs::remove_file(&staged_path).ok();
+        Ok(staged? == deployed)
+    }
+
+    /// Copy the cached remote config to `path`, to apply overrides to it.
+    fn restore_cached_remote_config(&self, path: &str) -> Result<()> {
+        let cached_path = self.cached_remote_config_path();
+        if !Path::new(&cached_path).exists() {
+            bail!("no cached remote config, run `mihoro update` with network access first");
+        }
+        let modified = fs::metadata(&cached_path)?.modified()?;
+        println!(
+            "{} Using remote config cached {}",
+            self.prefix.yellow(),
+            format_age(Utc::now() - DateTime::<Utc>::from(modified))
+        );
+        fs::copy(&cached_path, path)?;
+        Ok(())
+    }
+
+    fn cached_remote_config_path(&self) -> String {
+        format!("{}/remote-config", self.mihoro_cache_root)
+    }
+
+    /// Where a downloaded remote config is normalized, until the config generated from it is
+    /// deployed and it replaces the cached remote config.
+    fn fetched_remote_config_path(&self) -> String {
+        format!("{}/remote-config.new", self.mihoro_cache_root)
+    }
+
+    /// Where the next target config is generated and validated before it is deployed.
+    fn next_config_path(&self) -> String {
+        let path = format!("{}/next-config", self.mihoro_cache_root);
+        create_parent_dir(&path).ok();
+        path
+    }
+
+    /// Where the last downloaded copy of `subscription` is kept.
+    fn subscription_cache_path(&self, subscription: &Subscription) -> Result<String> {
+        let name = &subscription.name;
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            bail!("invalid subscription name `{name}`, expected a name without `/` or leading `.`");
+        }
+        Ok(format!("{}/subscriptions/{name}", self.mihoro_cache_root))
+    }
+
+    /// Download the `subscriptions` due for a refresh, i.e. those without a cached copy younger
+    /// than their `update_interval`. Subscriptions failing to download keep their cached copy.
+    async fn refresh_subscriptions(&self, client: &Client) -> Result<()> {
+        let now = Utc::now();
+        for subscription in &self.config.subscriptions {
+            let cached_path = self.subscription_cache_path(subscription)?;
+            let interval = subscription
+                .update_interval
+                .unwrap_or(self.config.config_update_interval);
+            // Scheduled updates start a little early or late, which should not skip a refresh
+            let due_after = chrono::Duration::hours(interval.into())
+                - chrono::Duration::minutes(SUBSCRIPTION_DUE_SLACK_MINUTES);
+            let age = fs::metadata(&cached_path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(|modified| now - DateTime::<Utc>::from(modified));
+            if age.is_some_and(|age| age < due_after) {
+                continue;
+            }
+
+            let fetched_path = format!("{cached_path}.new");
+            let fetched = async {
+                // URLs are shown as written, as for `remote_config_url`
+                let url = expand_env_placeholders(&subscription.url)?;
+                let headers = self.config.downloads.config.request_headers()?;
+                download_file_with_headers(client, &url, &fetched_path, headers)
+                    .await
+                    .map_err(|err| match url == subscription.url {
+                        true => err,
+                        false => reword_error(err, |err| {
+                            format!("{err:#}").replace(&url, &subscription.url)
+                        }),
+                    })?;
+                self.normalize_downloaded_config(&fetched_path)?;
+                read_core_config(&fetched_path, self.config.core)?;
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+            match fetched {
+                Ok(()) => {
+                    fs::rename(&fetched_path, &cached_path)?;
+                    println!(
+                        "{} Downloaded subscription `{}`",
+                        self.prefix.green(),
+                        subscription.name
+                    );
+                }
+                Err(err) => {
+                    fs::remove_file(&fetched_path).ok();
+                    println!(
+                        "{} failed to download subscription `{}`, {}: {}",
+                        "warning:".yellow(),
+                        subscription.name,
+                        match age {
+                            Some(_) => "keeping its cached copy",
+                            None => "leaving it out",
+                        },
+                        err
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the cached copies of `subscriptions` into the remote config at `path`, reporting each
+    /// unless `quiet`. Subscriptions never downloaded are left out.
+    fn merge_subscriptions(&self, path: &str, quiet: bool) -> Result<()> {
+        if self.config.subscriptions.is_empty() {
+            return Ok(());
+        }
+        if self.config.core != Core::Mihomo {
+            bail!("`subscriptions` can only be merged into mihomo configs");
+        }
+        let read = |path: &str| -> Result<serde_yaml::Value> {
+            serde_yaml::from_str(&fs::read_to_string(path)?)
+                .with_context(|| format!("failed to parse {path}"))
+        };
+        let mut config = read(path)?;
+        let now = Utc::now();
+        for subscription in &self.config.subscriptions {
+            let cached_path = self.subscription_cache_path(subscription)?;
+            let Ok(modified) = fs::metadata(&cached_path).and_then(|metadata| metadata.modified())
+            else {
+                continue;
+            };
+            let cached = read(&cached_path)?;
+            let merged = merge_subscription(&mut config, &subscription.name, &cached)?;
+            if !quiet {
+                println!(
+                    "{} Merged {} nodes of subscription `{}`, downloaded {}",
+                    self.prefix.yellow(),
+                    merged,
+                    subscription.name,
+                    format_age(now - DateTime::<Utc>::from(modified))
+                );
+            }
+        }
+        write_file_atomic(path, serde_yaml::to_string(&config)?)?;
+        Ok(())
+    }
+
+    /// Apply overrides to the remote config at `next_path` and replace the target config with the
+    /// result once it validates, snapshotting the config it replaces. Fails leaving the target
+    /// config as is otherwise.
+    fn deploy_config(&self, next_path: &str) -> Result<()> {
+        let validated = self
+            .apply_override(next_path)
+            .and_then(|_| self.validate_config(next_path));
+        if let Err(err) = validated {
+            fs::remove_file(next_path).ok();
+            fs::remove_file(self.fetched_remote_config_path()).ok();
+            bail!(
+                "new config is invalid, keeping {}: {:#}",
+                self.mihomo_target_config_path,
+                err
+            );
+        }
+        snapshot_config(
+            &self.mihomo_target_config_path,
+            &self.mihomo_target_backups_root,
+        )?;
+        create_parent_dir(&self.mihomo_target_config_path)?;
+        write_file_atomic(&self.mihomo_target_config_path, fs::read(next_path)?)?;
+        fs::remove_file(next_path).ok();
+        Ok(())
+    }
+
+    /// Check that the config at `path` parses, and that the core accepts it (`mihomo -t` or
+    /// `sing-box check`) if its binary is installed locally.
+    fn validate_config(&self, path: &str) -> Result<()> {
+        read_core_config(path, self.config.core)?;
+        if self.remote.is_some() || fs::metadata(&self.mihomo_target_binary_path).is_err() {
+            return Ok(());
+        }
+        let output = Command::new(&self.mihomo_target_binary_path)
+            .args(
+                self.config
+                    .core
+                    .check_args(&self.mihomo_target_config_root, path),
+            )
+            .output()
+            .with_context(|| format!("failed to run {}", self.mihomo_target_binary_path))?;
+        if !output.status.success() {
+            let log =
+                String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+            println!("{}", log.trim_end().dimmed());
+            bail!("{} rejected it", self.config.core.binary_name());
+        }
+        Ok(())
+    }
+
+    /// Undo encodings of a downloaded config: compression mislabeled by the server, base64 and
+    /// JSON instead of YAML for mihomo.
+    fn normalize_downloaded_config(&self, path: &str) -> Result<()> {
+        if try_decompress_file_inplace(path)? {
+            println!(
+                "{} Decompressed remote config served without content encoding",
+                self.prefix.yellow()
+            );
+        }
+
+        // Try to decode base64 file in place if file is base64 encoding, otherwise do nothing
+        try_decode_base64_file_inplace(path)?;
+
+        if self.config.core == Core::Mihomo
+            && self.config.remote_config_format == RemoteConfigFormat::SingBox
+        {
+            let conversion = convert_sing_box_file_inplace(path, &self.config.test_url)?;
+            self.print_conversion("sing-box outbounds", &conversion);
+            return Ok(());
+        }
+
+        if self.config.core == Core::Mihomo {
+            if let Some(conversion) =
+                try_convert_share_links_file_inplace(path, &self.config.test_url)?
+            {
+                self.print_conversion("share links", &conversion);
+            }
+        }
+
+        if self.config.core == Core::Mihomo && try_convert_json_file_inplace(path)? {
+            println!(
+                "{} Converted JSON remote config to YAML",
+                self.prefix.yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Report a remote config converted to mihomo's format from `source`, e.g. share links.
+    fn print_conversion(&self, source: &str, conversion: &Conversion) {
+        println!(
+            "{} Converted {} {} to proxies, selected with `{}`",
+            self.prefix.yellow(),
+            conversion.converted,
+            source,
+            SELECT_GROUP
+        );
+        for (link, reason) in &conversion.skipped {
+            let shown: String = link.chars().take(40).collect();
+            let ellipsis = if shown.len() < link.len() { "..." } else { "" };
+            println!(
+                "{} Skipped `{shown}{ellipsis}`: {reason}",
+                "warning:".yellow()
+            );
+        }
+    }
+
+    /// Apply `mihomo_config` overrides to the downloaded config at `path`.
+    ///
+    /// sing-box configs are used as is and only validated to be JSON.
+    fn apply_override(&self, path: &str) -> Result<()> {
+        match self.config.core {
+            Core::Mihomo => {
+                let applied = apply_mihomo_override(path, &self.config.mihomo_config)?;
+                if !applied.nodes.dropped.is_empty() {
+                    println!(
+                        "{} Filtered out {} nodes with `node_include` and `node_exclude`",
+                        self.prefix.yellow(),
+                        applied.nodes.dropped.len()
+                    );
+                }
+                for group in &applied.nodes.emptied_groups {
+                    println!(
+                        "{} All members of proxy group `{}` filtered out, pointing it at DIRECT",
+                        "warning:".yellow(),
+                        group
+                    );
+                }
+                if applied.renamed > 0 {
+                    println!(
+                        "{} Renamed {} nodes with `node_rename`",
+                        self.prefix.yellow(),
+                        applied.renamed
+                    );
+                }
+                let merged = applied.rules;
+                if merged.duplicates > 0 {
+                    println!(
+                        "{} Dropped {} duplicate rules",
+                        self.prefix.yellow(),
+                        merged.duplicates
+                    );
+                }
+                for (rule, covered_by) in merged.shadowed.iter().take(MAX_SHADOWED_WARNINGS) {
+                    println!(
+                        "{} Rule `{}` never matches, covered by earlier `{}`",
+                        "warning:".yellow(),
+                        rule,
+                        covered_by
+                    );
+                }
+                if merged.shadowed.len() > MAX_SHADOWED_WARNINGS {
+                    println!(
+                        "{} ...and {} more shadowed rules",
+                        "warning:".yellow(),
+                        merged.shadowed.len() - MAX_SHADOWED_WARNINGS
+                    );
+                }
+                Ok(())
+            }
+            Core::SingBox => {
+                let raw_config = fs::read_to_string(path)?;
+                serde_json::from_str::<serde_json::Value>(&raw_config)
+                    .with_context(|| "remote config is not a valid sing-box JSON config")?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Download geodata files (`geoip.dat`/`geosite.dat` or `country.mmdb`) from `geox_url`,
+    /// returning whether any were downloaded.
+    pub async fn update_geodata(&self, client: Client) -> Result<bool> {
+        if self.config.core == Core::SingBox {
+            println!(
+                "{} sing-box manages its own rule sets, skipping geodata",
+                self.prefix.yellow()
+            );
+            return Ok(false);
+        }
+        if let Some(geox_url) = self.config.mihomo_config.geox_url.clone() {
+            // Download geodata files based on `geodata_mode`
+            let geodata_mode = self.config.mihomo_config.geodata_mode.unwrap_or(false);
+            let geodata_files = if geodata_mode {
+                vec![
+                    (&geox_url.geoip, "geoip.dat"),
+                    (&geox_url.geosite, "geosite.dat"),
+                ]
+            } else {
+                vec![(&geox_url.mmdb, "country.mmdb")]
+            };
+            for (url, filename) in geodata_files {
+                let path = format!("{}/{}", &self.mihomo_target_config_root, filename);
+                let headers = self.config.downloads.geodata.request_headers()?;
+                download_file_with_headers(&client, url, &path, headers).await?;
+                self.lock_artifact(&format!("geodata/{filename}"), &path, Some(url), None)?;
+                self.push(&path)?;
+            }
+
+            println!("{} Downloaded and updated geodata", self.prefix.green());
+            Ok(true)
+        } else {
+            println!(
+                "{} `geox_url` undefined, refer to {}",
+                self.prefix.yellow(),
+                "'https://wiki.metacubex.one/config/general/#geo_3'"
+                    .bold()
+                    .underline()
+            );
+            Ok(false)
+        }
+    }
+
+    /// Apply config overrides to the last fetched remote config, or the existing `config.yaml` if
+    /// none is cached, and restart `mihomo.service`.
+    ///
+    /// The config is hot-reloaded through the controller if it is reachable, keeping connections
+    /// open, unless `restart` is set.
+    pub async fn apply(&self, restart: bool) -> Result<()> {
+        // Apply mihomo config override
+        self.pull(&self.mihomo_target_config_path)?;
+        let previous = read_core_config(&self.mihomo_target_config_path, self.config.core)?;
+        // Start over from the remote config if cached, as overrides like `node_rename` templates
+        // would apply on top of themselves
+        let cached_path = self.cached_remote_config_path();
+        let next_path = self.next_config_path();
+        if Path::new(&cached_path).exists() {
+            fs::copy(&cached_path, &next_path)?;
+            self.merge_subscriptions(&next_path, false)?;
+        } else {
+            fs::copy(&self.mihomo_target_config_path, &next_path)?;
+        }
+        self.deploy_config(&next_path).map(|_| {
+            println!(
+                "{} Applied mihomo config overrides",
+                self.prefix.green().bold()
+            );
+        })?;
+        let current = read_core_config(&self.mihomo_target_config_path, self.config.core)?;
+        print_changes(&diff_configs(&previous, &current));
+        self.push(&self.mihomo_target_config_path)?;
+
+        if !restart && self.reload().await {
+            return Ok(());
+        }
+
+        // Restart mihomo systemd service
+        self.restart().map(|_| {
+            println!(
+                "{} Restarted {}",
+                self.prefix.green().bold(),
+                self.service_name
+            );
+        })?;
+        self.wait_until_healthy().await
+    }
+
+    /// Hot-reload mihomo's config through the controller, returning whether it was reloaded.
+    ///
+    /// Fails without an `external_controller` or if mihomo is not running, in which case it needs
+    /// a restart anyway.
+    pub async fn reload(&self) -> bool {
+        let Ok(api) = self.api() else {
+            return false;
+        };
+        match api.reload_config().await {
+            Ok(()) => {
+                println!(
+                    "{} Reloaded config of {} through the controller",
+                    self.prefix.green().bold(),
+                    self.service_name
+                );
+                true
+            }
+            Err(err) => {
+                println!(
+                    "{} Failed to reload config through the controller ({err}), restarting {} \
+                     instead",
+                    "warning:".yellow(),
+                    self.service_name
+                );
+                false
+            }
+        }
+    }
+
+    /// Wait up to `health_check_timeout` seconds for the controller to respond after a restart,
+    /// failing with the last lines of mihomo's logs if it does not, e.g. as mihomo keeps crashing on
+    /// a bad config.
+    ///
+    /// Skipped without an `external_controller` to check.
+    async fn wait_until_healthy(&self) -> Result<()> {
+        let timeout = self.config.health_check_timeout;
+        let Ok(api) = self.api() else {
+            return Ok(());
+        };
+        if timeout == 0 {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        loop {
+            if let Ok(version) = api.version().await {
+                println!(
+                    "{} {} is up, running {}",
+                    self.prefix.green(),
+                    self.service_name,
+                    version
+                );
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        self.print_log_excerpt();
+        bail!(
+            "{} did not respond within {}s after restarting, check its config and `mihoro log`",
+            self.service_name,
+            timeout
+        )
+    }
+
+    /// Start mihomo's service, printing its last logs if it fails to start.
+    pub fn start(&self) -> Result<()> {
+        self.with_log_excerpt(self.service().start())
+    }
+
+    /// Restart mihomo's service, printing its last logs if it fails to start again.
+    pub fn restart(&self) -> Result<()> {
+        self.with_log_excerpt(self.service().restart())
+    }
+
+    /// Print mihomo's last logs if `result` of a service action failed, e.g. to show that its
+    /// port is already in use.
+    fn with_log_excerpt(&self, result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            self.print_log_excerpt();
+        }
+        result
+    }
+
+    /// Print the last 20 lines of mihomo's logs, if there are any.
+    fn print_log_excerpt(&self) {
+        if let Some(excerpt) = self.log_excerpt(20) {
+            println!(
+                "{} Last logs of {}:",
+                "warning:".yellow(),
+                self.service_name
+            );
+            println!("{}", excerpt.dimmed());
+            println!(
+                "{} Run `{}` to follow them",
+                "hint:".cyan(),
+                "mihoro log".bold()
+            );
+        }
+    }
+
+    /// Last `lines` lines of mihomo's logs, from its log file if configured or from the journal.
+    fn log_excerpt(&self, lines: usize) -> Option<String> {
+        let mut cmd = match self.log_output_path().ok()? {
+            Some(log_path) => self.command(&["tail", "-n", &lines.to_string(), &log_path]),
+            None => {
+                let mut args = vec!["journalctl"];
+                if !self.system_scope() {
+                    args.push("--user");
+                }
+                let lines = lines.to_string();
+                args.extend(["-u", &self.service_name, "-n", &lines, "--no-pager"]);
+                self.command(&args)
+            }
+        };
+        let output = cmd.output().ok().filter(|output| output.status.success())?;
+        let excerpt = String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned();
+        (!excerpt.is_empty()).then_some(excerpt)
+    }
+
+    /// Stop and disable `mihomo.service`, removing the service file and `config.yaml`.
+    pub fn uninstall(&self) -> Result<()> {
+        let service = self.service();
+        if service.is_active() {
+            service.stop()?;
+        }
+        service.uninstall()?;
+        self.teardown()?;
+
+        if let Some(remote) = &self.remote {
+            remote.remove(&self.mihomo_target_config_path)?;
+        }
+        delete_file(&self.mihomo_target_config_path, &self.prefix)?;
+        println!(
+            "{} You may need to remove mihomo binary and config directory manually",
+            self.prefix.yellow()
+        );
+
+        let remove_cmd = match &self.remote {
+            Some(remote) => format!(
+                "ssh {} rm -R {} {}",
+                remote.destination,
+                remote.remote_path(&self.mihomo_target_binary_path)?,
+                remote.remote_path(&self.mihomo_target_config_root)?
+            ),
+            None => format!(
+                "rm -R {} {}",
+                self.mihomo_target_binary_path, self.mihomo_target_config_root
+            ),
+        };
+        println!("{} `{}`", "->".dimmed(), remove_cmd.underline().bold());
+        Ok(())
+    }
+
+    /// Files and directories included in backups, as `(name in archive, target path)`.
+    fn backup_entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("mihoro.toml", self.config_path.clone()),
+            ("config.yaml", self.mihomo_target_config_path.clone()),
+            ("mihoro-state.toml", self.mihoro_state_path.clone()),
+            ("history", self.mihomo_target_backups_root.clone()),
+            ("updates.json", self.state_file("updates.json")),
+            ("quality.json", self.state_file("quality.json")),
+            ("mihoro.lock", self.state_file("mihoro.lock")),
+        ];
+        if let Ok(ui_root) = self.ui_root() {
+            entries.push(("ui", ui_root));
+        }
+        entries
+    }
+
+    /// Archive `mihoro.toml`, mihomo's `config.yaml`, mihoro's state, the config history and the
+    /// installed dashboard into a `.tar.gz` backup at `backup_path`.
+    pub fn backup(&self, backup_path: &str) -> Result<()> {
+        self.pull(&self.mihomo_target_config_path)?;
+        let backup_path = tilde(backup_path).to_string();
+        let archived = create_backup(&backup_path, &self.backup_entries())?;
+        println!(
+            "{} Backed up {} to {}",
+            self.prefix.green(),
+            archived.join(", "),
+            backup_path.underline().yellow()
+        );
+        Ok(())
+    }
+
+    /// Restore a backup created with [`Mihoro::backup`] in place.
+    ///
+    /// Restored files are not applied, as `mihoro.toml` may have changed: parse the config again
+    /// and run [`Mihoro::apply`] afterwards.
+    pub fn restore(&self, backup_path: &str) -> Result<()> {
+        let backup_path = tilde(backup_path).to_string();
+        let entries = self.backup_entries();
+        let restored = restore_backup(&backup_path, &entries)?;
+        for (name, path) in &entries {
+            // Only the config and the dashboard live on the remote machine, state stays local
+            let remote = matches!(*name, "config.yaml" | "ui");
+            if remote && restored.iter().any(|restored| restored == name) {
+                self.push(path)?;
+            }
+        }
+        println!(
+            "{} Restored {} from {}",
+            self.prefix.green(),
+            restored.join(", "),
+            backup_path.underline().yellow()
+        );
+        Ok(())
+    }
+
+    /// Directory mihomo serves the web dashboard from, derived from `external_ui`.
+    ///
+    /// Relative `external_ui` paths are resolved against mihomo's config root, like mihomo does.
+    pub fn ui_root(&self) -> Result<String> {
+        let external_ui = self
+            .config
+            .mihomo_config
+            .external_ui
+            .as_ref()
+            .with_context(|| "`external_ui` undefined, set it to serve a dashboard")?;
+        if let Some(remote) = &self.remote {
+            if Path::new(external_ui).is_absolute() || external_ui.starts_with('~') {
+                return Ok(remote.stage_path(external_ui));
+            }
+        }
+        let external_ui = tilde(external_ui).to_string();
+        if Path::new(&external_ui).is_absolute() {
+            Ok(external_ui)
+        } else {
+            Ok(format!(
+                "{}/{}",
+                self.mihomo_target_config_root, external_ui
+            ))
+        }
+    }
+
+    /// Install latest web dashboard to `external_ui` and record its version in mihoro's state.
+    pub async fn ui_install(&self, client: Client, dashboard: Dashboard) -> Result<()> {
+        let version = github_latest_release(&client, dashboard.repo()).await?;
+        self.install_dashboard_version(&client, dashboard, version)
+            .await
+    }
+
+    /// Update the installed web dashboard if a newer release is available.
+    ///
+    /// Does nothing if no dashboard was installed with `mihoro ui install`.
+    pub async fn ui_update(&self, client: &Client) -> Result<()> {
+        let state = State::load(&self.mihoro_state_path)?;
+        let Some(installed) = state.dashboard else {
+            println!(
+                "{} No dashboard installed with `{}`, skipping dashboard update",
+                self.prefix.yellow(),
+                "mihoro ui install".bold()
+            );
+            return Ok(());
+        };
+
+        let version = github_latest_release(client, installed.name.repo()).await?;
+        if version == installed.version {
+            println!(
+                "{} Dashboard {} {} is up to date",
+                self.prefix.green(),
+                installed.name.to_string().bold(),
+                installed.version
+            );
+            return Ok(());
+        }
+        self.install_dashboard_version(client, installed.name, version)
+            .await
+    }
+
+    /// Client for mihomo's external controller, on the remote machine if managed over SSH.
+    pub fn api(&self) -> Result<ApiClient> {
+        let mihomo_config = &self.config.mihomo_config;
+        let external_controller = mihomo_config
+            .external_controller
+            .as_ref()
+            .with_context(|| "`external_controller` undefined, controller API is not served")?;
+        ApiClient::new(
+            external_controller,
+            mihomo_config.secret.as_deref(),
+            self.remote.as_ref().map(|remote| remote.hostname()),
+        )
+    }
+
+    /// Print each proxy group with its selected node and that node's latest delay.
+    pub async fn ctl_groups(&self) -> Result<()> {
+        let api = self.api()?;
+        let proxies = api.proxies().await?;
+        let groups = api.groups().await?;
+        let width = groups
+            .iter()
+            .map(|group| group.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let history = QualityHistory::load(&self.state_file("quality.json"))?;
+        for group in groups {
+            let now = group.now.as_deref().unwrap_or_default();
+            let delay = match proxies.get(now).and_then(|node| node.last_delay()) {
+                Some(delay) => format_delay(delay),
+                None => "--".dimmed().to_string(),
+            };
+            let score = match history.quality(now) {
+                Some(quality) => format!("score {}", quality.score),
+                None => String::new(),
+            };
+            println!(
+                "{:width$}  {}  {}  {}",
+                group.name.bold(),
+                now,
+                delay,
+                score.dimmed()
+            );
+        }
+        Ok(())
+    }
+
+    /// Switch the selected node of proxy group `group` to `node` through the controller, or to
+    /// its lowest delay member if `node` is `None`.
+    pub async fn select(&self, group: &str, node: Option<&str>) -> Result<()> {
+        let api = self.api()?;
+        let proxies = api.proxies().await?;
+        let Some(proxy_group) = proxies.get(group).filter(|proxy| proxy.is_group()) else {
+            let groups: Vec<String> = api.groups().await?.into_iter().map(|g| g.name).collect();
+            bail!(
+                "no proxy group `{group}`, groups are: {}",
+                groups.join(", ")
+            );
+        };
+        // mihomo also lets URLTest and Fallback groups be pinned to a node
+        if !["Selector", "URLTest", "Fallback"].contains(&proxy_group.kind.as_str()) {
+            bail!(
+                "`{group}` is a {} group, which does not select a single node",
+                proxy_group.kind
+            );
+        }
+        let (node, delay) = match node {
+            Some(node) => {
+                if !proxy_group.all.iter().any(|member| member == node) {
+                    bail!(
+                        "`{node}` is not in `{group}`, its members are: {}",
+                        proxy_group.all.join(", ")
+                    );
+                }
+                (node.to_owned(), None)
+            }
+            None => {
+                println!(
+                    "{} Testing {} members of {}...",
+                    self.prefix.cyan(),
+                    proxy_group.all.len(),
+                    group.bold()
+                );
+                let (node, delay) = self.fastest_node(&api, group).await?;
+                (node, Some(delay))
+            }
+        };
+
+        api.select(group, &node).await?;
+        println!(
+            "{} Switched {} to {}{}",
+            self.prefix.green(),
+            group.bold(),
+            node.bold(),
+            delay
+                .map(|delay| format!(" ({})", format_delay(delay)))
+                .unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// Test delay of every node through the controller and record results in quality history.
+    ///
+    /// Meant to run periodically (e.g. from a timer), so that nodes are scored over days.
+    pub async fn ctl_probe(&self) -> Result<()> {
+        let api = self.api()?;
+        let nodes: Vec<String> = api
+            .proxies()
+            .await?
+            .into_values()
+            .filter(|proxy| proxy.is_node())
+            .map(|proxy| proxy.name)
+            .collect();
+        let results = self.test_delays(&api, nodes).await?;
+
+        let quality_path = self.state_file("quality.json");
+        let mut history = QualityHistory::load(&quality_path)?;
+        let tested = results.len();
+        let mut responded = 0;
+        for (node, delay) in results {
+            responded += usize::from(delay.is_some());
+            history.record(&node, delay);
+        }
+        history.save(&quality_path)?;
+        println!(
+            "{} Tested {} nodes, {} responded",
+            self.prefix.green(),
+            tested,
+            responded
+        );
+        Ok(())
+    }
+
+    /// Switch the running core to proxy `mode` through the controller, or show the current mode.
+    ///
+    /// The mode configured in `mihoro.toml` is restored when mihomo restarts.
+    pub async fn mode(&self, mode: Option<&MihomoMode>) -> Result<()> {
+        let api = self.api()?;
+        let Some(mode) = mode else {
+            println!("{}", api.mode().await?);
+            return Ok(());
+        };
+        api.set_mode(mode).await?;
+        println!(
+            "{} Switched to {} mode until mihomo restarts, set `{}` to keep it",
+            self.prefix.green(),
+            format!("{mode:?}").to_lowercase().bold(),
+            "mihomo_config.mode".bold()
+        );
+        Ok(())
+    }
+
+    /// Resolve `domain` through mihomo's resolver, marking answers from the fake-ip range.
+    pub async fn dns_query(&self, domain: &str, record_type: &str) -> Result<()> {
+        let api = self.api()?;
+        let answers = api.dns_query(domain, record_type).await?;
+        if answers.is_empty() {
+            println!(
+                "{} No {} records for {}",
+                self.prefix.yellow(),
+                record_type,
+                domain
+            );
+            return Ok(());
+        }
+
+        let fake_ip_range = self.fake_ip_range();
+        for answer in answers {
+            let fake_ip = answer
+                .data
+                .parse::<IpAddr>()
+                .ok()
+                .zip(fake_ip_range)
+                .is_some_and(|(ip, range)| {
+                    let prefix = if ip.is_ipv4() { 32 } else { 128 };
+                    cidr_contains(range, (ip, prefix))
+                });
+            let record_type = match answer.kind {
+                1 => "A".to_owned(),
+                5 => "CNAME".to_owned(),
+                28 => "AAAA".to_owned(),
+                65 => "HTTPS".to_owned(),
+                kind => kind.to_string(),
+            };
+            let marker = match fake_ip {
+                true => format!("  {}", "fake-ip".dimmed()),
+                false => String::new(),
+            };
+            println!(
+                "{}  {:5}  {:>6}s  {}{}",
+                answer.name.bold(),
+                record_type,
+                answer.ttl,
+                answer.data,
+                marker
+            );
+        }
+        Ok(())
+    }
+
+    /// Fake IP network of the deployed config, `None` if DNS is not in fake-ip mode.
+    fn fake_ip_range(&self) -> Option<(IpAddr, u8)> {
+        let config = read_core_config(&self.mihomo_target_config_path, Core::Mihomo).ok()?;
+        let dns = &config["dns"];
+        if dns["enhanced-mode"].as_str() != Some("fake-ip") {
+            return None;
+        }
+        parse_cidr(dns["fake-ip-range"].as_str().unwrap_or("198.18.0.1/16"))
+    }
+
+    /// Test delay of nodes (or all members of a proxy group) through the controller and print them
+    /// sorted from fastest to slowest, testing all nodes if `target` is not given.
+    pub async fn ping(&self, target: Option<&str>) -> Result<()> {
+        let api = self.api()?;
+        let proxies = api.proxies().await?;
+        let names: Vec<String> = match target.map(|target| (target, proxies.get(target))) {
+            None => proxies
+                .into_values()
+                .filter(|proxy| proxy.is_node())
+                .map(|proxy| proxy.name)
+                .collect(),
+            Some((_, Some(group))) if group.is_group() => group.all.clone(),
+            Some((_, Some(node))) => vec![node.name.clone()],
+            Some((target, None)) => bail!("no proxy group or node `{target}`"),
+        };
+
+        print_delays(self.test_delays(&api, names).await?);
+        Ok(())
+    }
+
+    /// Test all members of proxy group `group` through the group's own delay test, so that
+    /// URLTest and Fallback groups re-evaluate their selection right away.
+    pub async fn ping_group(&self, group: &str) -> Result<()> {
+        let api = self.api()?;
+        let proxies = api.proxies().await?;
+        let Some(members) = proxies
+            .get(group)
+            .filter(|proxy| proxy.is_group())
+            .map(|proxy| &proxy.all)
+        else {
+            bail!("no proxy group `{group}`");
+        };
+
+        let delays = api.group_delay(group, &self.config.test_url, 5000).await?;
+        print_delays(
+            members
+                .iter()
+                .map(|member| (member.clone(), delays.get(member).copied()))
+                .collect(),
+        );
+        if let Some(now) = api.proxies().await?.get(group).and_then(|g| g.now.clone()) {
+            println!(
+                "{} {} now uses {}",
+                self.prefix.green(),
+                group.bold(),
+                now.bold()
+            );
+        }
+        Ok(())
+    }
+
+    /// Test delay of each of `names` concurrently against `test_url`, `None` for those that did
+    /// not respond.
+    async fn test_delays(
+        &self,
+        api: &ApiClient,
+        names: Vec<String>,
+    ) -> Result<Vec<(String, Option<u64>)>> {
+        let results: Vec<(String, Result<Option<u64>>)> = stream::iter(names)
+            .map(|name| async move {
+                let delay = api.delay(&name, &self.config.test_url, 5000).await;
+                (name, delay)
+            })
+            .buffer_unordered(16)
+            .collect()
+            .await;
+        results
+            .into_iter()
+            .map(|(name, delay)| Ok((name, delay?)))
+            .collect()
+    }
+
+    /// Lowest delay member of proxy group `group` and its delay, testing all members concurrently.
+    ///
+    /// Built-ins like `DIRECT` are skipped, as they would always win.
+    async fn fastest_node(&self, api: &ApiClient, group: &str) -> Result<(String, u64)> {
+        let proxies = api.proxies().await?;
+        let members = proxies
+            .get(group)
+            .filter(|proxy| proxy.is_group())
+            .map(|proxy| &proxy.all)
+            .with_context(|| format!("proxy group `{group}` not found"))?
+            .iter()
+            .filter(|member| {
+                proxies
+                    .get(*member)
+                    .is_some_and(|proxy| proxy.is_node() || proxy.is_group())
+            })
+            .cloned()
+            .collect();
+        let delays = self.test_delays(api, members).await?;
+
+        let mut fastest: Option<(String, u64)> = None;
+        for (node, delay) in delays {
+            if let Some(delay) = delay {
+                if fastest.as_ref().is_none_or(|(_, min)| delay < *min) {
+                    fastest = Some((node, delay));
+                }
+            }
+        }
+        fastest.with_context(|| format!("no member of `{group}` responded to delay tests"))
+    }
+
+    /// Apply the schedule rule active now, once per time window so that manual selections made
+    /// within the window are kept.
+    pub async fn schedule_apply(&self) -> Result<()> {
+        let mut state = State::load(&self.mihoro_state_path)?;
+        let Some(rule) = active_rule(&self.config.schedule, Local::now().naive_local())? else {
+            println!("{} No schedule rule active", self.prefix.yellow());
+            if state.schedule.take().is_some() {
+                state.save(&self.mihoro_state_path)?;
+            }
+            return Ok(());
+        };
+        if state.schedule.as_deref() == Some(&rule.name) {
+            println!(
+                "{} Schedule `{}` already applied",
+                self.prefix.yellow(),
+                rule.name
+            );
+            return Ok(());
+        }
+
+        let api = self.api()?;
+        let node = if rule.select == FASTEST {
+            self.fastest_node(&api, &rule.group).await?.0
+        } else {
+            rule.select.clone()
+        };
+        api.select(&rule.group, &node).await?;
+        println!(
+            "{} Applied schedule `{}`, switched {} to {}",
+            self.prefix.green(),
+            rule.name,
+            rule.group.bold(),
+            node.bold()
+        );
+
+        state.schedule = Some(rule.name.clone());
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Names of mihoro's own systemd service and timer running `task` (e.g. `schedule`) for the
+    /// selected instance, as `(service, timer)`.
+    fn timer_unit_names(&self, task: &str) -> (String, String) {
+        let name = match &self.instance {
+            Some(instance) => format!("mihoro-{task}-{instance}"),
+            None => format!("mihoro-{task}"),
+        };
+        (format!("{name}.service"), format!("{name}.timer"))
+    }
+
+    /// Command line running this mihoro binary with `args` on the selected config and instance.
+    fn mihoro_command(&self, args: &str) -> Result<String> {
+        let mihoro_binary = std::env::current_exe()?;
+        let mut command = format!("{} -m {}", mihoro_binary.display(), self.config_path);
+        if let Some(instance) = &self.instance {
+            command.push_str(&format!(" -i {instance}"));
+        }
+        command.push_str(&format!(" {args}"));
+        Ok(command)
+    }
+
+    /// Write systemd units `units` as `(name, contents)` to `user_systemd_root`.
+    fn write_units(&self, units: [(&str, String); 2]) -> Result<()> {
+        let units_root = tilde(&self.config.user_systemd_root).to_string();
+        for (name, unit) in units {
+            let path = format!("{units_root}/{name}");
+            create_parent_dir(&path)?;
+            fs::write(&path, unit)?;
+            println!(
+                "{} Created {}",
+                self.prefix.green(),
+                path.underline().yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Stop, disable and remove timer `timer_name` and its service `service_name`.
+    fn remove_timer_units(&self, service_name: &str, timer_name: &str) -> Result<()> {
+        self.systemctl().stop(timer_name).execute()?;
+        self.systemctl().disable(timer_name).execute()?;
+        let units_root = tilde(&self.config.user_systemd_root).to_string();
+        delete_file(&format!("{units_root}/{timer_name}"), &self.prefix)?;
+        delete_file(&format!("{units_root}/{service_name}"), &self.prefix)?;
+        self.systemctl().daemon_reload().execute()?;
+        Ok(())
+    }
+
+    /// Install and start a systemd timer running `mihoro schedule apply` every 5 minutes.
+    pub fn schedule_install(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("schedule timers run mihoro locally, install mihoro on the remote host instead");
+        }
+        if self.config.schedule.is_empty() {
+            bail!("`schedule` undefined, define schedule rules first");
+        }
+
+        let exec_start = self.mihoro_command("schedule apply")?;
+        let (service_name, timer_name) = self.timer_unit_names("schedule");
+        let (service, timer) = schedule_units(&exec_start);
+        self.write_units([(&service_name, service), (&timer_name, timer)])?;
+
+        self.systemctl().daemon_reload().execute()?;
+        self.systemctl().enable(&timer_name).execute()?;
+        self.systemctl().start(&timer_name).execute()?;
+        Ok(())
+    }
+
+    /// Stop and remove the systemd timer installed with [`Mihoro::schedule_install`].
+    pub fn schedule_uninstall(&self) -> Result<()> {
+        let (service_name, timer_name) = self.timer_unit_names("schedule");
+        self.remove_timer_units(&service_name, &timer_name)
+    }
+
+    /// Hours between runs of `task`, `None` if it is not scheduled. The config is updated every
+    /// `config_interval` hours if given.
+    fn update_interval(&self, task: UpdateTask, config_interval: Option<u32>) -> Option<u32> {
+        match task {
+            // Updates run as often as the most frequent subscription, refreshing only those due
+            UpdateTask::Config => Some(
+                self.config
+                    .subscriptions
+                    .iter()
+                    .filter_map(|subscription| subscription.update_interval)
+                    .fold(
+                        config_interval.unwrap_or(self.config.config_update_interval),
+                        u32::min,
+                    ),
+            ),
+            UpdateTask::Geodata => self.config.geodata_update_interval,
+            UpdateTask::Binary => self.config.binary_update_interval,
+        }
+    }
+
+    /// Run `mihoro update`, `update-geodata` and `upgrade` with `backend`, each every
+    /// `*_update_interval` hours if set (the config every `interval` hours if given), replacing
+    /// any previous schedule of either backend.
+    pub fn cron_enable(&self, backend: CronBackend, interval: Option<u32>) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("auto-updates run mihoro locally, install mihoro on the remote host instead");
+        }
+        // Check all intervals before replacing the current schedule
+        let tasks = UpdateTask::ALL
+            .into_iter()
+            .filter_map(|task| Some((task, self.update_interval(task, interval)?)))
+            .map(|(task, interval)| Ok((task, interval, interval_schedule(interval)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.cron_disable()?;
+
+        match backend {
+            CronBackend::Crontab => {
+                // cron runs jobs without a session, yet `systemctl --user` needs its runtime dir
+                let mut env = String::new();
+                if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+                    env.push_str(&format!("XDG_RUNTIME_DIR={runtime_dir} "));
+                }
+                let mut crontab = read_crontab()?;
+                for (task, _, (schedule, _)) in &tasks {
+                    let command = format!(
+                        "{env}{} >>{} 2>&1",
+                        self.mihoro_command(task.command())?,
+                        self.state_file("cron.log")
+                    );
+                    let (service_name, _) = self.timer_unit_names(task.command());
+                    let marker = service_name.trim_end_matches(".service");
+                    crontab.push_str(&crontab_entry(&command, schedule, marker));
+                    crontab.push('\n');
+                }
+                write_crontab(&crontab)?;
+                for (task, interval, _) in &tasks {
+                    println!(
+                        "{} Added crontab entry updating {} every {} hours",
+                        self.prefix.green(),
+                        task.target(),
+                        interval
+                    );
+                }
+                println!(
+                    "{} Logging updates to {}",
+                    self.prefix.green(),
+                    self.state_file("cron.log").underline()
+                );
+            }
+            CronBackend::SystemdTimer => {
+                for (task, _, (_, on_calendar)) in &tasks {
+                    let (service_name, timer_name) = self.timer_unit_names(task.command());
+                    let (service, timer) =
+                        update_units(*task, &self.mihoro_command(task.command())?, on_calendar);
+                    self.write_units([(&service_name, service), (&timer_name, timer)])?;
+                    self.systemctl().daemon_reload().execute()?;
+                    self.systemctl().enable(&timer_name).execute()?;
+                    self.systemctl().start(&timer_name).execute()?;
+                }
+                if !self.system_scope() && linger_enabled() == Some(false) {
+                    println!(
+                        "{} User timers only run while you are logged in, run `loginctl \
+                         enable-linger` to update while logged out",
+                        "hint:".cyan()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop running scheduled updates, with either backend.
+    pub fn cron_disable(&self) -> Result<()> {
+        let units_root = tilde(&self.config.user_systemd_root).to_string();
+        let crontab = match command_exists("crontab") {
+            true => Some(read_crontab()?),
+            false => None,
+        };
+        let mut new_crontab = crontab.clone();
+        for task in UpdateTask::ALL {
+            let (service_name, timer_name) = self.timer_unit_names(task.command());
+            let marker = service_name.trim_end_matches(".service");
+            if let Some(crontab) = &mut new_crontab {
+                if find_entry(crontab, marker).is_some() {
+                    *crontab = remove_entry(crontab, marker);
+                    println!(
+                        "{} Removed crontab entry updating {}",
+                        self.prefix.green(),
+                        task.target()
+                    );
+                }
+            }
+            if Path::new(&format!("{units_root}/{timer_name}")).exists() {
+                self.remove_timer_units(&service_name, &timer_name)?;
+            }
+        }
+        if let Some(new_crontab) =
+            new_crontab.filter(|new_crontab| Some(new_crontab) != crontab.as_ref())
+        {
+            write_crontab(&new_crontab)?;
+        }
+        Ok(())
+    }
+
+    /// Print how updates are scheduled, and when systemd timers run them next.
+    pub fn cron_status(&self) -> Result<()> {
+        let units_root = tilde(&self.config.user_systemd_root).to_string();
+        let crontab = match command_exists("crontab") {
+            true => read_crontab()?,
+            false => String::new(),
+        };
+        let mut enabled = false;
+
+        for task in UpdateTask::ALL {
+            let (service_name, timer_name) = self.timer_unit_names(task.command());
+            let marker = service_name.trim_end_matches(".service");
+            if let Some(entry) = find_entry(&crontab, marker) {
+                let schedule: Vec<&str> = entry.split_whitespace().take(5).collect();
+                println!(
+                    "{} Updating {} from crontab at `{}`, logging to {}",
+                    self.prefix.green(),
+                    task.target(),
+                    schedule.join(" "),
+                    self.state_file("cron.log").underline()
+                );
+                enabled = true;
+            }
+            if Path::new(&format!("{units_root}/{timer_name}")).exists() {
+                self.print_timer_status(task, &timer_name)?;
+                enabled = true;
+            }
+        }
+        if !enabled {
+            println!(
+                "{} Auto-update disabled, run `mihoro cron enable` to update periodically",
+                self.prefix.yellow()
+            );
+        }
+        Ok(())
+    }
+
+    /// Print the state of systemd timer `timer_name` running `task`, and when it runs next.
+    fn print_timer_status(&self, task: UpdateTask, timer_name: &str) -> Result<()> {
+        let properties: HashMap<String, String> = self
+            .systemctl()
+            .show(
+                timer_name,
+                &["ActiveState", "NextElapseUSecRealtime", "LastTriggerUSec"],
+            )
+            .output()?
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let property = |name: &str| {
+            properties
+                .get(name)
+                .filter(|value| !value.is_empty() && *value != "n/a")
+                .cloned()
+        };
+        println!(
+            "{} Updating {} with {} ({})",
+            self.prefix.green(),
+            task.target(),
+            timer_name,
+            property("ActiveState").unwrap_or_else(|| String::from("unknown"))
+        );
+        println!(
+            "  next  {}",
+            property("NextElapseUSecRealtime").unwrap_or_else(|| String::from("-"))
+        );
+        println!(
+            "  last  {}",
+            property("LastTriggerUSec").unwrap_or_else(|| String::from("never"))
+        );
+        Ok(())
+    }
+
+    /// Print recorded delay tests and the quality score of `node`.
+    pub fn ctl_history(&self, node: &str) -> Result<()> {
+        let history = QualityHistory::load(&self.state_file("quality.json"))?;
+        let (Some(samples), Some(quality)) = (history.nodes.get(node), history.quality(node))
+        else {
+            bail!("no delay tests recorded for `{node}`, run `mihoro ctl probe` first");
+        };
+        for sample in samples {
+            let time = Local
+                .timestamp_opt(sample.time, 0)
+                .single()
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let delay = match sample.delay {
+                Some(delay) => format_delay(delay),
+                None => "timeout".red().to_string(),
+            };
+            println!("{}  {}", time.dimmed(), delay);
+        }
+        println!(
+            "{} {} samples, {:.0}% available, mean delay {}, score {}",
+            node.bold(),
+            quality.samples,
+            quality.availability * 100.0,
+            quality
+                .mean_delay
+                .map_or(String::from("--"), |delay| format!("{delay}ms")),
+            quality.score.to_string().bold()
+        );
+        Ok(())
+    }
+
+    /// Print proxy and rule providers with their size, health and last update, flagging remote
+    /// providers that have not been updated for over a day.
+    pub async fn ctl_providers(&self) -> Result<()> {
+        let providers = self.api()?.providers().await?;
+        if providers.is_empty() {
+            println!("{} No providers defined", self.prefix.yellow());
+            return Ok(());
+        }
+        let width = providers
+            .iter()
+            .map(|provider| provider.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let now = Utc::now();
+        for provider in providers {
+            let size = match provider.rule_count {
+                Some(count) => format!("{count} rules"),
+                None => {
+                    let alive = provider
+                        .proxies
+                        .iter()
+                        .filter(|node| node.last_delay().is_some())
+                        .count();
+                    format!("{} nodes, {} alive", provider.proxies.len(), alive)
+                }
+            };
+            let updated_at = provider
+                .updated_at
+                .as_deref()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+            let updated = match updated_at {
+                Some(time) => {
+                    let age = now.signed_duration_since(time);
+                    let text = format!("updated {}", format_age(age));
+                    if provider.vehicle == "HTTP" && age > chrono::Duration::days(1) {
+                        text.yellow().to_string()
+                    } else {
+                        text.dimmed().to_string()
+                    }
+                }
+                None => "never updated".yellow().to_string(),
+            };
+            println!(
+                "{:width$}  {:5}  {:6}  {:18}  {}",
+                provider.name.bold(),
+                provider.kind,
+                provider.vehicle,
+                size,
+                updated
+            );
+        }
+        Ok(())
+    }
+
+    /// Close active connections to hosts containing `host`, or all connections if `None`.
+    pub async fn ctl_kill(&self, host: Option<&str>) -> Result<()> {
+        let api = self.api()?;
+        let Some(pattern) = host else {
+            api.close_all_connections().await?;
+            println!("{} Closed all connections", self.prefix.green());
+            return Ok(());
+        };
+
+        let mut closed = 0;
+        for connection in api.connections().await? {
+            if connection.host().contains(pattern) {
+                api.close_connection(&connection.id).await?;
+                println!(
+                    "{} Closed {}:{}",
+                    self.prefix.green(),
+                    connection.host(),
+                    connection.metadata.destination_port
+                );
+                closed += 1;
+            }
+        }
+        if closed == 0 {
+            println!(
+                "{} No connections to hosts matching `{}`",
+                self.prefix.yellow(),
+                pattern
+            );
+        }
+        Ok(())
+    }
+
+    /// List active connections through the controller, optionally only those to hosts or from
+    /// processes containing the given patterns.
+    pub async fn connections(&self, domain: Option<&str>, process: Option<&str>) -> Result<()> {
+        let api = self.api()?;
+        let connections: Vec<ApiConnection> = api
+            .connections()
+            .await?
+            .into_iter()
+            .filter(|connection| domain.is_none_or(|domain| connection.host().contains(domain)))
+            .filter(|connection| {
+                process.is_none_or(|process| connection.metadata.process.contains(process))
+            })
+            .collect();
+        if connections.is_empty() {
+            println!("{} No matching connections", self.prefix.yellow());
+            return Ok(());
+        }
+
+        let rows: Vec<(String, String, String)> = connections
+            .iter()
+            .map(|connection| {
+                let rule = match connection.rule_payload.as_str() {
+                    "" => connection.rule.clone(),
+                    payload => format!("{}({})", connection.rule, payload),
+                };
+                // Chains list the node first, show them from the group down instead
+                let chain: Vec<&str> = connection.chains.iter().rev().map(String::as_str).collect();
+                let target = format!(
+                    "{}:{}",
+                    connection.host(),
+                    connection.metadata.destination_port
+                );
+                (target, rule, chain.join(" > "))
+            })
+            .collect();
+        let target_width = rows.iter().map(|row| row.0.chars().count()).max();
+        let rule_width = rows.iter().map(|row| row.1.chars().count()).max();
+        let chain_width = rows.iter().map(|row| row.2.chars().count()).max();
+        let process_width = connections
+            .iter()
+            .map(|connection| connection.metadata.process.chars().count())
+            .max();
+        for (connection, (target, rule, chain)) in connections.iter().zip(&rows) {
+            // IDs go last, for `connections close --id`
+            println!(
+                "{:tw$}  {:rw$}  {:cw$}  {} {:>8}  {} {:>8}  {:pw$}  {}",
+                target.bold(),
+                rule,
+                chain.cyan(),
+                "↑".dimmed(),
+                format_bytes(connection.upload),
+                "↓".dimmed(),
+                format_bytes(connection.download),
+                connection.metadata.process,
+                connection.id.dimmed(),
+                tw = target_width.unwrap_or(0),
+                rw = rule_width.unwrap_or(0),
+                cw = chain_width.unwrap_or(0),
+                pw = process_width.unwrap_or(0),
+            );
+        }
+        Ok(())
+    }
+
+    /// Close the connection with ID `id`, as listed by the controller.
+    pub async fn connection_close(&self, id: &str) -> Result<()> {
+        let api = self.api()?;
+        let Some(connection) = api
+            .connections()
+            .await?
+            .into_iter()
+            .find(|connection| connection.id == id)
+        else {
+            bail!("no active connection with ID `{id}`");
+        };
+        api.close_connection(id).await?;
+        println!(
+            "{} Closed {}:{}",
+            self.prefix.green(),
+            connection.host(),
+            connection.metadata.destination_port
+        );
+        Ok(())
+    }
+
+    /// Show live upload and download throughput through the controller until interrupted, with a
+    /// sparkline of recent downloads.
+    pub async fn traffic(&self) -> Result<()> {
+        let api = self.api()?;
+        let mut traffic = pin!(api.traffic().await?);
+        println!(
+            "{} Live traffic through {}, press Ctrl-C to stop",
+            self.prefix.cyan(),
+            api.base_url.underline()
+        );
+
+        let mut downloads = VecDeque::with_capacity(TRAFFIC_SPARKLINE_WIDTH);
+        while let Some(sample) = traffic.next().await {
+            let sample = sample?;
+            if downloads.len() == TRAFFIC_SPARKLINE_WIDTH {
+                downloads.pop_front();
+            }
+            downloads.push_back(sample.down);
+            print!(
+                "\r\x1b[2K{} {:>10}/s  {} {:>10}/s  {}",
+                "↑".dimmed(),
+                format_bytes(sample.up),
+                "↓".dimmed(),
+                format_bytes(sample.down),
+                sparkline(downloads.make_contiguous()).cyan()
+            );
+            io::stdout().flush()?;
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Snapshot of the core's resource usage, from the controller and systemd's accounting of
+    /// `mihomo.service`.
+    pub async fn stats(&self) -> Result<()> {
+        let (version, memory, connections) = match self.api() {
+            Ok(api) => (
+                api.version().await,
+                api.memory().await,
+                api.connection_stats().await,
+            ),
+            Err(err) => (Err(anyhow!("{err}")), Err(anyhow!("{err}")), Err(err)),
+        };
+        let unit = match self.init_system {
+            InitSystem::Systemd => self
+                .systemctl()
+                .show(
+                    &self.service_name,
+                    &[
+                        "ActiveState",
+                        "MainPID",
+                        "ActiveEnterTimestamp",
+                        "NRestarts",
+                        "CPUUsageNSec",
+                        "MemoryCurrent",
+                    ],
+                )
+                .output()
+                .map(|output| {
+                    output
+                        .lines()
+                        .filter_map(|line| line.split_once('='))
+                        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                        .collect::<HashMap<_, _>>()
+                }),
+            _ => Err(anyhow!("resource accounting requires systemd")),
+        };
+
+        let show = |value: Result<String>| match value {
+            Ok(value) => value,
+            Err(err) => format!("{} {}", "unknown".yellow(), format!("({err})").dimmed()),
+        };
+        // systemd reports unset accounting as `[not set]` or as u64::MAX
+        let accounted = |name: &str| -> Result<u64> {
+            let unit = unit.as_ref().map_err(|err| anyhow!("{err}"))?;
+            unit.get(name)
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value != u64::MAX)
+                .with_context(|| format!("no `{name}` accounting"))
+        };
+
+        println!(
+            "version      {}",
+            show(version.map(|version| version.bold().to_string()))
+        );
+        println!(
+            "memory       {}",
+            show(memory.map(|memory| format_bytes(memory.inuse).bold().to_string()))
+        );
+        println!(
+            "connections  {}",
+            show(connections.map(|stats| format!(
+                "{} active, {} {} {} {} total",
+                stats.active.to_string().bold(),
+                "↑".dimmed(),
+                format_bytes(stats.upload_total),
+                "↓".dimmed(),
+                format_bytes(stats.download_total)
+            )))
+        );
+        let service = unit.as_ref().map_err(|err| anyhow!("{err}")).map(|unit| {
+            let state = unit.get("ActiveState").map_or("unknown", String::as_str);
+            let mut service = if state == "active" {
+                state.green().to_string()
+            } else {
+                state.yellow().to_string()
+            };
+            if let Some(pid) = unit.get("MainPID").filter(|pid| *pid != "0") {
+                service.push_str(&format!(", pid {pid}"));
+            }
+            if let Some(since) = unit
+                .get("ActiveEnterTimestamp")
+                .filter(|since| !since.is_empty() && state == "active")
+            {
+                service.push_str(&format!(", since {since}"));
+            }
+            service
+        });
+        println!("service      {}", show(service));
+        println!(
+            "restarts     {}",
+            show(accounted("NRestarts").map(|restarts| restarts.to_string()))
+        );
+        println!(
+            "cpu time     {}",
+            show(accounted("CPUUsageNSec").map(|nsec| format!("{:.2}s", nsec as f64 / 1e9)))
+        );
+        println!(
+            "cgroup       {}",
+            show(accounted("MemoryCurrent").map(|bytes| format!("{} memory", format_bytes(bytes))))
+        );
+        Ok(())
+    }
+
+    /// List the rules of the running config through the controller, in the order they match.
+    pub async fn rules(&self) -> Result<()> {
+        let rules = self.api()?.rules().await?;
+        let index_width = rules.len().to_string().len();
+        let kind_width = rules.iter().map(|rule| rule.kind.len()).max().unwrap_or(0);
+        let payload_width = rules
+            .iter()
+            .map(|rule| rule.payload.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (index, rule) in rules.iter().enumerate() {
+            println!(
+                "{:>index_width$}  {:kind_width$}  {:payload_width$}  {}",
+                (index + 1).to_string().dimmed(),
+                rule.kind,
+                rule.payload,
+                rule.proxy.cyan()
+            );
+        }
+        Ok(())
+    }
+
+    /// Show which rule of the running config `destination` (a domain or an IP) matches and the
+    /// proxy it goes through.
+    ///
+    /// Rules depending on data only mihomo has (GeoSite, GeoIP, rule sets, processes) cannot be
+    /// evaluated and are reported if they come before the matching rule.
+    pub async fn rules_match(&self, destination: &str) -> Result<()> {
+        let rules = self.api()?.rules().await?;
+        let mut undecided = Vec::new();
+        let matched = rules.iter().enumerate().find(|(_, rule)| {
+            match rule_matches(&rule.kind, &rule.payload, destination) {
+                Some(matches) => matches,
+                None => {
+                    undecided.push(*rule);
+                    false
+                }
+            }
+        });
+
+        let describe = |rule: &ApiRule| match rule.payload.as_str() {
+            "" => rule.kind.clone(),
+            payload => format!("{},{}", rule.kind, payload),
+        };
+        match matched {
+            Some((index, rule)) => println!(
+                "{} {} matches rule #{} {} -> {}",
+                self.prefix.green(),
+                destination.bold(),
+                index + 1,
+                describe(rule),
+                rule.proxy.cyan().bold()
+            ),
+            None => println!(
+                "{} No rule matches {}, it goes DIRECT",
+                self.prefix.yellow(),
+                destination.bold()
+            ),
+        }
+        if !undecided.is_empty() {
+            println!(
+                "{} {} earlier rules cannot be checked locally and may match first:",
+                "warning:".yellow(),
+                undecided.len()
+            );
+            for rule in undecided.iter().take(MAX_SHADOWED_WARNINGS) {
+                println!("  {} -> {}", describe(rule), rule.proxy);
+            }
+            if undecided.len() > MAX_SHADOWED_WARNINGS {
+                println!("  ...and {} more", undecided.len() - MAX_SHADOWED_WARNINGS);
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-fetch provider `name`, or all providers, through the controller, health checking the
+    /// nodes of proxy providers afterwards.
+    pub async fn providers_update(&self, name: Option<&str>) -> Result<()> {
+        let api = self.api()?;
+        let providers = api.providers().await?;
+        let selected: Vec<&ApiProvider> = providers
+            .iter()
+            .filter(|provider| name.is_none_or(|name| provider.name == name))
+            .collect();
+        if let (Some(name), true) = (name, selected.is_empty()) {
+            let names: Vec<&str> = providers.iter().map(|p| p.name.as_str()).collect();
+            bail!("no provider `{name}`, providers are: {}", names.join(", "));
+        }
+        if selected.is_empty() {
+            println!("{} No providers defined", self.prefix.yellow());
+            return Ok(());
+        }
+
+        let mut failed = 0;
+        for provider in selected {
+            let updated = match api.update_provider(provider).await {
+                Ok(()) if provider.kind == "Proxy" => {
+                    api.healthcheck_provider(&provider.name).await
+                }
+                updated => updated,
+            };
+            match updated {
+                Ok(()) => println!(
+                    "{} Updated {} provider {}",
+                    self.prefix.green(),
+                    provider.kind.to_lowercase(),
+                    provider.name.bold()
+                ),
+                Err(err) => {
+                    println!(
+                        "{} failed to update {}: {}",
+                        "warning:".yellow(),
+                        provider.name.bold(),
+                        err
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("failed to update {failed} providers");
+        }
+        Ok(())
+    }
+
+    /// Open the web dashboard served by mihomo in the default browser with `xdg-open`.
+    pub fn ui_open(&self) -> Result<()> {
+        let mihomo_config = &self.config.mihomo_config;
+        let external_controller = mihomo_config
+            .external_controller
+            .as_ref()
+            .with_context(|| "`external_controller` undefined, dashboard is not served")?;
+        let installed = State::load(&self.mihoro_state_path)?
+            .dashboard
+            .map(|dashboard| dashboard.name);
+        let url = dashboard_url(
+            external_controller,
+            mihomo_config.secret.as_deref(),
+            installed,
+        )?;
+
+        println!("{} Opening {}", self.prefix.cyan(), url.underline());
+        Command::new("xdg-open")
+            .arg(&url)
+            .spawn()
+            .with_context(|| "failed to execute xdg-open")?
+            .wait()?;
+        Ok(())
+    }
+
+    async fn install_dashboard_version(
+        &self,
+        client: &Client,
+        dashboard: Dashboard,
+        version: String,
+    ) -> Result<()> {
+        let ui_root = self.ui_root()?;
+        install_dashboard(client, dashboard, &version, &ui_root, &self.prefix).await?;
+        self.lock_artifact(
+            "dashboard",
+            &ui_root,
+            Some(&dashboard.archive_url(&version)),
+            Some(&version),
+        )?;
+        self.push(&ui_root)?;
+        let mut state = State::load(&self.mihoro_state_path)?;
+        state.dashboard = Some(DashboardState {
+            name: dashboard,
+            version,
+        });
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Install firewall rules redirecting LAN traffic into mihomo, turning this machine into a
+    /// transparent proxy gateway.
+    ///
+    /// Uses `tproxy_port` (TCP and UDP) if defined, otherwise `redir_port` (TCP only). Rules are
+    /// recorded in mihoro's state to be removed with [`Mihoro::gateway_disable`].
+    pub fn gateway_enable(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!(
+                "`gateway` only manages the local firewall and kernel parameters, run it on the \
+                 remote host instead"
+            );
+        }
+        let mihomo_config = &self.config.mihomo_config;
+        let (mode, port) = match (mihomo_config.tproxy_port, mihomo_config.redir_port) {
+            (Some(port), _) => (GatewayMode::Tproxy, port),
+            (None, Some(port)) => (GatewayMode::Redir, port),
+            (None, None) => bail!("`tproxy_port` and `redir_port` undefined, define either first"),
+        };
+        if !mihomo_config.allow_lan.unwrap_or(false) {
+            println!(
+                "{} `{}` is false, LAN clients may be refused by mihomo",
+                "warning:".yellow(),
+                "allow_lan".bold()
+            );
+        }
+
+        // Replace previously installed rules
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.gateway.is_some() {
+            self.gateway_disable()?;
+            state = State::load(&self.mihoro_state_path)?;
+        }
+
+        let gateway = GatewayState {
+            backend: detect_backend()?,
+            mode,
+            port,
+            previous_sysctls: current_gateway_sysctls(),
+        };
+        let as_root = is_root();
+        for command in enable_commands(&gateway) {
+            command.run(as_root)?;
+        }
+        println!(
+            "{} Enabled gateway with {:?} redirecting LAN traffic to {:?} port {}",
+            self.prefix.green(),
+            gateway.backend,
+            gateway.mode,
+            gateway.port
+        );
+
+        // Enable forwarding and loosen reverse path filtering
+        for command in sysctl::install_commands() {
+            command.run(as_root)?;
+        }
+        println!(
+            "{} Applied kernel parameters from {}",
+            self.prefix.green(),
+            SYSCTL_DROPIN_PATH.underline().yellow()
+        );
+
+        state.gateway = Some(gateway);
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Remove firewall rules installed with [`Mihoro::gateway_enable`].
+    pub fn gateway_disable(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!(
+                "`gateway` only manages the local firewall and kernel parameters, run it on the \
+                 remote host instead"
+            );
+        }
+        let mut state = State::load(&self.mihoro_state_path)?;
+        let Some(gateway) = state.gateway.take() else {
+            println!("{} Gateway is not enabled", self.prefix.yellow());
+            return Ok(());
+        };
+
+        // Remove as many rules as possible even if some of them are already gone
+        let as_root = is_root();
+        let commands = disable_commands(&gateway)
+            .into_iter()
+            .chain(sysctl::remove_commands(&gateway.previous_sysctls));
+        for command in commands {
+            if let Err(err) = command.run(as_root) {
+                println!("{} {}", "warning:".yellow(), err);
+            }
+        }
+        println!("{} Disabled gateway", self.prefix.green());
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Point the host's resolver to mihomo's DNS server if `host_dns` is set and the core config
+    /// enables TUN with `dns-hijack`, replacing any previous configuration.
+    pub fn host_dns_configure(&self) -> Result<()> {
+        let Some(backend) = self.config.host_dns else {
+            return Ok(());
+        };
+        if self.remote.is_some() {
+            bail!(
+                "`host_dns` only configures the local resolver, run it on the remote host instead"
+            );
+        }
+
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.host_dns.is_some() {
+            self.host_dns_revert()?;
+            state = State::load(&self.mihoro_state_path)?;
+        }
+        let Some(server) = tun_dns_server(&self.mihomo_target_config_path)? else {
+            println!(
+                "{} `host_dns` is set but TUN with `dns-hijack` is disabled, leaving host DNS as is",
+                "warning:".yellow()
+            );
+            return Ok(());
+        };
+
+        let host_dns = HostDnsState { backend, server };
+        let as_root = is_root();
+        for command in resolver::configure_commands(&host_dns)? {
+            command.run(as_root)?;
+        }
+        println!(
+            "{} Pointed host DNS to mihomo at {} with {:?}",
+            self.prefix.green(),
+            host_dns.server,
+            host_dns.backend
+        );
+        state.host_dns = Some(host_dns);
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Revert host DNS configured with [`Mihoro::host_dns_configure`], if any.
+    pub fn host_dns_revert(&self) -> Result<()> {
+        let mut state = State::load(&self.mihoro_state_path)?;
+        let Some(host_dns) = state.host_dns.take() else {
+            return Ok(());
+        };
+
+        let as_root = is_root();
+        for command in resolver::revert_commands(&host_dns) {
+            if let Err(err) = command.run(as_root) {
+                println!("{} {}", "warning:".yellow(), err);
+            }
+        }
+        println!("{} Restored host DNS", self.prefix.green());
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Revert all system-level changes recorded in mihoro's state, in reverse order of how they
+    /// depend on mihomo: host DNS first, then gateway firewall rules and sysctls, then LAN ports.
+    pub fn teardown(&self) -> Result<()> {
+        let state = State::load(&self.mihoro_state_path)?;
+        if state.host_dns.is_none() && state.gateway.is_none() && state.lan.is_none() {
+            println!("{} No system changes to revert", self.prefix.yellow());
+            return Ok(());
+        }
+        if state.host_dns.is_some() {
+            self.host_dns_revert()?;
+        }
+        if state.gateway.is_some() {
+            self.gateway_disable()?;
+        }
+        if state.lan.is_some() {
+            self.lan_close()?;
+        }
+        Ok(())
+    }
+
+    /// Open mihomo's proxy ports in the host firewall (firewalld or ufw) for LAN clients.
+    pub fn lan_open(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("`lan` only manages the local firewall, run it on the remote host instead");
+        }
+        if !self.config.mihomo_config.allow_lan.unwrap_or(false) {
+            bail!("`allow_lan` is false, enable it and apply first");
+        }
+
+        // Replace previously opened ports, which may have changed since
+        let mut state = State::load(&self.mihoro_state_path)?;
+        if state.lan.is_some() {
+            self.lan_close()?;
+            state = State::load(&self.mihoro_state_path)?;
+        }
+
+        let (port, socks_port) = self.proxy_ports();
+        let mut ports = vec![port, socks_port];
+        ports.dedup();
+        let lan = LanState {
+            firewall: detect_firewall()?,
+            ports,
+        };
+        let as_root = is_root();
+        for command in open_commands(&lan) {
+            command.run(as_root)?;
+        }
+        println!(
+            "{} Opened ports {:?} in {:?} for LAN clients",
+            self.prefix.green(),
+            lan.ports,
+            lan.firewall
+        );
+
+        state.lan = Some(lan);
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Close ports opened with [`Mihoro::lan_open`].
+    pub fn lan_close(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("`lan` only manages the local firewall, run it on the remote host instead");
+        }
+        let mut state = State::load(&self.mihoro_state_path)?;
+        let Some(lan) = state.lan.take() else {
+            println!("{} No ports opened for LAN", self.prefix.yellow());
+            return Ok(());
+        };
+
+        // Close as many ports as possible even if some of them are already closed
+        let as_root = is_root();
+        for command in close_commands(&lan) {
+            if let Err(err) = command.run(as_root) {
+                println!("{} {}", "warning:".yellow(), err);
+            }
+        }
+        println!(
+            "{} Closed ports {:?} in {:?}",
+            self.prefix.green(),
+            lan.ports,
+            lan.firewall
+        );
+        state.save(&self.mihoro_state_path)
+    }
+
+    /// Diagnose common setup problems, returning an error if any check fails.
+    pub async fn doctor(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("`doctor` only checks the local machine, run it on the remote host instead");
+        }
+
+        let mut checks: Vec<Check> = vec![
+            check_file("mihomo binary", &self.mihomo_target_binary_path),
+            check_file("mihomo config", &self.mihomo_target_config_path),
+            check_init_system(self.init_system, self.config.service_manager.is_some()),
+            check_file(self.service().kind(), &self.mihomo_target_service_path),
+        ];
+
+        // Kernel parameters only matter when LAN traffic is redirected into mihomo
+        let mihomo_config = &self.config.mihomo_config;
+        let gateway_enabled = State::load(&self.mihoro_state_path)?.gateway.is_some();
+        if gateway_enabled
+            || mihomo_config
+                .tproxy_port
+                .or(mihomo_config.redir_port)
+                .is_some()
+        {
+            checks.extend(check_gateway_sysctls());
+        }
+
+        // System services need neither a user session nor lingering, and only systemd's user
+        // services rely on them
+        if self.system_scope() {
+            checks.push(Check::pass(
+                "service scope",
+                String::from("system, running as root"),
+            ));
+        } else if self.init_system == InitSystem::Systemd {
+            checks.push(if user_bus_available() {
+                Check::pass(
+                    "user D-Bus session",
+                    String::from("reachable by systemctl --user"),
+                )
+            } else {
+                Check::fail(
+                    "user D-Bus session",
+                    String::from("missing, log in directly instead of with su or sudo"),
+                )
+            });
+            match linger_enabled() {
+                Some(true) => checks.push(Check::pass("user lingering", String::from("enabled"))),
+                Some(false) => checks.push(Check::fail(
+                    "user lingering",
+                    String::from("disabled, mihomo stops on logout, run `loginctl enable-linger`"),
+                )),
+                None => (),
+            }
+        }
+
+        checks.push(match self.proxy_test().await {
+            Ok(elapsed) => Check::pass(
+                "proxy connectivity",
+                format!("{} in {}ms", self.config.test_url, elapsed.as_millis()),
+            ),
+            Err(err) => Check::fail("proxy connectivity", err.to_string()),
+        });
+
+        print_checks(&checks);
+        let failed = checks.iter().filter(|check| !check.passed).count();
+        if failed > 0 {
+            bail!("{} of {} checks failed", failed, checks.len());
+        }
+        Ok(())
+    }
+
+    /// Probe that the service is active, its controller answers and `test_url` is reachable through
+    /// the proxy, without any output.
+    ///
+    /// The controller is only probed if `external_controller` is defined, and the proxy only on the
+    /// local machine.
+    pub async fn healthcheck(&self) -> Health {
+        if !self.service().is_active() {
+            return Health::ServiceInactive;
+        }
+
+        if self.config.mihomo_config.external_controller.is_some() {
+            let answered = match self.api() {
+                Ok(api) => api.version().await.is_ok(),
+                Err(_) => false,
+            };
+            if !answered {
+                return Health::ControllerUnreachable;
+            }
+        }
+
+        if self.remote.is_none() && self.proxy_test().await.is_err() {
+            return Health::ProxyUnreachable;
+        }
+        Health::Healthy
+    }
+
+    /// Whether lingering is disabled on the local machine, so that mihomo stops once the user
+    /// logs out.
+    pub fn linger_missing(&self) -> bool {
+        self.remote.is_none() && !self.system_scope() && linger_enabled() == Some(false)
+    }
+
+    /// Record installed artifact `path` as `name` in `mihoro.lock`, with the URL it was downloaded
+    /// from and its version if known.
+    fn lock_artifact(
+        &self,
+        name: &str,
+        path: &str,
+        source: Option<&str>,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let lock_path = self.state_file("mihoro.lock");
+        let mut lockfile = Lockfile::load(&lock_path)?;
+        lockfile.record(name, path, source, version)?;
+        lockfile.save(&lock_path)
+    }
+
+    /// Re-hash artifacts recorded in `mihoro.lock`, returning an error if any was modified or is
+    /// missing.
+    pub fn verify(&self) -> Result<()> {
+        if self.remote.is_some() {
+            bail!("`verify` only checks the local machine, run it on the remote host instead");
+        }
+
+        let lockfile = Lockfile::load(&self.state_file("mihoro.lock"))?;
+        if lockfile.artifacts.is_empty() {
+            bail!("no artifacts recorded yet, run `mihoro setup` or `mihoro update-geodata` first");
+        }
+        let checks: Vec<Check> = lockfile
+            .verify()
+            .into_iter()
+            .map(|(name, artifact, verification)| match verification {
+                Verification::Intact => {
+                    Check::pass(name, format!("sha256 {}", short_hash(&artifact.sha256)))
+                }
+                Verification::Modified { sha256 } => Check::fail(
+                    name,
+                    format!(
+                        "sha256 {} differs from locked {} at {}",
+                        short_hash(&sha256),
+                        short_hash(&artifact.sha256),
+                        artifact.path
+                    ),
+                ),
+                Verification::Missing => Check::fail(name, format!("missing at {}", artifact.path)),
+            })
+            .collect();
+
+        print_checks(&checks);
+        let failed = checks.iter().filter(|check| !check.passed).count();
+        if failed > 0 {
+            bail!(
+                "{} of {} artifacts modified or missing, reinstall them if unexpected",
+                failed,
+                checks.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Proxy nodes and groups defined in mihomo's config, read from the file directly so that it
+    /// works while the service is stopped.
+    pub fn nodes(&self) -> Result<Nodes> {
+        self.pull(&self.mihomo_target_config_path)?;
+        Nodes::read(&self.mihomo_target_config_path, self.config.core)
+    }
+
+    /// Ports used for proxy export, as `(http_port, socks_port)`.
+    ///
+    /// `mixed_port` takes precedence over `port` and `socks_port` if defined.
+    pub fn proxy_ports(&self) -> (u16, u16) {
+        let mihomo_config = &self.config.mihomo_config;
+        (
+            mihomo_config.mixed_port.unwrap_or(mihomo_config.port),
+            mihomo_config.mixed_port.unwrap_or(mihomo_config.socks_port),
+        )
+    }
+
+    /// Request `test_url` through mihomo's HTTP proxy, returning how long it took.
+    pub async fn proxy_test(&self) -> Result<Duration> {
+        if self.remote.is_some() {
+            bail!("proxy test only runs on the local machine, run it on the remote host instead");
+        }
+        let (port, _) = self.proxy_ports();
+        test_proxy(&format!("http://127.0.0.1:{port}"), &self.config.test_url).await
+    }
+
+    /// Shell commands to export proxy environment variables pointing to localhost.
+    pub fn proxy_export(&self) -> String {
+        let (port, socks_port) = self.proxy_ports();
+        proxy_export_cmd("127.0.0.1", &port, &socks_port)
+    }
+
+    /// Shell commands to export proxy environment variables pointing to this machine's LAN IP.
+    pub fn proxy_export_lan(&self) -> Result<String> {
+        if !self.config.mihomo_config.allow_lan.unwrap_or(false) {
+            println!(
+                "{} `{}` is false, proxy is not available for LAN",
+                "warning:".yellow(),
+                "allow_lan".bold()
+            );
+        }
+
+        let (port, socks_port) = self.proxy_ports();
+        Ok(proxy_export_cmd(
+            &local_ip()?.to_string(),
+            &port,
+            &socks_port,
+        ))
+    }
+
+    /// Shell commands to unset proxy environment variables.
+    pub fn proxy_unset(&self) -> String {
+        proxy_unset_cmd()
+    }
+
+    /// Replace mihoro with `command`, run with proxy environment variables set just for it.
+    ///
+    /// Only returns if `command` could not be executed.
+    pub fn exec(&self, command: &[String]) -> Result<()> {
+        let Some((program, args)) = command.split_first() else {
+            bail!("no command to execute");
+        };
+        let (port, socks_port) = self.proxy_ports();
+        let err = Command::new(program)
+            .args(args)
+            .envs(proxy_env("127.0.0.1", port, socks_port))
+            .exec();
+        bail!("failed to execute `{program}`: {err}")
+    }
+
+    /// `~/.ssh/config` stanza sending SSH connections to `host_pattern` through the proxy, via
+    /// `corkscrew` if installed and OpenBSD `nc` otherwise.
+    pub fn proxy_ssh(&self, host_pattern: &str) -> String {
+        let (port, socks_port) = self.proxy_ports();
+        let corkscrew = !command_exists("nc") && command_exists("corkscrew");
+        ssh_config_stanza(host_pattern, "127.0.0.1", port, socks_port, corkscrew)
+    }
+
+    /// Write the [`Mihoro::proxy_ssh`] stanza to `~/.ssh/mihoro.conf`, to be pulled into
+    /// `~/.ssh/config` with an `Include`.
+    pub fn proxy_ssh_install(&self, host_pattern: &str) -> Result<()> {
+        let include_path = tilde("~/.ssh/mihoro.conf").to_string();
+        create_parent_dir(&include_path)?;
+        fs::write(
+            &include_path,
+            format!(
+                "# Generated by mihoro, routing SSH through mihomo\n{}\n",
+                self.proxy_ssh(host_pattern)
+            ),
+        )?;
+        println!(
+            "{} Wrote SSH proxy config to {}",
+            self.prefix.green(),
+            include_path.underline().yellow()
+        );
+
+        let ssh_config = fs::read_to_string(tilde("~/.ssh/config").as_ref()).unwrap_or_default();
+        if !ssh_config
+            .lines()
+            .any(|line| line.trim().eq_ignore_ascii_case("include mihoro.conf"))
+        {
+            println!(
+                "{} Add `{}` at the top of ~/.ssh/config to enable it",
+                self.prefix.yellow(),
+                "Include mihoro.conf".bold()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Move state from its pre-XDG location under mihomo's config root, if not yet moved.
+fn migrate_legacy_state(legacy_path: &str, state_path: &str) -> Result<()> {
+    if Path::new(legacy_path).exists() && !Path::new(state_path).exists() {
+        create_parent_dir(state_path)?;
+        fs::copy(legacy_path, state_path)?;
+        fs::remove_file(legacy_path)?;
+    }
+    Ok(())
+}
+
+/// Move config snapshots from `legacy_root` under mihoro's data directory, where earlier versions
+/// kept them, to `backups_root` next to the core config.
+fn migrate_legacy_history(legacy_root: &str, backups_root: &str) -> Result<()> {
+    if !Path::new(legacy_root).is_dir() || Path::new(backups_root).exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(backups_root)?;
+    for snapshot in list_snapshots(legacy_root)? {
+        let moved = Path::new(backups_root).join(snapshot.file_name().unwrap_or_default());
+        // Keep modification times, which snapshot ages are told by, unless on another filesystem
+        if fs::rename(&snapshot, &moved).is_err() {
+            fs::copy(&snapshot, &moved)?;
+            fs::remove_file(&snapshot)?;
+        }
+    }
+    fs::remove_dir(legacy_root).ok();
+    Ok(())
+}
+
+/// Delay in milliseconds, colored by how usable it is.
+fn format_delay(delay: u64) -> String {
+    let text = format!("{delay}ms");
+    match delay {
+        0..=199 => text.green().to_string(),
+        200..=499 => text.yellow().to_string(),
+        _ => text.red().to_string(),
+    }
+}
+
+/// Remaining traffic and expiry of subscription `info` at `now` on a single line, highlighted if
+/// running low.
+fn format_subscription(info: &SubscriptionInfo, now: DateTime<Utc>) -> String {
+    let traffic = match info.remaining() {
+        Some(remaining) => format!(
+            "{} of {} left",
+            format_bytes(remaining),
+            format_bytes(info.total)
+        ),
+        None => format!("{} used, unlimited", format_bytes(info.used())),
+    };
+    let line = match info.days_left(now) {
+        Some(days) => format!("{traffic}, {}", format_days_left(days)),
+        None => traffic,
+    };
+    match info.running_low(now) {
+        true => line.yellow().to_string(),
+        false => line,
+    }
+}
+
+/// Days until a subscription expires, like `expires in 12 days`.
+fn format_days_left(days: i64) -> String {
+    match days {
+        ..=-1 => format!("expired {} days ago", -days),
+        0 => String::from("expires today"),
+        1 => String::from("expires tomorrow"),
+        days => format!("expires in {days} days"),
+    }
+}
+
+/// Byte count like `1.5 MB`, in the largest unit below 1024 of it.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// First version number like `v1.18.8` or `1.9.0` in `output`, normalized to start with `v`.
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .map(|word| word.trim_start_matches('v'))
+        .find(|word| word.split('.').count() >= 2 && word.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|version| format!("v{version}"))
+}
+
+/// Print node delays sorted from fastest, nodes that did not respond last.
+fn print_delays(mut delays: Vec<(String, Option<u64>)>) {
+    delays.sort_by(|(a_name, a_delay), (b_name, b_delay)| {
+        (a_delay.is_none(), a_delay, a_name).cmp(&(b_delay.is_none(), b_delay, b_name))
+    });
+    let width = delays
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (name, delay) in &delays {
+        let delay = match delay {
+            Some(delay) => format_delay(*delay),
+            None => "timeout".red().dimmed().to_string(),
+        };
+        println!("{name:width$}  {delay}");
+    }
+}
+
+/// Bars scaled to the largest of `values`, one per value.
+fn sparkline(values: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|value| BARS[(value * (BARS.len() as u64 - 1) / max) as usize])
+        .collect()
+}
+
+/// `err` with its message replaced by `message`, keeping the HTTP status it may carry.
+fn reword_error(
+    err: anyhow::Error,
+    message: impl FnOnce(&anyhow::Error) -> String,
+) -> anyhow::Error {
+    let message = message(&err);
+    match err.downcast::<HttpStatusError>() {
+        Ok(err) => HttpStatusError::new(err.status, message).into(),
+        Err(_) => anyhow!(message),
+    }
+}
+
+/// HTTP status of a download failing with `err`, if the server responded at all.
+fn http_status(err: &anyhow::Error) -> Option<u16> {
+    err.chain()
+        .find_map(|err| err.downcast_ref::<HttpStatusError>())
+        .map(|err| err.status.as_u16())
+}
+
+/// Age like `3h ago`, in its largest whole unit.
+fn format_age(age: chrono::Duration) -> String {
+    match age.num_seconds().max(0) {
+        seconds @ 0..=59 => format!("{seconds}s ago"),
+        seconds @ 60..=3599 => format!("{}m ago", seconds / 60),
+        seconds @ 3600..=86399 => format!("{}h ago", seconds / 3600),
+        seconds => format!("{}d ago", seconds / 86400),
+    }
 }
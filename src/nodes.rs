@@ -0,0 +1,223 @@
+use crate::config::Core;
+
+use std::fs;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+/// A proxy node defined in the core config.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub kind: String,
+    pub server: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// A proxy group selecting between nodes or other groups.
+#[derive(Debug, Clone)]
+pub struct ProxyGroup {
+    pub name: String,
+    pub kind: String,
+    pub members: Vec<String>,
+}
+
+/// Nodes and groups parsed from the core config, without asking the running core.
+#[derive(Debug, Clone, Default)]
+pub struct Nodes {
+    pub nodes: Vec<Node>,
+    pub groups: Vec<ProxyGroup>,
+}
+
+/// Sing-box outbound types selecting between other outbounds.
+const SING_BOX_GROUP_TYPES: [&str; 2] = ["selector", "urltest"];
+
+/// Sing-box outbound types that are not proxies.
+const SING_BOX_BUILTIN_TYPES: [&str; 3] = ["direct", "block", "dns"];
+
+/// Keywords commonly found in node names, mapped to region codes.
+const REGION_KEYWORDS: [(&str, &str); 24] = [
+    ("香港", "HK"),
+    ("Hong Kong", "HK"),
+    ("HK", "HK"),
+    ("台湾", "TW"),
+    ("Taiwan", "TW"),
+    ("TW", "TW"),
+    ("日本", "JP"),
+    ("Japan", "JP"),
+    ("JP", "JP"),
+    ("新加坡", "SG"),
+    ("Singapore", "SG"),
+    ("SG", "SG"),
+    ("美国", "US"),
+    ("United States", "US"),
+    ("US", "US"),
+    ("韩国", "KR"),
+    ("Korea", "KR"),
+    ("KR", "KR"),
+    ("英国", "GB"),
+    ("United Kingdom", "GB"),
+    ("UK", "GB"),
+    ("德国", "DE"),
+    ("Germany", "DE"),
+    ("DE", "DE"),
+];
+
+impl Nodes {
+    /// Read nodes and groups from the core config at `path`.
+    pub fn read(path: &str, core: Core) -> Result<Nodes> {
+        let raw = fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        match core {
+            Core::Mihomo => {
+                let config: serde_yaml::Value = serde_yaml::from_str(&raw)
+                    .with_context(|| format!("failed to parse {path}"))?;
+                let str_field = |item: &serde_yaml::Value, key: &str| {
+                    item[key].as_str().map(String::from).unwrap_or_default()
+                };
+                let items = |key: &str| config[key].as_sequence().cloned().unwrap_or_default();
+
+                let nodes = items("proxies")
+                    .iter()
+                    .map(|proxy| Node {
+                        name: str_field(proxy, "name"),
+                        kind: str_field(proxy, "type"),
+                        server: proxy["server"].as_str().map(String::from),
+                        port: port(&proxy["port"]),
+                    })
+                    .collect();
+                let groups = items("proxy-groups")
+                    .iter()
+                    .map(|group| ProxyGroup {
+                        name: str_field(group, "name"),
+                        kind: str_field(group, "type"),
+                        members: group["proxies"]
+                            .as_sequence()
+                            .map(|members| {
+                                members
+                                    .iter()
+                                    .filter_map(|member| member.as_str().map(String::from))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    })
+                    .collect();
+                Ok(Nodes { nodes, groups })
+            }
+            Core::SingBox => {
+                let config: serde_json::Value = serde_json::from_str(&raw)
+                    .with_context(|| format!("failed to parse {path}"))?;
+                let mut nodes = Nodes::default();
+                for outbound in config["outbounds"].as_array().into_iter().flatten() {
+                    let name = outbound["tag"].as_str().unwrap_or_default().to_string();
+                    let kind = outbound["type"].as_str().unwrap_or_default().to_string();
+                    if SING_BOX_GROUP_TYPES.contains(&kind.as_str()) {
+                        let members = outbound["outbounds"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|member| member.as_str().map(String::from))
+                            .collect();
+                        nodes.groups.push(ProxyGroup {
+                            name,
+                            kind,
+                            members,
+                        });
+                    } else if !SING_BOX_BUILTIN_TYPES.contains(&kind.as_str()) {
+                        let server = outbound["server"].as_str().map(String::from);
+                        let port = outbound["server_port"]
+                            .as_u64()
+                            .and_then(|port| port.try_into().ok());
+                        nodes.nodes.push(Node {
+                            name,
+                            kind,
+                            server,
+                            port,
+                        });
+                    }
+                }
+                Ok(nodes)
+            }
+        }
+    }
+}
+
+/// Port of a mihomo proxy, given as a number or, by some providers, a string.
+fn port(value: &serde_yaml::Value) -> Option<u16> {
+    match value {
+        serde_yaml::Value::String(port) => port.parse().ok(),
+        value => value.as_u64().and_then(|port| port.try_into().ok()),
+    }
+}
+
+impl Node {
+    /// Region guessed from the node's name, by its flag emoji or common keywords.
+    pub fn region(&self) -> Option<String> {
+        region_of(&self.name)
+    }
+}
+
+/// Region code guessed from a node name, by its flag emoji or common keywords.
+pub fn region_of(name: &str) -> Option<String> {
+    // Flag emojis are pairs of regional indicator symbols, mapping to country codes
+    let indicators: Vec<char> = name
+        .chars()
+        .filter_map(|c| match c as u32 {
+            0x1F1E6..=0x1F1FF => char::from_u32(c as u32 - 0x1F1E6 + 'A' as u32),
+            _ => None,
+        })
+        .take(2)
+        .collect();
+    if indicators.len() == 2 {
+        return Some(indicators.into_iter().collect());
+    }
+
+    REGION_KEYWORDS
+        .iter()
+        .find(|(keyword, _)| name.contains(keyword))
+        .map(|(_, region)| region.to_string())
+}
+
+/// Flag emoji of a two-letter region code, e.g. `HK`.
+pub fn flag_of(region: &str) -> String {
+    region
+        .chars()
+        .filter(char::is_ascii_uppercase)
+        .filter_map(|c| char::from_u32(c as u32 - 'A' as u32 + 0x1F1E6))
+        .collect()
+}
+
+/// Print nodes and groups as aligned tables.
+pub fn print_nodes(nodes: &Nodes) {
+    let width = |names: &mut dyn Iterator<Item = &String>| {
+        names.map(|name| name.chars().count()).max().unwrap_or(0)
+    };
+
+    println!("{} ({})", "Nodes".bold(), nodes.nodes.len());
+    let name_width = width(&mut nodes.nodes.iter().map(|node| &node.name));
+    let kind_width = width(&mut nodes.nodes.iter().map(|node| &node.kind));
+    for node in &nodes.nodes {
+        let address = match (&node.server, node.port) {
+            (Some(server), Some(port)) => format!("{server}:{port}"),
+            (server, _) => server.clone().unwrap_or_default(),
+        };
+        println!(
+            "  {:name_width$}  {:kind_width$}  {:2}  {}",
+            node.name,
+            node.kind.cyan(),
+            node.region().unwrap_or_else(|| String::from("--")).yellow(),
+            address.dimmed()
+        );
+    }
+
+    println!("{} ({})", "Groups".bold(), nodes.groups.len());
+    let name_width = width(&mut nodes.groups.iter().map(|group| &group.name));
+    let kind_width = width(&mut nodes.groups.iter().map(|group| &group.kind));
+    for group in &nodes.groups {
+        println!(
+            "  {:name_width$}  {:kind_width$}  {}",
+            group.name,
+            group.kind.cyan(),
+            format!("{} members", group.members.len()).dimmed()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::Core;
+use crate::utils::command_exists;
+
+use std::collections::HashSet;
+use std::fs;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+/// Proxy nodes and rules of a core config, compared before and after an update.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSummary {
+    pub nodes: Vec<String>,
+    pub rules: Vec<String>,
+    /// Whether TUN is enabled, which needs network administration capabilities.
+    pub tun: bool,
+}
+
+impl ConfigSummary {
+    /// Read node names and rules from the core config at `path`.
+    pub fn read(path: &str, core: Core) -> Result<ConfigSummary> {
+        let raw = fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        let summary = match core {
+            Core::Mihomo => {
+                let config: serde_yaml::Value = serde_yaml::from_str(&raw)
+                    .with_context(|| format!("failed to parse {path}"))?;
+                ConfigSummary {
+                    nodes: yaml_names(&config["proxies"]),
+                    rules: config["rules"]
+                        .as_sequence()
+                        .map(|rules| {
+                            rules
+                                .iter()
+                                .filter_map(|rule| rule.as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    tun: config["tun"]["enable"].as_bool() == Some(true),
+                }
+            }
+            Core::SingBox => {
+                let config: serde_json::Value = serde_json::from_str(&raw)
+                    .with_context(|| format!("failed to parse {path}"))?;
+                ConfigSummary {
+                    nodes: config["outbounds"]
+                        .as_array()
+                        .map(|outbounds| {
+                            outbounds
+                                .iter()
+                                .filter_map(|outbound| outbound["tag"].as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    rules: config["route"]["rules"]
+                        .as_array()
+                        .map(|rules| rules.iter().map(|rule| rule.to_string()).collect())
+                        .unwrap_or_default(),
+                    tun: config["inbounds"].as_array().is_some_and(|inbounds| {
+                        inbounds.iter().any(|inbound| inbound["type"] == "tun")
+                    }),
+                }
+            }
+        };
+        Ok(summary)
+    }
+
+    /// Human readable summary of changes from `previous` to this config.
+    pub fn changes_since(&self, previous: Option<&ConfigSummary>) -> String {
+        let Some(previous) = previous else {
+            return format!("{} nodes, {} rules", self.nodes.len(), self.rules.len());
+        };
+
+        // Renamed nodes count as one removed and one added
+        let old_nodes: HashSet<&String> = previous.nodes.iter().collect();
+        let new_nodes: HashSet<&String> = self.nodes.iter().collect();
+        let nodes = match (
+            new_nodes.difference(&old_nodes).count(),
+            old_nodes.difference(&new_nodes).count(),
+        ) {
+            (0, 0) => plural(self.nodes.len(), "node"),
+            (added, 0) => format!("+{}", plural(added, "node")),
+            (0, removed) => format!("-{}", plural(removed, "node")),
+            (added, removed) => format!("+{}, -{}", plural(added, "node"), plural(removed, "node")),
+        };
+
+        let old_rules: HashSet<&String> = previous.rules.iter().collect();
+        let new_rules: HashSet<&String> = self.rules.iter().collect();
+        let rules = match old_rules.symmetric_difference(&new_rules).count() {
+            0 => String::from("rules unchanged"),
+            changed => format!("{} changed", plural(changed, "rule")),
+        };
+
+        format!("{nodes}, {rules}")
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
+fn yaml_names(items: &serde_yaml::Value) -> Vec<String> {
+    items
+        .as_sequence()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item["name"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Show a desktop notification with `notify-send`.
+pub fn send_notification(summary: &str, body: &str) -> Result<()> {
+    if !command_exists("notify-send") {
+        bail!("`notify-send` not found, install libnotify to receive notifications");
+    }
+    let status = Command::new("notify-send")
+        .arg("--app-name=mihoro")
+        .arg(summary)
+        .arg(body)
+        .status()?;
+    if !status.success() {
+        bail!("`notify-send` exited with {status}");
+    }
+    Ok(())
+}
@@ -1,5 +1,10 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
 use clap_complete::shells::Shell;
+use reqwest::{Client, Proxy};
 
+/// Shell commands for exporting `https_proxy`, `http_proxy` and `all_proxy` in the current shell.
 pub fn proxy_export_cmd(hostname: &str, http_port: &u16, socks_port: &u16) -> String {
     // Check current shell
     let shell = Shell::from_env().unwrap_or(Shell::Bash);
@@ -23,6 +28,26 @@ pub fn proxy_export_cmd(hostname: &str, http_port: &u16, socks_port: &u16) -> St
     }
 }
 
+/// Proxy environment variables for a child process, in both lower and upper case as tools differ
+/// in which they read, with local addresses excluded through `no_proxy`.
+pub fn proxy_env(hostname: &str, http_port: u16, socks_port: u16) -> Vec<(String, String)> {
+    let vars = [
+        ("https_proxy", format!("http://{hostname}:{http_port}")),
+        ("http_proxy", format!("http://{hostname}:{http_port}")),
+        ("all_proxy", format!("socks5://{hostname}:{socks_port}")),
+        ("no_proxy", "localhost,127.0.0.1,::1".to_owned()),
+    ];
+    vars.into_iter()
+        .flat_map(|(name, value)| {
+            [
+                (name.to_owned(), value.clone()),
+                (name.to_uppercase(), value),
+            ]
+        })
+        .collect()
+}
+
+/// Shell commands for unsetting proxy environment variables in the current shell.
 pub fn proxy_unset_cmd() -> String {
     // Check current shell
     let shell = Shell::from_env().unwrap_or(Shell::Bash);
@@ -37,3 +62,42 @@ pub fn proxy_unset_cmd() -> String {
         }
     }
 }
+
+/// `~/.ssh/config` stanza routing SSH connections to hosts matching `host_pattern` through the
+/// proxy, with `corkscrew` over the HTTP proxy or OpenBSD `nc` over the SOCKS5 proxy.
+pub fn ssh_config_stanza(
+    host_pattern: &str,
+    hostname: &str,
+    http_port: u16,
+    socks_port: u16,
+    corkscrew: bool,
+) -> String {
+    let proxy_command = if corkscrew {
+        format!("corkscrew {hostname} {http_port} %h %p")
+    } else {
+        format!("nc -X 5 -x {hostname}:{socks_port} %h %p")
+    };
+    format!("Host {host_pattern}\n    ProxyCommand {proxy_command}")
+}
+
+/// Request `test_url` through the HTTP proxy at `proxy_url`, returning how long it took.
+pub async fn test_proxy(proxy_url: &str, test_url: &str) -> Result<Duration> {
+    let client = Client::builder()
+        .proxy(Proxy::all(proxy_url)?)
+        .timeout(Duration::from_secs(10))
+        .build()?;
+
+    let start = Instant::now();
+    let res = client
+        .get(test_url)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach {test_url} through {proxy_url}"))?;
+    if !res.status().is_success() {
+        bail!(
+            "{test_url} responded with {} through {proxy_url}",
+            res.status()
+        );
+    }
+    Ok(start.elapsed())
+}
@@ -0,0 +1,91 @@
+use crate::utils::create_parent_dir;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Delay test results are kept for this many days.
+const MAX_AGE_DAYS: i64 = 30;
+
+/// Delay test results recorded per node, scoring nodes by stability over time.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct QualityHistory {
+    pub nodes: BTreeMap<String, Vec<Sample>>,
+}
+
+/// A single delay test of a node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sample {
+    /// Unix timestamp of the test.
+    pub time: i64,
+    /// Delay in milliseconds, `None` if the node timed out or failed.
+    pub delay: Option<u64>,
+}
+
+/// Summary of a node's recorded samples.
+#[derive(Debug, Clone)]
+pub struct NodeQuality {
+    pub samples: usize,
+    /// Ratio of successful tests, from 0 to 1.
+    pub availability: f64,
+    pub mean_delay: Option<u64>,
+    /// Score from 0 to 100, favouring nodes that are reliably up and fast.
+    pub score: u32,
+}
+
+impl QualityHistory {
+    /// Read history from `path`, empty if it does not exist yet.
+    pub fn load(path: &str) -> Result<QualityHistory> {
+        if !Path::new(path).exists() {
+            return Ok(QualityHistory::default());
+        }
+        let raw = fs::read_to_string(path)?;
+        serde_json::from_str(&raw).with_context(|| format!("failed to parse {path}"))
+    }
+
+    /// Write history to `path`, dropping samples older than 30 days.
+    pub fn save(&mut self, path: &str) -> Result<()> {
+        let cutoff = (Utc::now() - Duration::days(MAX_AGE_DAYS)).timestamp();
+        for samples in self.nodes.values_mut() {
+            samples.retain(|sample| sample.time >= cutoff);
+        }
+        self.nodes.retain(|_, samples| !samples.is_empty());
+
+        create_parent_dir(path)?;
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Record a delay test of `node` taken now.
+    pub fn record(&mut self, node: &str, delay: Option<u64>) {
+        self.nodes.entry(node.to_owned()).or_default().push(Sample {
+            time: Utc::now().timestamp(),
+            delay,
+        });
+    }
+
+    /// Quality of `node`, `None` if it was never tested.
+    pub fn quality(&self, node: &str) -> Option<NodeQuality> {
+        let samples = self.nodes.get(node).filter(|samples| !samples.is_empty())?;
+        let delays: Vec<u64> = samples.iter().filter_map(|sample| sample.delay).collect();
+        let availability = delays.len() as f64 / samples.len() as f64;
+        let mean_delay =
+            (!delays.is_empty()).then(|| delays.iter().sum::<u64>() / delays.len() as u64);
+
+        // Availability weighs most, every 100ms of mean delay costs 5 points
+        let delay_penalty = mean_delay.map_or(0.0, |delay| delay as f64 / 20.0);
+        let score = (availability * 100.0 - delay_penalty)
+            .clamp(0.0, 100.0)
+            .round() as u32;
+        Some(NodeQuality {
+            samples: samples.len(),
+            availability,
+            mean_delay,
+            score,
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use crate::utils::{create_parent_dir, github_latest_release};
+
+use std::fs;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+/// Releases are checked at most once per this many seconds.
+const CHECK_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// Latest mihoro release as last fetched from GitHub.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReleaseCache {
+    checked_at: i64,
+    latest: String,
+}
+
+/// Newer mihoro release than the running version, if any.
+///
+/// The latest release is cached at `cache_path` and only refetched once a day, with a short timeout
+/// so that an unreachable GitHub does not hold up commands.
+pub async fn newer_release(client: &Client, cache_path: &str) -> Result<Option<String>> {
+    let now = Utc::now().timestamp();
+    let cached = fs::read_to_string(cache_path)
+        .ok()
+        .and_then(|raw| toml::from_str::<ReleaseCache>(&raw).ok())
+        .filter(|cache| now - cache.checked_at < CHECK_INTERVAL_SECS);
+
+    let latest = match cached {
+        Some(cache) => cache.latest,
+        None => {
+            // Failed checks are cached as well, so that they are only retried the next day
+            let latest = tokio::time::timeout(
+                Duration::from_secs(3),
+                github_latest_release(client, "spencerwooo/mihoro"),
+            )
+            .await
+            .ok()
+            .and_then(|release| release.ok())
+            .unwrap_or_else(|| format!("v{}", env!("CARGO_PKG_VERSION")));
+            create_parent_dir(cache_path)?;
+            fs::write(
+                cache_path,
+                toml::to_string(&ReleaseCache {
+                    checked_at: now,
+                    latest: latest.clone(),
+                })?,
+            )?;
+            latest
+        }
+    };
+
+    let is_newer = parse_version(&latest) > parse_version(env!("CARGO_PKG_VERSION"));
+    Ok(is_newer.then_some(latest))
+}
+
+/// Numeric components of a version like `v0.5.0`, for comparison.
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
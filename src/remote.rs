@@ -0,0 +1,118 @@
+use crate::utils::{create_parent_dir, shell_quote};
+
+use std::{path::Path, process::Command};
+
+use anyhow::{bail, Context, Result};
+
+/// A remote machine managed over SSH.
+///
+/// Files are prepared in a local staging directory that mirrors the remote filesystem, and then
+/// synced to the remote machine with `scp`. Within the staging root, `home/` maps to the remote
+/// user's home directory and `root/` maps to the remote filesystem root.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub destination: String,
+    pub staging_root: String,
+}
+
+impl Remote {
+    /// Create a remote for SSH `destination` (e.g. `user@server` or a `~/.ssh/config` alias),
+    /// staging files under `<cache_dir>/hosts/<destination>`.
+    pub fn new(destination: &str, cache_dir: &str) -> Remote {
+        Remote {
+            destination: destination.to_owned(),
+            staging_root: format!("{cache_dir}/hosts/{destination}"),
+        }
+    }
+
+    /// Hostname of the remote machine, without the user part of the SSH destination.
+    pub fn hostname(&self) -> &str {
+        self.destination
+            .rsplit_once('@')
+            .map_or(self.destination.as_str(), |(_, host)| host)
+    }
+
+    /// Local staging path of `path` on the remote machine, `~` refers to the remote home.
+    pub fn stage_path(&self, path: &str) -> String {
+        match path.strip_prefix("~/") {
+            Some(relative) => format!("{}/home/{}", self.staging_root, relative),
+            None if path.starts_with('/') => format!("{}/root{}", self.staging_root, path),
+            None => format!("{}/home/{}", self.staging_root, path),
+        }
+    }
+
+    /// Path on the remote machine of a staged file, relative to the remote home if applicable.
+    pub fn remote_path(&self, staged_path: &str) -> Result<String> {
+        let path = staged_path
+            .strip_prefix(&self.staging_root)
+            .with_context(|| format!("`{}` is not staged for remote", staged_path))?;
+        if let Some(relative) = path.strip_prefix("/home/") {
+            Ok(relative.to_owned())
+        } else if let Some(absolute) = path.strip_prefix("/root") {
+            Ok(absolute.to_owned())
+        } else {
+            bail!("`{}` is not staged for remote", staged_path)
+        }
+    }
+
+    /// Path of a staged file as referred to by a systemd unit, with `%h` for the remote home.
+    pub fn unit_path(&self, staged_path: &str) -> Result<String> {
+        let path = self.remote_path(staged_path)?;
+        if path.starts_with('/') {
+            Ok(path)
+        } else {
+            Ok(format!("%h/{path}"))
+        }
+    }
+
+    /// Upload a staged file or directory to the remote machine, replacing the remote copy.
+    pub fn push(&self, staged_path: &str) -> Result<()> {
+        let remote_path = self.remote_path(staged_path)?;
+        let remote_parent = Path::new(&remote_path)
+            .parent()
+            .map(|parent| parent.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut prepare = format!("mkdir -p {}", shell_quote(&remote_parent));
+        if Path::new(staged_path).is_dir() {
+            prepare.push_str(&format!(" && rm -rf {}", shell_quote(&remote_path)));
+        }
+        self.ssh(&prepare)?;
+
+        run(Command::new("scp")
+            .arg("-pqr")
+            .arg(staged_path)
+            .arg(format!("{}:{}", self.destination, remote_path)))
+    }
+
+    /// Download a file from the remote machine into its staging path.
+    pub fn pull(&self, staged_path: &str) -> Result<()> {
+        let remote_path = self.remote_path(staged_path)?;
+        create_parent_dir(staged_path)?;
+        run(Command::new("scp")
+            .arg("-pq")
+            .arg(format!("{}:{}", self.destination, remote_path))
+            .arg(staged_path))
+    }
+
+    /// Remove a file on the remote machine.
+    pub fn remove(&self, staged_path: &str) -> Result<()> {
+        let remote_path = self.remote_path(staged_path)?;
+        self.ssh(&format!("rm -f {}", shell_quote(&remote_path)))
+    }
+
+    /// Run shell command `cmd` on the remote machine.
+    pub fn ssh(&self, cmd: &str) -> Result<()> {
+        run(Command::new("ssh").arg(&self.destination).arg(cmd))
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let status = cmd
+        .status()
+        .with_context(|| format!("failed to execute {}", program))?;
+    if !status.success() {
+        bail!("`{}` exited with {}", program, status);
+    }
+    Ok(())
+}
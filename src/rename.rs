@@ -0,0 +1,247 @@
+use crate::convert::unique_name;
+use crate::nodes::{flag_of, region_of};
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// A rule of `node_rename`, applied to node names in order with the rules before it, e.g.
+/// stripping a provider's prefix and then prepending the region's flag:
+///
+/// ```toml
+/// [[mihomo_config.node_rename]]
+/// pattern = '^\[\w+\]\s*'
+/// replace = ""
+///
+/// [[mihomo_config.node_rename]]
+/// template = "{flag} {name}"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeRename {
+    /// Regex of the names to rename, all names if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+
+    /// Replacement of every match of `pattern`, referring to capture groups as `$1` or `${name}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace: Option<String>,
+
+    /// New name of matching nodes, filling in `{name}` (after `replace`), `{region}` (the region
+    /// code guessed from the name) and `{flag}` (its flag emoji).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl NodeRename {
+    /// Name `name` is renamed to, compiled `pattern` given.
+    fn apply(&self, pattern: Option<&Regex>, name: &str) -> String {
+        if pattern.is_some_and(|pattern| !pattern.is_match(name)) {
+            return name.to_owned();
+        }
+        let name = match (pattern, &self.replace) {
+            (Some(pattern), Some(replace)) => pattern.replace_all(name, replace.as_str()).into(),
+            _ => name.to_owned(),
+        };
+        let Some(template) = &self.template else {
+            return name;
+        };
+        let region = region_of(&name);
+        template
+            .replace(
+                "{flag}",
+                &region.as_deref().map(flag_of).unwrap_or_default(),
+            )
+            .replace("{region}", region.as_deref().unwrap_or_default())
+            .replace("{name}", &name)
+            .trim()
+            .to_owned()
+    }
+}
+
+/// Rename nodes in mihomo's `proxies` by `renames`, updating their references in the members of
+/// `groups` and the targets of `rules`. Returns the number of nodes renamed.
+///
+/// Names that end up taken by an earlier node are suffixed with a number, as mihomo requires
+/// unique names.
+pub fn rename_nodes(
+    proxies: &mut Value,
+    groups: Option<&mut Value>,
+    rules: Option<&mut Value>,
+    renames: &[NodeRename],
+) -> Result<usize> {
+    let Some(proxies) = proxies.as_sequence_mut() else {
+        return Ok(0);
+    };
+    if renames.is_empty() {
+        return Ok(0);
+    }
+    let patterns = renames
+        .iter()
+        .map(|rename| {
+            if rename.replace.is_none() && rename.template.is_none() {
+                bail!("`node_rename` rules need a `replace` or a `template`");
+            }
+            if rename.replace.is_some() && rename.pattern.is_none() {
+                bail!("`replace` in `node_rename` needs a `pattern` to replace");
+            }
+            rename
+                .pattern
+                .as_deref()
+                .map(|pattern| {
+                    Regex::new(pattern)
+                        .with_context(|| format!("invalid regex `{pattern}` in `node_rename`"))
+                })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut renamed = HashMap::new();
+    let mut names = HashSet::new();
+    for proxy in proxies.iter_mut() {
+        let Some(name) = proxy["name"].as_str().map(String::from) else {
+            continue;
+        };
+        let new_name = renames
+            .iter()
+            .zip(&patterns)
+            .fold(name.clone(), |name, (rename, pattern)| {
+                rename.apply(pattern.as_ref(), &name)
+            });
+        let new_name = unique_name(&new_name, &mut names);
+        if new_name != name {
+            proxy["name"] = Value::from(new_name.clone());
+            renamed.insert(name, new_name);
+        }
+    }
+    if renamed.is_empty() {
+        return Ok(0);
+    }
+
+    let members = groups
+        .and_then(Value::as_sequence_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(|group| group.get_mut("proxies").and_then(Value::as_sequence_mut))
+        .flatten();
+    for member in members {
+        if let Some(new_name) = member.as_str().and_then(|name| renamed.get(name)) {
+            *member = Value::from(new_name.as_str());
+        }
+    }
+
+    for rule in rules.and_then(Value::as_sequence_mut).into_iter().flatten() {
+        if let Some(new_rule) = rule.as_str().and_then(|rule| rename_target(rule, &renamed)) {
+            *rule = Value::from(new_rule);
+        }
+    }
+    Ok(renamed.len())
+}
+
+/// `rule` with its target node renamed, `None` if its target was not renamed.
+///
+/// Logical rules (`AND`, `OR`, `NOT`) nest commas in their payload and are left as is.
+fn rename_target(rule: &str, renamed: &HashMap<String, String>) -> Option<String> {
+    let mut parts: Vec<&str> = rule.split(',').collect();
+    let target = match parts.first()?.trim() {
+        "MATCH" => 1,
+        "AND" | "OR" | "NOT" => return None,
+        _ => 2,
+    };
+    let new_name = renamed.get(parts.get(target)?.trim())?;
+    parts[target] = new_name;
+    Some(parts.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn names(proxies: &Value) -> Vec<&str> {
+        proxies
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|proxy| proxy["name"].as_str())
+            .collect()
+    }
+
+    fn regex(pattern: &str, replace: &str) -> NodeRename {
+        NodeRename {
+            pattern: Some(pattern.to_owned()),
+            replace: Some(replace.to_owned()),
+            template: None,
+        }
+    }
+
+    fn template(template: &str) -> NodeRename {
+        NodeRename {
+            pattern: None,
+            replace: None,
+            template: Some(template.to_owned()),
+        }
+    }
+
+    #[test]
+    fn replaces_regex_matches() {
+        let mut proxies = yaml("[{name: '[Air] HK 01'}, {name: '[Air] JP 01'}, {name: US 01}]");
+        let renamed = rename_nodes(&mut proxies, None, None, &[regex(r"^\[(\w+)\] ", "$1 ")]);
+        assert_eq!(renamed.unwrap(), 2);
+        assert_eq!(names(&proxies), ["Air HK 01", "Air JP 01", "US 01"]);
+    }
+
+    #[test]
+    fn fills_in_templates_after_earlier_renames() {
+        let mut proxies = yaml("[{name: '[Air] Hong Kong 01'}, {name: '[Air] Mars 01'}]");
+        let renames = [regex(r"^\[\w+\] ", ""), template("{flag} {region} {name}")];
+        let renamed = rename_nodes(&mut proxies, None, None, &renames);
+        assert_eq!(renamed.unwrap(), 2);
+        assert_eq!(names(&proxies), ["🇭🇰 HK Hong Kong 01", "Mars 01"]);
+    }
+
+    #[test]
+    fn suffixes_names_taken_by_earlier_nodes() {
+        let mut proxies = yaml("[{name: 'HK 01'}, {name: 'A HK 01'}, {name: 'B HK 01'}]");
+        let renamed = rename_nodes(&mut proxies, None, None, &[regex(r"^\w ", "")]);
+        assert_eq!(renamed.unwrap(), 2);
+        assert_eq!(names(&proxies), ["HK 01", "HK 01 2", "HK 01 3"]);
+    }
+
+    #[test]
+    fn updates_references_in_groups_and_rules() {
+        let mut proxies = yaml("[{name: '[Air] HK 01'}, {name: 'JP 01'}]");
+        let mut groups = yaml("[{name: Proxy, proxies: ['[Air] HK 01', 'JP 01', DIRECT]}]");
+        let mut rules = yaml(
+            "['DOMAIN,a.com,[Air] HK 01', 'MATCH,[Air] HK 01', 'AND,((DOMAIN,b.com)),[Air] HK 01']",
+        );
+        let renamed = rename_nodes(
+            &mut proxies,
+            Some(&mut groups),
+            Some(&mut rules),
+            &[regex(r"^\[\w+\] ", "")],
+        );
+        assert_eq!(renamed.unwrap(), 1);
+        assert_eq!(groups[0]["proxies"], yaml("['HK 01', 'JP 01', DIRECT]"));
+        assert_eq!(
+            rules,
+            yaml("['DOMAIN,a.com,HK 01', 'MATCH,HK 01', 'AND,((DOMAIN,b.com)),[Air] HK 01']")
+        );
+    }
+
+    #[test]
+    fn rejects_replacements_without_patterns() {
+        let mut proxies = yaml("[{name: HK 01}]");
+        let rename = NodeRename {
+            pattern: None,
+            replace: Some(String::new()),
+            template: None,
+        };
+        assert!(rename_nodes(&mut proxies, None, None, &[rename]).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::utils::RootCommand;
+
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// systemd-resolved drop-in installed while host DNS points to mihomo.
+pub const RESOLVED_DROPIN_PATH: &str = "/etc/systemd/resolved.conf.d/mihoro.conf";
+
+/// Original `/etc/resolv.conf`, moved aside while it is overridden.
+pub const RESOLV_CONF_BACKUP_PATH: &str = "/etc/resolv.conf.mihoro";
+
+/// How the host's resolver is pointed to mihomo's DNS server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostDns {
+    /// Route all domains to mihomo with a systemd-resolved drop-in.
+    Resolved,
+    /// Replace `/etc/resolv.conf`, requires mihomo's DNS to listen on port 53.
+    ResolvConf,
+}
+
+/// Host DNS configured to use mihomo, recorded so that it is reverted exactly.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostDnsState {
+    pub backend: HostDns,
+    /// Address of mihomo's DNS server, as `ip:port`.
+    pub server: String,
+}
+
+/// mihomo DNS server to send host DNS to if TUN with `dns-hijack` and the DNS server are enabled
+/// in core config `path`, `None` if TUN or DNS hijacking is disabled.
+pub fn tun_dns_server(path: &str) -> Result<Option<String>> {
+    let raw = fs::read_to_string(path)?;
+    let config: serde_yaml::Value =
+        serde_yaml::from_str(&raw).with_context(|| format!("failed to parse {path}"))?;
+    let tun = &config["tun"];
+    let hijacks = tun["dns-hijack"]
+        .as_sequence()
+        .is_some_and(|hijack| !hijack.is_empty());
+    if tun["enable"].as_bool() != Some(true) || !hijacks {
+        return Ok(None);
+    }
+
+    let dns = &config["dns"];
+    let listen = dns["listen"]
+        .as_str()
+        .filter(|_| dns["enable"].as_bool() == Some(true));
+    let Some(listen) = listen else {
+        bail!("TUN hijacks DNS but mihomo's `dns` is disabled or has no `listen` address");
+    };
+    let (host, port) = listen
+        .rsplit_once(':')
+        .with_context(|| format!("invalid `dns.listen` address `{listen}`"))?;
+    let host = match host {
+        "" | "0.0.0.0" => "127.0.0.1",
+        "[::]" => "[::1]",
+        host => host,
+    };
+    Ok(Some(format!("{host}:{port}")))
+}
+
+/// Commands pointing host DNS to `dns.server`.
+pub fn configure_commands(dns: &HostDnsState) -> Result<Vec<RootCommand>> {
+    match dns.backend {
+        HostDns::Resolved => Ok(vec![
+            RootCommand::new("mkdir -p /etc/systemd/resolved.conf.d"),
+            RootCommand::new(&format!("dd of={RESOLVED_DROPIN_PATH} status=none")).stdin(format!(
+                "# Managed by mihoro, removed with `mihoro stop`\n\
+                 [Resolve]\nDNS={}\nDomains=~.\n",
+                dns.server
+            )),
+            RootCommand::new("systemctl restart systemd-resolved"),
+        ]),
+        HostDns::ResolvConf => {
+            let Some(ip) = dns.server.strip_suffix(":53") else {
+                bail!(
+                    "resolv.conf cannot use DNS on a port other than 53, set `dns.listen` to port \
+                     53 or use `host_dns = \"resolved\"`"
+                );
+            };
+            let ip = ip.trim_start_matches('[').trim_end_matches(']');
+            Ok(vec![
+                RootCommand::new(&format!("mv /etc/resolv.conf {RESOLV_CONF_BACKUP_PATH}")),
+                RootCommand::new("dd of=/etc/resolv.conf status=none").stdin(format!(
+                    "# Managed by mihoro, restored with `mihoro stop`\nnameserver {ip}\n"
+                )),
+            ])
+        }
+    }
+}
+
+/// Commands reverting [`configure_commands`].
+pub fn revert_commands(dns: &HostDnsState) -> Vec<RootCommand> {
+    match dns.backend {
+        HostDns::Resolved => vec![
+            RootCommand::new(&format!("rm -f {RESOLVED_DROPIN_PATH}")),
+            RootCommand::new("systemctl restart systemd-resolved"),
+        ],
+        HostDns::ResolvConf => vec![RootCommand::new(&format!(
+            "mv {RESOLV_CONF_BACKUP_PATH} /etc/resolv.conf"
+        ))],
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// Outcome of merging rule sources with [`merge_rules`].
+#[derive(Debug, Clone, Default)]
+pub struct MergedRules {
+    pub rules: Vec<String>,
+    /// Number of exact duplicates dropped.
+    pub duplicates: usize,
+    /// Rules that can never match as an earlier rule covers them, as `(rule, covered by)`.
+    pub shadowed: Vec<(String, String)>,
+}
+
+/// A parsed `TYPE,payload,target[,options]` rule, as far as needed to compare what it matches.
+struct Rule {
+    kind: String,
+    /// Payload in lowercase, as domains match regardless of case.
+    payload: String,
+}
+
+impl Rule {
+    /// Parse `raw`, `None` for logical rules (`AND`, `OR`, `NOT`) and `SUB-RULE`s whose payloads
+    /// cannot be compared.
+    fn parse(raw: &str) -> Option<Rule> {
+        let mut parts = raw.splitn(3, ',').map(str::trim);
+        let kind = parts.next()?.to_uppercase();
+        if ["AND", "OR", "NOT", "SUB-RULE"].contains(&kind.as_str()) {
+            return None;
+        }
+        if kind == "MATCH" {
+            return Some(Rule {
+                kind,
+                payload: String::new(),
+            });
+        }
+        let payload = parts.next()?.to_lowercase();
+        // Rules without a target are invalid, leave them to mihomo to report
+        parts.next()?;
+        Some(Rule { kind, payload })
+    }
+}
+
+/// Earlier rules indexed by what they match, each mapped to its position among the merged rules.
+///
+/// Domain rules are looked up by domain, as subscriptions carry thousands of them, while keyword
+/// and CIDR rules are compared one by one.
+#[derive(Default)]
+struct RuleIndex {
+    /// First rule of each kind and payload, covering later rules of the same kind and payload.
+    exact: HashMap<(String, String), usize>,
+    /// First `DOMAIN-SUFFIX` rule of each domain.
+    suffixes: HashMap<String, usize>,
+    keywords: Vec<(String, usize)>,
+    cidrs: Vec<((IpAddr, u8), usize)>,
+    /// First `MATCH` rule, covering every later rule.
+    catch_all: Option<usize>,
+}
+
+impl RuleIndex {
+    /// Position of the first indexed rule matching every request `rule` matches.
+    fn covering(&self, rule: &Rule) -> Option<usize> {
+        let mut found = self.catch_all;
+        let mut earliest = |index: Option<usize>| {
+            found = match (found, index) {
+                (Some(found), Some(index)) => Some(found.min(index)),
+                (found, index) => found.or(index),
+            };
+        };
+        earliest(
+            self.exact
+                .get(&(rule.kind.clone(), rule.payload.clone()))
+                .copied(),
+        );
+        let keyword_covering = || {
+            self.keywords
+                .iter()
+                .find(|(keyword, _)| rule.payload.contains(keyword.as_str()))
+                .map(|(_, index)| *index)
+        };
+        match rule.kind.as_str() {
+            "DOMAIN" | "DOMAIN-SUFFIX" => {
+                // Suffix rules match their domain and its subdomains, i.e. any of the rule's
+                // domain's trailing labels
+                let mut domain = rule.payload.as_str();
+                loop {
+                    earliest(self.suffixes.get(domain).copied());
+                    match domain.split_once('.') {
+                        Some((_, parent)) => domain = parent,
+                        None => break,
+                    }
+                }
+                earliest(keyword_covering());
+            }
+            "DOMAIN-KEYWORD" => earliest(keyword_covering()),
+            "IP-CIDR" | "IP-CIDR6" => {
+                if let Some(cidr) = parse_cidr(&rule.payload) {
+                    earliest(
+                        self.cidrs
+                            .iter()
+                            .find(|(outer, _)| cidr_contains(*outer, cidr))
+                            .map(|(_, index)| *index),
+                    );
+                }
+            }
+            _ => {}
+        }
+        found
+    }
+
+    /// Index `rule` at position `index`, unless an earlier rule matching the same is indexed.
+    fn insert(&mut self, rule: Rule, index: usize) {
+        match rule.kind.as_str() {
+            "MATCH" => {
+                self.catch_all.get_or_insert(index);
+            }
+            "DOMAIN-SUFFIX" => {
+                self.suffixes.entry(rule.payload.clone()).or_insert(index);
+            }
+            "DOMAIN-KEYWORD" => self.keywords.push((rule.payload.clone(), index)),
+            "IP-CIDR" | "IP-CIDR6" => {
+                if let Some(cidr) = parse_cidr(&rule.payload) {
+                    self.cidrs.push((cidr, index));
+                }
+            }
+            _ => {}
+        }
+        self.exact.entry((rule.kind, rule.payload)).or_insert(index);
+    }
+}
+
+/// Merge `prepend` rules in front of `rules`, dropping exact duplicates and reporting rules shadowed
+/// by an earlier rule. `rules` are returned as is if there is nothing to prepend.
+///
+/// Shadowed rules are kept, as mihomo simply never reaches them. `prepend` rules found again in
+/// `rules` (e.g. as they were merged before) are dropped without counting them as duplicates.
+pub fn merge_rules(prepend: &[String], rules: &[String]) -> MergedRules {
+    if prepend.is_empty() {
+        return MergedRules {
+            rules: rules.to_vec(),
+            ..MergedRules::default()
+        };
+    }
+    let prepend: Vec<String> = prepend.iter().map(|rule| normalize(rule)).collect();
+    let prepended: HashSet<&str> = prepend.iter().map(String::as_str).collect();
+    let mut merged = MergedRules::default();
+    let mut seen = HashSet::new();
+    let mut index = RuleIndex::default();
+    for (position, raw) in prepend.iter().chain(rules).enumerate() {
+        let normalized = normalize(raw);
+        if !seen.insert(normalized.clone()) {
+            if position < prepend.len() || !prepended.contains(normalized.as_str()) {
+                merged.duplicates += 1;
+            }
+            continue;
+        }
+
+        if let Some(rule) = Rule::parse(&normalized) {
+            if let Some(earlier) = index.covering(&rule) {
+                merged
+                    .shadowed
+                    .push((normalized.clone(), merged.rules[earlier].clone()));
+            }
+            index.insert(rule, merged.rules.len());
+        }
+        merged.rules.push(normalized);
+    }
+    merged
+}
+
+/// Whether a rule of `kind` (as named by the controller, e.g. `DomainSuffix`) with `payload`
+/// matches `destination`, a domain or an IP, `None` if that cannot be decided locally (e.g. for
+/// GeoSite or rule sets, or IP rules for a domain that mihomo would resolve first).
+pub fn rule_matches(kind: &str, payload: &str, destination: &str) -> Option<bool> {
+    let kind = kind.replace('-', "").to_uppercase();
+    let destination = destination.to_lowercase();
+    let payload = payload.to_lowercase();
+    let ip = destination
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .ok();
+    match (kind.as_str(), ip) {
+        ("MATCH", _) => Some(true),
+        ("DOMAIN" | "DOMAINSUFFIX" | "DOMAINKEYWORD" | "GEOSITE", Some(_)) => Some(false),
+        ("DOMAIN", None) => Some(destination == payload),
+        ("DOMAINSUFFIX", None) => {
+            Some(destination == payload || destination.ends_with(&format!(".{payload}")))
+        }
+        ("DOMAINKEYWORD", None) => Some(destination.contains(&payload)),
+        ("IPCIDR" | "IPCIDR6", Some(ip)) => {
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            parse_cidr(&payload).map(|cidr| cidr_contains(cidr, (ip, prefix)))
+        }
+        _ => None,
+    }
+}
+
+/// Rule with whitespace around its fields trimmed.
+fn normalize(rule: &str) -> String {
+    rule.split(',').map(str::trim).collect::<Vec<_>>().join(",")
+}
+
+/// Network and prefix length of `cidr`, e.g. `198.18.0.1/16`.
+pub fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = cidr.split_once('/')?;
+    let ip: IpAddr = ip.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    (prefix <= max_prefix).then_some((ip, prefix))
+}
+
+/// Whether network `outer` contains network `inner`.
+pub fn cidr_contains(outer: (IpAddr, u8), inner: (IpAddr, u8)) -> bool {
+    let ((outer_ip, outer_prefix), (inner_ip, inner_prefix)) = (outer, inner);
+    if outer_prefix > inner_prefix {
+        return false;
+    }
+    match (outer_ip, inner_ip) {
+        (IpAddr::V4(outer_ip), IpAddr::V4(inner_ip)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(outer_prefix))
+                .unwrap_or(0);
+            u32::from(outer_ip) & mask == u32::from(inner_ip) & mask
+        }
+        (IpAddr::V6(outer_ip), IpAddr::V6(inner_ip)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(outer_prefix))
+                .unwrap_or(0);
+            u128::from(outer_ip) & mask == u128::from(inner_ip) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| rule.to_string()).collect()
+    }
+
+    #[test]
+    fn prepends_rules_and_normalizes_whitespace() {
+        let merged = merge_rules(
+            &rules(&["DOMAIN, example.com , DIRECT"]),
+            &rules(&["DOMAIN-SUFFIX,google.com,Proxy", "MATCH,Proxy"]),
+        );
+        assert_eq!(
+            merged.rules,
+            rules(&[
+                "DOMAIN,example.com,DIRECT",
+                "DOMAIN-SUFFIX,google.com,Proxy",
+                "MATCH,Proxy"
+            ])
+        );
+        assert_eq!(merged.duplicates, 0);
+        assert!(merged.shadowed.is_empty());
+    }
+
+    #[test]
+    fn drops_duplicates_but_not_previously_merged_prepend_rules() {
+        let prepend = rules(&["DOMAIN,a.com,DIRECT", "DOMAIN,a.com,DIRECT"]);
+        let merged = merge_rules(
+            &prepend,
+            &rules(&[
+                "DOMAIN,a.com,DIRECT",
+                "DOMAIN,b.com,Proxy",
+                "DOMAIN,b.com,Proxy",
+            ]),
+        );
+        assert_eq!(
+            merged.rules,
+            rules(&["DOMAIN,a.com,DIRECT", "DOMAIN,b.com,Proxy"])
+        );
+        // One duplicate within `prepend`, one within the rules, the merged copy is not counted
+        assert_eq!(merged.duplicates, 2);
+    }
+
+    #[test]
+    fn keeps_rules_as_is_without_rules_to_prepend() {
+        let original = rules(&[
+            "DOMAIN, a.com ,DIRECT",
+            "DOMAIN,a.com,DIRECT",
+            "MATCH,Proxy",
+        ]);
+        let merged = merge_rules(&[], &original);
+        assert_eq!(merged.rules, original);
+        assert_eq!(merged.duplicates, 0);
+        assert!(merged.shadowed.is_empty());
+    }
+
+    #[test]
+    fn finds_domain_rules_shadowed_by_suffixes_and_keywords() {
+        let merged = merge_rules(
+            &rules(&["DOMAIN-SUFFIX,example.com,DIRECT"]),
+            &rules(&[
+                "DOMAIN,www.example.com,Proxy",
+                "DOMAIN-SUFFIX,EXAMPLE.com,Proxy",
+                "DOMAIN,notexample.com,Proxy",
+                "DOMAIN-KEYWORD,google,Proxy",
+                "DOMAIN-SUFFIX,google.com,DIRECT",
+            ]),
+        );
+        let shadowed: Vec<&str> = merged
+            .shadowed
+            .iter()
+            .map(|(rule, _)| rule.as_str())
+            .collect();
+        assert_eq!(
+            shadowed,
+            [
+                "DOMAIN,www.example.com,Proxy",
+                "DOMAIN-SUFFIX,EXAMPLE.com,Proxy",
+                "DOMAIN-SUFFIX,google.com,DIRECT"
+            ]
+        );
+        assert_eq!(merged.shadowed[0].1, "DOMAIN-SUFFIX,example.com,DIRECT");
+    }
+
+    #[test]
+    fn finds_cidrs_and_everything_after_match_shadowed() {
+        let merged = merge_rules(
+            &rules(&["IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"]),
+            &rules(&[
+                "IP-CIDR,10.1.0.0/16,Proxy",
+                "IP-CIDR,11.0.0.0/16,Proxy",
+                "IP-CIDR6,fd00::/8,DIRECT",
+                "IP-CIDR6,fd12::/16,Proxy",
+                "AND,((DOMAIN,a.com),(NETWORK,UDP)),REJECT",
+                "MATCH,Proxy",
+                "DOMAIN,late.com,DIRECT",
+            ]),
+        );
+        let shadowed: Vec<&str> = merged
+            .shadowed
+            .iter()
+            .map(|(rule, _)| rule.as_str())
+            .collect();
+        assert_eq!(
+            shadowed,
+            [
+                "IP-CIDR,10.1.0.0/16,Proxy",
+                "IP-CIDR6,fd12::/16,Proxy",
+                "DOMAIN,late.com,DIRECT"
+            ]
+        );
+        assert_eq!(merged.rules.len(), 8);
+    }
+
+    #[test]
+    fn matches_destinations_locally_where_possible() {
+        assert_eq!(
+            rule_matches("DomainSuffix", "example.com", "www.example.com"),
+            Some(true)
+        );
+        assert_eq!(
+            rule_matches("DomainSuffix", "example.com", "notexample.com"),
+            Some(false)
+        );
+        assert_eq!(rule_matches("IPCIDR", "10.0.0.0/8", "10.2.3.4"), Some(true));
+        assert_eq!(rule_matches("IPCIDR", "10.0.0.0/8", "a.com"), None);
+        assert_eq!(rule_matches("GeoSite", "cn", "a.cn"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_cidrs() {
+        assert_eq!(parse_cidr("10.0.0.0/40"), None);
+        assert_eq!(parse_cidr("fd00::/129"), None);
+        assert_eq!(parse_cidr("10.0.0.0"), None);
+        assert!(parse_cidr("fd00::/128").is_some());
+        assert!(cidr_contains(
+            parse_cidr("0.0.0.0/0").unwrap(),
+            parse_cidr("1.2.3.4/32").unwrap()
+        ));
+    }
+}
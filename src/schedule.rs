@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// Selects the lowest delay member of the group instead of a named node.
+pub const FASTEST: &str = "fastest";
+
+/// A time window during which a proxy group is switched to a node, e.g.
+///
+/// ```toml
+/// [[schedule]]
+/// name = "work"
+/// days = ["mon", "tue", "wed", "thu", "fri"]
+/// from = "09:00"
+/// to = "18:00"
+/// group = "Proxy"
+/// select = "Office HK"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleRule {
+    pub name: String,
+    /// Weekdays the rule applies on (`mon`, `tue`, ...), every day if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// Local start time as `HH:MM`, inclusive.
+    pub from: String,
+    /// Local end time as `HH:MM`, exclusive. Windows may wrap past midnight (`22:00` to `07:00`),
+    /// and span the whole day if equal to `from`.
+    pub to: String,
+    /// Proxy group to switch.
+    pub group: String,
+    /// Node to select in `group`, or `fastest` to select its lowest delay member.
+    pub select: String,
+}
+
+impl ScheduleRule {
+    /// Check `days`, `from` and `to`, so that a malformed rule is reported when the config loads
+    /// rather than once its window comes around.
+    pub fn validate(&self) -> Result<()> {
+        for time in [&self.from, &self.to] {
+            parse_time(time, &self.name)?;
+        }
+        self.weekdays()?;
+        Ok(())
+    }
+
+    /// Whether the rule applies at local time `now`.
+    pub fn is_active(&self, now: NaiveDateTime) -> Result<bool> {
+        let from = parse_time(&self.from, &self.name)?;
+        let to = parse_time(&self.to, &self.name)?;
+        let time = now.time();
+
+        // For windows wrapping past midnight, the morning part belongs to the previous day
+        let (in_window, day) = if from == to {
+            (true, now.weekday())
+        } else if from < to {
+            (from <= time && time < to, now.weekday())
+        } else if time >= from {
+            (true, now.weekday())
+        } else {
+            (time < to, now.weekday().pred())
+        };
+        if !in_window {
+            return Ok(false);
+        }
+
+        let weekdays = self.weekdays()?;
+        Ok(weekdays.is_empty() || weekdays.contains(&day))
+    }
+
+    fn weekdays(&self) -> Result<Vec<Weekday>> {
+        self.days
+            .iter()
+            .map(|name| {
+                name.parse::<Weekday>()
+                    .ok()
+                    .with_context(|| format!("invalid day `{name}` in schedule `{}`", self.name))
+            })
+            .collect()
+    }
+}
+
+fn parse_time(time: &str, rule: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .with_context(|| format!("invalid time `{time}` in schedule `{rule}`, expected `HH:MM`"))
+}
+
+/// First rule in `rules` active at `now`.
+pub fn active_rule(rules: &[ScheduleRule], now: NaiveDateTime) -> Result<Option<&ScheduleRule>> {
+    for rule in rules {
+        if rule.is_active(now)? {
+            return Ok(Some(rule));
+        }
+    }
+    Ok(None)
+}
+
+/// Systemd service and timer units running `mihoro schedule apply` every 5 minutes, as
+/// `(service, timer)`.
+pub fn schedule_units(exec_start: &str) -> (String, String) {
+    let service = format!(
+        "[Unit]
+Description=Apply mihoro schedule rules
+
+[Service]
+Type=oneshot
+ExecStart={exec_start}
+"
+    );
+    let timer = String::from(
+        "[Unit]
+Description=Apply mihoro schedule rules periodically
+
+[Timer]
+OnCalendar=*:0/5
+
+[Install]
+WantedBy=timers.target
+",
+    );
+    (service, timer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn rule(days: &[&str], from: &str, to: &str) -> ScheduleRule {
+        ScheduleRule {
+            name: "test".to_owned(),
+            days: days.iter().map(|day| day.to_string()).collect(),
+            from: from.to_owned(),
+            to: to.to_owned(),
+            group: "Proxy".to_owned(),
+            select: FASTEST.to_owned(),
+        }
+    }
+
+    /// Local time on a day of the week of 2024-01-01, a Monday, e.g. `at(5, 23, 0)` for Friday.
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_within_a_window() {
+        let rule = rule(&["mon"], "09:00", "18:00");
+        assert!(rule.is_active(at(1, 9, 0)).unwrap());
+        assert!(!rule.is_active(at(1, 18, 0)).unwrap());
+        assert!(!rule.is_active(at(2, 12, 0)).unwrap());
+    }
+
+    #[test]
+    fn wraps_windows_past_midnight_into_the_next_day() {
+        let rule = rule(&["fri"], "22:00", "07:00");
+        assert!(rule.is_active(at(5, 23, 0)).unwrap());
+        assert!(rule.is_active(at(6, 3, 0)).unwrap());
+        assert!(!rule.is_active(at(6, 7, 0)).unwrap());
+        assert!(!rule.is_active(at(6, 22, 0)).unwrap());
+        // The morning of Friday belongs to Thursday's window
+        assert!(!rule.is_active(at(5, 3, 0)).unwrap());
+    }
+
+    #[test]
+    fn spans_the_whole_day_if_from_equals_to() {
+        let rule = rule(&["mon"], "08:00", "08:00");
+        assert!(rule.is_active(at(1, 0, 0)).unwrap());
+        assert!(rule.is_active(at(1, 7, 59)).unwrap());
+        assert!(rule.is_active(at(1, 23, 59)).unwrap());
+        assert!(!rule.is_active(at(2, 8, 0)).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(rule(&["mon", "fri"], "22:00", "07:00").validate().is_ok());
+        assert!(rule(&[], "9am", "18:00").validate().is_err());
+        assert!(rule(&[], "09:00", "24:00").validate().is_err());
+        assert!(rule(&["funday"], "09:00", "18:00").validate().is_err());
+    }
+}
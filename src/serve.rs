@@ -0,0 +1,169 @@
+use crate::mihoro::Mihoro;
+
+use std::fs;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, UnixListener},
+    time,
+};
+
+/// Clients have this long to send the request line and headers, so that a stalled client does
+/// not block the requests queued behind it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest request or header line accepted, in bytes.
+const MAX_LINE_BYTES: u64 = 8192;
+
+/// Most headers accepted in a request.
+const MAX_HEADERS: usize = 64;
+
+/// Config of the `mihoro serve` update endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServeConfig {
+    /// `host:port` to listen on, or `unix:<path>` for a unix socket.
+    pub listen: String,
+    /// Bearer token required in the `Authorization` header of requests.
+    pub token: String,
+}
+
+/// Serve `POST /update` on the configured address, triggering `mihoro update` for each request.
+///
+/// Requests are handled one at a time, so concurrent triggers never run overlapping updates.
+pub async fn serve(mihoro: &Mihoro, client: Client, serve_config: &ServeConfig) -> Result<()> {
+    if serve_config.token.is_empty() {
+        bail!("`serve.token` undefined, refusing to serve unauthenticated endpoint")
+    }
+
+    if let Some(socket_path) = serve_config.listen.strip_prefix("unix:") {
+        // Remove stale socket left behind by a previous run
+        if fs::metadata(socket_path).is_ok() {
+            fs::remove_file(socket_path)?;
+        }
+        let listener = UnixListener::bind(socket_path)
+            .with_context(|| format!("failed to listen on `{}`", socket_path))?;
+        print_listening(mihoro, &serve_config.listen);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            handle(mihoro, &client, &serve_config.token, stream).await;
+        }
+    } else {
+        let listener = TcpListener::bind(&serve_config.listen)
+            .await
+            .with_context(|| format!("failed to listen on `{}`", serve_config.listen))?;
+        print_listening(mihoro, &serve_config.listen);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            handle(mihoro, &client, &serve_config.token, stream).await;
+        }
+    }
+}
+
+fn print_listening(mihoro: &Mihoro, listen: &str) {
+    println!(
+        "{} Listening on {} for {} requests",
+        mihoro.prefix.cyan(),
+        listen.underline(),
+        "POST /update".bold()
+    );
+}
+
+/// Handle a single HTTP request, errors are reported to the client and never stop the server.
+async fn handle<S>(mihoro: &Mihoro, client: &Client, token: &str, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let request = time::timeout(REQUEST_TIMEOUT, read_request(token, &mut stream)).await;
+    let (status, body) = match request {
+        Ok(Ok(request)) => respond(mihoro, client, request).await,
+        Ok(Err(err)) => ("400 Bad Request", err.to_string()),
+        Err(_) => (
+            "408 Request Timeout",
+            String::from("timed out reading request"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}\n",
+        body.len() + 1
+    );
+    let _ = stream.get_mut().write_all(response.as_bytes()).await;
+    let _ = stream.get_mut().shutdown().await;
+}
+
+/// Request line and headers of a request, as far as `respond` cares.
+struct Request {
+    method: String,
+    path: String,
+    /// Whether the request carries `token` as its bearer token.
+    authorized: bool,
+}
+
+async fn read_request<S>(token: &str, stream: &mut BufReader<S>) -> Result<Request>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request_line = read_line(stream).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        authorized: false,
+    };
+
+    // Read headers until the empty line, only `Authorization` matters
+    for _ in 0..=MAX_HEADERS {
+        let header = read_line(stream).await?;
+        if header.trim().is_empty() {
+            return Ok(request);
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization")
+                && value.trim() == format!("Bearer {token}")
+            {
+                request.authorized = true;
+            }
+        }
+    }
+    bail!("too many headers, at most {MAX_HEADERS} are accepted")
+}
+
+/// Read a line of at most `MAX_LINE_BYTES`, empty at the end of the stream.
+async fn read_line<S>(stream: &mut BufReader<S>) -> Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut line = String::new();
+    let read = stream.take(MAX_LINE_BYTES).read_line(&mut line).await?;
+    if read as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        bail!("request line or header longer than {MAX_LINE_BYTES} bytes");
+    }
+    Ok(line)
+}
+
+async fn respond(mihoro: &Mihoro, client: &Client, request: Request) -> (&'static str, String) {
+    if request.path != "/update" {
+        return ("404 Not Found", String::from("not found"));
+    }
+    if request.method != "POST" {
+        return ("405 Method Not Allowed", String::from("method not allowed"));
+    }
+    if !request.authorized {
+        return ("401 Unauthorized", String::from("unauthorized"));
+    }
+
+    println!("{} Update requested", mihoro.prefix.cyan());
+    match mihoro.update(client.clone()).await {
+        Ok(_) => ("200 OK", String::from("updated")),
+        Err(err) => {
+            eprintln!("{} {}", "error:".bright_red().bold(), err);
+            ("500 Internal Server Error", err.to_string())
+        }
+    }
+}
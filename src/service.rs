@@ -0,0 +1,277 @@
+pub mod launchd;
+pub mod openrc;
+pub mod runit;
+pub mod s6;
+pub mod systemd;
+pub mod sysv;
+
+use crate::config::{Config, InitSystem};
+use crate::remote::Remote;
+use crate::utils::{delete_file, shell_quote};
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use self::launchd::{agent_label, Launchd, LAUNCH_AGENTS_ROOT};
+use self::openrc::{OpenRc, OPENRC_INIT_ROOT};
+use self::runit::{Runit, RUNIT_SYSTEM_ROOT, RUNIT_USER_ROOT};
+use self::s6::{S6, S6_RC_SOURCE_ROOT, S6_USER_ROOT};
+use self::systemd::Systemd;
+use self::sysv::{SysV, SYSV_INIT_ROOT};
+
+/// An init system running mihomo as a service, e.g. systemd or runit.
+///
+/// Each implementation lays out the service definition at [`ServiceTarget::path`] and drives it
+/// with its own tools, locally or on the remote machine over SSH.
+pub trait ServiceManager {
+    /// The service being managed.
+    fn target(&self) -> &ServiceTarget;
+
+    /// What the service definition is called, e.g. `systemd service` or `init script`.
+    fn kind(&self) -> &'static str;
+
+    /// Fail early if the service cannot be installed, e.g. without the privileges it needs.
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Path of a target file as referred to by the service definition.
+    ///
+    /// Definitions are shell scripts by default, referring to the remote home as `$HOME`.
+    fn definition_path(&self, path: &str) -> Result<String> {
+        match &self.target().remote {
+            Some(remote) => remote
+                .unit_path(path)
+                .map(|path| path.replacen("%h/", "$HOME/", 1)),
+            None => Ok(path.to_owned()),
+        }
+    }
+
+    /// Write the service definition running `spec`, and upload it if managed over SSH.
+    fn install(&self, spec: &ServiceSpec) -> Result<()>;
+
+    /// Have the service started at boot (or login), and start it now.
+    fn enable(&self) -> Result<()>;
+
+    fn start(&self) -> Result<()>;
+
+    fn stop(&self) -> Result<()>;
+
+    fn restart(&self) -> Result<()>;
+
+    /// Print the status of the service.
+    fn status(&self) -> Result<()>;
+
+    /// Whether the service is running.
+    fn is_active(&self) -> bool;
+
+    /// Whether the service definition exists on the machine running the service.
+    fn is_installed(&self) -> bool {
+        let target = self.target();
+        target.host_path().is_ok_and(|path| {
+            target
+                .command(&["test", "-e", &path])
+                .output()
+                .is_ok_and(|output| output.status.success())
+        })
+    }
+
+    /// Disable the stopped service and remove its definition.
+    fn uninstall(&self) -> Result<()>;
+
+    /// Target file the service's output goes to if `log_file` is unset, or `None` for the journal.
+    fn output_path(&self) -> Result<Option<String>>;
+}
+
+/// What mihomo's service runs, with paths as referred to by its definition.
+#[derive(Debug, Clone)]
+pub struct ServiceSpec {
+    /// Command line running the core.
+    pub exec_start: String,
+    /// File the core's output is appended to, if `log_file` is set.
+    pub log_path: Option<String>,
+    /// Core config root, written to by the core as it runs (e.g. geodata and `cache.db`).
+    pub config_root: String,
+    /// Whether the core config enables TUN.
+    pub tun: bool,
+}
+
+/// mihomo's service as handed to a [`ServiceManager`].
+#[derive(Debug, Clone)]
+pub struct ServiceTarget {
+    /// Name the init system knows the service by, e.g. `mihomo.service`.
+    pub name: String,
+    /// Service definition, a file or directory, at its staging path if managed over SSH.
+    pub path: String,
+    /// Remote machine running the service, if managed over SSH.
+    pub remote: Option<Remote>,
+    pub prefix: String,
+}
+
+impl ServiceTarget {
+    /// Command for running command line `args` locally, or on the remote machine if managed over
+    /// SSH, with each argument quoted for the remote shell.
+    pub fn command<S: AsRef<str>>(&self, args: &[S]) -> Command {
+        match &self.remote {
+            Some(remote) => {
+                let mut cmd = Command::new("ssh");
+                cmd.arg("-t")
+                    .arg(&remote.destination)
+                    .args(args.iter().map(|arg| shell_quote(arg.as_ref())));
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(args[0].as_ref());
+                cmd.args(args[1..].iter().map(AsRef::as_ref));
+                cmd
+            }
+        }
+    }
+
+    /// Run command line `args`, on the remote machine if managed over SSH.
+    pub fn run(&self, args: &[String]) -> Result<ExitStatus> {
+        self.command(args)
+            .status()
+            .with_context(|| format!("failed to execute {}", args[0]))
+    }
+
+    /// Path of the service definition on the machine running the service.
+    pub fn host_path(&self) -> Result<String> {
+        match &self.remote {
+            Some(remote) => remote.remote_path(&self.path),
+            None => Ok(self.path.clone()),
+        }
+    }
+
+    /// Upload the service definition to the remote machine if managed over SSH.
+    pub fn push(&self) -> Result<()> {
+        match &self.remote {
+            Some(remote) => remote.push(&self.path),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove the service definition, on the remote machine too if managed over SSH.
+    pub fn remove(&self) -> Result<()> {
+        if let Some(remote) = &self.remote {
+            remote.ssh(&format!(
+                "rm -rf {}",
+                shell_quote(&remote.remote_path(&self.path)?)
+            ))?;
+        }
+        if !Path::new(&self.path).is_dir() {
+            return delete_file(&self.path, &self.prefix);
+        }
+        fs::remove_dir_all(&self.path)?;
+        println!(
+            "{} Removed {}",
+            self.prefix.cyan(),
+            self.path.underline().yellow()
+        );
+        Ok(())
+    }
+}
+
+/// Name of service `name` (e.g. `mihomo`) under `init`, and the path of its definition.
+///
+/// Definitions go under `systemd_root` with systemd, and into the system's service directories
+/// with other init systems if `system` is set, i.e. when running as root on the local machine.
+/// Under systemd, services named `<prefix>@<instance>` are instances of the `<prefix>@.service`
+/// template, which is their definition.
+pub fn service_location(
+    init: InitSystem,
+    name: &str,
+    systemd_root: &str,
+    system: bool,
+) -> (String, String) {
+    // systemd names services by their unit file, launchd by their label, others by their service
+    // directory
+    let name = match init {
+        InitSystem::Systemd => format!("{name}.service"),
+        InitSystem::Launchd => agent_label(name),
+        InitSystem::Runit | InitSystem::S6 | InitSystem::SysV | InitSystem::OpenRc => {
+            name.to_owned()
+        }
+    };
+    let root = match init {
+        InitSystem::Systemd => systemd_root,
+        InitSystem::Runit if system => RUNIT_SYSTEM_ROOT,
+        InitSystem::Runit => RUNIT_USER_ROOT,
+        InitSystem::S6 if system => S6_RC_SOURCE_ROOT,
+        InitSystem::S6 => S6_USER_ROOT,
+        InitSystem::SysV => SYSV_INIT_ROOT,
+        InitSystem::OpenRc => OPENRC_INIT_ROOT,
+        InitSystem::Launchd => LAUNCH_AGENTS_ROOT,
+    };
+    let path = match init {
+        InitSystem::Launchd => format!("{root}/{name}.plist"),
+        InitSystem::Systemd => match template_instance(&name) {
+            Some((template, _)) => format!("{root}/{template}"),
+            None => format!("{root}/{name}"),
+        },
+        _ => format!("{root}/{name}"),
+    };
+    (name, path)
+}
+
+/// Template and instance name of systemd unit `name`, e.g. `mihomo@.service` and `work` for
+/// `mihomo@work.service`, or `None` if it is not an instance of a template.
+pub fn template_instance(name: &str) -> Option<(String, &str)> {
+    let (prefix, instance) = name.strip_suffix(".service")?.split_once('@')?;
+    match prefix.is_empty() || instance.is_empty() {
+        true => None,
+        false => Some((format!("{prefix}@.service"), instance)),
+    }
+}
+
+/// Init system running on the local machine, probed from the state directories each init system
+/// keeps under `/run`.
+///
+/// Returns `None` if none or several of them are found, e.g. inside containers. SysV init leaves
+/// no such trace and is never detected.
+pub fn detect_init_system() -> Option<InitSystem> {
+    if cfg!(target_os = "macos") {
+        return Some(InitSystem::Launchd);
+    }
+    let probes = [
+        ("/run/systemd/system", InitSystem::Systemd),
+        ("/run/openrc", InitSystem::OpenRc),
+        ("/run/runit", InitSystem::Runit),
+        ("/run/s6-rc", InitSystem::S6),
+    ];
+    let mut found = probes
+        .iter()
+        .filter(|(path, _)| Path::new(path).exists())
+        .map(|(_, init)| *init);
+    match (found.next(), found.next()) {
+        (Some(init), None) => Some(init),
+        _ => None,
+    }
+}
+
+/// [`ServiceManager`] for `target` under `init`, managing a system unit rather than a user unit
+/// under systemd if `system` is set.
+pub fn service_manager(
+    init: InitSystem,
+    config: &Config,
+    target: ServiceTarget,
+    system: bool,
+) -> Box<dyn ServiceManager> {
+    match init {
+        InitSystem::Systemd => Box::new(Systemd {
+            target,
+            system,
+            directives: config.systemd.clone().unwrap_or_default(),
+            hardening: config.systemd_hardening,
+        }),
+        InitSystem::Runit => Box::new(Runit { target }),
+        InitSystem::S6 => Box::new(S6 { target }),
+        InitSystem::SysV => Box::new(SysV { target }),
+        InitSystem::Launchd => Box::new(Launchd { target }),
+        InitSystem::OpenRc => Box::new(OpenRc { target }),
+    }
+}
@@ -0,0 +1,246 @@
+use super::{ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::create_parent_dir;
+
+use std::fs;
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use shellexpand::tilde;
+
+/// Directory of the user's launch agents, loaded in their GUI session.
+pub const LAUNCH_AGENTS_ROOT: &str = "~/Library/LaunchAgents";
+
+/// Label of the launch agent running `name`, e.g. `io.mihoro.mihomo`.
+pub fn agent_label(name: &str) -> String {
+    format!("io.mihoro.{name}")
+}
+
+/// Log file the launch agent `label` writes to if no `log_file` is configured, as launchd has no
+/// journal to send output to.
+pub fn agent_log_path(label: &str) -> String {
+    tilde(&format!("~/Library/Logs/{label}.log")).to_string()
+}
+
+/// Create launch agent `plist_path` with label `label`, running `exec_start` at login and
+/// restarting it whenever it exits, with output appended to `log_path`.
+///
+/// Reference: https://www.launchd.info
+pub fn create_launch_agent(
+    label: &str,
+    exec_start: &str,
+    log_path: &str,
+    plist_path: &str,
+    prefix: &str,
+) -> Result<()> {
+    let arguments: String = exec_start
+        .split_whitespace()
+        .map(|arg| format!("    <string>{}</string>\n", escape_xml(arg)))
+        .collect();
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{arguments}  </array>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <true/>
+  <key>StandardOutPath</key>
+  <string>{log}</string>
+  <key>StandardErrorPath</key>
+  <string>{log}</string>
+</dict>
+</plist>
+"#,
+        log = escape_xml(log_path),
+    );
+
+    create_parent_dir(plist_path)?;
+    create_parent_dir(log_path)?;
+    fs::write(plist_path, plist).with_context(|| format!("failed to write {plist_path}"))?;
+    println!(
+        "{} Created launch agent at {}",
+        prefix.green(),
+        plist_path.underline().yellow()
+    );
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// launchd domain of the current user's GUI session, e.g. `gui/501`.
+pub fn gui_domain() -> Result<String> {
+    let output = Command::new("id")
+        .arg("-u")
+        .output()
+        .with_context(|| "failed to execute id")?;
+    let uid = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if uid.is_empty() {
+        bail!("failed to determine the current user's ID");
+    }
+    Ok(format!("gui/{uid}"))
+}
+
+/// mihomo's launch agent, only managed on the local machine.
+pub struct Launchd {
+    pub target: ServiceTarget,
+}
+
+impl Launchd {
+    /// `launchctl` service target of the agent, e.g. `gui/501/io.mihoro.mihomo`.
+    fn service_target(&self) -> Result<String> {
+        Ok(format!("{}/{}", gui_domain()?, self.target.name))
+    }
+}
+
+impl ServiceManager for Launchd {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "launch agent"
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.target.remote.is_some() {
+            bail!("launch agents can only be set up on the local machine, run mihoro on the Mac");
+        }
+        Ok(())
+    }
+
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        let log_path = spec
+            .log_path
+            .clone()
+            .unwrap_or_else(|| agent_log_path(&self.target.name));
+        create_launch_agent(
+            &self.target.name,
+            &spec.exec_start,
+            &log_path,
+            &self.target.path,
+            &self.target.prefix,
+        )
+    }
+
+    /// Agents run at load, and are loaded again at login from `~/Library/LaunchAgents`.
+    fn enable(&self) -> Result<()> {
+        self.start()
+    }
+
+    fn start(&self) -> Result<()> {
+        Launchctl::new()
+            .bootstrap(&gui_domain()?, &self.target.path)
+            .execute()?;
+        Ok(())
+    }
+
+    /// Unloading the agent is the only way to keep launchd from restarting it.
+    fn stop(&self) -> Result<()> {
+        Launchctl::new()
+            .bootout(&self.service_target()?)
+            .execute()?;
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        Launchctl::new()
+            .kickstart(&self.service_target()?, true)
+            .execute()?;
+        Ok(())
+    }
+
+    fn status(&self) -> Result<()> {
+        Launchctl::new().print(&self.service_target()?).execute()?;
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.service_target()
+            .and_then(|target| Launchctl::new().print(&target).output())
+            .is_ok_and(|status| status.contains("state = running"))
+    }
+
+    /// Stopping unloaded the agent already, removing it keeps it from loading at login.
+    fn uninstall(&self) -> Result<()> {
+        self.target.remove()
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        Ok(Some(agent_log_path(&self.target.name)))
+    }
+}
+
+/// Builder for `launchctl` invocations, e.g. `Launchctl::new().kickstart("gui/501/label", true)`.
+pub struct Launchctl {
+    launchctl: Command,
+}
+
+impl Default for Launchctl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Launchctl {
+    pub fn new() -> Self {
+        Self {
+            launchctl: Command::new("launchctl"),
+        }
+    }
+
+    /// Load the agent at `plist_path` into `domain`, starting it as it runs at load.
+    pub fn bootstrap(&mut self, domain: &str, plist_path: &str) -> &mut Self {
+        self.launchctl.arg("bootstrap").arg(domain).arg(plist_path);
+        self
+    }
+
+    /// Unload service `target` (`domain/label`), stopping it until bootstrapped again.
+    pub fn bootout(&mut self, target: &str) -> &mut Self {
+        self.launchctl.arg("bootout").arg(target);
+        self
+    }
+
+    /// Start service `target`, killing the running instance first if `kill` is set.
+    pub fn kickstart(&mut self, target: &str, kill: bool) -> &mut Self {
+        self.launchctl.arg("kickstart");
+        if kill {
+            self.launchctl.arg("-k");
+        }
+        self.launchctl.arg(target);
+        self
+    }
+
+    /// Print the state of service `target`.
+    pub fn print(&mut self, target: &str) -> &mut Self {
+        self.launchctl.arg("print").arg(target);
+        self
+    }
+
+    /// Run and capture stdout, without printing errors, e.g. for a service not loaded.
+    pub fn output(&mut self) -> Result<String> {
+        let output = self
+            .launchctl
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| "failed to execute launchctl")?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub fn execute(&mut self) -> Result<ExitStatus> {
+        self.launchctl
+            .spawn()?
+            .wait()
+            .with_context(|| "failed to execute launchctl")
+    }
+}
@@ -0,0 +1,148 @@
+use super::{ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::{create_parent_dir, is_root};
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+
+/// Directory of OpenRC service scripts, which are always system-wide.
+pub const OPENRC_INIT_ROOT: &str = "/etc/init.d";
+
+/// Create OpenRC service script `script_path` for service `name`, running `exec_start` in the
+/// background supervised by `openrc-run`.
+///
+/// Output is appended to `log_path` if given and discarded otherwise.
+///
+/// Reference: https://github.com/OpenRC/openrc/blob/master/service-script-guide.md
+pub fn create_openrc_script(
+    name: &str,
+    exec_start: &str,
+    log_path: Option<&str>,
+    script_path: &str,
+    prefix: &str,
+) -> Result<()> {
+    let (command, command_args) = exec_start.split_once(' ').unwrap_or((exec_start, ""));
+    let log = log_path
+        .map(|path| format!("output_log=\"{path}\"\nerror_log=\"{path}\"\n"))
+        .unwrap_or_default();
+    let script = format!(
+        r#"#!/sbin/openrc-run
+
+name="{name}"
+description="mihomo Daemon, Another Clash Kernel."
+command="{command}"
+command_args="{command_args}"
+command_background=true
+pidfile="/run/${{RC_SVCNAME}}.pid"
+{log}
+depend() {{
+	need net
+	after firewall
+}}
+"#
+    );
+
+    create_parent_dir(script_path)?;
+    fs::write(script_path, script).with_context(|| format!("failed to write {script_path}"))?;
+    fs::set_permissions(script_path, fs::Permissions::from_mode(0o755))?;
+    println!(
+        "{} Created OpenRC service at {}",
+        prefix.green(),
+        script_path.underline().yellow()
+    );
+    Ok(())
+}
+
+/// mihomo's OpenRC service, always a system service.
+pub struct OpenRc {
+    pub target: ServiceTarget,
+}
+
+impl OpenRc {
+    fn rc_service(&self, action: &str) -> Result<()> {
+        self.target
+            .command(&["rc-service", &self.target.name, action])
+            .status()
+            .with_context(|| "failed to execute rc-service")?;
+        Ok(())
+    }
+
+    fn rc_update(&self, action: &str) -> Result<()> {
+        self.target
+            .command(&["rc-update", action, &self.target.name, "default"])
+            .status()
+            .with_context(|| "failed to execute rc-update")?;
+        Ok(())
+    }
+}
+
+impl ServiceManager for OpenRc {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "OpenRC service"
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.target.remote.is_none() && !is_root() {
+            bail!(
+                "OpenRC services are installed to {OPENRC_INIT_ROOT}, run `mihoro setup` as root"
+            );
+        }
+        Ok(())
+    }
+
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        create_openrc_script(
+            &self.target.name,
+            &spec.exec_start,
+            spec.log_path.as_deref(),
+            &self.target.path,
+            &self.target.prefix,
+        )?;
+        self.target.push()
+    }
+
+    /// Add the service to the `default` runlevel, and start it.
+    fn enable(&self) -> Result<()> {
+        self.rc_update("add")?;
+        self.start()
+    }
+
+    fn start(&self) -> Result<()> {
+        self.rc_service("start")
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.rc_service("stop")
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.rc_service("restart")
+    }
+
+    fn status(&self) -> Result<()> {
+        self.rc_service("status")
+    }
+
+    /// `rc-service <name> status` exits with 0 only if the service is started.
+    fn is_active(&self) -> bool {
+        self.target
+            .command(&["rc-service", &self.target.name, "status"])
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn uninstall(&self) -> Result<()> {
+        self.rc_update("del")?;
+        self.target.remove()
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        bail!("OpenRC discards mihomo's output, set `log_file` to follow its logs")
+    }
+}
@@ -0,0 +1,237 @@
+use super::{ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::{create_parent_dir, shell_quote};
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, ExitStatus};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+/// Service directories of the system's runit, enabled by linking them into [`RUNIT_ENABLED_ROOT`].
+pub const RUNIT_SYSTEM_ROOT: &str = "/etc/sv";
+
+/// Directory supervised by the system's `runsvdir`.
+pub const RUNIT_ENABLED_ROOT: &str = "/var/service";
+
+/// Directory supervised by the user's own `runsvdir`, services in which are enabled as they are
+/// created.
+pub const RUNIT_USER_ROOT: &str = "~/service";
+
+/// Create runit service directory `service_dir` with a `run` script executing `exec_start`.
+///
+/// Output is appended to `log_path` if given, otherwise it is logged by `svlogd` to
+/// [`svlogd_current`] through a `log/run` script.
+///
+/// Reference: https://docs.voidlinux.org/config/services/index.html
+pub fn create_runit_service(
+    exec_start: &str,
+    log_path: Option<&str>,
+    service_dir: &str,
+    prefix: &str,
+) -> Result<()> {
+    let run = match log_path {
+        Some(path) => format!("#!/bin/sh\nexec {exec_start} >>{path} 2>&1\n"),
+        None => format!("#!/bin/sh\nexec 2>&1\nexec {exec_start}\n"),
+    };
+    write_script(&format!("{service_dir}/run"), &run)?;
+    if log_path.is_none() {
+        write_script(
+            &format!("{service_dir}/log/run"),
+            "#!/bin/sh\nmkdir -p main\nexec svlogd -tt main\n",
+        )?;
+    }
+
+    println!(
+        "{} Created runit service at {}",
+        prefix.green(),
+        service_dir.underline().yellow()
+    );
+    Ok(())
+}
+
+/// Log of the service at `service_dir` written by `svlogd`, if it logs through `log/run`.
+pub fn svlogd_current(service_dir: &str) -> String {
+    format!("{service_dir}/log/main/current")
+}
+
+fn write_script(path: &str, contents: &str) -> Result<()> {
+    create_parent_dir(path)?;
+    fs::write(path, contents).with_context(|| format!("failed to write {path}"))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// mihomo's runit service, a system service if under [`RUNIT_SYSTEM_ROOT`].
+pub struct Runit {
+    pub target: ServiceTarget,
+}
+
+impl Runit {
+    fn sv(&self) -> Sv {
+        match &self.target.remote {
+            Some(remote) => Sv::remote(&remote.destination),
+            None => Sv::new(),
+        }
+    }
+
+    /// Service directory as passed to `sv`, on the remote machine if managed over SSH.
+    fn service_dir(&self) -> Result<String> {
+        // `sv` looks up paths not starting with `.` or `/` as service names
+        self.target
+            .host_path()
+            .map(|path| match path.starts_with('/') {
+                true => path,
+                false => format!("./{path}"),
+            })
+    }
+
+    fn enabled_link(&self) -> Option<String> {
+        self.target
+            .path
+            .starts_with(RUNIT_SYSTEM_ROOT)
+            .then(|| format!("{RUNIT_ENABLED_ROOT}/{}", self.target.name))
+    }
+}
+
+impl ServiceManager for Runit {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "runit service"
+    }
+
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        create_runit_service(
+            &spec.exec_start,
+            spec.log_path.as_deref(),
+            &self.target.path,
+            &self.target.prefix,
+        )?;
+        self.target.push()
+    }
+
+    /// Link system services into `/var/service`, while the user's `runsvdir` picks up services in
+    /// `~/service` by itself. `runsv` then starts them within a few seconds.
+    fn enable(&self) -> Result<()> {
+        if let Some(link) = self.enabled_link() {
+            if fs::symlink_metadata(&link).is_err() {
+                std::os::unix::fs::symlink(&self.target.path, &link)
+                    .with_context(|| format!("failed to link service into {link}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> Result<()> {
+        self.sv().start(&self.service_dir()?).execute()?;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.sv().stop(&self.service_dir()?).execute()?;
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.sv().restart(&self.service_dir()?).execute()?;
+        Ok(())
+    }
+
+    fn status(&self) -> Result<()> {
+        self.sv().status(&self.service_dir()?).execute()?;
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.service_dir()
+            .and_then(|dir| self.sv().status(&dir).output())
+            .is_ok_and(|status| status.starts_with("run:"))
+    }
+
+    /// Unlinked or removed service directories are stopped by `runsvdir`.
+    fn uninstall(&self) -> Result<()> {
+        if let Some(link) = self.enabled_link() {
+            fs::remove_file(&link).ok();
+        }
+        self.target.remove()
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        Ok(Some(svlogd_current(&self.target.path)))
+    }
+}
+
+/// Builder for `sv` invocations on a service directory, e.g. `Sv::new().start("/etc/sv/mihomo")`.
+///
+/// Services are always referred to by path, as `sv` looks up bare names in the system's
+/// [`RUNIT_ENABLED_ROOT`] only.
+pub struct Sv {
+    sv: Command,
+    /// Whether `sv` runs over SSH, its arguments quoted for the remote shell.
+    remote: bool,
+}
+
+impl Default for Sv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sv {
+    pub fn new() -> Self {
+        Self {
+            sv: Command::new("sv"),
+            remote: false,
+        }
+    }
+
+    /// Run `sv` on a remote machine over SSH instead.
+    pub fn remote(destination: &str) -> Self {
+        let mut sv = Command::new("ssh");
+        sv.arg(destination).arg("sv");
+        Self { sv, remote: true }
+    }
+
+    /// Pass `command` and `service_dir` to `sv`.
+    fn action(&mut self, command: &str, service_dir: &str) -> &mut Self {
+        for arg in [command, service_dir] {
+            match self.remote {
+                true => self.sv.arg(shell_quote(arg)),
+                false => self.sv.arg(arg),
+            };
+        }
+        self
+    }
+
+    pub fn start(&mut self, service_dir: &str) -> &mut Self {
+        self.action("start", service_dir)
+    }
+
+    pub fn stop(&mut self, service_dir: &str) -> &mut Self {
+        self.action("stop", service_dir)
+    }
+
+    pub fn restart(&mut self, service_dir: &str) -> &mut Self {
+        self.action("restart", service_dir)
+    }
+
+    pub fn status(&mut self, service_dir: &str) -> &mut Self {
+        self.action("status", service_dir)
+    }
+
+    /// Run and capture stdout, e.g. `run: /etc/sv/mihomo: (pid 123) 45s` for `status`.
+    pub fn output(&mut self) -> Result<String> {
+        let output = self.sv.output().with_context(|| "failed to execute sv")?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub fn execute(&mut self) -> Result<ExitStatus> {
+        self.sv
+            .spawn()?
+            .wait()
+            .with_context(|| "failed to execute sv")
+    }
+}
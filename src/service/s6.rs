@@ -0,0 +1,238 @@
+use super::{ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::create_parent_dir;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+
+/// Source definitions of s6-rc services, compiled into its database by `s6-db-reload`, as laid
+/// out on Artix Linux.
+pub const S6_RC_SOURCE_ROOT: &str = "/etc/s6/sv";
+
+/// Contents of the `default` bundle, services listed in which are started at boot.
+pub const S6_RC_DEFAULT_BUNDLE: &str = "/etc/s6/adminsv/default/contents.d";
+
+/// Scan directory of the system's `s6-svscan`, holding live service directories of s6-rc.
+pub const S6_LIVE_ROOT: &str = "/run/service";
+
+/// Scan directory of the user's own `s6-svscan`, services in which are supervised as they are
+/// created.
+pub const S6_USER_ROOT: &str = "~/service";
+
+/// mihomo's s6 service, and how it is controlled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum S6Service {
+    /// Defined under [`S6_RC_SOURCE_ROOT`] and brought up and down with `s6-rc`.
+    Rc { name: String },
+    /// A plain service directory supervised by `s6-svscan`, controlled with `s6-svc`.
+    Supervised { dir: String },
+}
+
+impl S6Service {
+    /// Live service directory, as passed to `s6-svc` and `s6-svstat`.
+    fn live_dir(&self) -> String {
+        match self {
+            S6Service::Rc { name } => format!("{S6_LIVE_ROOT}/{name}"),
+            S6Service::Supervised { dir } => dir.clone(),
+        }
+    }
+
+    /// Scan directory of the `s6-svscan` supervising the service.
+    pub fn scan_dir(&self) -> String {
+        match self {
+            S6Service::Rc { .. } => S6_LIVE_ROOT.to_owned(),
+            S6Service::Supervised { dir } => {
+                Path::new(dir).parent().map_or(String::from("."), |parent| {
+                    parent.to_string_lossy().into_owned()
+                })
+            }
+        }
+    }
+
+    /// Command line starting the service.
+    pub fn start(&self) -> Vec<String> {
+        match self {
+            S6Service::Rc { name } => args(&["s6-rc", "-u", "change", name]),
+            S6Service::Supervised { dir } => args(&["s6-svc", "-u", dir]),
+        }
+    }
+
+    /// Command line stopping the service.
+    pub fn stop(&self) -> Vec<String> {
+        match self {
+            S6Service::Rc { name } => args(&["s6-rc", "-d", "change", name]),
+            S6Service::Supervised { dir } => args(&["s6-svc", "-d", dir]),
+        }
+    }
+
+    /// Command line restarting the service, by sending it `SIGTERM` for the supervisor to restart
+    /// it.
+    pub fn restart(&self) -> Vec<String> {
+        args(&["s6-svc", "-r", &self.live_dir()])
+    }
+
+    /// Command line printing the service's status, e.g. `up (pid 123) 45 seconds`.
+    pub fn status(&self) -> Vec<String> {
+        args(&["s6-svstat", &self.live_dir()])
+    }
+}
+
+/// mihomo's s6 service, an s6-rc service if under [`S6_RC_SOURCE_ROOT`].
+pub struct S6 {
+    pub target: ServiceTarget,
+}
+
+impl S6 {
+    /// The service, referring to its directory on the remote machine if managed over SSH.
+    fn service(&self) -> Result<S6Service> {
+        if self.target.path.starts_with(S6_RC_SOURCE_ROOT) {
+            return Ok(S6Service::Rc {
+                name: self.target.name.clone(),
+            });
+        }
+        Ok(S6Service::Supervised {
+            dir: self.target.host_path()?,
+        })
+    }
+}
+
+impl ServiceManager for S6 {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "s6 service"
+    }
+
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        create_s6_service(
+            &spec.exec_start,
+            spec.log_path.as_deref(),
+            &self.target.path,
+            matches!(self.service()?, S6Service::Rc { .. }),
+            &self.target.prefix,
+        )?;
+        self.target.push()
+    }
+
+    /// s6-rc services are added to the `default` bundle and compiled into the live database with
+    /// `s6-db-reload` before being brought up, while supervised services are picked up as soon as
+    /// `s6-svscan` rescans its directory.
+    fn enable(&self) -> Result<()> {
+        let service = self.service()?;
+        match &service {
+            S6Service::Rc { name } => {
+                let entry = format!("{S6_RC_DEFAULT_BUNDLE}/{name}");
+                create_parent_dir(&entry)?;
+                fs::write(&entry, "").with_context(|| format!("failed to write {entry}"))?;
+                self.target.run(&args(&["s6-db-reload"]))?;
+                self.target.run(&service.start())?;
+            }
+            S6Service::Supervised { .. } => {
+                self.target
+                    .run(&args(&["s6-svscanctl", "-a", &service.scan_dir()]))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> Result<()> {
+        self.target.run(&self.service()?.start())?;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.target.run(&self.service()?.stop())?;
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.target.run(&self.service()?.restart())?;
+        Ok(())
+    }
+
+    fn status(&self) -> Result<()> {
+        self.target.run(&self.service()?.status())?;
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.service()
+            .and_then(|service| {
+                let status = service.status();
+                let output = self.target.command(&status).output()?;
+                Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+            })
+            .is_ok_and(|status| status.starts_with("up"))
+    }
+
+    /// Drop the removed service from s6-rc's database, or have `s6-svscan` stop supervising it.
+    fn uninstall(&self) -> Result<()> {
+        let service = self.service()?;
+        if let S6Service::Rc { name } = &service {
+            fs::remove_file(format!("{S6_RC_DEFAULT_BUNDLE}/{name}")).ok();
+        }
+        self.target.remove()?;
+        match service {
+            S6Service::Rc { .. } => self.target.run(&args(&["s6-db-reload"]))?,
+            S6Service::Supervised { .. } => {
+                self.target
+                    .run(&args(&["s6-svscanctl", "-an", &service.scan_dir()]))?
+            }
+        };
+        Ok(())
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        bail!(
+            "s6 services log to the catch-all logger of s6-svscan, set `log_file` to follow \
+             mihomo's logs"
+        )
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Create s6 service directory `service_dir` with a `run` script executing `exec_start`, plus the
+/// `type` and dependencies s6-rc needs if `rc` is set.
+///
+/// Output is appended to `log_path` if given, otherwise it goes to the catch-all logger of
+/// `s6-svscan`.
+///
+/// Reference: https://skarnet.org/software/s6-rc/s6-rc-compile.html
+pub fn create_s6_service(
+    exec_start: &str,
+    log_path: Option<&str>,
+    service_dir: &str,
+    rc: bool,
+    prefix: &str,
+) -> Result<()> {
+    let run = match log_path {
+        Some(path) => format!("#!/bin/sh\nexec {exec_start} >>{path} 2>&1\n"),
+        None => format!("#!/bin/sh\nexec 2>&1\nexec {exec_start}\n"),
+    };
+    let run_path = format!("{service_dir}/run");
+    create_parent_dir(&run_path)?;
+    fs::write(&run_path, run).with_context(|| format!("failed to write {run_path}"))?;
+    fs::set_permissions(&run_path, fs::Permissions::from_mode(0o755))?;
+
+    if rc {
+        fs::write(format!("{service_dir}/type"), "longrun\n")?;
+        let dependencies = Path::new(service_dir).join("dependencies.d");
+        fs::create_dir_all(&dependencies)?;
+        fs::write(dependencies.join("base"), "")?;
+    }
+
+    println!(
+        "{} Created s6 service at {}",
+        prefix.green(),
+        service_dir.underline().yellow()
+    );
+    Ok(())
+}
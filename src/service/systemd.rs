@@ -0,0 +1,677 @@
+use super::{template_instance, ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::{create_parent_dir, shell_quote};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+/// Unit directories of the system instance, units in which are managed without `--user`.
+pub const SYSTEM_UNIT_ROOTS: [&str; 3] = [
+    "/etc/systemd/system",
+    "/usr/lib/systemd/system",
+    "/lib/systemd/system",
+];
+
+/// Extra directives for the generated unit, from the `[systemd]` table of `mihoro.toml`.
+///
+/// Directives are appended to their section as `Key=value` lines, after the generated ones, so
+/// that single-valued settings like `Restart=` override mihoro's defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SystemdConfig {
+    /// Directives appended to `[Unit]`, e.g. `After=docker.service`.
+    #[serde(default)]
+    pub unit: Vec<String>,
+    /// Directives appended to `[Service]`, e.g. `Environment=SAFE_PATHS=/etc/mihomo`.
+    #[serde(default)]
+    pub service: Vec<String>,
+
+    /// Scheduling priority of mihomo, from -20 (highest) to 19 (lowest), as `Nice=`.
+    pub nice: Option<i32>,
+    /// CPU time mihomo may use, e.g. `50%` of one CPU, as `CPUQuota=`.
+    pub cpu_quota: Option<String>,
+    /// Memory mihomo may use before being killed, e.g. `512M`, as `MemoryMax=`.
+    pub memory_max: Option<String>,
+    /// Maximum number of open files, 1000000 by default, as `LimitNOFILE=`.
+    pub limit_nofile: Option<u64>,
+    /// Maximum number of tasks (threads) mihomo may create, as `TasksMax=`.
+    pub tasks_max: Option<u64>,
+
+    /// When to restart mihomo, e.g. `on-failure`, `always` by default, as `Restart=`.
+    pub restart: Option<String>,
+    /// Seconds to wait before restarting mihomo, 5 by default, as `RestartSec=`.
+    pub restart_sec: Option<u64>,
+    /// Starts allowed within `start_limit_interval_sec` before systemd gives up, as
+    /// `StartLimitBurst=`.
+    pub start_limit_burst: Option<u32>,
+    /// Window in seconds counting starts towards `start_limit_burst`, as `StartLimitIntervalSec=`.
+    pub start_limit_interval_sec: Option<u64>,
+}
+
+impl SystemdConfig {
+    /// `section`'s directives as unit file lines, failing on any not of the form `Key=value`.
+    fn lines(section: &str, directives: &[String]) -> Result<String> {
+        directives
+            .iter()
+            .map(|directive| match directive.split_once('=') {
+                Some((key, _)) if !key.trim().is_empty() && !directive.contains('\n') => {
+                    Ok(format!("{}\n", directive.trim()))
+                }
+                _ => bail!(
+                    "invalid `systemd.{section}` directive `{directive}`, expected `Key=value`"
+                ),
+            })
+            .collect()
+    }
+
+    /// Resource limit directives for `[Service]`, failing on values systemd would reject.
+    ///
+    /// Reference: https://www.freedesktop.org/software/systemd/man/latest/systemd.resource-control.html
+    fn limits(&self) -> Result<String> {
+        let mut limits = String::new();
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("invalid `systemd.nice` {nice}, expected -20 to 19");
+            }
+            limits.push_str(&format!("Nice={nice}\n"));
+        }
+        if let Some(quota) = &self.cpu_quota {
+            if !quota
+                .strip_suffix('%')
+                .is_some_and(|percent| percent.parse::<u32>().is_ok_and(|p| p > 0))
+            {
+                bail!("invalid `systemd.cpu_quota` `{quota}`, expected a percentage like `50%`");
+            }
+            limits.push_str(&format!("CPUQuota={quota}\n"));
+        }
+        if let Some(max) = &self.memory_max {
+            let digits = max.trim_end_matches(['K', 'M', 'G', 'T', '%']);
+            if max != "infinity" && (digits.is_empty() || digits.parse::<u64>().is_err()) {
+                bail!("invalid `systemd.memory_max` `{max}`, expected a size like `512M`");
+            }
+            limits.push_str(&format!("MemoryMax={max}\n"));
+        }
+        limits.push_str(&format!(
+            "LimitNOFILE={}\n",
+            self.limit_nofile.unwrap_or(1000000)
+        ));
+        if let Some(tasks) = self.tasks_max {
+            limits.push_str(&format!("TasksMax={tasks}\n"));
+        }
+        Ok(limits)
+    }
+
+    /// Restart policy directives, for `[Unit]` and `[Service]` respectively.
+    ///
+    /// Reference: https://www.freedesktop.org/software/systemd/man/latest/systemd.service.html#Restart=
+    fn restart_policy(&self) -> Result<(String, String)> {
+        const RESTART_VALUES: [&str; 7] = [
+            "no",
+            "on-success",
+            "on-failure",
+            "on-abnormal",
+            "on-watchdog",
+            "on-abort",
+            "always",
+        ];
+        let restart = self.restart.as_deref().unwrap_or("always");
+        if !RESTART_VALUES.contains(&restart) {
+            bail!(
+                "invalid `systemd.restart` `{restart}`, expected one of {}",
+                RESTART_VALUES.join(", ")
+            );
+        }
+
+        let mut unit = String::new();
+        if let Some(burst) = self.start_limit_burst {
+            unit.push_str(&format!("StartLimitBurst={burst}\n"));
+        }
+        if let Some(interval) = self.start_limit_interval_sec {
+            unit.push_str(&format!("StartLimitIntervalSec={interval}\n"));
+        }
+        let service = format!(
+            "Restart={restart}\nRestartSec={}\n",
+            self.restart_sec.unwrap_or(5)
+        );
+        Ok((unit, service))
+    }
+
+    /// Fail on any invalid directive or limit, before setup downloads anything.
+    fn validate(&self) -> Result<()> {
+        Self::lines("unit", &self.unit)?;
+        Self::lines("service", &self.service)?;
+        self.limits()?;
+        self.restart_policy()?;
+        Ok(())
+    }
+}
+
+/// Sandboxing directives for running `spec`, leaving only its config root and log file writable.
+///
+/// Reference: https://www.freedesktop.org/software/systemd/man/latest/systemd.exec.html
+fn hardening_directives(spec: &ServiceSpec) -> String {
+    let mut writable = vec![spec.config_root.as_str()];
+    if let Some(log_dir) = spec
+        .log_path
+        .as_deref()
+        .and_then(|path| Path::new(path).parent())
+        .and_then(Path::to_str)
+    {
+        if !Path::new(log_dir).starts_with(&spec.config_root) {
+            writable.push(log_dir);
+        }
+    }
+    // TUN needs to create its device and routes, and hijacked DNS binds port 53
+    let capabilities = match spec.tun {
+        true => "CAP_NET_ADMIN CAP_NET_BIND_SERVICE",
+        false => "",
+    };
+    format!(
+        "ProtectSystem=strict
+ProtectHome=read-only
+ReadWritePaths={}
+PrivateTmp=true
+NoNewPrivileges=true
+RestrictAddressFamilies=AF_INET AF_INET6 AF_UNIX AF_NETLINK
+CapabilityBoundingSet={capabilities}
+",
+        writable.join(" ")
+    )
+}
+
+/// `spec` as run by instance `instance` of a template, with its config root
+/// (`<mihomo_config_root>-<instance>` by default) referred to through the instance name `%i`.
+fn template_spec(spec: &ServiceSpec, instance: &str) -> ServiceSpec {
+    let Some(root) = spec.config_root.strip_suffix(instance) else {
+        return spec.clone();
+    };
+    let root = format!("{root}%i");
+    let templated = |value: &str| value.replace(&spec.config_root, &root);
+    ServiceSpec {
+        exec_start: templated(&spec.exec_start),
+        log_path: spec.log_path.as_deref().map(templated),
+        config_root: root.clone(),
+        tun: spec.tun,
+    }
+}
+
+/// Create a systemd service file for running mihomo as a service, sandboxed if `hardening` is set
+/// and with `directives` appended.
+///
+/// By default, user systemd services are created under `~/.config/systemd/user/mihomo.service` and
+/// invoked with `systemctl --user start mihomo.service`. Directory is created if not present.
+///
+/// Reference: https://wiki.metacubex.one/startup/service/
+pub fn create_mihomo_service(
+    spec: &ServiceSpec,
+    hardening: bool,
+    directives: &SystemdConfig,
+    mihomo_service_path: &str,
+    prefix: &str,
+) -> Result<()> {
+    let service = mihomo_service_unit(spec, hardening, directives)?;
+
+    // Create mihomo service directory if not exists
+    create_parent_dir(mihomo_service_path)?;
+
+    // Write mihomo.service contents to file
+    fs::write(mihomo_service_path, service)?;
+
+    println!(
+        "{} Created systemd service at {}",
+        prefix.green(),
+        mihomo_service_path.underline().yellow()
+    );
+    Ok(())
+}
+
+/// Contents of the unit file created by [`create_mihomo_service`].
+fn mihomo_service_unit(
+    spec: &ServiceSpec,
+    hardening: bool,
+    directives: &SystemdConfig,
+) -> Result<String> {
+    // Append output to log file instead of the journal if configured
+    let log_output = spec
+        .log_path
+        .as_deref()
+        .map(|path| format!("StandardOutput=append:{path}\nStandardError=append:{path}\n"))
+        .unwrap_or_default();
+    let hardening = match hardening {
+        true => hardening_directives(spec),
+        false => String::new(),
+    };
+    let unit_directives = SystemdConfig::lines("unit", &directives.unit)?;
+    let service_directives = SystemdConfig::lines("service", &directives.service)?;
+    let limits = directives.limits()?;
+    let (start_limits, restart) = directives.restart_policy()?;
+    Ok(format!(
+        "[Unit]
+Description=mihomo Daemon, Another Clash Kernel.
+After=network.target NetworkManager.service systemd-networkd.service iwd.service
+{}{}
+[Service]
+Type=simple
+LimitNPROC=500
+{}{}ExecStartPre=/usr/bin/sleep 1s
+ExecStart={}
+ExecReload=/bin/kill -HUP $MAINPID
+{}{}{}
+[Install]
+WantedBy=default.target",
+        start_limits,
+        unit_directives,
+        limits,
+        restart,
+        spec.exec_start,
+        log_output,
+        hardening,
+        service_directives
+    ))
+}
+
+/// mihomo's systemd service, a user unit unless `system` is set.
+pub struct Systemd {
+    pub target: ServiceTarget,
+    pub system: bool,
+    pub directives: SystemdConfig,
+    pub hardening: bool,
+}
+
+impl Systemd {
+    fn systemctl(&self) -> Systemctl {
+        match &self.target.remote {
+            Some(remote) => Systemctl::remote(&remote.destination),
+            None if self.system => Systemctl::system(),
+            None => Systemctl::new(),
+        }
+    }
+
+    /// Unit file of this instance of a template, preferred by systemd over the template.
+    fn instance_target(&self) -> ServiceTarget {
+        ServiceTarget {
+            path: Path::new(&self.target.path)
+                .with_file_name(&self.target.name)
+                .to_string_lossy()
+                .into_owned(),
+            ..self.target.clone()
+        }
+    }
+
+    /// Whether instances of `template` other than this one are enabled, i.e. linked from
+    /// `default.target.wants` next to the template.
+    fn other_instances_enabled(&self, template: &str) -> bool {
+        let Ok(path) = self.target.host_path() else {
+            return false;
+        };
+        let prefix = template.trim_end_matches(".service");
+        let wants = Path::new(&path).with_file_name("default.target.wants");
+        self.target
+            .command(&["ls", &wants.to_string_lossy()])
+            .stderr(Stdio::null())
+            .output()
+            .is_ok_and(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .any(|unit| unit.starts_with(prefix) && unit != self.target.name)
+            })
+    }
+}
+
+impl ServiceManager for Systemd {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "systemd service"
+    }
+
+    fn check(&self) -> Result<()> {
+        self.directives.validate()
+    }
+
+    /// Units refer to the remote home as `%h`.
+    fn definition_path(&self, path: &str) -> Result<String> {
+        match &self.target.remote {
+            Some(remote) => remote.unit_path(path),
+            None => Ok(path.to_owned()),
+        }
+    }
+
+    /// Instances of a template share it, unless set up differently than the instances that wrote
+    /// it, in which case they get a unit of their own.
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        let Some((_, instance)) = template_instance(&self.target.name) else {
+            create_mihomo_service(
+                spec,
+                self.hardening,
+                &self.directives,
+                &self.target.path,
+                &self.target.prefix,
+            )?;
+            return self.target.push();
+        };
+        let spec = template_spec(spec, instance);
+        let unit = mihomo_service_unit(&spec, self.hardening, &self.directives)?;
+        let own = self.instance_target();
+        let target = match fs::read_to_string(&self.target.path) {
+            Ok(template) if template != unit => &own,
+            _ => {
+                if Path::new(&own.path).exists() {
+                    own.remove()?;
+                }
+                &self.target
+            }
+        };
+        create_mihomo_service(
+            &spec,
+            self.hardening,
+            &self.directives,
+            &target.path,
+            &target.prefix,
+        )?;
+        target.push()
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.systemctl().enable(&self.target.name).execute()?;
+        self.start()
+    }
+
+    fn start(&self) -> Result<()> {
+        self.systemctl().start(&self.target.name).execute()?;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.systemctl().stop(&self.target.name).execute()?;
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.systemctl().restart(&self.target.name).execute()?;
+        Ok(())
+    }
+
+    /// `systemctl status` exits with 3 if the unit is inactive, 4 if it does not exist.
+    fn status(&self) -> Result<()> {
+        let status = self.systemctl().status(&self.target.name).wait()?;
+        if status.code() == Some(4) {
+            bail!("{} not found, run `mihoro setup` first", self.target.name);
+        }
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.systemctl()
+            .is_active(&self.target.name)
+            .wait()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// The unit may live elsewhere than `user_systemd_root`, so ask systemd whether it knows it.
+    fn is_installed(&self) -> bool {
+        self.systemctl()
+            .cat(&self.target.name)
+            .wait()
+            .is_ok_and(|status| status.success())
+    }
+
+    fn uninstall(&self) -> Result<()> {
+        self.systemctl().disable(&self.target.name).execute()?;
+        match template_instance(&self.target.name) {
+            Some((template, _)) => {
+                let own = self.instance_target();
+                if Path::new(&own.path).exists() {
+                    own.remove()?;
+                }
+                // Other instances still run off the template
+                if !self.other_instances_enabled(&template) {
+                    self.target.remove()?;
+                }
+            }
+            None => self.target.remove()?,
+        }
+        self.systemctl().daemon_reload().execute()?;
+        self.systemctl().reset_failed().execute()?;
+        println!(
+            "{} Disabled and reloaded systemd services",
+            self.target.prefix.green()
+        );
+        Ok(())
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Builder for `systemctl --user` invocations, e.g. `Systemctl::new().start("mihomo.service")`.
+pub struct Systemctl {
+    systemctl: Command,
+    user: bool,
+    /// Whether `systemctl` runs over SSH, its arguments quoted for the remote shell.
+    remote: bool,
+}
+
+impl Default for Systemctl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Systemctl {
+    pub fn new() -> Self {
+        Self {
+            systemctl: Command::new("systemctl"),
+            user: true,
+            remote: false,
+        }
+    }
+
+    /// Manage system units instead of the user's, e.g. when running as root.
+    pub fn system() -> Self {
+        Self {
+            systemctl: Command::new("systemctl"),
+            user: false,
+            remote: false,
+        }
+    }
+
+    /// Run `systemctl` on a remote machine over SSH instead.
+    pub fn remote(destination: &str) -> Self {
+        let mut systemctl = Command::new("ssh");
+        systemctl.arg(destination).arg("systemctl");
+        Self {
+            systemctl,
+            user: true,
+            remote: true,
+        }
+    }
+
+    /// `systemctl` command with `--user` passed if managing user units, followed by `args`.
+    fn scoped(&mut self, args: &[&str]) -> &mut Command {
+        if self.user {
+            self.systemctl.arg("--user");
+        }
+        for arg in args {
+            match self.remote {
+                true => self.systemctl.arg(shell_quote(arg)),
+                false => self.systemctl.arg(arg),
+            };
+        }
+        &mut self.systemctl
+    }
+
+    pub fn enable(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["enable", service]);
+        self
+    }
+
+    pub fn start(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["start", service]);
+        self
+    }
+
+    pub fn stop(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["stop", service]);
+        self
+    }
+
+    pub fn restart(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["restart", service]);
+        self
+    }
+
+    pub fn status(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["status", service]);
+        self
+    }
+
+    /// Check whether `service` is active, reported through the exit status only.
+    pub fn is_active(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["is-active", "--quiet", service])
+            .stderr(Stdio::null());
+        self
+    }
+
+    /// Check whether `service` has a unit file, reported through the exit status only.
+    pub fn cat(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["cat", service])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        self
+    }
+
+    pub fn disable(&mut self, service: &str) -> &mut Self {
+        self.scoped(&["disable", service]);
+        self
+    }
+
+    pub fn daemon_reload(&mut self) -> &mut Self {
+        self.scoped(&["daemon-reload"]);
+        self
+    }
+
+    pub fn reset_failed(&mut self) -> &mut Self {
+        self.scoped(&["reset-failed"]);
+        self
+    }
+
+    /// Print `properties` of `service` as `Name=value` lines, e.g. `MemoryCurrent`.
+    pub fn show(&mut self, service: &str, properties: &[&str]) -> &mut Self {
+        let properties = format!("--property={}", properties.join(","));
+        self.scoped(&["show", service, &properties]);
+        self
+    }
+
+    /// Run and capture stdout, failing if systemctl exits unsuccessfully.
+    pub fn output(&mut self) -> Result<String> {
+        let output = self
+            .systemctl
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| "failed to execute systemctl")?;
+        if !output.status.success() {
+            bail!("systemctl exited with {}", output.status);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run with stdout passed through, failing with systemctl's stderr if it exits unsuccessfully.
+    pub fn execute(&mut self) -> Result<()> {
+        let output = self
+            .systemctl
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .output()
+            .with_context(|| "failed to execute systemctl")?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            let reason = match stderr.trim() {
+                "" => output.status.to_string(),
+                stderr => stderr.to_owned(),
+            };
+            let args: Vec<_> = self
+                .systemctl
+                .get_args()
+                .map(|arg| arg.to_string_lossy())
+                .collect();
+            bail!(
+                "`{} {}` failed: {}",
+                self.systemctl.get_program().to_string_lossy(),
+                args.join(" "),
+                reason
+            );
+        }
+        // e.g. `Created symlink ...` from `systemctl enable`
+        eprint!("{stderr}");
+        Ok(())
+    }
+
+    /// Run with output passed through, returning the exit status without checking it, e.g. for
+    /// `status` and `is-active` which report the unit's state through it.
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        self.systemctl
+            .status()
+            .with_context(|| "failed to execute systemctl")
+    }
+}
+
+/// Name of the current user.
+pub fn current_user() -> Option<String> {
+    env::var("USER")
+        .ok()
+        .filter(|user| !user.is_empty())
+        .or_else(|| {
+            let output = Command::new("id").arg("-un").output().ok()?;
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        })
+}
+
+/// Whether lingering is enabled for the current user, keeping their user services running
+/// without a login session. `None` if it cannot be determined, e.g. without `loginctl`.
+pub fn linger_enabled() -> Option<bool> {
+    let output = Command::new("loginctl")
+        .args([
+            "show-user",
+            &current_user()?,
+            "--property=Linger",
+            "--value",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the user's systemd instance is reachable over D-Bus, which `systemctl --user` requires.
+///
+/// Sessions entered with `su` or `sudo -u`, or started from cron, lack the bus environment.
+pub fn user_bus_available() -> bool {
+    env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some()
+        || env::var_os("XDG_RUNTIME_DIR")
+            .is_some_and(|runtime_dir| Path::new(&runtime_dir).join("bus").exists())
+}
+
+/// Enable lingering for the current user with `loginctl enable-linger`.
+pub fn enable_linger() -> Result<()> {
+    let user = current_user().context("failed to determine current user")?;
+    let status = Command::new("loginctl")
+        .args(["enable-linger", &user])
+        .status()
+        .with_context(|| "failed to execute loginctl")?;
+    if !status.success() {
+        bail!("`loginctl enable-linger` failed, run `sudo loginctl enable-linger {user}` instead");
+    }
+    Ok(())
+}
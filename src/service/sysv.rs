@@ -0,0 +1,188 @@
+use super::{ServiceManager, ServiceSpec, ServiceTarget};
+use crate::utils::{create_parent_dir, is_root};
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+
+/// Directory of SysV init scripts, which are always system-wide.
+pub const SYSV_INIT_ROOT: &str = "/etc/init.d";
+
+/// Create LSB init script `script_path` for service `name`, running `exec_start` in the
+/// background with `start-stop-daemon` as on Debian and Devuan.
+///
+/// Output is appended to `log_path` if given and discarded otherwise.
+///
+/// Reference: https://wiki.debian.org/LSBInitScripts
+pub fn create_init_script(
+    name: &str,
+    exec_start: &str,
+    log_path: Option<&str>,
+    script_path: &str,
+    prefix: &str,
+) -> Result<()> {
+    let daemon = exec_start.split_whitespace().next().unwrap_or_default();
+    let script = format!(
+        r#"#!/bin/sh
+### BEGIN INIT INFO
+# Provides:          {name}
+# Required-Start:    $network $remote_fs
+# Required-Stop:     $network $remote_fs
+# Default-Start:     2 3 4 5
+# Default-Stop:      0 1 6
+# Short-Description: mihomo Daemon, Another Clash Kernel.
+### END INIT INFO
+
+NAME={name}
+DAEMON={daemon}
+COMMAND="{exec_start}"
+LOG={log}
+PIDFILE=/run/$NAME.pid
+
+. /lib/lsb/init-functions
+
+case "$1" in
+  start)
+    log_daemon_msg "Starting" "$NAME"
+    start-stop-daemon --start --quiet --background --make-pidfile --pidfile "$PIDFILE" \
+      --startas /bin/sh -- -c "exec $COMMAND >>$LOG 2>&1"
+    log_end_msg $?
+    ;;
+  stop)
+    log_daemon_msg "Stopping" "$NAME"
+    start-stop-daemon --stop --quiet --retry 5 --remove-pidfile --pidfile "$PIDFILE"
+    log_end_msg $?
+    ;;
+  restart|force-reload)
+    "$0" stop
+    "$0" start
+    ;;
+  status)
+    status_of_proc -p "$PIDFILE" "$DAEMON" "$NAME"
+    ;;
+  *)
+    echo "Usage: $0 {{start|stop|restart|status}}" >&2
+    exit 3
+    ;;
+esac
+"#,
+        log = log_path.unwrap_or("/dev/null"),
+    );
+
+    create_parent_dir(script_path)?;
+    fs::write(script_path, script).with_context(|| format!("failed to write {script_path}"))?;
+    fs::set_permissions(script_path, fs::Permissions::from_mode(0o755))?;
+    println!(
+        "{} Created init script at {}",
+        prefix.green(),
+        script_path.underline().yellow()
+    );
+    Ok(())
+}
+
+/// mihomo's SysV init script, always a system service.
+pub struct SysV {
+    pub target: ServiceTarget,
+}
+
+impl SysV {
+    fn service(&self, action: &str) -> Result<()> {
+        self.target
+            .run(&service_command(&self.target.name, action))?;
+        Ok(())
+    }
+}
+
+impl ServiceManager for SysV {
+    fn target(&self) -> &ServiceTarget {
+        &self.target
+    }
+
+    fn kind(&self) -> &'static str {
+        "init script"
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.target.remote.is_none() && !is_root() {
+            bail!(
+                "SysV init scripts are installed to {SYSV_INIT_ROOT}, run `mihoro setup` as root"
+            );
+        }
+        Ok(())
+    }
+
+    fn install(&self, spec: &ServiceSpec) -> Result<()> {
+        create_init_script(
+            &self.target.name,
+            &spec.exec_start,
+            spec.log_path.as_deref(),
+            &self.target.path,
+            &self.target.prefix,
+        )?;
+        self.target.push()
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.target.run(&enable_command(&self.target.name))?;
+        self.start()
+    }
+
+    fn start(&self) -> Result<()> {
+        self.service("start")
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.service("stop")
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.service("restart")
+    }
+
+    fn status(&self) -> Result<()> {
+        self.service("status")
+    }
+
+    /// LSB init scripts report a running service with exit status 0.
+    fn is_active(&self) -> bool {
+        self.target
+            .command(&["service", &self.target.name, "status"])
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn uninstall(&self) -> Result<()> {
+        self.target.run(&disable_command(&self.target.name))?;
+        self.target.remove()
+    }
+
+    fn output_path(&self) -> Result<Option<String>> {
+        bail!("SysV init scripts discard mihomo's output, set `log_file` to follow its logs")
+    }
+}
+
+/// Command line running `action` (e.g. `start` or `status`) on service `name`.
+pub fn service_command(name: &str, action: &str) -> Vec<String> {
+    vec![String::from("service"), name.to_owned(), action.to_owned()]
+}
+
+/// Command line starting service `name` at boot.
+pub fn enable_command(name: &str) -> Vec<String> {
+    vec![
+        String::from("update-rc.d"),
+        name.to_owned(),
+        String::from("defaults"),
+    ]
+}
+
+/// Command line no longer starting service `name` at boot.
+pub fn disable_command(name: &str) -> Vec<String> {
+    vec![
+        String::from("update-rc.d"),
+        String::from("-f"),
+        name.to_owned(),
+        String::from("remove"),
+    ]
+}
@@ -0,0 +1,66 @@
+use crate::gateway::GatewayState;
+use crate::lan::LanState;
+use crate::resolver::HostDnsState;
+use crate::subscription::SubscriptionInfo;
+use crate::ui::Dashboard;
+use crate::utils::create_parent_dir;
+
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Persistent state managed by `mihoro` itself (not meant to be edited by hand).
+///
+/// Stored as toml next to mihomo's `config.yaml`, records things like the installed dashboard and
+/// firewall rules that need to be reverted later.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct State {
+    pub dashboard: Option<DashboardState>,
+    pub gateway: Option<GatewayState>,
+    pub lan: Option<LanState>,
+    pub host_dns: Option<HostDnsState>,
+
+    /// Name of the schedule rule last applied, so that it is applied only once per window.
+    pub schedule: Option<String>,
+
+    /// Usage and expiry of the subscription, as reported by the last config download.
+    pub subscription: Option<SubscriptionInfo>,
+
+    /// Validators of the cached remote config, to download it again only if it changed.
+    pub remote_config: Option<RemoteConfigState>,
+}
+
+/// Web dashboard installed with `mihoro ui install`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DashboardState {
+    pub name: Dashboard,
+    pub version: String,
+}
+
+/// `ETag` and `Last-Modified` of the remote config last downloaded from `url`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RemoteConfigState {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl State {
+    /// Read state from path, falls back to an empty state if the file does not exist yet.
+    pub fn load(path: &str) -> Result<State> {
+        if !Path::new(path).exists() {
+            return Ok(State::default());
+        }
+        let raw_state = fs::read_to_string(path)?;
+        let state: State = toml::from_str(&raw_state)?;
+        Ok(state)
+    }
+
+    /// Serialize state as toml and write to path.
+    pub fn save(&self, path: &str) -> Result<()> {
+        create_parent_dir(path)?;
+        fs::write(path, toml::to_string(&self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+/// Response header providers report subscription usage and expiry in.
+pub const USERINFO_HEADER: &str = "subscription-userinfo";
+
+/// Traffic usage and expiry of the subscription at `remote_config_url`, as of its last download.
+///
+/// Parsed from the `subscription-userinfo` header, e.g.
+/// `upload=455727941; download=6174315083; total=1073741824000; expire=1671815872`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    /// Bytes uploaded through the subscription's nodes.
+    pub upload: u64,
+    /// Bytes downloaded through the subscription's nodes.
+    pub download: u64,
+    /// Traffic allowance in bytes, 0 if unlimited.
+    pub total: u64,
+    /// When the subscription expires as a unix timestamp, if it does.
+    pub expire: Option<i64>,
+    /// When the header was received, as a unix timestamp.
+    pub fetched_at: i64,
+}
+
+impl SubscriptionInfo {
+    /// Parse the `subscription-userinfo` header from response `headers`, received at `now`.
+    ///
+    /// Unknown keys are ignored, and missing or malformed values count as 0, as providers are not
+    /// consistent about them.
+    pub fn from_headers(headers: &HeaderMap, now: DateTime<Utc>) -> Option<SubscriptionInfo> {
+        let header = headers.get(USERINFO_HEADER)?.to_str().ok()?;
+        let mut info = SubscriptionInfo {
+            fetched_at: now.timestamp(),
+            ..Default::default()
+        };
+        for (key, value) in header
+            .split(';')
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+        {
+            // Some providers send fractional byte counts
+            let number = value.parse::<f64>().unwrap_or(0.0).max(0.0);
+            match key {
+                "upload" => info.upload = number as u64,
+                "download" => info.download = number as u64,
+                "total" => info.total = number as u64,
+                "expire" if number > 0.0 => info.expire = Some(number as i64),
+                _ => (),
+            }
+        }
+        Some(info)
+    }
+
+    /// Bytes used, uploaded and downloaded.
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+
+    /// Bytes left of the traffic allowance, `None` if unlimited.
+    pub fn remaining(&self) -> Option<u64> {
+        (self.total > 0).then(|| self.total.saturating_sub(self.used()))
+    }
+
+    /// When the subscription expires, `None` if it does not.
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expire
+            .and_then(|expire| DateTime::from_timestamp(expire, 0))
+    }
+
+    /// Whole days left until the subscription expires at `now`, negative once expired.
+    pub fn days_left(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.expires_at()
+            .map(|expires_at| (expires_at - now).num_days())
+    }
+
+    /// Whether less than a tenth of the traffic or less than a week is left at `now`.
+    pub fn running_low(&self, now: DateTime<Utc>) -> bool {
+        let low_traffic = self
+            .remaining()
+            .is_some_and(|remaining| remaining < self.total / 10);
+        let expiring = self.days_left(now).is_some_and(|days| days < 7);
+        low_traffic || expiring
+    }
+}
@@ -0,0 +1,35 @@
+use colored::Colorize;
+
+/// Outcome of each step of `mihoro setup` or `mihoro update`, printed as an aligned table once the
+/// run completes.
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    rows: Vec<(String, String)>,
+}
+
+impl RunSummary {
+    pub fn new() -> RunSummary {
+        RunSummary::default()
+    }
+
+    /// Record the outcome of `step`, e.g. `("binary", "unchanged")`.
+    pub fn add(&mut self, step: &str, outcome: impl Into<String>) {
+        self.rows.push((step.to_owned(), outcome.into()));
+    }
+
+    pub fn rows(&self) -> &[(String, String)] {
+        &self.rows
+    }
+
+    pub fn print(&self, prefix: &str) {
+        println!("{} Summary", prefix.green());
+        let width = self.rows.iter().map(|(step, _)| step.len()).max();
+        for (step, outcome) in &self.rows {
+            println!(
+                "  {}  {}",
+                format!("{step:<width$}", width = width.unwrap_or(0)).bold(),
+                outcome
+            );
+        }
+    }
+}
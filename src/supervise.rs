@@ -0,0 +1,135 @@
+use crate::interrupt;
+use crate::mihoro::Mihoro;
+
+use std::future;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use reqwest::Client;
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+/// Seconds the core is given to exit after `SIGTERM` before it is killed.
+const STOP_TIMEOUT: u64 = 10;
+
+/// Supervise the core in the foreground for `mihoro run`, until mihoro receives `SIGTERM` or
+/// `SIGINT`, stopping the core first.
+///
+/// The config is fetched again on `SIGHUP` and every `update_interval` hours if given, then
+/// reloaded through the controller, or by restarting the core if that fails. mihoro exits with an
+/// error if the core exits on its own, leaving restarts to the container runtime.
+pub async fn supervise(
+    mihoro: &Mihoro,
+    client: Client,
+    update_interval: Option<u64>,
+) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    // Exiting on Ctrl-C right away would leave the core running if it did not get the signal too,
+    // e.g. when sent to mihoro alone as the container's init
+    let mut sigint = signal(SignalKind::interrupt())?;
+    interrupt::handle_elsewhere();
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut updates = update_interval.map(|hours| {
+        let period = Duration::from_secs(hours * 3600);
+        let mut updates = time::interval_at(time::Instant::now() + period, period);
+        updates.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        updates
+    });
+
+    let mut core = spawn_core(mihoro)?;
+    loop {
+        tokio::select! {
+            status = core.wait() => {
+                bail!("{} exited with {}", mihoro.config.core.binary_name(), status?);
+            }
+            _ = sigterm.recv() => {
+                println!("{} Received SIGTERM, stopping", mihoro.prefix.cyan());
+                return stop_core(&mut core).await;
+            }
+            _ = sigint.recv() => {
+                println!("{} Received SIGINT, stopping", mihoro.prefix.cyan());
+                return stop_core(&mut core).await;
+            }
+            _ = sighup.recv() => {
+                println!("{} Received SIGHUP, updating config", mihoro.prefix.cyan());
+            }
+            _ = next_update(&mut updates) => {
+                println!("{} Updating config", mihoro.prefix.cyan());
+            }
+        }
+
+        // Keep the core running on its current config if the update fails or changes nothing
+        match mihoro.refresh_config(&client).await {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(err) => {
+                println!(
+                    "{} {}, keeping the current config",
+                    "warning:".yellow(),
+                    err
+                );
+                continue;
+            }
+        }
+        if !mihoro.reload().await {
+            stop_core(&mut core).await?;
+            core = spawn_core(mihoro)?;
+        }
+    }
+}
+
+/// Wait for the next periodic update, or forever without `update_interval`.
+async fn next_update(updates: &mut Option<Interval>) {
+    match updates {
+        Some(updates) => {
+            updates.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Start the core in the foreground, its output going to mihoro's own.
+fn spawn_core(mihoro: &Mihoro) -> Result<Child> {
+    let exec_start = mihoro.config.core.exec_start(
+        &mihoro.mihomo_target_binary_path,
+        &mihoro.mihomo_target_config_root,
+        mihoro.config.mihomo_config_name.as_deref(),
+    );
+    let mut args = exec_start.split_whitespace();
+    let program = args.next().context("empty core command line")?;
+    let core = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    println!(
+        "{} Started {} (pid {})",
+        mihoro.prefix.green(),
+        mihoro.config.core.binary_name(),
+        core.id().unwrap_or_default()
+    );
+    Ok(core)
+}
+
+/// Stop the core with `SIGTERM`, killing it if it is still running after [`STOP_TIMEOUT`].
+async fn stop_core(core: &mut Child) -> Result<()> {
+    let Some(pid) = core.id() else {
+        // Already exited and reaped
+        return Ok(());
+    };
+    // SAFETY: `kill` has no memory safety requirements, and `pid` is our unreaped child
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+    }
+    if time::timeout(Duration::from_secs(STOP_TIMEOUT), core.wait())
+        .await
+        .is_err()
+    {
+        core.kill().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use crate::utils::RootCommand;
+
+use std::{collections::BTreeMap, fs};
+
+/// Drop-in installed while the gateway is enabled.
+pub const SYSCTL_DROPIN_PATH: &str = "/etc/sysctl.d/99-mihoro.conf";
+
+/// Kernel parameters required for forwarding and redirecting LAN traffic into mihomo.
+///
+/// Reverse path filtering is set to loose mode, as strict mode drops packets routed back through
+/// the tproxy routing table.
+pub const GATEWAY_SYSCTLS: [(&str, &str); 4] = [
+    ("net.ipv4.ip_forward", "1"),
+    ("net.ipv6.conf.all.forwarding", "1"),
+    ("net.ipv4.conf.all.rp_filter", "2"),
+    ("net.ipv4.conf.default.rp_filter", "2"),
+];
+
+/// Read the current value of kernel parameter `key` from `/proc/sys`.
+///
+/// Returns `None` if the parameter does not exist, e.g. IPv6 parameters with IPv6 disabled.
+pub fn read_sysctl(key: &str) -> Option<String> {
+    fs::read_to_string(format!("/proc/sys/{}", key.replace('.', "/")))
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+/// Current values of the gateway kernel parameters available on this machine.
+pub fn current_gateway_sysctls() -> BTreeMap<String, String> {
+    GATEWAY_SYSCTLS
+        .iter()
+        .filter_map(|(key, _)| read_sysctl(key).map(|value| (key.to_string(), value)))
+        .collect()
+}
+
+/// Commands installing the sysctl drop-in and applying it immediately.
+pub fn install_commands() -> Vec<RootCommand> {
+    let dropin = GATEWAY_SYSCTLS
+        .iter()
+        .filter(|(key, _)| read_sysctl(key).is_some())
+        .map(|(key, value)| format!("{key} = {value}\n"))
+        .collect::<String>();
+    vec![
+        RootCommand::new(&format!("dd of={SYSCTL_DROPIN_PATH} status=none")).stdin(format!(
+            "# Managed by mihoro, removed with `mihoro gateway disable`\n{dropin}"
+        )),
+        RootCommand::new(&format!("sysctl -q -p {SYSCTL_DROPIN_PATH}")),
+    ]
+}
+
+/// Commands removing the sysctl drop-in and restoring `previous` values.
+pub fn remove_commands(previous: &BTreeMap<String, String>) -> Vec<RootCommand> {
+    let mut commands = vec![RootCommand::new(&format!("rm -f {SYSCTL_DROPIN_PATH}"))];
+    for (key, value) in previous {
+        commands.push(RootCommand::new(&format!("sysctl -q -w {key}={value}")));
+    }
+    commands
+}
@@ -0,0 +1,150 @@
+use crate::interrupt;
+use crate::utils::{download_file, extract_archive};
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+/// Web dashboards that can be served by mihomo through `external_ui`.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dashboard {
+    Metacubexd,
+    Yacd,
+    Zashboard,
+}
+
+impl fmt::Display for Dashboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Dashboard::Metacubexd => "metacubexd",
+            Dashboard::Yacd => "yacd",
+            Dashboard::Zashboard => "zashboard",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Dashboard {
+    /// GitHub repository publishing the dashboard's releases.
+    pub fn repo(&self) -> &'static str {
+        match self {
+            Dashboard::Metacubexd => "MetaCubeX/metacubexd",
+            Dashboard::Yacd => "MetaCubeX/Yacd-meta",
+            Dashboard::Zashboard => "Zephyruso/zashboard",
+        }
+    }
+
+    /// Download url of the dashboard's prebuilt static files for release `tag`.
+    pub fn archive_url(&self, tag: &str) -> String {
+        match self {
+            Dashboard::Metacubexd => format!(
+                "https://github.com/MetaCubeX/metacubexd/releases/download/{tag}/compressed-dist.tgz"
+            ),
+            // Yacd-meta does not attach build artifacts to releases, its `gh-pages` branch is
+            // the prebuilt dashboard
+            Dashboard::Yacd => String::from(
+                "https://github.com/MetaCubeX/Yacd-meta/archive/refs/heads/gh-pages.tar.gz",
+            ),
+            Dashboard::Zashboard => format!(
+                "https://github.com/Zephyruso/zashboard/releases/download/{tag}/dist.zip"
+            ),
+        }
+    }
+}
+
+/// Url of the dashboard served by mihomo's external controller at `/ui`.
+///
+/// Controller address and `secret` are passed as query parameters so that the dashboard connects
+/// to the backend directly. metacubexd and zashboard read them from the `#/setup` route, while yacd
+/// (and unknown dashboards) read them from the query string.
+pub fn dashboard_url(
+    external_controller: &str,
+    secret: Option<&str>,
+    dashboard: Option<Dashboard>,
+) -> Result<String> {
+    let (host, port) = external_controller
+        .rsplit_once(':')
+        .with_context(|| format!("`external_controller` `{external_controller}` invalid"))?;
+
+    // Controller listening on all interfaces is reachable from localhost
+    let host = match host {
+        "" | "0.0.0.0" | "[::]" => "127.0.0.1",
+        _ => host,
+    };
+
+    let mut url = Url::parse(&format!("http://{host}:{port}/ui/"))?;
+    url.query_pairs_mut()
+        .append_pair("hostname", host)
+        .append_pair("port", port)
+        .append_pair("secret", secret.unwrap_or(""));
+
+    match dashboard {
+        Some(Dashboard::Metacubexd | Dashboard::Zashboard) => {
+            let query = url.query().unwrap_or("").to_owned();
+            url.set_query(None);
+            url.set_fragment(Some(&format!("/setup?{query}")));
+        }
+        Some(Dashboard::Yacd) | None => (),
+    }
+    Ok(url.to_string())
+}
+
+/// Download release `version` of `dashboard` and unpack it into `ui_root`.
+///
+/// Any previously installed dashboard under `ui_root` is replaced.
+pub async fn install_dashboard(
+    client: &Client,
+    dashboard: Dashboard,
+    version: &str,
+    ui_root: &str,
+    prefix: &str,
+) -> Result<()> {
+    let archive_url = dashboard.archive_url(version);
+
+    // Download and unpack next to `ui_root` so a failed download never leaves it half-populated
+    let archive_path = format!("{ui_root}.download");
+    let unpack_root = format!("{ui_root}.unpack");
+    download_file(client, &archive_url, &archive_path).await?;
+    interrupt::track(&archive_path);
+    if Path::new(&unpack_root).exists() {
+        fs::remove_dir_all(&unpack_root)?;
+    }
+    interrupt::track(&unpack_root);
+    extract_archive(&archive_path, &archive_url, &unpack_root)?;
+    fs::remove_file(&archive_path)?;
+    interrupt::untrack(&archive_path);
+
+    // Dashboards are usually packed under a single top-level directory (e.g. `dist/`)
+    let mut entries = fs::read_dir(&unpack_root)?.collect::<io::Result<Vec<_>>>()?;
+    let dist_root = if entries.len() == 1 && entries[0].path().is_dir() {
+        entries.remove(0).path()
+    } else {
+        PathBuf::from(&unpack_root)
+    };
+
+    if Path::new(ui_root).exists() {
+        fs::remove_dir_all(ui_root)?;
+    }
+    fs::rename(&dist_root, ui_root)?;
+    if Path::new(&unpack_root).exists() {
+        fs::remove_dir_all(&unpack_root)?;
+    }
+    interrupt::untrack(&unpack_root);
+
+    println!(
+        "{} Installed {} {} to {}",
+        prefix.green(),
+        dashboard.to_string().bold(),
+        version,
+        ui_root.underline().yellow()
+    );
+    Ok(())
+}
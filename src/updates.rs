@@ -0,0 +1,78 @@
+use crate::utils::create_parent_dir;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// Only the most recent runs are kept.
+const MAX_RUNS: usize = 50;
+
+/// Results of recent config updates, oldest first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct UpdateHistory {
+    pub runs: Vec<UpdateRun>,
+}
+
+/// A single config update attempt.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateRun {
+    /// Unix timestamp the update started at.
+    pub time: i64,
+    /// URL the remote config was fetched from, `None` if regenerated from the cached one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// HTTP status the provider responded with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Size of the downloaded remote config, before conversion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Number of nodes in the deployed config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<usize>,
+    /// Why the update failed, `None` if it succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl UpdateRun {
+    /// An update attempt starting now.
+    pub fn start() -> UpdateRun {
+        UpdateRun {
+            time: Utc::now().timestamp(),
+            url: None,
+            status: None,
+            bytes: None,
+            nodes: None,
+            error: None,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl UpdateHistory {
+    /// Read history from `path`, empty if it does not exist yet.
+    pub fn load(path: &str) -> Result<UpdateHistory> {
+        if !Path::new(path).exists() {
+            return Ok(UpdateHistory::default());
+        }
+        let raw = fs::read_to_string(path)?;
+        serde_json::from_str(&raw).with_context(|| format!("failed to parse {path}"))
+    }
+
+    /// Write history to `path`, keeping the 50 most recent runs.
+    pub fn save(&mut self, path: &str) -> Result<()> {
+        let excess = self.runs.len().saturating_sub(MAX_RUNS);
+        self.runs.drain(..excess);
+
+        create_parent_dir(path)?;
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
@@ -1,17 +1,27 @@
 use std::{
     cmp::min,
+    env,
     fs::{self, File},
-    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    io::{self, Read, Write},
+    os::unix::fs::MetadataExt,
     path::Path,
+    process::{Command, Stdio},
 };
 
-use anyhow::{Context, Result};
+use crate::interrupt;
+
+use anyhow::{bail, Context, Result};
 use base64::{prelude::BASE64_STANDARD, Engine};
 use colored::Colorize;
-use flate2::read::GzDecoder;
+use flate2::read::{GzDecoder, MultiGzDecoder, ZlibDecoder};
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
+use regex::Regex;
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE, USER_AGENT},
+    Client, StatusCode,
+};
+use serde::Deserialize;
 use truncatable::Truncatable;
 
 /// Creates the parent directory for a given path if it does not exist.
@@ -29,6 +39,99 @@ pub fn create_parent_dir(path: &str) -> Result<()> {
     Ok(())
 }
 
+/// Whether mihoro is running as root, asking `id` where there is no `/proc` (e.g. on macOS).
+pub fn is_root() -> bool {
+    match fs::metadata("/proc/self") {
+        Ok(metadata) => metadata.uid() == 0,
+        Err(_) => Command::new("id")
+            .arg("-u")
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "0"),
+    }
+}
+
+/// OS name used in release asset names for the machine mihoro runs on, e.g. `darwin` on macOS.
+pub fn release_os() -> &'static str {
+    match env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    }
+}
+
+/// OS a release asset is built for if its URL names one, e.g. `linux` for
+/// `mihomo-linux-amd64-v1.18.8.gz`.
+pub fn asset_os(url: &str) -> Option<&'static str> {
+    let name = url.rsplit('/').next().unwrap_or(url).to_lowercase();
+    ["linux", "darwin", "windows", "freebsd", "android"]
+        .into_iter()
+        .find(|os| name.contains(&format!("-{os}-")))
+}
+
+/// `arg` quoted for a POSIX shell, e.g. to pass it within a command line run over SSH.
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Whether executable `name` is found on `$PATH`.
+pub fn command_exists(name: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| {
+            let path = dir.join(name);
+            Path::new(&path).is_file()
+        })
+    })
+}
+
+/// A command to run as root, with optional input piped to stdin.
+#[derive(Debug, Clone)]
+pub struct RootCommand {
+    pub argv: Vec<String>,
+    pub stdin: Option<String>,
+}
+
+impl RootCommand {
+    /// Command from whitespace separated `argv`.
+    pub fn new(argv: &str) -> RootCommand {
+        RootCommand {
+            argv: argv.split_whitespace().map(String::from).collect(),
+            stdin: None,
+        }
+    }
+
+    /// Pipe `input` to the command's stdin.
+    pub fn stdin(mut self, input: String) -> RootCommand {
+        self.stdin = Some(input);
+        self
+    }
+
+    /// Run command, prefixed with `sudo` unless running as root.
+    pub fn run(&self, as_root: bool) -> Result<()> {
+        let mut cmd = if as_root {
+            Command::new(&self.argv[0])
+        } else {
+            let mut cmd = Command::new("sudo");
+            cmd.arg(&self.argv[0]);
+            cmd
+        };
+        cmd.args(&self.argv[1..]);
+        if self.stdin.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
+
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("failed to execute {}", self.argv[0]))?;
+        if let (Some(input), Some(mut stdin)) = (&self.stdin, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("`{}` exited with {}", self.argv.join(" "), status);
+        }
+        Ok(())
+    }
+}
+
 /// Download file from url to path with a reusable http client.
 ///
 /// Renders a progress bar if content-length is available from the url headers provided. If not,
@@ -41,15 +144,75 @@ pub fn create_parent_dir(path: &str) -> Result<()> {
 /// Note: Allow `clippy::unused_io_amount` because we are writing downloaded chunks on the fly.
 #[allow(clippy::unused_io_amount)]
 pub async fn download_file(client: &Client, url: &str, path: &str) -> Result<()> {
+    download_file_with_headers(client, url, path, HeaderMap::new()).await?;
+    Ok(())
+}
+
+/// Response to a download, its body written to the destination.
+#[derive(Debug, Clone)]
+pub struct Downloaded {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+}
+
+/// Error of a server responding to a download with a non-success status.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    message: String,
+}
+
+impl HttpStatusError {
+    pub fn new(status: StatusCode, message: String) -> HttpStatusError {
+        HttpStatusError { status, message }
+    }
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Download file from `url` to `path` like [`download_file`], sending extra request `headers`
+/// and returning the response's status and headers.
+///
+/// Returns `None` without touching `path` if the server responds with `304 Not Modified` to
+/// conditional request headers.
+pub async fn download_file_with_headers(
+    client: &Client,
+    url: &str,
+    path: &str,
+    headers: HeaderMap,
+) -> Result<Option<Downloaded>> {
     // Create parent directory for download destination if not exists
     create_parent_dir(path)?;
 
     // Create shared http client for multiple downloads when possible
     let res = client
         .get(url)
+        .headers(headers)
         .send()
         .await
         .with_context(|| format!("failed to GET from '{}'", &url))?;
+    if res.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        let status = res.status();
+        let response_headers = res.headers().clone();
+        let body = res.text().await.unwrap_or_default();
+        let message = match error_page_details(status, &response_headers, &body) {
+            Some(details) => format!("'{url}' responded with an error page {details}"),
+            None => format!("'{url}' responded with {status}"),
+        };
+        return Err(HttpStatusError::new(status, message).into());
+    }
+
+    let status = res.status();
+    let response_headers = res.headers().clone();
 
     // If content length is not available or 0, use a spinner instead of a progress bar
     let total_size = res.content_length().unwrap_or(0);
@@ -78,29 +241,110 @@ pub async fn download_file(client: &Client, url: &str, path: &str) -> Result<()>
         .underline();
     pb.set_message(format!("Downloading {truncated_url}"));
 
-    // Start file download and update progress bar when new data chunk is received
-    let mut file = File::create(path)?;
-    let mut downloaded: u64 = 0;
-    let mut stream = res.bytes_stream();
+    // Start file download and update progress bar when new data chunk is received, downloading to
+    // a partial file first so that an existing file at `path` survives failed downloads
+    let partial_path = partial_path(path);
+    interrupt::track(&partial_path);
+    let download = async {
+        let mut file = File::create(&partial_path)?;
+        let mut downloaded: u64 = 0;
+        let mut stream = res.bytes_stream();
 
-    while let Some(item) = stream.next().await {
-        let chunk = item.with_context(|| "error while downloading file")?;
+        while let Some(item) = stream.next().await {
+            let chunk = item.with_context(|| "error while downloading file")?;
 
-        file.write(&chunk)
-            .with_context(|| "error while writing to file")?;
-        if total_size != 0 {
-            let new = min(downloaded + (chunk.len() as u64), total_size);
-            downloaded = new;
-            pb.set_position(new);
-        } else {
-            pb.inc(chunk.len() as u64);
+            file.write(&chunk)
+                .with_context(|| "error while writing to file")?;
+            if total_size != 0 {
+                let new = min(downloaded + (chunk.len() as u64), total_size);
+                downloaded = new;
+                pb.set_position(new);
+            } else {
+                pb.inc(chunk.len() as u64);
+            }
         }
-    }
+        Ok(())
+    };
+    finish_partial(download.await, &partial_path, path)?;
 
     pb.finish_with_message(format!("Downloaded to {}", path.underline()));
-    Ok(())
+    Ok(Some(Downloaded {
+        status,
+        headers: response_headers,
+    }))
+}
+
+/// Number of lines of an error page's text shown by [`error_page_details`].
+const ERROR_PAGE_LINES: usize = 5;
+
+/// Status, content type and first lines of text of a response whose `body` is an HTML page
+/// rather than the file asked for, e.g. a provider's page about rate limiting or an expired
+/// subscription. Returns `None` if `body` does not look like HTML.
+pub fn error_page_details(status: StatusCode, headers: &HeaderMap, body: &str) -> Option<String> {
+    let head = body
+        .trim_start()
+        .chars()
+        .take(1024)
+        .collect::<String>()
+        .to_lowercase();
+    let is_html = head.starts_with("<!doctype html")
+        || head.starts_with("<html")
+        || ["<head", "<body", "<title", "<br />", "<br>"]
+            .iter()
+            .any(|tag| head.contains(tag));
+    if !is_html {
+        return None;
+    }
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("no content type");
+    // Drop markup and scripts, keeping the page's text
+    let text = Regex::new(r"(?is)<(script|style)\b.*?</(script|style)>|<[^>]*>")
+        .map(|tags| tags.replace_all(body, "\n").into_owned())
+        .unwrap_or_default();
+    let excerpt = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .take(ERROR_PAGE_LINES)
+        .map(|line| {
+            let shown: String = line.chars().take(100).collect();
+            let ellipsis = if shown.len() < line.len() { "..." } else { "" };
+            format!("{shown}{ellipsis}")
+        })
+        .collect::<Vec<_>>()
+        .join(" / ");
+    Some(format!("({status}, {content_type}): \"{excerpt}\""))
+}
+
+/// Path of the partial file that `path` is written to before being renamed into place.
+fn partial_path(path: &str) -> String {
+    format!("{path}.part")
+}
+
+/// Move complete partial file `partial_path` into place at `path`, or remove it if `written`
+/// failed.
+fn finish_partial(written: Result<()>, partial_path: &str, path: &str) -> Result<()> {
+    let result = written.and_then(|_| Ok(fs::rename(partial_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(partial_path);
+    }
+    interrupt::untrack(partial_path);
+    result
+}
+
+/// Write `contents` to `path` through a partial file renamed into place, so that `path` is never
+/// left half-written, even if interrupted.
+pub fn write_file_atomic(path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+    let partial_path = partial_path(path);
+    interrupt::track(&partial_path);
+    let written = fs::write(&partial_path, contents).map_err(anyhow::Error::from);
+    finish_partial(written, &partial_path, path)
 }
 
+/// Delete file at path if it exists.
 pub fn delete_file(path: &str, prefix: &str) -> Result<()> {
     // Delete file if exists
     if Path::new(path).exists() {
@@ -111,14 +355,21 @@ pub fn delete_file(path: &str, prefix: &str) -> Result<()> {
     Ok(())
 }
 
+/// Extract gzip file to `filename` and remove the original gzip file.
 pub fn extract_gzip(gzip_path: &str, filename: &str, prefix: &str) -> Result<()> {
     // Create parent directory for extraction dest if not exists
     create_parent_dir(filename)?;
 
-    // Extract gzip file
-    let mut archive = GzDecoder::new(fs::File::open(gzip_path)?);
-    let mut file = fs::File::create(filename)?;
-    io::copy(&mut archive, &mut file)?;
+    // Extract gzip file, replacing `filename` only once fully extracted
+    let partial_path = partial_path(filename);
+    interrupt::track(&partial_path);
+    let extract = || -> Result<()> {
+        let mut archive = GzDecoder::new(fs::File::open(gzip_path)?);
+        let mut file = fs::File::create(&partial_path)?;
+        io::copy(&mut archive, &mut file)?;
+        Ok(())
+    };
+    finish_partial(extract(), &partial_path, filename)?;
     fs::remove_file(gzip_path)?;
     println!(
         "{} Extracted to {}",
@@ -128,6 +379,87 @@ pub fn extract_gzip(gzip_path: &str, filename: &str, prefix: &str) -> Result<()>
     Ok(())
 }
 
+/// Extract binary `binary_name` from a `.tar.gz` archive to `filename` and remove the archive.
+///
+/// Release archives (e.g. sing-box's) usually nest the binary under a versioned directory, so the
+/// first file named `binary_name` anywhere in the archive is extracted.
+pub fn extract_tar_gz_binary(
+    archive_path: &str,
+    binary_name: &str,
+    filename: &str,
+    prefix: &str,
+) -> Result<()> {
+    create_parent_dir(filename)?;
+
+    let partial_path = partial_path(filename);
+    interrupt::track(&partial_path);
+    let extract = || -> Result<()> {
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(archive_path)?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type().is_file()
+                && entry
+                    .path()?
+                    .file_name()
+                    .is_some_and(|name| name == binary_name)
+            {
+                entry.unpack(&partial_path)?;
+                return Ok(());
+            }
+        }
+        bail!("`{}` not found in downloaded archive", binary_name);
+    };
+    finish_partial(extract(), &partial_path, filename)?;
+
+    fs::remove_file(archive_path)?;
+    println!(
+        "{} Extracted to {}",
+        prefix.green(),
+        filename.underline().yellow()
+    );
+    Ok(())
+}
+
+/// Extract a `.zip` or `.tar.gz` archive into directory `dest`.
+///
+/// Archive format is inferred from the extension of `source_url`, as downloaded archives are saved
+/// to arbitrary paths.
+pub fn extract_archive(archive_path: &str, source_url: &str, dest: &str) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    let archive_file = File::open(archive_path)?;
+    if source_url.ends_with(".zip") {
+        zip::ZipArchive::new(archive_file)?
+            .extract(dest)
+            .with_context(|| format!("failed to extract zip archive to `{}`", dest))?;
+    } else {
+        tar::Archive::new(GzDecoder::new(archive_file))
+            .unpack(dest)
+            .with_context(|| format!("failed to extract tar archive to `{}`", dest))?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+}
+
+/// Query the tag name of the latest release of GitHub repository `repo` (e.g. `owner/name`).
+pub async fn github_latest_release(client: &Client, repo: &str) -> Result<String> {
+    let url = format!("https://api.github.com/repos/{repo}/releases/latest");
+    let release: GithubRelease = client
+        .get(&url)
+        .header(USER_AGENT, "mihoro")
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("failed to GET from '{}'", &url))?
+        .json()
+        .await
+        .with_context(|| format!("failed to parse latest release of `{}`", repo))?;
+    Ok(release.tag_name)
+}
+
 /// Try and decode a base64 encoded file in place.
 ///
 /// Decodes the base64 encoded content of a file in place and writes the decoded content back to the
@@ -137,23 +469,14 @@ pub fn extract_gzip(gzip_path: &str, filename: &str, prefix: &str) -> Result<()>
 ///
 /// * `filepath` - Path to the file to decode base64 content in place.
 pub fn try_decode_base64_file_inplace(filepath: &str) -> Result<()> {
-    // Open the file for reading and writing
-    let mut file = File::options().read(true).write(true).open(filepath)?;
-    let mut base64_buf = Vec::new();
-
     // Read the file content into the buffer
-    file.read_to_end(&mut base64_buf)?;
+    let base64_buf = fs::read(filepath)?;
 
     // Try to decode the base64 content
     match BASE64_STANDARD.decode(&base64_buf) {
         Ok(decoded_bytes) => {
-            // Truncate the file and seek to the beginning
-            file.set_len(0)?;
-            file.seek(SeekFrom::Start(0))?;
-
             // Write the decoded bytes back to the file
-            let mut writer = BufWriter::new(&file);
-            writer.write_all(&decoded_bytes)?;
+            write_file_atomic(filepath, decoded_bytes)?;
         }
         Err(_) => {
             // If decoding fails, do nothing and return Ok
@@ -163,3 +486,84 @@ pub fn try_decode_base64_file_inplace(filepath: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Decompress a gzip or zlib compressed file in place, detected by its magic bytes.
+///
+/// Compressed responses are decoded by the HTTP client already, unless servers send them without
+/// a `Content-Encoding` header. Returns whether the file was decompressed.
+pub fn try_decompress_file_inplace(filepath: &str) -> Result<bool> {
+    let raw = fs::read(filepath)?;
+    let mut decompressed = Vec::new();
+    let decoded = match raw.as_slice() {
+        [0x1f, 0x8b, ..] => MultiGzDecoder::new(raw.as_slice())
+            .read_to_end(&mut decompressed)
+            .is_ok(),
+        [cmf, flg, ..] if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 => {
+            ZlibDecoder::new(raw.as_slice())
+                .read_to_end(&mut decompressed)
+                .is_ok()
+        }
+        _ => false,
+    };
+    if !decoded {
+        return Ok(false);
+    }
+    write_file_atomic(filepath, decompressed)?;
+    Ok(true)
+}
+
+/// Convert a JSON file to YAML in place if it holds a JSON object, otherwise leave it as is.
+///
+/// Some subscription converters serve clash configs as JSON, which mihomo configs are then
+/// derived from like any other YAML config. Returns whether the file was converted.
+pub fn try_convert_json_file_inplace(filepath: &str) -> Result<bool> {
+    let raw = fs::read_to_string(filepath)?;
+    if !raw.trim_start().starts_with('{') {
+        return Ok(false);
+    }
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(&raw) else {
+        return Ok(false);
+    };
+    write_file_atomic(filepath, serde_yaml::to_string(&json)?)?;
+    Ok(true)
+}
+
+/// Copy `text` to the clipboard, returning the method used.
+///
+/// Tries `wl-copy` on Wayland, `xclip` or `xsel` on X11 and `pbcopy` on macOS, falling back to the
+/// OSC 52 escape sequence, which most terminal emulators (also over SSH) pass to the clipboard.
+pub fn copy_to_clipboard(text: &str) -> Result<&'static str> {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = env::var_os("DISPLAY").is_some();
+    let candidates: [(&str, &[&str], bool); 4] = [
+        ("wl-copy", &[], wayland),
+        ("xclip", &["-selection", "clipboard"], x11),
+        ("xsel", &["--clipboard", "--input"], x11),
+        ("pbcopy", &[], true),
+    ];
+    for (program, args, available) in candidates {
+        if !available || !command_exists(program) {
+            continue;
+        }
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(program);
+        }
+    }
+
+    // Write to the terminal directly, as stdout may be captured by e.g. `eval $(...)`
+    let sequence = format!("\x1b]52;c;{}\x07", BASE64_STANDARD.encode(text));
+    match File::options().write(true).open("/dev/tty") {
+        Ok(mut tty) => tty.write_all(sequence.as_bytes())?,
+        Err(_) => io::stderr().write_all(sequence.as_bytes())?,
+    }
+    Ok("OSC 52")
+}
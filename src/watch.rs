@@ -0,0 +1,78 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use inotify::{Inotify, WatchMask};
+
+/// Watches files for changes with inotify.
+///
+/// Parent directories are watched instead of the files themselves, as editors commonly save by
+/// writing a new file and renaming it over the original one.
+pub struct FileWatcher {
+    inotify: Inotify,
+    filenames: HashSet<OsString>,
+    buffer: [u8; 4096],
+}
+
+impl FileWatcher {
+    /// Start watching `paths` for changes.
+    pub fn new(paths: &[&str]) -> Result<FileWatcher> {
+        let inotify = Inotify::init().with_context(|| "failed to initialize inotify")?;
+        let mut filenames = HashSet::new();
+        for path in paths {
+            let path = Path::new(path);
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let filename = path
+                .file_name()
+                .with_context(|| format!("`{}` is not a file", path.display()))?;
+
+            inotify
+                .watches()
+                .add(
+                    &parent,
+                    WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+                )
+                .with_context(|| format!("failed to watch `{}`", parent.display()))?;
+            filenames.insert(filename.to_os_string());
+        }
+
+        Ok(FileWatcher {
+            inotify,
+            filenames,
+            buffer: [0; 4096],
+        })
+    }
+
+    /// Block until any of the watched files changes.
+    ///
+    /// Bursts of changes (e.g. an editor writing a file in several steps) are debounced into a
+    /// single change.
+    pub fn wait(&mut self) -> Result<()> {
+        loop {
+            let events = self.inotify.read_events_blocking(&mut self.buffer)?;
+            let changed = events
+                .into_iter()
+                .any(|event| event.name.is_some_and(|name| self.filenames.contains(name)));
+            if changed {
+                break;
+            }
+        }
+
+        // Drain events arriving shortly after the first change
+        thread::sleep(Duration::from_millis(200));
+        while self
+            .inotify
+            .read_events(&mut self.buffer)
+            .is_ok_and(|mut events| events.next().is_some())
+        {}
+        Ok(())
+    }
+}